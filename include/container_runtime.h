@@ -0,0 +1,57 @@
+/*
+ * C interface of the container runtime, for supervisors embedding it instead of running
+ * the container_runtime binary. Build the library with scripts/build-capi.sh.
+ *
+ * Every function returns one of the CR_* codes. On failure, if err isn't NULL, *err is
+ * set to a message which has to be freed with cr_free_string. create forks the
+ * container's monitor, like the binary does, so the caller's process is forked too.
+ */
+
+#ifndef CONTAINER_RUNTIME_H
+#define CONTAINER_RUNTIME_H
+
+#include <stdbool.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CR_OK 0
+/* An argument or option is invalid. */
+#define CR_ERR_ARGS 1
+/* The bundle or its config.json is invalid. */
+#define CR_ERR_BUNDLE 2
+/* The container doesn't exist, or the operation doesn't apply to its status. */
+#define CR_ERR_STATE 3
+#define CR_ERR_IO 4
+/* Any other failure of the runtime. */
+#define CR_ERR_RUNTIME 5
+#define CR_ERR_PANIC 6
+
+/*
+ * Creates the container id from the bundle at bundle. opts_json may be NULL, or a JSON
+ * object with any of "strict", "secureDefaults", "interactive" (booleans), "timeoutSecs"
+ * (a number), "pod" (a string) and "labels" (an object of strings).
+ */
+int cr_create(const char *id, const char *bundle, const char *opts_json, char **err);
+
+/* Starts the created container id. */
+int cr_start(const char *id, char **err);
+
+/* Sends signal, a name like "SIGTERM" or "TERM" or a number, to the container's process. */
+int cr_kill(const char *id, const char *signal, char **err);
+
+/* Deletes the container id, stopping it first if force is set. */
+int cr_delete(const char *id, bool force, char **err);
+
+/* Sets *state_json to the container's state, as printed by the state command. */
+int cr_state(const char *id, char **state_json, char **err);
+
+/* Frees a string returned by the functions above, NULL is fine. */
+void cr_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,7 @@
+#!/bin/sh
+# Builds the runtime as a shared library with the C interface declared in
+# include/container_runtime.h, libcontainer_runtime_lib.so. Extra arguments go to cargo,
+# e.g. --target.
+set -eu
+
+cargo rustc --lib --release --features capi --crate-type cdylib "$@"
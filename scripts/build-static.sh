@@ -0,0 +1,18 @@
+#!/bin/sh
+# Builds a fully static binary, for minimal or immutable hosts and initramfs. Takes the
+# target to build for, x86_64 musl by default. The target must be installed with
+# `rustup target add`, the binary ends up in target/<target>/release-static.
+set -eu
+
+TARGET=${1:-x86_64-unknown-linux-musl}
+
+RUSTFLAGS="${RUSTFLAGS:-} -C target-feature=+crt-static" \
+    cargo build --profile release-static --features static --target "$TARGET" --bin container_runtime
+
+BIN="target/$TARGET/release-static/container_runtime"
+# Nothing for the dynamic loader to do, or the host would need our libc.
+if readelf -l "$BIN" | grep -q INTERP; then
+    echo "$BIN is not static" >&2
+    exit 1
+fi
+echo "$BIN"
@@ -0,0 +1,15 @@
+#!/bin/sh
+# Compile checks for every supported target. Pass targets as arguments to check a
+# subset, e.g. from a CI matrix. Targets must be installed with `rustup target add`.
+set -eu
+
+TARGETS=${*:-"x86_64-unknown-linux-gnu x86_64-unknown-linux-musl aarch64-unknown-linux-gnu aarch64-unknown-linux-musl"}
+
+for target in $TARGETS; do
+    echo "checking $target"
+    cargo check --all-targets --target "$target"
+    # Static builds, see build-static.sh.
+    case "$target" in
+        *-musl) cargo check --all-targets --features static --target "$target" ;;
+    esac
+done
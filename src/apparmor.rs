@@ -0,0 +1,18 @@
+//! AppArmor profile of the container process.
+
+use crate::error::ContainerErr;
+use crate::procfs;
+use std::path::Path;
+
+/// Sets the profile the current process switches to on its next exec.
+pub fn set_exec_profile(profile: &str) -> Result<(), ContainerErr> {
+    // Newer kernels have an AppArmor specific attr dir, the shared one is used by
+    // whichever LSM is loaded first.
+    let attr = if Path::new("/proc/self/attr/apparmor/exec").exists() {
+        "/proc/self/attr/apparmor/exec"
+    } else {
+        "/proc/self/attr/exec"
+    };
+    procfs::write(attr, &format!("exec {}", profile))
+        .map_err(|e| ContainerErr::AppArmor(format!("failed to set profile {}: {:?}", profile, e)))
+}
@@ -1,59 +1,881 @@
+use crate::completion::Shell;
+use container_runtime_lib::cmd::{
+    parse_detach_keys, validate_id, AttachOpts, CheckpointOpts, CreateOpts, DeleteOpts, DeviceOpts,
+    EventsOpts, ExecOpts, GlobalOpts, KillOpts, ListOpts, ProcessOverrides, PruneOpts, RestoreOpts,
+    RunOpts, StartOpts, StateOpts, StopOpts, UpdateOpts,
+};
 use container_runtime_lib::error::ContainerErr;
 use std::env::Args;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Command {
+    Attach {
+        container_id: String,
+        opts: AttachOpts,
+    },
+    Checkpoint {
+        container_id: String,
+        opts: CheckpointOpts,
+    },
+    Completion {
+        shell: Shell,
+    },
     Create {
         container_id: String,
         bundle_path: String,
+        opts: CreateOpts,
     },
     Delete {
         container_id: String,
+        opts: DeleteOpts,
+    },
+    Device {
+        action: DeviceAction,
+        container_id: String,
+        host_path: String,
     },
+    Events {
+        container_id: String,
+        opts: EventsOpts,
+    },
+    Exec {
+        container_id: String,
+        command: Vec<String>,
+        opts: ExecOpts,
+    },
+    Features,
+    /// `--help-json`, describes the commands.
+    HelpJson,
     Kill {
         container_id: String,
         signal: String,
+        opts: KillOpts,
     },
+    List {
+        opts: ListOpts,
+    },
+    Prune {
+        opts: PruneOpts,
+    },
+    Resize {
+        container_id: String,
+        rows: u16,
+        cols: u16,
+    },
+    Restore {
+        container_id: String,
+        bundle_path: String,
+        opts: RestoreOpts,
+    },
+    Run {
+        container_id: String,
+        bundle_path: String,
+        opts: RunOpts,
+    },
+    Selftest,
     Start {
         container_id: String,
+        opts: StartOpts,
     },
     State {
         container_id: String,
+        opts: StateOpts,
+    },
+    Stop {
+        container_id: String,
+        opts: StopOpts,
+    },
+    Update {
+        container_id: String,
+        opts: UpdateOpts,
+    },
+    Wait {
+        container_id: String,
+    },
+    Pod {
+        action: PodAction,
+        name: String,
     },
 }
 
-pub fn parse_args(args: Args) -> Result<Command, ContainerErr> {
-    let args: Vec<String> = args.collect();
-    match args.len() {
-        3 => match args[1].as_str() {
-            "start" => Ok(Command::Start {
-                container_id: args[2].clone(),
-            }),
-            "delete" => Ok(Command::Delete {
-                container_id: args[2].clone(),
-            }),
-            "state" => Ok(Command::State {
-                container_id: args[2].clone(),
-            }),
-            _ => Err(ContainerErr::invalid_args(&format!(
-                "Unrecognized command: {}",
-                args[1]
-            ))),
-        },
-        4 => match args[1].as_str() {
-            "create" => Ok(Command::Create {
-                container_id: args[2].clone(),
-                bundle_path: args[3].clone(),
-            }),
-            "kill" => Ok(Command::Kill {
-                container_id: args[2].clone(),
-                signal: args[3].clone(),
-            }),
-            _ => Err(ContainerErr::invalid_args(&format!(
-                "Unrecognized command: {}",
-                args[1]
-            ))),
+#[derive(Debug)]
+pub enum DeviceAction {
+    Add(DeviceOpts),
+    Remove,
+}
+
+#[derive(Debug)]
+pub enum PodAction {
+    Create,
+    Inspect,
+    Delete,
+}
+
+/// What a command accepts. Commands are parsed with it, and it's what `completion` and
+/// `--help-json` describe.
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// The positional arguments, as shown in the usage.
+    pub args: &'static [&'static str],
+    /// Flags taking a value.
+    pub value_flags: &'static [&'static str],
+    /// Flags taking no value.
+    pub switches: &'static [&'static str],
+    /// See `parse_cmd_args`.
+    pub passthrough_after: Option<usize>,
+}
+
+impl CommandSpec {
+    const fn new(name: &'static str, args: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            args,
+            value_flags: &[],
+            switches: &[],
+            passthrough_after: None,
+        }
+    }
+
+    const fn value_flags(mut self, flags: &'static [&'static str]) -> Self {
+        self.value_flags = flags;
+        self
+    }
+
+    const fn switches(mut self, flags: &'static [&'static str]) -> Self {
+        self.switches = flags;
+        self
+    }
+
+    const fn passthrough_after(mut self, count: usize) -> Self {
+        self.passthrough_after = Some(count);
+        self
+    }
+
+    fn parse<I: Iterator<Item = String>>(&self, args: I) -> Result<CmdArgs, ContainerErr> {
+        parse_cmd_args(
+            args,
+            self.value_flags,
+            self.switches,
+            self.passthrough_after,
+        )
+    }
+}
+
+/// Global flags, given before the command. All of them take a value.
+pub const GLOBAL_FLAGS: &[&str] = &["--cgroup-manager", "--trace-output", "--disable-controller"];
+
+/// Flags of create, which run takes as well.
+const CREATE_VALUE_FLAGS: &[&str] = &[
+    "--publish",
+    "--timeout",
+    "--preserve-fds",
+    "--log-driver",
+    "--pod",
+    "--restart",
+    "--label",
+    "--env",
+    "--args",
+    "--append-arg",
+    "--cwd",
+    "--resource-policy",
+    "--gpus",
+];
+const CREATE_SWITCHES: &[&str] = &[
+    "--strict",
+    "--secure-defaults",
+    "--interactive",
+    "--time-report",
+];
+const RUN_SWITCHES: &[&str] = &[
+    "--strict",
+    "--secure-defaults",
+    "--interactive",
+    "--time-report",
+    "--no-stdin",
+];
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("attach", &["<container-id>"])
+        .value_flags(&["--detach-keys"])
+        .switches(&["--no-stdin"]),
+    CommandSpec::new("checkpoint", &["<container-id>"])
+        .value_flags(&["--image-path", "--work-path", "--parent-path", "--empty-ns"])
+        .switches(&["--pre-dump", "--leave-running", "--tcp-established"]),
+    CommandSpec::new("completion", &["bash|zsh|fish"]),
+    CommandSpec::new("create", &["<container-id>", "<bundle>"])
+        .value_flags(CREATE_VALUE_FLAGS)
+        .switches(CREATE_SWITCHES),
+    CommandSpec::new("delete", &["<container-id>"]).switches(&["--force"]),
+    CommandSpec::new("device", &["add|remove", "<container-id>", "<host-path>"])
+        .value_flags(&["--permissions"]),
+    CommandSpec::new("events", &["<container-id>"])
+        .value_flags(&["--interval", "--psi-threshold"])
+        .switches(&["--stats"]),
+    CommandSpec::new("exec", &["<container-id>", "<command>..."])
+        .value_flags(&[
+            "--env",
+            "--cwd",
+            "--user",
+            "--pid-file",
+            "--process",
+            "-p",
+            "--preserve-fds",
+            "--console-socket",
+        ])
+        .switches(&["--tty", "--detach"])
+        .passthrough_after(1),
+    CommandSpec::new("features", &[]),
+    CommandSpec::new("kill", &["<container-id>", "<signal>"]).switches(&["--all"]),
+    CommandSpec::new("list", &[]).value_flags(&["--filter"]),
+    CommandSpec::new("pod", &["create|inspect|delete", "<name>"]),
+    CommandSpec::new("prune", &[]).value_flags(&["--age"]),
+    CommandSpec::new("resize", &["<container-id>", "<rows>", "<cols>"]),
+    CommandSpec::new("restore", &["<container-id>", "<bundle>"])
+        .value_flags(&["--image-path", "--work-path", "--netns", "--mount-map"])
+        .switches(&["--tcp-established"]),
+    CommandSpec::new("run", &["<container-id>", "<bundle>", "[-- <command>...]"])
+        .value_flags(CREATE_VALUE_FLAGS)
+        .switches(RUN_SWITCHES),
+    CommandSpec::new("selftest", &[]),
+    CommandSpec::new("start", &["<container-id>"]).value_flags(&["--timeout"]),
+    CommandSpec::new("state", &["<container-id>"]).switches(&["--watch"]),
+    CommandSpec::new("stop", &["<container-id>"]).value_flags(&["--timeout"]),
+    CommandSpec::new("update", &["<container-id>"])
+        .value_flags(&[
+            "--resources",
+            "-r",
+            "--cpu-quota-min",
+            "--cpu-quota-max",
+            "--interval",
+        ])
+        .switches(&["--auto-cpu"]),
+    CommandSpec::new("wait", &["<container-id>"]),
+];
+
+/// Positional arguments and flags following a subcommand.
+struct CmdArgs {
+    positional: Vec<String>,
+    flags: Vec<(String, String)>,
+    /// The arguments after `--`, a command line replacing the process' args.
+    command: Option<Vec<String>>,
+}
+
+impl CmdArgs {
+    /// All values provided for a flag, in the order they were given.
+    fn values(&self, flag: &str) -> Vec<String> {
+        self.flags
+            .iter()
+            .filter(|(f, _)| f == flag)
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    /// The last value provided for a flag.
+    fn value(&self, flag: &str) -> Option<String> {
+        self.values(flag).pop()
+    }
+
+    /// Whether a flag was given at all.
+    fn has(&self, flag: &str) -> bool {
+        self.flags.iter().any(|(f, _)| f == flag)
+    }
+
+    /// The container id, the first positional argument of the commands taking one.
+    fn container_id(&self) -> Result<String, ContainerErr> {
+        validate_id(&self.positional[0])?;
+        Ok(self.positional[0].clone())
+    }
+
+    fn expect_positional(&self, count: usize, cmd: &str) -> Result<(), ContainerErr> {
+        if self.positional.len() != count {
+            return Err(ContainerErr::invalid_args(&format!(
+                "Invalid number of arguments for {}",
+                cmd
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Splits the arguments of a subcommand into positional arguments and flags.
+/// `value_flags` lists the flags this subcommand accepts which take a value, either as
+/// the next argument or in `--flag=value` form. `switches` lists the flags which take no
+/// value. Once `passthrough_after` positional arguments have been seen, the remaining
+/// arguments are taken as positional even if they look like flags. Arguments after a `--`
+/// before then are the command.
+fn parse_cmd_args<I: Iterator<Item = String>>(
+    args: I,
+    value_flags: &[&str],
+    switches: &[&str],
+    passthrough_after: Option<usize>,
+) -> Result<CmdArgs, ContainerErr> {
+    let mut positional = Vec::new();
+    let mut flags = Vec::new();
+    let mut command = None;
+    let mut args = args.peekable();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            let rest: Vec<String> = args.collect();
+            if rest.is_empty() {
+                return Err(ContainerErr::invalid_args("Missing command after --"));
+            }
+            command = Some(rest);
+            break;
+        }
+        if passthrough_after.is_some_and(|n| positional.len() >= n) {
+            positional.push(arg);
+            positional.extend(args);
+            break;
+        }
+        if !arg.starts_with('-') {
+            positional.push(arg);
+            continue;
+        }
+
+        if switches.contains(&arg.as_str()) {
+            flags.push((arg, String::new()));
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+
+        if !value_flags.contains(&name.as_str()) {
+            return Err(ContainerErr::invalid_args(&format!(
+                "Unrecognized flag: {}",
+                name
+            )));
+        }
+
+        let value = match inline_value {
+            Some(value) => value,
+            None => args.next().ok_or_else(|| {
+                ContainerErr::invalid_args(&format!("Missing value for flag: {}", name))
+            })?,
+        };
+        flags.push((name, value));
+    }
+
+    Ok(CmdArgs {
+        positional,
+        flags,
+        command,
+    })
+}
+
+/// Parses a whole number of seconds.
+fn parse_duration_secs(value: &str) -> Result<Duration, ContainerErr> {
+    value
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|_| ContainerErr::invalid_args(&format!("Invalid duration: {}", value)))
+}
+
+/// Parses a non-negative number, e.g. of descriptors.
+fn parse_count(value: &str) -> Result<u32, ContainerErr> {
+    value
+        .parse()
+        .map_err(|_| ContainerErr::invalid_args(&format!("Invalid number: {}", value)))
+}
+
+/// Parses a percentage, e.g. of time stalled.
+fn parse_percentage(value: &str) -> Result<f64, ContainerErr> {
+    value
+        .parse()
+        .ok()
+        .filter(|p| (0.0..=100.0).contains(p))
+        .ok_or_else(|| ContainerErr::invalid_args(&format!("Invalid percentage: {}", value)))
+}
+
+/// Parses a terminal's number of rows or columns.
+fn parse_dimension(value: &str) -> Result<u16, ContainerErr> {
+    value
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| ContainerErr::invalid_args(&format!("Invalid terminal size: {}", value)))
+}
+
+/// Parses an `old=new` path pair.
+fn parse_mount_map(value: &str) -> Result<(PathBuf, PathBuf), ContainerErr> {
+    match value.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+            Ok((PathBuf::from(old), PathBuf::from(new)))
+        }
+        _ => Err(ContainerErr::invalid_args(&format!(
+            "Invalid mount map, expected old=new: {}",
+            value
+        ))),
+    }
+}
+
+/// Parses a `key=value` label, the value may be empty.
+fn parse_label(value: &str) -> Result<(String, String), ContainerErr> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(ContainerErr::invalid_args(&format!(
+            "Invalid label, expected key=value: {}",
+            value
+        ))),
+    }
+}
+
+/// Parses `--args`, a JSON array of strings like process.args in config.json.
+fn parse_args_json(value: &str) -> Result<Vec<String>, ContainerErr> {
+    match serde_json::from_str::<Vec<String>>(value) {
+        Ok(args) if !args.is_empty() => Ok(args),
+        _ => Err(ContainerErr::invalid_args(&format!(
+            "Invalid args, expected a JSON array of strings: {}",
+            value
+        ))),
+    }
+}
+
+fn create_opts(parsed: &CmdArgs) -> Result<CreateOpts, ContainerErr> {
+    Ok(CreateOpts {
+        publish: parsed.values("--publish"),
+        strict: parsed.has("--strict"),
+        secure_defaults: parsed.has("--secure-defaults"),
+        timeout: parsed
+            .value("--timeout")
+            .map(|timeout| parse_duration_secs(&timeout))
+            .transpose()?,
+        preserve_fds: parsed
+            .value("--preserve-fds")
+            .map(|n| parse_count(&n))
+            .transpose()?
+            .unwrap_or_default(),
+        log_driver: parsed
+            .value("--log-driver")
+            .map(|driver| driver.parse())
+            .transpose()?,
+        pod: parsed.value("--pod"),
+        restart: parsed
+            .value("--restart")
+            .map(|policy| policy.parse())
+            .transpose()?,
+        interactive: parsed.has("--interactive"),
+        labels: parsed
+            .values("--label")
+            .iter()
+            .map(|label| parse_label(label))
+            .collect::<Result<_, _>>()?,
+        time_report: parsed.has("--time-report"),
+        resource_policy: parsed
+            .value("--resource-policy")
+            .map(|policy| policy.parse())
+            .transpose()?,
+        gpus: parsed
+            .value("--gpus")
+            .map(|gpus| gpus.parse())
+            .transpose()?,
+        process: ProcessOverrides {
+            env: parsed.values("--env"),
+            args: match (&parsed.command, parsed.value("--args")) {
+                (Some(_), Some(_)) => {
+                    return Err(ContainerErr::invalid_args(
+                        "--args and -- both replace the args, only one can be given",
+                    ))
+                }
+                (Some(command), None) => Some(command.clone()),
+                (None, args) => args.map(|args| parse_args_json(&args)).transpose()?,
+            },
+            append_args: parsed.values("--append-arg"),
+            cwd: parsed.value("--cwd"),
         },
-        _ => Err(ContainerErr::invalid_args("Invalid number of arguments")),
+    })
+}
+
+/// Parses the global options, which come before the command, and the command.
+pub fn parse_args(args: Args) -> Result<(GlobalOpts, Command), ContainerErr> {
+    let mut args = args.skip(1);
+    let mut next = || {
+        args.next()
+            .ok_or_else(|| ContainerErr::invalid_args("Invalid number of arguments"))
+    };
+
+    let mut global = GlobalOpts::default();
+    let mut cmd = next()?;
+    while cmd.starts_with('-') {
+        if cmd == "--help-json" {
+            return Ok((global, Command::HelpJson));
+        }
+        let (name, value) = match cmd.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => (cmd.clone(), next()?),
+        };
+        match name.as_str() {
+            "--cgroup-manager" => global.cgroup_manager = value.parse()?,
+            "--trace-output" => global.trace_output = value.parse()?,
+            "--disable-controller" => global
+                .disabled_controllers
+                .extend(value.split(',').map(String::from)),
+            _ => {
+                return Err(ContainerErr::invalid_args(&format!(
+                    "Unrecognized flag: {}",
+                    name
+                )))
+            }
+        }
+        cmd = next()?;
+    }
+
+    Ok((global, parse_command(cmd, args)?))
+}
+
+fn parse_command<I: Iterator<Item = String>>(
+    cmd: String,
+    args: I,
+) -> Result<Command, ContainerErr> {
+    let spec = COMMANDS
+        .iter()
+        .find(|spec| spec.name == cmd)
+        .ok_or_else(|| ContainerErr::invalid_args(&format!("Unrecognized command: {}", cmd)))?;
+    let parsed = spec.parse(args)?;
+    match cmd.as_str() {
+        "completion" => {
+            parsed.expect_positional(1, &cmd)?;
+            Ok(Command::Completion {
+                shell: parsed.positional[0].parse()?,
+            })
+        }
+        "create" => {
+            parsed.expect_positional(2, &cmd)?;
+            Ok(Command::Create {
+                container_id: parsed.container_id()?,
+                bundle_path: parsed.positional[1].clone(),
+                opts: create_opts(&parsed)?,
+            })
+        }
+        "run" => {
+            parsed.expect_positional(2, &cmd)?;
+            Ok(Command::Run {
+                container_id: parsed.container_id()?,
+                bundle_path: parsed.positional[1].clone(),
+                opts: RunOpts {
+                    create: create_opts(&parsed)?,
+                    no_stdin: parsed.has("--no-stdin"),
+                },
+            })
+        }
+        "attach" => {
+            parsed.expect_positional(1, &cmd)?;
+            let mut opts = AttachOpts {
+                no_stdin: parsed.has("--no-stdin"),
+                ..AttachOpts::default()
+            };
+            if let Some(keys) = parsed.value("--detach-keys") {
+                opts.detach_keys = parse_detach_keys(&keys)?;
+            }
+            Ok(Command::Attach {
+                container_id: parsed.container_id()?,
+                opts,
+            })
+        }
+        "checkpoint" => {
+            parsed.expect_positional(1, &cmd)?;
+            let image_path = parsed
+                .value("--image-path")
+                .ok_or_else(|| ContainerErr::invalid_args("Missing --image-path"))?;
+            Ok(Command::Checkpoint {
+                container_id: parsed.container_id()?,
+                opts: CheckpointOpts {
+                    image_path: PathBuf::from(image_path),
+                    work_path: parsed.value("--work-path").map(PathBuf::from),
+                    parent_path: parsed.value("--parent-path").map(PathBuf::from),
+                    pre_dump: parsed.has("--pre-dump"),
+                    leave_running: parsed.has("--leave-running"),
+                    tcp_established: parsed.has("--tcp-established"),
+                    empty_namespaces: parsed.values("--empty-ns"),
+                },
+            })
+        }
+        "restore" => {
+            parsed.expect_positional(2, &cmd)?;
+            let image_path = parsed
+                .value("--image-path")
+                .ok_or_else(|| ContainerErr::invalid_args("Missing --image-path"))?;
+            Ok(Command::Restore {
+                container_id: parsed.container_id()?,
+                bundle_path: parsed.positional[1].clone(),
+                opts: RestoreOpts {
+                    image_path: PathBuf::from(image_path),
+                    work_path: parsed.value("--work-path").map(PathBuf::from),
+                    netns: parsed.value("--netns").map(PathBuf::from),
+                    mount_map: parsed
+                        .values("--mount-map")
+                        .iter()
+                        .map(|map| parse_mount_map(map))
+                        .collect::<Result<_, _>>()?,
+                    tcp_established: parsed.has("--tcp-established"),
+                },
+            })
+        }
+        "start" => {
+            parsed.expect_positional(1, &cmd)?;
+            let mut opts = StartOpts::default();
+            if let Some(timeout) = parsed.value("--timeout") {
+                opts.timeout = parse_duration_secs(&timeout)?;
+            }
+            Ok(Command::Start {
+                container_id: parsed.container_id()?,
+                opts,
+            })
+        }
+        "stop" => {
+            parsed.expect_positional(1, &cmd)?;
+            let mut opts = StopOpts::default();
+            if let Some(timeout) = parsed.value("--timeout") {
+                opts.timeout = parse_duration_secs(&timeout)?;
+            }
+            Ok(Command::Stop {
+                container_id: parsed.container_id()?,
+                opts,
+            })
+        }
+        "delete" => {
+            parsed.expect_positional(1, &cmd)?;
+            Ok(Command::Delete {
+                container_id: parsed.container_id()?,
+                opts: DeleteOpts {
+                    force: parsed.has("--force"),
+                },
+            })
+        }
+        "device" => {
+            parsed.expect_positional(3, &cmd)?;
+            let action = match parsed.positional[0].as_str() {
+                "add" => DeviceAction::Add(DeviceOpts {
+                    permissions: parsed
+                        .value("--permissions")
+                        .unwrap_or(DeviceOpts::default().permissions),
+                }),
+                "remove" => DeviceAction::Remove,
+                action => {
+                    return Err(ContainerErr::invalid_args(&format!(
+                        "Unrecognized device command: {}",
+                        action
+                    )))
+                }
+            };
+            validate_id(&parsed.positional[1])?;
+            Ok(Command::Device {
+                action,
+                container_id: parsed.positional[1].clone(),
+                host_path: parsed.positional[2].clone(),
+            })
+        }
+        "events" => {
+            parsed.expect_positional(1, &cmd)?;
+            let mut opts = EventsOpts {
+                stats: parsed.has("--stats"),
+                psi_threshold: parsed
+                    .value("--psi-threshold")
+                    .map(|threshold| parse_percentage(&threshold))
+                    .transpose()?,
+                ..EventsOpts::default()
+            };
+            if let Some(interval) = parsed.value("--interval") {
+                opts.interval = parse_duration_secs(&interval)?;
+            }
+            Ok(Command::Events {
+                container_id: parsed.container_id()?,
+                opts,
+            })
+        }
+        "exec" => {
+            let process = parsed.value("--process").or(parsed.value("-p"));
+            // The command comes from the process document if there is one.
+            let command = match &parsed.command {
+                Some(command) => {
+                    parsed.expect_positional(1, &cmd)?;
+                    command.clone()
+                }
+                None => {
+                    let min_positional = if process.is_some() { 1 } else { 2 };
+                    if parsed.positional.len() < min_positional {
+                        return Err(ContainerErr::invalid_args(&format!(
+                            "Invalid number of arguments for {}",
+                            cmd
+                        )));
+                    }
+                    parsed.positional[1..].to_vec()
+                }
+            };
+            Ok(Command::Exec {
+                container_id: parsed.container_id()?,
+                command,
+                opts: ExecOpts {
+                    env: parsed.values("--env"),
+                    cwd: parsed.value("--cwd"),
+                    user: parsed.value("--user"),
+                    tty: parsed.has("--tty"),
+                    console_socket: parsed.value("--console-socket").map(PathBuf::from),
+                    detach: parsed.has("--detach"),
+                    pid_file: parsed.value("--pid-file").map(PathBuf::from),
+                    process: process.map(PathBuf::from),
+                    preserve_fds: parsed
+                        .value("--preserve-fds")
+                        .map(|n| parse_count(&n))
+                        .transpose()?
+                        .unwrap_or_default(),
+                },
+            })
+        }
+        "state" => {
+            parsed.expect_positional(1, &cmd)?;
+            Ok(Command::State {
+                container_id: parsed.container_id()?,
+                opts: StateOpts {
+                    watch: parsed.has("--watch"),
+                },
+            })
+        }
+        "list" => {
+            parsed.expect_positional(0, &cmd)?;
+            Ok(Command::List {
+                opts: ListOpts {
+                    filters: parsed
+                        .values("--filter")
+                        .iter()
+                        .map(|filter| filter.parse())
+                        .collect::<Result<_, _>>()?,
+                },
+            })
+        }
+        "resize" => {
+            parsed.expect_positional(3, &cmd)?;
+            Ok(Command::Resize {
+                container_id: parsed.container_id()?,
+                rows: parse_dimension(&parsed.positional[1])?,
+                cols: parse_dimension(&parsed.positional[2])?,
+            })
+        }
+        "prune" => {
+            parsed.expect_positional(0, &cmd)?;
+            Ok(Command::Prune {
+                opts: PruneOpts {
+                    age: parsed
+                        .value("--age")
+                        .map(|age| parse_duration_secs(&age))
+                        .transpose()?,
+                },
+            })
+        }
+        "selftest" => {
+            parsed.expect_positional(0, &cmd)?;
+            Ok(Command::Selftest)
+        }
+        "features" => {
+            parsed.expect_positional(0, &cmd)?;
+            Ok(Command::Features)
+        }
+        "update" => {
+            parsed.expect_positional(1, &cmd)?;
+            let quota = |flag: &str| {
+                parsed
+                    .value(flag)
+                    .map(|quota| {
+                        quota.parse::<u64>().map_err(|_| {
+                            ContainerErr::invalid_args(&format!("Invalid cpu quota: {}", quota))
+                        })
+                    })
+                    .transpose()
+            };
+            let mut opts = UpdateOpts {
+                resources: parsed
+                    .value("--resources")
+                    .or(parsed.value("-r"))
+                    .map(PathBuf::from),
+                auto_cpu: parsed.has("--auto-cpu"),
+                cpu_quota_min: quota("--cpu-quota-min")?,
+                cpu_quota_max: quota("--cpu-quota-max")?,
+                ..UpdateOpts::default()
+            };
+            if let Some(interval) = parsed.value("--interval") {
+                opts.interval = parse_duration_secs(&interval)?;
+            }
+            Ok(Command::Update {
+                container_id: parsed.container_id()?,
+                opts,
+            })
+        }
+        "wait" => {
+            parsed.expect_positional(1, &cmd)?;
+            Ok(Command::Wait {
+                container_id: parsed.container_id()?,
+            })
+        }
+        "kill" => {
+            parsed.expect_positional(2, &cmd)?;
+            Ok(Command::Kill {
+                container_id: parsed.container_id()?,
+                signal: parsed.positional[1].clone(),
+                opts: KillOpts {
+                    all: parsed.has("--all"),
+                },
+            })
+        }
+        "pod" => {
+            parsed.expect_positional(2, &cmd)?;
+            let action = match parsed.positional[0].as_str() {
+                "create" => PodAction::Create,
+                "inspect" => PodAction::Inspect,
+                "delete" => PodAction::Delete,
+                action => {
+                    return Err(ContainerErr::invalid_args(&format!(
+                        "Unrecognized pod command: {}",
+                        action
+                    )))
+                }
+            };
+            Ok(Command::Pod {
+                action,
+                name: parsed.positional[1].clone(),
+            })
+        }
+        _ => unreachable!("no parser for {}", cmd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, ContainerErr> {
+        let mut args = args.iter().map(|arg| arg.to_string());
+        let cmd = args.next().unwrap();
+        parse_command(cmd, args)
+    }
+
+    #[test]
+    fn test_command_after_separator() {
+        let Ok(Command::Run { opts, .. }) =
+            parse(&["run", "web", "bundle", "--", "sh", "-c", "ls"])
+        else {
+            panic!("run with a command wasn't parsed");
+        };
+        assert_eq!(vec!["sh", "-c", "ls"], opts.create.process.args.unwrap());
+
+        let Ok(Command::Exec { command, .. }) = parse(&["exec", "--tty", "web", "--", "sh", "--x"])
+        else {
+            panic!("exec with a command wasn't parsed");
+        };
+        assert_eq!(vec!["sh", "--x"], command);
+        // The separator is the command's own once the command started.
+        let Ok(Command::Exec { command, .. }) = parse(&["exec", "web", "env", "--", "ls"]) else {
+            panic!("exec wasn't parsed");
+        };
+        assert_eq!(vec!["env", "--", "ls"], command);
+
+        assert!(parse(&["run", "web", "bundle", "--"]).is_err());
+        assert!(parse(&["exec", "web", "sh", "--x"]).is_ok());
+        assert!(parse(&["run", "web", "bundle", "--args", "[\"sh\"]", "--", "sh"]).is_err());
+        assert!(parse(&["run", "web", "--", "sh"]).is_err());
     }
 }
@@ -0,0 +1,248 @@
+//! Attaching to a detached container's stdio.
+//!
+//! The monitor listens on `attach.sock` in the container's state dir. Clients connected
+//! to it get the container's stdout and stderr as they're written, and can send the
+//! container input, if it was created with `--interactive`, and signals. Both directions
+//! use the same frames: a byte for the kind of frame, the length of the payload as a big
+//! endian u32 and the payload. An empty stdin frame is the client's EOF, it closes the
+//! container's stdin.
+
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use crate::state::Pid;
+use crate::syscalls;
+use log::debug;
+use std::fs::{self, Permissions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::pipe::PipeWriter;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Clients which don't keep up with the container's output for this long are dropped,
+/// rather than holding up its log.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Frames bigger than this are refused.
+const MAX_FRAME: u32 = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Stdin = 0,
+    Stdout = 1,
+    Stderr = 2,
+    /// The payload is the signal number as a big endian u32.
+    Signal = 3,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = io::Error;
+
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            0 => Ok(FrameKind::Stdin),
+            1 => Ok(FrameKind::Stdout),
+            2 => Ok(FrameKind::Stderr),
+            3 => Ok(FrameKind::Signal),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown frame kind {}", kind),
+            )),
+        }
+    }
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, kind: FrameKind, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(kind as u8);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+/// Reads the next frame, None once the other end closed the connection.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<(FrameKind, Vec<u8>)>> {
+    let mut header = [0u8; 5];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let kind = FrameKind::try_from(header[0])?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+    if len > MAX_FRAME {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("frame of {} bytes", len),
+        ));
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((kind, payload)))
+}
+
+/// The container's process, as far as attached clients are concerned.
+struct Process {
+    pid: Pid,
+    stdin: Option<PipeWriter>,
+}
+
+/// The monitor's end, shared by its output copiers and a thread per client.
+pub struct Server {
+    clients: Mutex<Vec<UnixStream>>,
+    process: Mutex<Option<Process>>,
+}
+
+impl Server {
+    /// Starts accepting clients on the socket in the state dir.
+    pub fn listen(dirs: &ContainerDirs) -> Result<Arc<Self>, ContainerErr> {
+        let path = dirs.attach_socket();
+        // Left behind by a previous monitor of the container, before a restore.
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(ContainerErr::IO(e)),
+            _ => {}
+        }
+        let listener = UnixListener::bind(&path).map_err(ContainerErr::IO)?;
+        fs::set_permissions(&path, Permissions::from_mode(0o600)).map_err(ContainerErr::IO)?;
+
+        let server = Arc::new(Self {
+            clients: Mutex::new(Vec::new()),
+            process: Mutex::new(None),
+        });
+        let accepting = server.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = accepting.clone().accept(stream) {
+                    debug!("failed to accept attach client: {:?}", e);
+                }
+            }
+        });
+        Ok(server)
+    }
+
+    /// Points clients at the container's current init process and its stdin.
+    pub fn set_process(&self, pid: Pid, stdin: Option<PipeWriter>) {
+        *self.process.lock().unwrap() = Some(Process { pid, stdin });
+    }
+
+    /// Forgets the init process once it exited, closing its stdin.
+    pub fn clear_process(&self) {
+        *self.process.lock().unwrap() = None;
+    }
+
+    /// Sends output of the container to every client, dropping those which fail.
+    pub fn broadcast(&self, kind: FrameKind, data: &[u8]) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain_mut(|client| write_frame(client, kind, data).is_ok());
+    }
+
+    fn accept(self: Arc<Self>, stream: UnixStream) -> io::Result<()> {
+        debug!("attach client connected");
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        self.clients.lock().unwrap().push(stream.try_clone()?);
+        thread::spawn(move || {
+            if let Err(e) = self.serve(stream) {
+                debug!("attach client failed: {:?}", e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Handles a client's input until it disconnects.
+    fn serve(&self, mut stream: UnixStream) -> io::Result<()> {
+        while let Some((kind, payload)) = read_frame(&mut stream)? {
+            let mut process = self.process.lock().unwrap();
+            let Some(process) = process.as_mut() else {
+                continue;
+            };
+            match kind {
+                FrameKind::Stdin if payload.is_empty() => {
+                    debug!("closing stdin of {}", process.pid);
+                    process.stdin = None;
+                }
+                FrameKind::Stdin => {
+                    // Fails once the container closed its stdin, or exited.
+                    if let Some(stdin) = &mut process.stdin {
+                        if stdin.write_all(&payload).is_err() {
+                            process.stdin = None;
+                        }
+                    }
+                }
+                FrameKind::Signal => {
+                    let sig = payload
+                        .try_into()
+                        .map(u32::from_be_bytes)
+                        .map_err(|_| io::Error::from(ErrorKind::InvalidData))?;
+                    debug!("forwarding signal {} to {}", sig, process.pid);
+                    syscalls::kill(process.pid as libc::pid_t, sig as libc::c_int)?;
+                }
+                FrameKind::Stdout | FrameKind::Stderr => {
+                    return Err(io::Error::from(ErrorKind::InvalidData));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let mut raw = Vec::new();
+        write_frame(&mut raw, FrameKind::Stdout, b"hello").unwrap();
+        write_frame(&mut raw, FrameKind::Signal, &15u32.to_be_bytes()).unwrap();
+        assert_eq!(&[1, 0, 0, 0, 5], &raw[..5]);
+
+        let mut reader = raw.as_slice();
+        assert_eq!(
+            Some((FrameKind::Stdout, b"hello".to_vec())),
+            read_frame(&mut reader).unwrap()
+        );
+        assert_eq!(
+            Some((FrameKind::Signal, vec![0, 0, 0, 15])),
+            read_frame(&mut reader).unwrap()
+        );
+        assert_eq!(None, read_frame(&mut reader).unwrap());
+
+        assert!(read_frame(&mut [7u8, 0, 0, 0, 0].as_slice()).is_err());
+        // Cut off in the middle of the payload.
+        assert!(read_frame(&mut [1u8, 0, 0, 0, 5, b'h'].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_server() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(std::path::PathBuf::from(format!("/tmp/attach_{}", time)));
+        dirs.create().unwrap();
+        let server = Server::listen(&dirs).unwrap();
+        let (mut stdin, stdin_writer) = std::pipe::pipe().unwrap();
+        server.set_process(std::process::id(), Some(stdin_writer));
+
+        let mut client = UnixStream::connect(dirs.attach_socket()).unwrap();
+        write_frame(&mut client, FrameKind::Stdin, b"input").unwrap();
+        let mut buf = [0u8; 5];
+        stdin.read_exact(&mut buf).unwrap();
+        assert_eq!(b"input", &buf);
+        write_frame(&mut client, FrameKind::Stdin, b"").unwrap();
+        assert_eq!(0, stdin.read(&mut buf).unwrap());
+
+        // The client is registered by the time its input arrived.
+        server.broadcast(FrameKind::Stderr, b"output");
+        assert_eq!(
+            Some((FrameKind::Stderr, b"output".to_vec())),
+            read_frame(&mut client).unwrap()
+        );
+
+        server.clear_process();
+        dirs.remove().unwrap();
+    }
+}
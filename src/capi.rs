@@ -0,0 +1,290 @@
+//! C ABI for embedding the runtime, built with the `capi` feature, see
+//! scripts/build-capi.sh. include/container_runtime.h declares it.
+//!
+//! Every function returns one of the `CR_*` codes. On failure, if `err` isn't NULL, it's
+//! pointed at a message the caller frees with `cr_free_string`. Panics don't cross the
+//! boundary, they're reported as `CR_ERR_PANIC`.
+
+use crate::cmd::{
+    create, delete, kill, start, validate_id, CreateOpts, DeleteOpts, KillOpts, StartOpts,
+};
+use crate::ctx::setup_ctx;
+use crate::error::ContainerErr;
+use crate::store::StateStore;
+use libc::{c_char, c_int};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::panic::{catch_unwind, UnwindSafe};
+use std::time::Duration;
+
+pub const CR_OK: c_int = 0;
+/// An argument or option is invalid.
+pub const CR_ERR_ARGS: c_int = 1;
+/// The bundle or its config.json is invalid.
+pub const CR_ERR_BUNDLE: c_int = 2;
+/// The container doesn't exist, or the operation doesn't apply to its status.
+pub const CR_ERR_STATE: c_int = 3;
+pub const CR_ERR_IO: c_int = 4;
+/// Any other failure of the runtime.
+pub const CR_ERR_RUNTIME: c_int = 5;
+pub const CR_ERR_PANIC: c_int = 6;
+
+/// Options of `cr_create`, a JSON object. Every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+struct CreateParams {
+    strict: bool,
+    secure_defaults: bool,
+    timeout_secs: Option<u64>,
+    pod: Option<String>,
+    interactive: bool,
+    labels: BTreeMap<String, String>,
+}
+
+impl From<CreateParams> for CreateOpts {
+    fn from(params: CreateParams) -> Self {
+        Self {
+            strict: params.strict,
+            secure_defaults: params.secure_defaults,
+            timeout: params.timeout_secs.map(Duration::from_secs),
+            pod: params.pod,
+            interactive: params.interactive,
+            labels: params.labels.into_iter().collect(),
+            ..CreateOpts::default()
+        }
+    }
+}
+
+fn code(err: &ContainerErr) -> c_int {
+    match err {
+        ContainerErr::Args(_) | ContainerErr::InvalidId(_) | ContainerErr::Options(_) => {
+            CR_ERR_ARGS
+        }
+        ContainerErr::Bundle(_)
+        | ContainerErr::InvalidConfig(_)
+        | ContainerErr::UnsupportedPlatform(_) => CR_ERR_BUNDLE,
+        ContainerErr::State(_) | ContainerErr::AlreadyStarted(_) => CR_ERR_STATE,
+        ContainerErr::IO(_) => CR_ERR_IO,
+        _ => CR_ERR_RUNTIME,
+    }
+}
+
+/// Hands `s` over to the caller, who frees it with `cr_free_string`.
+fn into_raw(s: String) -> *mut c_char {
+    // Messages and JSON don't contain NULs, but cut them off rather than fail.
+    let s = s.split('\0').next().unwrap_or_default();
+    CString::new(s).unwrap_or_default().into_raw()
+}
+
+/// Runs `f`, turning its result into a code and, if `err` isn't NULL, a message.
+unsafe fn call<F>(err: *mut *mut c_char, f: F) -> c_int
+where
+    F: FnOnce() -> Result<(), ContainerErr> + UnwindSafe,
+{
+    let (code, message) = match catch_unwind(f) {
+        Ok(Ok(())) => return CR_OK,
+        Ok(Err(e)) => (code(&e), format!("{:?}", e)),
+        Err(_) => (CR_ERR_PANIC, String::from("the runtime panicked")),
+    };
+    if !err.is_null() {
+        *err = into_raw(message);
+    }
+    code
+}
+
+/// Borrows a string argument, which has to be valid utf8.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, ContainerErr> {
+    if s.is_null() {
+        return Err(ContainerErr::invalid_args(&format!("{} is NULL", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| ContainerErr::invalid_args(&format!("{} isn't utf8", name)))
+}
+
+/// Borrows a container id argument, which has to be a valid id.
+unsafe fn id_arg<'a>(s: *const c_char) -> Result<&'a str, ContainerErr> {
+    let id = str_arg(s, "id")?;
+    validate_id(id)?;
+    Ok(id)
+}
+
+/// Creates the container `id` from the bundle at `bundle`. `opts_json` may be NULL.
+///
+/// # Safety
+///
+/// The strings have to be NULL or NUL terminated, `err` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn cr_create(
+    id: *const c_char,
+    bundle: *const c_char,
+    opts_json: *const c_char,
+    err: *mut *mut c_char,
+) -> c_int {
+    call(err, || {
+        let id = id_arg(id)?;
+        let bundle = str_arg(bundle, "bundle")?;
+        let params: CreateParams = if opts_json.is_null() {
+            CreateParams::default()
+        } else {
+            serde_json::from_str(str_arg(opts_json, "opts_json")?)
+                .map_err(|e| ContainerErr::invalid_args(&e.to_string()))?
+        };
+        create(id.to_string(), bundle.to_string(), params.into())
+    })
+}
+
+/// Starts the created container `id`.
+///
+/// # Safety
+///
+/// `id` has to be NULL or NUL terminated, `err` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn cr_start(id: *const c_char, err: *mut *mut c_char) -> c_int {
+    call(err, || start(id_arg(id)?.to_string(), StartOpts::default()))
+}
+
+/// Sends `signal`, a name like "SIGTERM" or "TERM" or a number, to the container's init
+/// process.
+///
+/// # Safety
+///
+/// The strings have to be NULL or NUL terminated, `err` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn cr_kill(
+    id: *const c_char,
+    signal: *const c_char,
+    err: *mut *mut c_char,
+) -> c_int {
+    call(err, || {
+        let id = id_arg(id)?;
+        kill(
+            id.to_string(),
+            str_arg(signal, "signal")?.to_string(),
+            KillOpts::default(),
+        )
+    })
+}
+
+/// Deletes the container `id`, stopping it first if `force` is set.
+///
+/// # Safety
+///
+/// `id` has to be NULL or NUL terminated, `err` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn cr_delete(id: *const c_char, force: bool, err: *mut *mut c_char) -> c_int {
+    call(err, || {
+        delete(id_arg(id)?.to_string(), DeleteOpts { force })
+    })
+}
+
+/// Points `state_json` at the container's state, as printed by the state command.
+///
+/// # Safety
+///
+/// `id` has to be NULL or NUL terminated, `state_json` and `err` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn cr_state(
+    id: *const c_char,
+    state_json: *mut *mut c_char,
+    err: *mut *mut c_char,
+) -> c_int {
+    call(err, || {
+        let id = id_arg(id)?;
+        if state_json.is_null() {
+            return Err(ContainerErr::invalid_args("state_json is NULL"));
+        }
+        let state = setup_ctx()?.store().load(id)?;
+        let json = serde_json::to_string(&state).map_err(|e| ContainerErr::State(e.to_string()))?;
+        *state_json = into_raw(json);
+        Ok(())
+    })
+}
+
+/// Frees a string returned by the other functions, NULL is fine.
+///
+/// # Safety
+///
+/// `s` has to come from this library and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn cr_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    const HEADER: &str = include_str!("../include/container_runtime.h");
+
+    #[test]
+    fn test_header() {
+        for (name, value) in [
+            ("CR_OK", CR_OK),
+            ("CR_ERR_ARGS", CR_ERR_ARGS),
+            ("CR_ERR_BUNDLE", CR_ERR_BUNDLE),
+            ("CR_ERR_STATE", CR_ERR_STATE),
+            ("CR_ERR_IO", CR_ERR_IO),
+            ("CR_ERR_RUNTIME", CR_ERR_RUNTIME),
+            ("CR_ERR_PANIC", CR_ERR_PANIC),
+        ] {
+            let define = format!("#define {} {}\n", name, value);
+            assert!(HEADER.contains(&define), "{}", define);
+        }
+        for function in [
+            "int cr_create(",
+            "int cr_start(",
+            "int cr_kill(",
+            "int cr_delete(",
+            "int cr_state(",
+            "void cr_free_string(",
+        ] {
+            assert!(HEADER.contains(function), "{}", function);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut err = ptr::null_mut();
+        assert_eq!(CR_ERR_ARGS, unsafe {
+            cr_kill(c"abc".as_ptr(), ptr::null(), &mut err)
+        });
+        assert_eq!(
+            "Args(\"signal is NULL\")",
+            unsafe { CStr::from_ptr(err) }.to_str().unwrap()
+        );
+        unsafe { cr_free_string(err) };
+
+        // No message asked for.
+        assert_eq!(CR_ERR_ARGS, unsafe {
+            cr_create(
+                c"abc".as_ptr(),
+                c"/bundle".as_ptr(),
+                c"{\"nope\": 1}".as_ptr(),
+                ptr::null_mut(),
+            )
+        });
+        assert_eq!(CR_ERR_PANIC, unsafe {
+            call(ptr::null_mut(), || panic!("oops"))
+        });
+    }
+
+    #[test]
+    fn test_create_params() {
+        let params: CreateParams = serde_json::from_str(
+            r#"{"strict": true, "timeoutSecs": 5, "labels": {"app": "shop"}}"#,
+        )
+        .unwrap();
+        let opts = CreateOpts::from(params);
+        assert!(opts.strict);
+        assert_eq!(Some(Duration::from_secs(5)), opts.timeout);
+        assert_eq!(
+            vec![(String::from("app"), String::from("shop"))],
+            opts.labels
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! The device rules of linux.resources.devices. cgroup v2 has no devices controller
+//! files, the rules are compiled to an eBPF program attached to the cgroup instead, which
+//! the kernel runs whenever the container opens or makes a device node, like runc does.
+//!
+//! The last rule matching a device decides, and devices no rule matches are denied. The
+//! devices of linux.devices and the ones every container needs are allowed after the
+//! config's rules: /dev/null, zero, full, random, urandom, tty, console, ptmx, the ptys
+//! and making any node.
+
+use crate::config::{AllowedDevice, DeviceNode, DeviceType};
+use crate::error::ContainerErr;
+use crate::syscalls::{self, BpfInsn};
+use libc::{BPF_ALU, BPF_AND, BPF_JMP, BPF_K, BPF_LDX, BPF_MEM, BPF_RSH, BPF_W, BPF_X};
+use log::debug;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+/// From linux/bpf.h, libc only has the opcodes classic BPF shares.
+const BPF_PROG_TYPE_CGROUP_DEVICE: u32 = 15;
+const BPF_CGROUP_DEVICE: u32 = 6;
+const BPF_F_ALLOW_MULTI: u32 = 2;
+const BPF_ALU64: u32 = 0x07;
+const BPF_MOV: u32 = 0xb0;
+const BPF_JNE: u32 = 0x50;
+const BPF_EXIT: u32 = 0x90;
+
+/// Device types and access in struct bpf_cgroup_dev_ctx.
+const BPF_DEVCG_DEV_BLOCK: i32 = 1;
+const BPF_DEVCG_DEV_CHAR: i32 = 2;
+const BPF_DEVCG_ACC_MKNOD: i32 = 1;
+const BPF_DEVCG_ACC_READ: i32 = 2;
+const BPF_DEVCG_ACC_WRITE: i32 = 4;
+const ACC_ALL: i32 = BPF_DEVCG_ACC_MKNOD | BPF_DEVCG_ACC_READ | BPF_DEVCG_ACC_WRITE;
+
+/// The registers the program keeps the device's type, the access asked for, its major
+/// and its minor in. r1 points at the context, then it's scratch. r0 is the verdict.
+const R0: u8 = 0;
+const R1: u8 = 1;
+const R2: u8 = 2;
+const R3: u8 = 3;
+const R4: u8 = 4;
+const R5: u8 = 5;
+
+/// The type, major and minor, wildcards being None, and access of the default rules.
+const DEFAULT_RULES: [(char, Option<i64>, Option<i64>, &str); 11] = [
+    ('c', None, None, "m"),
+    ('b', None, None, "m"),
+    ('c', Some(1), Some(3), "rwm"),
+    ('c', Some(1), Some(5), "rwm"),
+    ('c', Some(1), Some(7), "rwm"),
+    ('c', Some(1), Some(8), "rwm"),
+    ('c', Some(1), Some(9), "rwm"),
+    ('c', Some(5), Some(0), "rwm"),
+    ('c', Some(5), Some(1), "rwm"),
+    ('c', Some(5), Some(2), "rwm"),
+    ('c', Some(136), None, "rwm"),
+];
+
+/// A rule as the program checks it, with the access as BPF_DEVCG_ACC_* bits.
+#[derive(Debug, PartialEq, Eq)]
+struct Rule {
+    allow: bool,
+    typ: Option<i32>,
+    major: Option<i64>,
+    minor: Option<i64>,
+    access: i32,
+}
+
+/// Replaces the device filter of `cgroup` by one for `devices`, which allows the `nodes`
+/// of linux.devices too. The new program is attached before the old ones are detached, so
+/// the cgroup is never without one.
+pub fn set_cgroup_devices<P: AsRef<Path>>(
+    cgroup: P,
+    devices: &[AllowedDevice],
+    nodes: &[DeviceNode],
+) -> Result<(), ContainerErr> {
+    let prog = compile(&rules(devices, nodes)?);
+    debug!(
+        "device filter for {:?}, {} instructions",
+        cgroup.as_ref(),
+        prog.len()
+    );
+    let dir = File::open(cgroup).map_err(ContainerErr::IO)?;
+    let old =
+        syscalls::bpf_prog_query(dir.as_raw_fd(), BPF_CGROUP_DEVICE).map_err(ContainerErr::IO)?;
+    let new = syscalls::bpf_prog_load(BPF_PROG_TYPE_CGROUP_DEVICE, BPF_CGROUP_DEVICE, &prog)
+        .map_err(ContainerErr::IO)?;
+    syscalls::bpf_prog_attach(
+        dir.as_raw_fd(),
+        new.as_raw_fd(),
+        BPF_CGROUP_DEVICE,
+        BPF_F_ALLOW_MULTI,
+    )
+    .map_err(ContainerErr::IO)?;
+    for id in old {
+        let prog = syscalls::bpf_prog_get_fd_by_id(id).map_err(ContainerErr::IO)?;
+        syscalls::bpf_prog_detach(dir.as_raw_fd(), prog.as_raw_fd(), BPF_CGROUP_DEVICE)
+            .map_err(ContainerErr::IO)?;
+    }
+    Ok(())
+}
+
+/// The config's rules followed by ones allowing its nodes, and the default ones.
+fn rules(devices: &[AllowedDevice], nodes: &[DeviceNode]) -> Result<Vec<Rule>, ContainerErr> {
+    let mut rules = Vec::new();
+    for device in devices {
+        rules.push(Rule {
+            allow: device.allow,
+            typ: match device.typ {
+                None | Some(DeviceType::All) => None,
+                Some(DeviceType::Char) => Some(BPF_DEVCG_DEV_CHAR),
+                Some(DeviceType::Block) => Some(BPF_DEVCG_DEV_BLOCK),
+            },
+            // The runtime-spec leaves them out for any, runc uses -1.
+            major: device.major.filter(|major| *major >= 0),
+            minor: device.minor.filter(|minor| *minor >= 0),
+            access: access(device.access.as_deref().unwrap_or("rwm"))?,
+        });
+    }
+    for node in nodes {
+        rules.push(Rule {
+            allow: true,
+            typ: Some(dev_type(node.typ)),
+            major: Some(node.major),
+            minor: Some(node.minor),
+            access: ACC_ALL,
+        });
+    }
+    for (typ, major, minor, acc) in DEFAULT_RULES {
+        rules.push(Rule {
+            allow: true,
+            typ: Some(dev_type(typ)),
+            major,
+            minor,
+            access: access(acc)?,
+        });
+    }
+    Ok(rules)
+}
+
+fn dev_type(typ: char) -> i32 {
+    match typ {
+        'b' => BPF_DEVCG_DEV_BLOCK,
+        _ => BPF_DEVCG_DEV_CHAR,
+    }
+}
+
+fn access(access: &str) -> Result<i32, ContainerErr> {
+    access.chars().try_fold(0, |bits, c| match c {
+        'r' => Ok(bits | BPF_DEVCG_ACC_READ),
+        'w' => Ok(bits | BPF_DEVCG_ACC_WRITE),
+        'm' => Ok(bits | BPF_DEVCG_ACC_MKNOD),
+        _ => Err(ContainerErr::Cgroup(format!(
+            "invalid device access: {}, expected some of rwm",
+            access
+        ))),
+    })
+}
+
+/// The program for `rules`. They're checked last to first, and the first one matching
+/// returns its verdict. A rule matches if the access asked for is a subset of its access.
+fn compile(rules: &[Rule]) -> Vec<BpfInsn> {
+    let mut prog = vec![
+        insn(BPF_LDX | BPF_MEM | BPF_W, R2, R1, 0, 0),
+        insn(BPF_ALU | BPF_AND | BPF_K, R2, 0, 0, 0xffff),
+        insn(BPF_LDX | BPF_MEM | BPF_W, R3, R1, 0, 0),
+        insn(BPF_ALU | BPF_RSH | BPF_K, R3, 0, 0, 16),
+        insn(BPF_LDX | BPF_MEM | BPF_W, R4, R1, 4, 0),
+        insn(BPF_LDX | BPF_MEM | BPF_W, R5, R1, 8, 0),
+    ];
+    for rule in rules.iter().rev() {
+        let mut checks = Vec::new();
+        if let Some(typ) = rule.typ {
+            checks.push(insn(BPF_JMP | BPF_JNE | BPF_K, R2, 0, 0, typ));
+        }
+        if rule.access != ACC_ALL {
+            checks.extend([
+                insn(BPF_ALU | BPF_MOV | BPF_X, R1, R3, 0, 0),
+                insn(BPF_ALU | BPF_AND | BPF_K, R1, 0, 0, rule.access),
+                insn(BPF_JMP | BPF_JNE | BPF_X, R1, R3, 0, 0),
+            ]);
+        }
+        if let Some(major) = rule.major {
+            checks.push(insn(BPF_JMP | BPF_JNE | BPF_K, R4, 0, 0, major as i32));
+        }
+        if let Some(minor) = rule.minor {
+            checks.push(insn(BPF_JMP | BPF_JNE | BPF_K, R5, 0, 0, minor as i32));
+        }
+        // A failed check skips the rest of the rule, and its verdict.
+        let len = checks.len();
+        for (i, check) in checks.iter_mut().enumerate() {
+            if check.code as u32 & 0x07 == BPF_JMP {
+                check.off = (len - i - 1 + 2) as i16;
+            }
+        }
+        let matches_all = checks.is_empty();
+        prog.extend(checks);
+        prog.extend(verdict(rule.allow));
+        // The verifier rejects unreachable instructions.
+        if matches_all {
+            return prog;
+        }
+    }
+    prog.extend(verdict(false));
+    prog
+}
+
+fn verdict(allow: bool) -> [BpfInsn; 2] {
+    [
+        insn(BPF_ALU64 | BPF_MOV | BPF_K, R0, 0, 0, allow as i32),
+        insn(BPF_JMP | BPF_EXIT, 0, 0, 0, 0),
+    ]
+}
+
+fn insn(code: u32, dst: u8, src: u8, off: i16, imm: i32) -> BpfInsn {
+    BpfInsn {
+        code: code as u8,
+        regs: src << 4 | dst,
+        off,
+        imm,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Runs the instructions `compile` emits, on a device of `typ` with `major` and
+    /// `minor`, asking for `access`.
+    fn run(prog: &[BpfInsn], typ: i32, major: i64, minor: i64, access: i32) -> bool {
+        let ctx = [(access << 16 | typ) as u64, major as u64, minor as u64];
+        let mut regs = [0u64; 11];
+        let mut pc = 0;
+        loop {
+            let i = prog[pc];
+            let (dst, src) = ((i.regs & 0xf) as usize, (i.regs >> 4) as usize);
+            pc += 1;
+            match i.code as u32 {
+                c if c == BPF_LDX | BPF_MEM | BPF_W => regs[dst] = ctx[i.off as usize / 4],
+                c if c == BPF_ALU | BPF_AND | BPF_K => regs[dst] &= i.imm as u64,
+                c if c == BPF_ALU | BPF_RSH | BPF_K => regs[dst] >>= i.imm,
+                c if c == BPF_ALU | BPF_MOV | BPF_X => regs[dst] = regs[src],
+                c if c == BPF_ALU64 | BPF_MOV | BPF_K => regs[dst] = i.imm as u64,
+                c if c == BPF_JMP | BPF_JNE | BPF_K && regs[dst] != i.imm as u64 => {
+                    pc += i.off as usize
+                }
+                c if c == BPF_JMP | BPF_JNE | BPF_X && regs[dst] != regs[src] => {
+                    pc += i.off as usize
+                }
+                c if c == BPF_JMP | BPF_JNE | BPF_K || c == BPF_JMP | BPF_JNE | BPF_X => {}
+                c if c == BPF_JMP | BPF_EXIT => return regs[0] == 1,
+                c => panic!("unexpected opcode {:#x}", c),
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile() {
+        let devices: Vec<AllowedDevice> = serde_json::from_value(json!([
+            {"allow": false, "access": "rwm"},
+            {"allow": true, "type": "c", "major": 10, "access": "rw"},
+            {"allow": false, "type": "c", "major": 10, "minor": 200, "access": "rwm"},
+            {"allow": true, "type": "b", "major": 8, "minor": 0, "access": "r"},
+        ]))
+        .unwrap();
+        let prog = compile(&rules(&devices, &[]).unwrap());
+        let (c, b) = (BPF_DEVCG_DEV_CHAR, BPF_DEVCG_DEV_BLOCK);
+        let (r, w, m) = (BPF_DEVCG_ACC_READ, BPF_DEVCG_ACC_WRITE, BPF_DEVCG_ACC_MKNOD);
+
+        assert!(run(&prog, c, 10, 229, r | w));
+        assert!(!run(&prog, c, 10, 200, r));
+        assert!(run(&prog, b, 8, 0, r));
+        assert!(!run(&prog, b, 8, 0, r | w));
+        assert!(!run(&prog, b, 8, 1, r));
+        // The default rules.
+        assert!(run(&prog, c, 1, 3, r | w));
+        assert!(run(&prog, c, 136, 4, r | w));
+        assert!(run(&prog, b, 259, 1, m));
+        assert!(!run(&prog, c, 4, 1, r));
+
+        // The nodes of linux.devices are allowed, whatever the config's rules say.
+        let node = DeviceNode {
+            path: String::from("/dev/nvidia0"),
+            typ: 'c',
+            major: 195,
+            minor: 0,
+            file_mode: 0o666,
+            uid: 0,
+            gid: 0,
+        };
+        let devices: Vec<AllowedDevice> = serde_json::from_value(json!([
+            {"allow": false, "access": "rwm"},
+            {"allow": false, "type": "c", "major": 195, "access": "rwm"},
+        ]))
+        .unwrap();
+        let prog = compile(&rules(&devices, &[node]).unwrap());
+        assert!(run(&prog, c, 195, 0, r | w | m));
+        assert!(!run(&prog, c, 195, 1, r));
+        assert!(!run(&prog, b, 195, 0, r));
+        assert!(run(&prog, c, 1, 3, r | w));
+
+        // Nothing before a rule matching every device is reachable, the verdict for
+        // devices no rule matches included.
+        let devices: Vec<AllowedDevice> = serde_json::from_value(json!([
+            {"allow": false, "type": "c", "major": 10, "minor": 200, "access": "rwm"},
+            {"allow": true, "access": "rwm"},
+        ]))
+        .unwrap();
+        let prog = compile(&rules(&devices, &[]).unwrap());
+        assert!(prog.iter().all(|i| i.imm != 200));
+        assert_eq!(verdict(true)[..], prog[prog.len() - 2..]);
+        assert!(run(&prog, c, 10, 200, r | w | m));
+
+        let devices: Vec<AllowedDevice> =
+            serde_json::from_value(json!([{"allow": true, "access": "rwx"}])).unwrap();
+        assert!(rules(&devices, &[]).is_err());
+    }
+}
@@ -1,20 +1,39 @@
 //! Functions for manipulating cgroups
 //! https://www.kernel.org/doc/Documentation/cgroup-v2.txt
 
+mod devices;
+mod pressure;
+mod stats;
 mod util;
 
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use libc::{c_char, statfs};
-use log::debug;
-use util::{read_flat_keyed_file, read_nested_keyed_file, write_nested_keyed_file};
+use log::{debug, warn};
+use util::{
+    read_flat_keyed_file, read_nested_keyed_file, read_newline_separated_file,
+    read_space_separated_file, write_nested_keyed_file,
+};
 
 use crate::config::{BlockIO, Config, Cpu, DevThrottle, HugePageLimits, Memory, Pids, Rdma};
+use crate::ctx::{CgroupManager, Ctx, ResourcePolicy};
 use crate::error::ContainerErr;
+use crate::extensions::Extensions;
+use crate::libc_compat::fs_magic;
+use crate::state::{Pid, State};
+use crate::syscalls;
+
+pub use devices::set_cgroup_devices;
+pub use pressure::read_pressure;
+pub use stats::{read_network, read_oom_kills, read_stats, PressureStats};
+
+const MEMINFO_PATH: &str = "/proc/meminfo";
 
 #[allow(dead_code)]
 #[derive(Debug, Eq, PartialEq)]
@@ -37,7 +56,7 @@ pub fn detect_cgroup_version<P: AsRef<Path>>(
         )));
     }
 
-    match statfs.f_type {
+    match fs_magic(&statfs) {
         libc::CGROUP2_SUPER_MAGIC => Ok(CgroupVersion::V2),
         libc::CGROUP_SUPER_MAGIC => Err(ContainerErr::Cgroup(String::from(
             "Cgroup v1 or hybrid not supported",
@@ -65,45 +84,206 @@ pub fn join_cgroup<P: AsRef<Path>>(cgroup: P) -> Result<(), ContainerErr> {
     Ok(())
 }
 
+/// Returns the path of the cgroup v2 group a process belongs to by reading /proc/<pid>/cgroup.
+///
+/// Example file data:
+///
+/// 0::/container_id
+///
+pub fn process_cgroup<P: AsRef<Path>>(pid: Pid, cgroups_root: P) -> Result<PathBuf, ContainerErr> {
+    let data =
+        std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).map_err(ContainerErr::IO)?;
+    for line in data.lines() {
+        if let Some(path) = line.strip_prefix("0::") {
+            return Ok(cgroups_root.as_ref().join(path.trim_start_matches('/')));
+        }
+    }
+    Err(ContainerErr::Cgroup(format!(
+        "no cgroup v2 entry for pid {}",
+        pid
+    )))
+}
+
+/// Kills every process in the cgroup. Uses cgroup.kill where the kernel has it (5.14+),
+/// otherwise signals each pid listed in cgroup.procs.
+pub fn kill_cgroup<P: AsRef<Path>>(cgroup: P) -> Result<(), ContainerErr> {
+    let cgroup = cgroup.as_ref();
+    let killed = OpenOptions::new()
+        .write(true)
+        .open(cgroup.join("cgroup.kill"))
+        .and_then(|mut f| f.write_all(b"1"));
+    if killed.is_ok() {
+        return Ok(());
+    }
+    signal_cgroup(cgroup, libc::SIGKILL)
+}
+
+/// Sends `sig` to each process listed in the cgroup's cgroup.procs. Processes forked
+/// while we're at it may be missed, unlike with cgroup.kill.
+pub fn signal_cgroup<P: AsRef<Path>>(cgroup: P, sig: libc::c_int) -> Result<(), ContainerErr> {
+    let cgroup = cgroup.as_ref();
+    for pid in read_newline_separated_file(cgroup.join("cgroup.procs"))? {
+        if let Ok(pid) = pid.parse::<libc::pid_t>() {
+            debug!("sending signal {} to {} in {:?}", sig, pid, cgroup);
+            let _ = syscalls::kill(pid, sig);
+        }
+    }
+    Ok(())
+}
+
+/// Waits up to `timeout` for the cgroup and its descendants to have no processes left,
+/// returns false if some are still around. A cgroup which doesn't exist is empty.
+pub fn wait_empty<P: AsRef<Path>>(cgroup: P, timeout: Duration) -> Result<bool, ContainerErr> {
+    let events = cgroup.as_ref().join("cgroup.events");
+    let deadline = Instant::now() + timeout;
+    loop {
+        let populated = match read_flat_keyed_file(&events) {
+            Ok(events) => events.get("populated").is_some_and(|p| p != "0"),
+            Err(ContainerErr::IO(e)) if e.kind() == ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        if !populated {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Removes a cgroup, retrying while it's busy because its processes are still exiting.
+/// Gives up after `timeout`.
+pub fn remove_cgroup<P: AsRef<Path>>(cgroup: P, timeout: Duration) -> Result<(), ContainerErr> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match std::fs::remove_dir(cgroup.as_ref()) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => return Err(ContainerErr::IO(e)),
+        }
+    }
+}
+
 /// Creates a cgroup at the provided path.
 /// Assumes this directory does not exist and will Err if it does.
-pub fn create_cgroup<P: AsRef<Path>>(cgroup_path: P, config: &Config) -> Result<(), ContainerErr> {
-    debug!("creating cgroup: {:?}", cgroup_path.as_ref());
-    std::fs::create_dir(&cgroup_path).map_err(ContainerErr::IO)?;
+pub fn create_cgroup<P: AsRef<Path>>(
+    cgroup_path: P,
+    config: &Config,
+    policy: ResourcePolicy,
+) -> Result<Vec<String>, ContainerErr> {
+    let cgroup_path = cgroup_path.as_ref();
+    debug!("creating cgroup: {:?}", cgroup_path);
+    // cgroupsPath may point into a hierarchy which doesn't exist yet.
+    if let Some(parent) = cgroup_path.parent() {
+        std::fs::create_dir_all(parent).map_err(ContainerErr::IO)?;
+    }
+    std::fs::create_dir(cgroup_path).map_err(ContainerErr::IO)?;
 
     // create the necessary files
     let filenames = ["cgroup.procs"];
     for f in filenames {
         let mut pb = PathBuf::new();
-        pb.push(&cgroup_path);
+        pb.push(cgroup_path);
         pb.push(f);
         let _ = File::create(pb).map_err(ContainerErr::IO)?;
     }
 
+    if Extensions::parse(config)?.oom_group.unwrap_or(true) {
+        set_oom_group(cgroup_path)?;
+    }
+    apply_settings(cgroup_path, config, policy)
+}
+
+/// Writes the settings of a running container's cgroup anew, after its resources were
+/// updated. With checkBeforeUpdate, memory limits are checked against the usage first,
+/// whatever the policy.
+pub fn update_cgroup<P: AsRef<Path>>(
+    cgroup_path: P,
+    config: &Config,
+    policy: ResourcePolicy,
+) -> Result<Vec<String>, ContainerErr> {
+    let cgroup_path = cgroup_path.as_ref();
+    debug!("updating cgroup: {:?}", cgroup_path);
+    if let Some(memory) = config.cgroup_memory() {
+        check_memory_usage(cgroup_path, memory)?;
+    }
+    apply_settings(cgroup_path, config, policy)
+}
+
+/// Writes the config's settings. Returns the warnings about the controllers whose
+/// settings were skipped, with the permissive policy.
+fn apply_settings(
+    cgroup_path: &Path,
+    config: &Config,
+    policy: ResourcePolicy,
+) -> Result<Vec<String>, ContainerErr> {
+    let mut warnings = Vec::new();
+    let mut apply = |controller: &str, result: Result<(), ContainerErr>| match result {
+        Err(e) if policy == ResourcePolicy::Permissive => {
+            let warning = format!("{} settings not applied: {:?}", controller, e);
+            warn!("{}", warning);
+            warnings.push(warning);
+            Ok(())
+        }
+        result => result,
+    };
+
     if let Some(memory) = config.cgroup_memory() {
-        set_cgroup_memory(&cgroup_path, memory)?;
+        apply("memory", set_cgroup_memory(cgroup_path, memory))?;
     }
 
     if let Some(cpu) = config.cgroup_cpu() {
-        set_cgroup_cpu(&cgroup_path, cpu)?;
+        apply("cpu", set_cgroup_cpu(cgroup_path, cpu))?;
     }
 
     if let Some(blockio) = config.blockio() {
-        set_cgroup_blockio(&cgroup_path, blockio)?;
+        apply("io", set_cgroup_blockio(cgroup_path, blockio))?;
     }
 
-    if let Some(hpl) = config.hugepage_limits() {
-        set_cgroup_hugepage(&cgroup_path, hpl)?;
+    // Every page size has its own file.
+    for hp in config.hugepage_limits().unwrap_or_default() {
+        apply("hugetlb", set_cgroup_hugepage(cgroup_path, hp))?;
     }
 
     if let Some(rdma) = config.rdma() {
-        set_cgroup_rdma(&cgroup_path, rdma)?;
+        apply("rdma", set_cgroup_rdma(cgroup_path, rdma))?;
     }
 
     if let Some(pids) = config.pids() {
-        set_cgroup_pids(&cgroup_path, pids)?;
+        apply("pids", set_cgroup_pids(cgroup_path, pids))?;
     }
-    Ok(())
+
+    // Loading the filter needs CAP_BPF, rootless containers go without one, like with runc.
+    if let Some(devices) = config.allowed_devices() {
+        if unsafe { libc::geteuid() } == 0 {
+            let nodes = config.device_nodes();
+            apply("devices", set_cgroup_devices(cgroup_path, devices, &nodes))?;
+        } else {
+            debug!("not root, no device filter for {:?}", cgroup_path);
+        }
+    }
+    Ok(warnings)
+}
+
+/// Lets the children of `cgroup` use every controller it has, by enabling them in its
+/// cgroup.subtree_control. The cgroup can't have processes of its own afterwards.
+pub fn enable_controllers<P: AsRef<Path>>(cgroup: P) -> Result<(), ContainerErr> {
+    let cgroup = cgroup.as_ref();
+    let controllers: Vec<String> = read_space_separated_file(cgroup.join("cgroup.controllers"))?
+        .into_iter()
+        .filter(|controller| !controller.is_empty())
+        .map(|controller| format!("+{}", controller))
+        .collect();
+    if controllers.is_empty() {
+        return Ok(());
+    }
+    debug!("enabling {:?} for children of {:?}", controllers, cgroup);
+    std::fs::write(cgroup.join("cgroup.subtree_control"), controllers.join(" "))
+        .map_err(ContainerErr::IO)
 }
 
 /// Resolves the cgroup path from cgroups_path set in the config defaulting
@@ -115,6 +295,11 @@ pub fn resolve_cgroup_path<P: AsRef<Path>>(
 ) -> PathBuf {
     let mut pb = PathBuf::new();
     match config_cgroups_path {
+        Some(path) if systemd_cgroup_path(path.as_ref()).is_some() => {
+            pb.push(cgroups_root);
+            pb.push(systemd_cgroup_path(path.as_ref()).unwrap());
+            pb
+        }
         Some(path) => {
             pb.push(cgroups_root);
             // If the path is absolute we're required by oci spec to treat this as
@@ -139,6 +324,114 @@ pub fn resolve_cgroup_path<P: AsRef<Path>>(
     }
 }
 
+/// Prefix of the scope units created for containers without a cgroupsPath when the systemd
+/// cgroup manager is used.
+const SYSTEMD_SCOPE_PREFIX: &str = "container_runtime";
+
+/// Resolves the container's cgroup from linux.cgroupsPath in its config for the cgroup
+/// manager in use. Done once by create, which records it in the state.
+pub fn container_cgroup_path(
+    ctx: &Ctx,
+    config: &Config,
+    container_id: &str,
+) -> Result<PathBuf, ContainerErr> {
+    match (ctx.cgroup_manager(), config.cgroups_path()) {
+        (CgroupManager::Cgroupfs, cgroups_path) => Ok(resolve_cgroup_path(
+            cgroups_path.map(Path::new),
+            ctx.cgroups_root(),
+            container_id,
+        )),
+        (CgroupManager::Systemd, Some(cgroups_path)) => {
+            systemd_cgroup_path(Path::new(cgroups_path))
+                .map(|path| ctx.cgroups_root().join(path))
+                .ok_or_else(|| {
+                    ContainerErr::Cgroup(format!(
+                        "cgroupsPath must be slice:prefix:name with the systemd cgroup manager: {}",
+                        cgroups_path
+                    ))
+                })
+        }
+        (CgroupManager::Systemd, None) => Ok(ctx
+            .cgroups_root()
+            .join("system.slice")
+            .join(format!("{}-{}.scope", SYSTEMD_SCOPE_PREFIX, container_id))),
+    }
+}
+
+/// The cgroup of a created container, as recorded in its state. Falls back to resolving
+/// it from the config for state which doesn't have it.
+pub fn state_cgroup_path(
+    ctx: &Ctx,
+    state: &State,
+    config: &Config,
+) -> Result<PathBuf, ContainerErr> {
+    match state.cgroup_path() {
+        Some(path) => Ok(path.to_path_buf()),
+        None => container_cgroup_path(ctx, config, state.id()),
+    }
+}
+
+/// The scopes the systemd cgroup manager created for containers without a cgroupsPath, by
+/// container id. Other cgroups can't be told apart from the host's.
+pub fn systemd_scopes(ctx: &Ctx) -> Result<Vec<(String, PathBuf)>, ContainerErr> {
+    let slice = ctx.cgroups_root().join("system.slice");
+    let entries = match std::fs::read_dir(&slice) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(ContainerErr::IO(e)),
+    };
+    let mut scopes = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(ContainerErr::IO)?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let id = name
+            .strip_prefix(SYSTEMD_SCOPE_PREFIX)
+            .and_then(|name| name.strip_prefix('-'))
+            .and_then(|name| name.strip_suffix(".scope"));
+        if let Some(id) = id {
+            scopes.push((id.to_string(), entry.path()));
+        }
+    }
+    Ok(scopes)
+}
+
+/// Translates the systemd style `slice:prefix:name` form of cgroupsPath to the path systemd
+/// would use, relative to the cgroup mount point: the slice is nested by its dash
+/// separated parents and the container gets a `prefix-name.scope` unit in it, e.g.
+/// `machine-app.slice:runtime:foo` is `machine.slice/machine-app.slice/runtime-foo.scope`.
+/// An empty slice is `system.slice`. Returns None if the path isn't in this form.
+fn systemd_cgroup_path(path: &Path) -> Option<PathBuf> {
+    let path = path.to_str()?;
+    let mut parts = path.split(':');
+    let (slice, prefix, name) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || name.is_empty() || path.contains('/') {
+        return None;
+    }
+
+    let slice = if slice.is_empty() {
+        "system.slice"
+    } else {
+        slice
+    };
+    let stem = slice.strip_suffix(".slice")?;
+    let mut pb = PathBuf::new();
+    let mut parent = String::new();
+    for part in stem.split('-') {
+        if !parent.is_empty() {
+            parent.push('-');
+        }
+        parent.push_str(part);
+        pb.push(format!("{}.slice", parent));
+    }
+
+    if prefix.is_empty() {
+        pb.push(format!("{}.scope", name));
+    } else {
+        pb.push(format!("{}-{}.scope", prefix, name));
+    }
+    Some(pb)
+}
+
 /// Write values from cgroup memory config into the appropriate files
 fn set_cgroup_memory<P: AsRef<Path>>(cgroup: P, memory: &Memory) -> Result<(), ContainerErr> {
     debug!("cgroup memory");
@@ -163,7 +456,10 @@ fn set_cgroup_memory<P: AsRef<Path>>(cgroup: P, memory: &Memory) -> Result<(), C
 
     if let Some(val) = memory.swap {
         debug!("memory.swap: {:?}", val);
-        write_to_cgroup_file(val.to_string().as_bytes(), &cgroup, "memory.swap.max")?;
+        match swap_unenforceable(&cgroup) {
+            Some(reason) => warn!("not limiting swap to {}, {}", val, reason),
+            None => write_to_cgroup_file(val.to_string().as_bytes(), &cgroup, "memory.swap.max")?,
+        }
     }
 
     if let Some(val) = memory.swappiness {
@@ -186,6 +482,103 @@ fn set_cgroup_memory<P: AsRef<Path>>(cgroup: P, memory: &Memory) -> Result<(), C
     Ok(())
 }
 
+/// Has the OOM killer kill all of the cgroup's processes together, rather than leave the
+/// container's process tree half dead. Without the memory controller there's no file for
+/// it, that's only worth a warning.
+pub fn set_oom_group<P: AsRef<Path>>(cgroup: P) -> Result<(), ContainerErr> {
+    let path = cgroup.as_ref().join("memory.oom.group");
+    debug!("memory.oom.group: 1");
+    let file = OpenOptions::new().write(true).open(&path);
+    match file.and_then(|mut f| f.write_all(b"1")) {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            warn!(
+                "not setting {:?}, the memory controller isn't enabled",
+                path
+            );
+            Ok(())
+        }
+        result => result.map_err(ContainerErr::IO),
+    }
+}
+
+/// Refuses limits, with checkBeforeUpdate, below what the cgroup already uses. The kernel
+/// would OOM kill the container right away. The swap limit is of memory and swap together.
+fn check_memory_usage(cgroup: &Path, memory: &Memory) -> Result<(), ContainerErr> {
+    if memory.check_before_update != Some(true) {
+        return Ok(());
+    }
+    let usage = |filename: &str| -> Result<i64, ContainerErr> {
+        let path = cgroup.join(filename);
+        match std::fs::read_to_string(&path) {
+            Ok(usage) => usage.trim().parse().map_err(|_| {
+                ContainerErr::Cgroup(format!("Unexpected contents of {:?}: {}", path, usage))
+            }),
+            // No swap accounting.
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(ContainerErr::IO(e)),
+        }
+    };
+    let current = usage("memory.current")?;
+    // Negative limits are no limit.
+    if let Some(limit) = memory.limit.filter(|limit| (0..current).contains(limit)) {
+        return Err(ContainerErr::Cgroup(format!(
+            "memory limit {} is below the current usage of {}",
+            limit, current
+        )));
+    }
+    if let Some(swap) = memory.swap.filter(|swap| *swap >= 0) {
+        let current = current + usage("memory.swap.current")?;
+        if swap < current {
+            return Err(ContainerErr::Cgroup(format!(
+                "memory and swap limit {} is below the current usage of {}",
+                swap, current
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Why a swap limit wouldn't be enforced in `cgroup`, None if it would. Without swap
+/// accounting, e.g. booted with swapaccount=0, the cgroup has no memory.swap.max.
+pub fn swap_unenforceable<P: AsRef<Path>>(cgroup: P) -> Option<&'static str> {
+    if !cgroup.as_ref().join("memory.swap.max").exists() {
+        return Some("swap accounting is disabled");
+    }
+    if !host_has_swap() {
+        return Some("the host has no swap");
+    }
+    None
+}
+
+/// Whether the kernel accounts swap to cgroups. The root cgroup has no memory files, so
+/// this looks at the first of its children with the memory controller.
+pub fn swap_accounting<P: AsRef<Path>>(cgroups_root: P) -> bool {
+    let Ok(entries) = std::fs::read_dir(cgroups_root) else {
+        return false;
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|dir| dir.join("memory.max").exists())
+        .is_some_and(|dir| dir.join("memory.swap.max").exists())
+}
+
+/// Whether the host has any swap configured.
+pub fn host_has_swap() -> bool {
+    std::fs::read_to_string(MEMINFO_PATH)
+        .ok()
+        .and_then(|meminfo| swap_total(&meminfo))
+        .is_some_and(|total| total > 0)
+}
+
+/// SwapTotal from /proc/meminfo, in kB.
+fn swap_total(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("SwapTotal:"))
+        .and_then(|total| total.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
 fn set_cgroup_cpu<P: AsRef<Path>>(cgroup: P, cpu: &Cpu) -> Result<(), ContainerErr> {
     if let Some(val) = cpu.burst {
         debug!("cpu burst: {:?}", val);
@@ -194,51 +587,145 @@ fn set_cgroup_cpu<P: AsRef<Path>>(cgroup: P, cpu: &Cpu) -> Result<(), ContainerE
     Ok(())
 }
 
+/// The cgroup's cpu.max, a quota of None is no limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuMax {
+    pub quota: Option<u64>,
+    pub period: u64,
+}
+
+pub fn read_cpu_max<P: AsRef<Path>>(cgroup: P) -> Result<CpuMax, ContainerErr> {
+    let path = cgroup.as_ref().join("cpu.max");
+    let data = std::fs::read_to_string(&path).map_err(ContainerErr::IO)?;
+    let invalid = || ContainerErr::Cgroup(format!("Unexpected contents of {:?}: {}", path, data));
+    let (quota, period) = data.trim().split_once(' ').ok_or_else(invalid)?;
+    let quota = match quota {
+        "max" => None,
+        quota => Some(quota.parse().map_err(|_| invalid())?),
+    };
+    Ok(CpuMax {
+        quota,
+        period: period.parse().map_err(|_| invalid())?,
+    })
+}
+
+/// Sets the quota and burst, leaving the period. The kernel refuses a burst above the
+/// quota, so when the quota goes down the burst is written first.
+pub fn set_cpu_quota<P: AsRef<Path>>(
+    cgroup: P,
+    current: CpuMax,
+    quota: u64,
+    burst: u64,
+) -> Result<(), ContainerErr> {
+    debug!("cpu quota: {} burst: {}", quota, burst);
+    let cpu_max = format!("{} {}", quota, current.period);
+    if current.quota.is_some_and(|current| quota < current) {
+        write_to_cgroup_file(burst.to_string().as_bytes(), &cgroup, "cpu.max.burst")?;
+        write_to_cgroup_file(cpu_max.as_bytes(), &cgroup, "cpu.max")
+    } else {
+        write_to_cgroup_file(cpu_max.as_bytes(), &cgroup, "cpu.max")?;
+        write_to_cgroup_file(burst.to_string().as_bytes(), &cgroup, "cpu.max.burst")
+    }
+}
+
 /// Writes information for the IO controller
 /// https://docs.kernel.org/admin-guide/cgroup-v2.html#io
 fn set_cgroup_blockio<P: AsRef<Path>>(cgroup: P, blockio: &BlockIO) -> Result<(), ContainerErr> {
-    if let Some(weight) = blockio.weight {
-        let io_weight_path = cgroup.as_ref().join("io.weight");
+    // Device weights apply without a default weight as well.
+    let device_weights: Vec<(String, u16)> = blockio
+        .weight_device
+        .iter()
+        .flatten()
+        .filter_map(|device| {
+            debug!("weight device: {:?}", device);
+            Some((format!("{}:{}", device.major, device.minor), device.weight?))
+        })
+        .collect();
+    if blockio.weight.is_some() || !device_weights.is_empty() {
+        let weight_file = WeightFile::detect(cgroup.as_ref())?;
+        let io_weight_path = cgroup.as_ref().join(weight_file.filename());
         let mut data = read_flat_keyed_file(&io_weight_path)?;
-
-        if let Some(weight_devices) = &blockio.weight_device {
-            for device in weight_devices {
-                debug!("weight device: {:?}", device);
-                if let Some(device_weight) = device.weight {
-                    let key = format!("{}:{}", device.major, device.minor);
-                    data.insert(key, device_weight.to_string());
-                }
-            }
+        for (device, weight) in device_weights {
+            data.insert(device, weight_file.convert(weight)?.to_string());
+        }
+        if let Some(weight) = blockio.weight {
+            data.insert(
+                String::from("default"),
+                weight_file.convert(weight)?.to_string(),
+            );
         }
-
-        data.insert(String::from("default"), weight.to_string());
         util::write_flat_keyed_file(&io_weight_path, data)?;
     }
 
+    let throttles = [
+        (&blockio.throttle_read_bps_device, "rbps"),
+        (&blockio.throttle_write_bps_device, "wbps"),
+        (&blockio.throttle_read_iops_device, "riops"),
+        (&blockio.throttle_write_iops_device, "wiops"),
+    ];
+    // io.max only exists with blk-throttle, configs without throttles mustn't need it.
+    if throttles.iter().all(|(devices, _)| devices.is_none()) {
+        return Ok(());
+    }
     let io_max_path = cgroup.as_ref().join("io.max");
     let mut io_max = read_nested_keyed_file(&io_max_path)?;
-
-    if let Some(throttle_read_bps_device) = &blockio.throttle_read_bps_device {
-        update_device(throttle_read_bps_device, "rbps", &mut io_max);
+    for (devices, subkey) in throttles {
+        if let Some(devices) = devices {
+            update_device(devices, subkey, &mut io_max);
+        }
     }
+    write_nested_keyed_file(&io_max_path, io_max)?;
 
-    if let Some(throttle_write_bps_device) = &blockio.throttle_write_bps_device {
-        update_device(throttle_write_bps_device, "wbps", &mut io_max);
-    }
+    Ok(())
+}
 
-    if let Some(throttle_read_iops_device) = &blockio.throttle_read_iops_device {
-        update_device(throttle_read_iops_device, "riops", &mut io_max);
-    }
+/// The file the io controller takes weights in. io.weight needs an io cost model or the
+/// blk-throttle weights, kernels with only the bfq scheduler have io.bfq.weight instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WeightFile {
+    Io,
+    Bfq,
+}
 
-    if let Some(throttle_write_iops_device) = &blockio.throttle_write_iops_device {
-        update_device(throttle_write_iops_device, "wiops", &mut io_max);
+impl WeightFile {
+    fn detect(cgroup: &Path) -> Result<Self, ContainerErr> {
+        for file in [WeightFile::Io, WeightFile::Bfq] {
+            if cgroup.join(file.filename()).exists() {
+                return Ok(file);
+            }
+        }
+        Err(ContainerErr::Cgroup(format!(
+            "{:?} has neither io.weight nor io.bfq.weight, blockIO weights need the io \
+             controller and the bfq io scheduler or an io cost model",
+            cgroup
+        )))
     }
 
-    write_nested_keyed_file(&io_max_path, io_max)?;
+    fn filename(self) -> &'static str {
+        match self {
+            WeightFile::Io => "io.weight",
+            WeightFile::Bfq => "io.bfq.weight",
+        }
+    }
 
-    Ok(())
+    /// The OCI weight, 10 to 1000, in the file's range. io.weight takes 1 to 10000,
+    /// io.bfq.weight 1 to 1000.
+    fn convert(self, weight: u16) -> Result<u64, ContainerErr> {
+        if !(10..=1000).contains(&weight) {
+            return Err(ContainerErr::Cgroup(format!(
+                "blockIO weight {} is outside 10 to 1000",
+                weight
+            )));
+        }
+        let weight = u64::from(weight);
+        Ok(match self {
+            WeightFile::Io => 1 + (weight - 10) * 9999 / 990,
+            WeightFile::Bfq => weight,
+        })
+    }
 }
 
+/// Sets `subkey` of each device's entry, keeping its other limits.
 fn update_device(
     dev_list: &[DevThrottle],
     subkey: &str,
@@ -246,36 +733,28 @@ fn update_device(
 ) {
     for dev in dev_list {
         debug!("device {:?}", dev);
-        if let Some(dev_entry) = file_map.get_mut(&format!("{}:{}", dev.major, dev.minor)) {
-            dev_entry.insert(String::from("rbps"), dev.rate.to_string());
-        } else {
-            let mut dev_entry = HashMap::new();
-            dev_entry.insert(String::from(subkey), dev.rate.to_string());
-            file_map.insert(format!("{}:{}", dev.major, dev.minor), dev_entry);
-        }
+        file_map
+            .entry(format!("{}:{}", dev.major, dev.minor))
+            .or_default()
+            .insert(String::from(subkey), dev.rate.to_string());
     }
 }
 
-/// Writes information for the hugetlb controller
+/// Writes a page size's limit for the hugetlb controller
 /// https://docs.kernel.org/admin-guide/cgroup-v2.html#hugetlb
-fn set_cgroup_hugepage<P: AsRef<Path>>(
-    cgroup: P,
-    limits: &[HugePageLimits],
-) -> Result<(), ContainerErr> {
-    for hp in limits {
-        debug!("hugepage {:?}", hp);
-        let hp_path = cgroup
-            .as_ref()
-            .join(format!("hugepage.{}.max", hp.page_size));
-        let mut f = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(hp_path)
-            .map_err(ContainerErr::IO)?;
-        f.write_all(hp.limit.to_string().as_bytes())
-            .map_err(ContainerErr::IO)?;
-    }
+fn set_cgroup_hugepage<P: AsRef<Path>>(cgroup: P, hp: &HugePageLimits) -> Result<(), ContainerErr> {
+    debug!("hugepage {:?}", hp);
+    let hp_path = cgroup
+        .as_ref()
+        .join(format!("hugepage.{}.max", hp.page_size));
+    let mut f = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(hp_path)
+        .map_err(ContainerErr::IO)?;
+    f.write_all(hp.limit.to_string().as_bytes())
+        .map_err(ContainerErr::IO)?;
     Ok(())
 }
 
@@ -305,22 +784,58 @@ fn set_cgroup_rdma<P: AsRef<Path>>(
     Ok(())
 }
 
-/// Writes max pids
+/// Writes max pids, a negative limit is no limit. The limit is read back, a limit which
+/// didn't take would only show once the container forks.
 /// https://docs.kernel.org/admin-guide/cgroup-v2.html#pid
 fn set_cgroup_pids<P: AsRef<Path>>(cgroup: P, pids: &Pids) -> Result<(), ContainerErr> {
+    let path = cgroup.as_ref().join("pids.max");
     let mut f = OpenOptions::new()
         .create(true)
         .truncate(true)
         .write(true)
-        .open(cgroup.as_ref().join("pids.max"))
+        .open(&path)
         .map_err(ContainerErr::IO)?;
 
     debug!("pids: {:?}", pids);
-    f.write_all(pids.limit.to_string().as_bytes())
-        .map_err(ContainerErr::IO)?;
+    let limit = match pids.limit {
+        limit if limit < 0 => String::from("max"),
+        limit => limit.to_string(),
+    };
+    f.write_all(limit.as_bytes()).map_err(ContainerErr::IO)?;
+
+    let applied = std::fs::read_to_string(&path).map_err(ContainerErr::IO)?;
+    if applied.trim() != limit {
+        return Err(ContainerErr::Cgroup(format!(
+            "{:?} is {} after writing {}",
+            path,
+            applied.trim(),
+            limit
+        )));
+    }
     Ok(())
 }
 
+/// Finds the cgroup, `cgroup` itself or one of its ancestors below `root`, whose
+/// pids.max is used up. Cloning into `cgroup` fails with EAGAIN if there's one.
+pub fn pids_limit_reached(cgroup: &Path, root: &Path) -> Option<PathBuf> {
+    let read = |dir: &Path, name: &str| std::fs::read_to_string(dir.join(name)).ok();
+    cgroup
+        .ancestors()
+        .take_while(|dir| dir.starts_with(root) && *dir != root)
+        .find(|dir| {
+            let (Some(max), Some(current)) = (read(dir, "pids.max"), read(dir, "pids.current"))
+            else {
+                return false;
+            };
+            match (max.trim().parse::<u64>(), current.trim().parse::<u64>()) {
+                (Ok(max), Ok(current)) => current >= max,
+                // "max", no limit.
+                _ => false,
+            }
+        })
+        .map(Path::to_path_buf)
+}
+
 fn write_to_cgroup_file<P: AsRef<Path>, F: AsRef<Path>>(
     bytes: &[u8],
     cgroup: P,
@@ -366,6 +881,30 @@ mod tests {
         assert_eq!(PathBuf::from("/sys/fs/cgroup/test-container"), result);
     }
 
+    #[test]
+    fn test_systemd_cgroup_path() {
+        let result = resolve_cgroup_path(
+            Some("machine-app.slice:runtime:foo"),
+            "/sys/fs/cgroup",
+            "test-container",
+        );
+        assert_eq!(
+            PathBuf::from("/sys/fs/cgroup/machine.slice/machine-app.slice/runtime-foo.scope"),
+            result
+        );
+
+        let result = resolve_cgroup_path(Some("::foo"), "/sys/fs/cgroup", "test-container");
+        assert_eq!(
+            PathBuf::from("/sys/fs/cgroup/system.slice/foo.scope"),
+            result
+        );
+
+        // Anything else is a plain path.
+        assert_eq!(None, systemd_cgroup_path(Path::new("/a:b:c/d")));
+        assert_eq!(None, systemd_cgroup_path(Path::new("app:runtime")));
+        assert_eq!(None, systemd_cgroup_path(Path::new("app:runtime:foo")));
+    }
+
     #[test]
     fn test_create_cgroup() {
         use std::fs::metadata;
@@ -381,8 +920,17 @@ mod tests {
 
         let config = Config::load("test_configs/").expect("to load full_config_example.json");
 
-        let result = create_cgroup(&dir, &config);
+        // A plain directory can't take a device filter, that's all which may be skipped.
+        let result = create_cgroup(&dir, &config, ResourcePolicy::Permissive);
         assert!(result.is_ok(), "{:?}", result);
+        let warnings = result.unwrap();
+        assert!(
+            warnings
+                .iter()
+                .all(|w| w.starts_with("devices settings not applied")),
+            "{:?}",
+            warnings
+        );
         let metadata = metadata(&procs_file);
         if let Err(e) = metadata {
             println!("{:?}", &procs_file);
@@ -392,4 +940,269 @@ mod tests {
         // try to cleanup
         std::fs::remove_dir_all(&dir).unwrap();
     }
+
+    #[test]
+    fn test_swap_unenforceable() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let meminfo = "MemTotal:       16316412 kB\nSwapTotal:       2097148 kB\n";
+        assert_eq!(Some(2097148), swap_total(meminfo));
+        assert_eq!(Some(0), swap_total("SwapTotal:             0 kB\n"));
+        assert_eq!(None, swap_total("MemTotal:       16316412 kB\n"));
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let root = PathBuf::from(format!("/tmp/swap_{}", time));
+        let cgroup = root.join("container");
+        std::fs::create_dir_all(&cgroup).unwrap();
+        std::fs::write(cgroup.join("memory.max"), "max\n").unwrap();
+        assert_eq!(
+            Some("swap accounting is disabled"),
+            swap_unenforceable(&cgroup)
+        );
+        assert!(!swap_accounting(&root));
+
+        // The limit isn't written then, rather than failing the create.
+        let memory: Memory = serde_json::from_value(serde_json::json!({"swap": 1024})).unwrap();
+        set_cgroup_memory(&cgroup, &memory).unwrap();
+        assert!(!cgroup.join("memory.swap.max").exists());
+
+        std::fs::write(cgroup.join("memory.swap.max"), "max\n").unwrap();
+        assert!(swap_accounting(&root));
+        assert_eq!(host_has_swap(), swap_unenforceable(&cgroup).is_none());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_check_memory_usage() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let cgroup = PathBuf::from(format!("/tmp/memory_usage_{}", time));
+        std::fs::create_dir(&cgroup).unwrap();
+        std::fs::write(cgroup.join("memory.current"), "1000\n").unwrap();
+        let memory = |value| -> Memory { serde_json::from_value(value).unwrap() };
+
+        let shrink = memory(serde_json::json!({"limit": 500, "checkBeforeUpdate": true}));
+        assert!(check_memory_usage(&cgroup, &shrink).is_err());
+        // Only when asked to.
+        let unchecked = memory(serde_json::json!({"limit": 500}));
+        assert!(check_memory_usage(&cgroup, &unchecked).is_ok());
+        let unlimited = memory(serde_json::json!({"limit": -1, "checkBeforeUpdate": true}));
+        assert!(check_memory_usage(&cgroup, &unlimited).is_ok());
+
+        let swap = memory(serde_json::json!({
+            "limit": 1500,
+            "swap": 1500,
+            "checkBeforeUpdate": true,
+        }));
+        assert!(check_memory_usage(&cgroup, &swap).is_ok());
+        std::fs::write(cgroup.join("memory.swap.current"), "1000\n").unwrap();
+        assert!(check_memory_usage(&cgroup, &swap).is_err());
+
+        std::fs::remove_dir_all(&cgroup).unwrap();
+    }
+
+    #[test]
+    fn test_set_cgroup_blockio() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let cgroup = PathBuf::from(format!("/tmp/blockio_{}", time));
+        std::fs::create_dir(&cgroup).unwrap();
+        std::fs::write(cgroup.join("io.weight"), "default 100\n").unwrap();
+        std::fs::write(
+            cgroup.join("io.max"),
+            "8:0 rbps=max wbps=1000 riops=max wiops=max\n",
+        )
+        .unwrap();
+
+        // Device weights and throttles only, no default weight.
+        let blockio: BlockIO = serde_json::from_value(serde_json::json!({
+            "weightDevice": [{"major": 8, "minor": 16, "weight": 1000}],
+            "throttleReadBpsDevice": [{"major": 8, "minor": 0, "rate": 2048}],
+            "throttleWriteIopsDevice": [
+                {"major": 8, "minor": 0, "rate": 10},
+                {"major": 8, "minor": 16, "rate": 20},
+            ],
+        }))
+        .unwrap();
+        set_cgroup_blockio(&cgroup, &blockio).unwrap();
+
+        let weights = read_flat_keyed_file(cgroup.join("io.weight")).unwrap();
+        assert_eq!("100", weights["default"]);
+        assert_eq!("10000", weights["8:16"]);
+        let io_max = read_nested_keyed_file(cgroup.join("io.max")).unwrap();
+        assert_eq!("2048", io_max["8:0"]["rbps"]);
+        assert_eq!("1000", io_max["8:0"]["wbps"]);
+        assert_eq!("10", io_max["8:0"]["wiops"]);
+        assert_eq!(
+            HashMap::from([(String::from("wiops"), String::from("20"))]),
+            io_max["8:16"]
+        );
+
+        // Without throttles io.max isn't needed.
+        std::fs::remove_file(cgroup.join("io.max")).unwrap();
+        let blockio: BlockIO = serde_json::from_value(serde_json::json!({"weight": 500})).unwrap();
+        set_cgroup_blockio(&cgroup, &blockio).unwrap();
+
+        std::fs::remove_dir_all(&cgroup).unwrap();
+    }
+
+    #[test]
+    fn test_weight_file() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let cgroup = PathBuf::from(format!("/tmp/weight_file_{}", time));
+        std::fs::create_dir(&cgroup).unwrap();
+        assert!(WeightFile::detect(&cgroup).is_err());
+        std::fs::write(cgroup.join("io.bfq.weight"), "default 100\n").unwrap();
+        assert_eq!(WeightFile::Bfq, WeightFile::detect(&cgroup).unwrap());
+        std::fs::write(cgroup.join("io.weight"), "default 100\n").unwrap();
+        assert_eq!(WeightFile::Io, WeightFile::detect(&cgroup).unwrap());
+        std::fs::remove_dir_all(&cgroup).unwrap();
+
+        assert_eq!(1, WeightFile::Io.convert(10).unwrap());
+        assert_eq!(5000, WeightFile::Io.convert(505).unwrap());
+        assert_eq!(10000, WeightFile::Io.convert(1000).unwrap());
+        assert_eq!(500, WeightFile::Bfq.convert(500).unwrap());
+        assert!(WeightFile::Io.convert(5).is_err());
+        assert!(WeightFile::Bfq.convert(1001).is_err());
+    }
+
+    #[test]
+    fn test_wait_empty() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = PathBuf::from(format!("/tmp/wait_empty_{}", time));
+        std::fs::create_dir(&dir).unwrap();
+        let timeout = Duration::from_millis(20);
+
+        std::fs::write(dir.join("cgroup.events"), "populated 1\nfrozen 0\n").unwrap();
+        assert!(!wait_empty(&dir, timeout).unwrap());
+        std::fs::write(dir.join("cgroup.events"), "populated 0\nfrozen 0\n").unwrap();
+        assert!(wait_empty(&dir, timeout).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(wait_empty(&dir, timeout).unwrap());
+    }
+
+    #[test]
+    fn test_kill_cgroup_procs() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::Command;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = PathBuf::from(format!("/tmp/kill_cgroup_{}", time));
+        std::fs::create_dir(&dir).unwrap();
+
+        // Without cgroup.kill, every pid in cgroup.procs is signalled.
+        for (sig, kill) in [(libc::SIGTERM, false), (libc::SIGKILL, true)] {
+            let mut first = Command::new("sleep").arg("30").spawn().unwrap();
+            let mut second = Command::new("sleep").arg("30").spawn().unwrap();
+            let procs = format!("{}\n{}\n", first.id(), second.id());
+            std::fs::write(dir.join("cgroup.procs"), procs).unwrap();
+            if kill {
+                kill_cgroup(&dir).unwrap();
+            } else {
+                signal_cgroup(&dir, sig).unwrap();
+            }
+            assert_eq!(Some(sig), first.wait().unwrap().signal());
+            assert_eq!(Some(sig), second.wait().unwrap().signal());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pids_limit_reached() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let root = PathBuf::from(format!("/tmp/pids_limit_{}", time));
+        let parent = root.join("parent");
+        let cgroup = parent.join("container");
+        std::fs::create_dir_all(&cgroup).unwrap();
+        let write = |dir: &Path, max: &str, current: &str| {
+            std::fs::write(dir.join("pids.max"), max).unwrap();
+            std::fs::write(dir.join("pids.current"), current).unwrap();
+        };
+
+        write(&cgroup, "max\n", "0\n");
+        write(&parent, "10\n", "3\n");
+        assert_eq!(None, pids_limit_reached(&cgroup, &root));
+        write(&parent, "10\n", "10\n");
+        assert_eq!(Some(parent.clone()), pids_limit_reached(&cgroup, &root));
+        write(&cgroup, "0\n", "0\n");
+        assert_eq!(Some(cgroup.clone()), pids_limit_reached(&cgroup, &root));
+        // The root isn't looked at.
+        write(&root, "1\n", "1\n");
+        write(&cgroup, "max\n", "0\n");
+        write(&parent, "max\n", "10\n");
+        assert_eq!(None, pids_limit_reached(&cgroup, &root));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_resource_policy() {
+        use serde_json::json;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let cgroup = PathBuf::from(format!("/tmp/resource_policy_{}", time));
+        std::fs::create_dir(&cgroup).unwrap();
+        // No io.weight or io.bfq.weight, like a cgroup without the io controller.
+        let config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "linux": {"namespaces": [], "resources": {
+                "block_io": {"weight": 500},
+                "pids": {"limit": 64},
+            }},
+        }))
+        .unwrap();
+
+        assert!(apply_settings(&cgroup, &config, ResourcePolicy::Strict).is_err());
+        let warnings = apply_settings(&cgroup, &config, ResourcePolicy::Permissive).unwrap();
+        assert_eq!(1, warnings.len());
+        assert!(
+            warnings[0].starts_with("io settings not applied"),
+            "{}",
+            warnings[0]
+        );
+        assert_eq!(
+            "64",
+            std::fs::read_to_string(cgroup.join("pids.max")).unwrap()
+        );
+
+        std::fs::remove_dir_all(&cgroup).unwrap();
+    }
 }
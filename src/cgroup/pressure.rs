@@ -0,0 +1,93 @@
+//! Pressure stall information, the share of time tasks in a cgroup were stalled waiting
+//! for a resource.
+//! https://docs.kernel.org/accounting/psi.html
+
+use crate::error::ContainerErr;
+use serde::Serialize;
+use std::path::Path;
+
+/// A line of a pressure file. The averages are percentages over the last 10, 60 and 300
+/// seconds, the total is in microseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct PressureLine {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    pub total: u64,
+}
+
+/// A cgroup's cpu.pressure, memory.pressure or io.pressure.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Pressure {
+    /// Some of the tasks were stalled.
+    pub some: PressureLine,
+    /// All of them were, cpu.pressure only has it since 5.13.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full: Option<PressureLine>,
+}
+
+/// Reads one of the cgroup's pressure files, e.g. `cpu.pressure`.
+pub fn read_pressure<P: AsRef<Path>>(cgroup: P, filename: &str) -> Result<Pressure, ContainerErr> {
+    let path = cgroup.as_ref().join(filename);
+    let data = std::fs::read_to_string(&path).map_err(ContainerErr::IO)?;
+    parse_pressure(&data)
+        .ok_or_else(|| ContainerErr::Cgroup(format!("Unexpected contents of {:?}", path)))
+}
+
+fn parse_pressure(data: &str) -> Option<Pressure> {
+    let mut some = None;
+    let mut full = None;
+    for line in data.lines() {
+        let (kind, fields) = line.split_once(' ')?;
+        let parsed = parse_line(fields)?;
+        match kind {
+            "some" => some = Some(parsed),
+            "full" => full = Some(parsed),
+            _ => return None,
+        }
+    }
+    Some(Pressure { some: some?, full })
+}
+
+fn parse_line(fields: &str) -> Option<PressureLine> {
+    let mut line = PressureLine::default();
+    for field in fields.split_whitespace() {
+        let (key, value) = field.split_once('=')?;
+        match key {
+            "avg10" => line.avg10 = value.parse().ok()?,
+            "avg60" => line.avg60 = value.parse().ok()?,
+            "avg300" => line.avg300 = value.parse().ok()?,
+            "total" => line.total = value.parse().ok()?,
+            // Newer kernels may add fields.
+            _ => {}
+        }
+    }
+    Some(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pressure() {
+        let pressure = parse_pressure(
+            "some avg10=12.50 avg60=3.04 avg300=0.71 total=1234567\n\
+             full avg10=1.00 avg60=0.00 avg300=0.00 total=42\n",
+        )
+        .unwrap();
+        assert_eq!(12.5, pressure.some.avg10);
+        assert_eq!(3.04, pressure.some.avg60);
+        assert_eq!(1234567, pressure.some.total);
+        assert_eq!(42, pressure.full.unwrap().total);
+
+        let pressure = parse_pressure("some avg10=0.00 avg60=0.00 avg300=0.00 total=0\n").unwrap();
+        assert_eq!(None, pressure.full);
+
+        assert_eq!(None, parse_pressure(""));
+        assert_eq!(
+            None,
+            parse_pressure("some avg10=x avg60=0.00 avg300=0.00 total=0")
+        );
+    }
+}
@@ -0,0 +1,535 @@
+//! Resource usage of a cgroup, as read from its controllers' interface files, for the
+//! `events` command. Controllers which aren't enabled for the cgroup are left out.
+//!
+//! Network interfaces aren't a cgroup's, their counters are read from the /proc/net/dev
+//! of a process in the container's network namespace.
+
+use super::pressure::{read_pressure, Pressure};
+use super::util::{read_flat_keyed_file, read_nested_keyed_file};
+use crate::error::ContainerErr;
+use crate::state::Pid;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::Path;
+
+const SYS_DEV_BLOCK: &str = "/sys/dev/block";
+const PARTITIONS_PATH: &str = "/proc/partitions";
+
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<CpuStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids: Option<PidsStats>,
+    /// By device, from io.stat.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub io: Vec<DeviceIoStats>,
+    /// By page size, e.g. `2MB`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub hugetlb: BTreeMap<String, HugetlbStats>,
+    pub pressure: PressureStats,
+    /// By interface name, loopback is left out.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub network: BTreeMap<String, InterfaceStats>,
+}
+
+/// The pressure files, None without PSI, e.g. when booted with psi=0.
+#[derive(Debug, Default, Serialize)]
+pub struct PressureStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<Pressure>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Pressure>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub io: Option<Pressure>,
+}
+
+impl PressureStats {
+    /// Each resource's pressure, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Pressure)> {
+        [
+            ("cpu", &self.cpu),
+            ("memory", &self.memory),
+            ("io", &self.io),
+        ]
+        .into_iter()
+        .filter_map(|(name, pressure)| Some((name, pressure.as_ref()?)))
+    }
+}
+
+/// From cpu.stat, times are in microseconds.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CpuStats {
+    pub usage_usec: u64,
+    pub user_usec: u64,
+    pub system_usec: u64,
+    pub nr_periods: u64,
+    pub nr_throttled: u64,
+    pub throttled_usec: u64,
+}
+
+/// In bytes, a limit of None is no limit.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryStats {
+    pub usage: u64,
+    pub limit: Option<u64>,
+    /// None without swap accounting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap_usage: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap_limit: Option<u64>,
+    /// memory.stat, e.g. anon, file and pgmajfault.
+    pub stat: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PidsStats {
+    pub current: u64,
+    pub limit: Option<u64>,
+}
+
+/// A device's io, since the cgroup was created.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceIoStats {
+    pub major: u64,
+    pub minor: u64,
+    /// e.g. `sda`, None if the device is gone.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    pub rbytes: u64,
+    pub wbytes: u64,
+    pub rios: u64,
+    pub wios: u64,
+    pub dbytes: u64,
+    pub dios: u64,
+    /// The bytes for people, e.g. `1.5 GiB`.
+    pub human: HumanIo,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct HumanIo {
+    pub rbytes: String,
+    pub wbytes: String,
+    pub dbytes: String,
+}
+
+/// A page size's usage, in bytes.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct HugetlbStats {
+    pub current: u64,
+    pub max: Option<u64>,
+    /// How often an allocation failed because of the limit.
+    pub events: u64,
+}
+
+/// A network interface's counters, since it was created.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterfaceStats {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+/// Reads what the cgroup's processes use.
+pub fn read_stats<P: AsRef<Path>>(cgroup: P) -> Result<Stats, ContainerErr> {
+    let cgroup = cgroup.as_ref();
+    Ok(Stats {
+        cpu: optional(read_cpu(cgroup))?,
+        memory: optional(read_memory(cgroup))?,
+        pids: optional(read_pids(cgroup))?,
+        io: optional(read_io(cgroup))?.unwrap_or_default(),
+        hugetlb: read_hugetlb(cgroup)?,
+        pressure: PressureStats {
+            cpu: optional_pressure(cgroup, "cpu.pressure")?,
+            memory: optional_pressure(cgroup, "memory.pressure")?,
+            io: optional_pressure(cgroup, "io.pressure")?,
+        },
+        network: BTreeMap::new(),
+    })
+}
+
+/// The interfaces of `pid`'s network namespace. A process which is gone has none.
+pub fn read_network(pid: Pid) -> Result<BTreeMap<String, InterfaceStats>, ContainerErr> {
+    let path = format!("/proc/{}/net/dev", pid);
+    let data = match std::fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(ContainerErr::IO(e)),
+    };
+    parse_net_dev(&data).ok_or_else(|| ContainerErr::Cgroup(format!("Unexpected {}", path)))
+}
+
+/// After two header lines, a line per interface: its name, a colon and 8 receive then 8
+/// transmit counters, each starting with bytes, packets, errs and drop.
+fn parse_net_dev(data: &str) -> Option<BTreeMap<String, InterfaceStats>> {
+    let mut interfaces = BTreeMap::new();
+    for line in data.lines().skip(2) {
+        let (name, counters) = line.split_once(':')?;
+        let counters: Vec<u64> = counters
+            .split_whitespace()
+            .map(|counter| counter.parse().ok())
+            .collect::<Option<_>>()?;
+        if counters.len() < 16 {
+            return None;
+        }
+        let name = name.trim();
+        if name == "lo" {
+            continue;
+        }
+        let stats = InterfaceStats {
+            rx_bytes: counters[0],
+            rx_packets: counters[1],
+            rx_errors: counters[2],
+            rx_dropped: counters[3],
+            tx_bytes: counters[8],
+            tx_packets: counters[9],
+            tx_errors: counters[10],
+            tx_dropped: counters[11],
+        };
+        interfaces.insert(name.to_string(), stats);
+    }
+    Some(interfaces)
+}
+
+/// The pressure files are there without PSI, but can't be read.
+fn optional_pressure(cgroup: &Path, filename: &str) -> Result<Option<Pressure>, ContainerErr> {
+    match read_pressure(cgroup, filename) {
+        Err(ContainerErr::IO(e)) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(None),
+        result => optional(result),
+    }
+}
+
+fn read_cpu(cgroup: &Path) -> Result<CpuStats, ContainerErr> {
+    let stat = read_counters(&cgroup.join("cpu.stat"))?;
+    let get = |key: &str| stat.get(key).copied().unwrap_or_default();
+    Ok(CpuStats {
+        usage_usec: get("usage_usec"),
+        user_usec: get("user_usec"),
+        system_usec: get("system_usec"),
+        nr_periods: get("nr_periods"),
+        nr_throttled: get("nr_throttled"),
+        throttled_usec: get("throttled_usec"),
+    })
+}
+
+fn read_memory(cgroup: &Path) -> Result<MemoryStats, ContainerErr> {
+    Ok(MemoryStats {
+        usage: read_value(&cgroup.join("memory.current"))?,
+        limit: read_limit(&cgroup.join("memory.max"))?,
+        swap_usage: optional(read_value(&cgroup.join("memory.swap.current")))?,
+        swap_limit: optional(read_limit(&cgroup.join("memory.swap.max")))?.flatten(),
+        stat: read_counters(&cgroup.join("memory.stat"))?,
+    })
+}
+
+fn read_pids(cgroup: &Path) -> Result<PidsStats, ContainerErr> {
+    Ok(PidsStats {
+        current: read_value(&cgroup.join("pids.current"))?,
+        limit: read_limit(&cgroup.join("pids.max"))?,
+    })
+}
+
+/// io.stat has a line per device, e.g. `8:0 rbytes=1024 wbytes=0 rios=1 wios=0 dbytes=0
+/// dios=0`, sorted by device here.
+fn read_io(cgroup: &Path) -> Result<Vec<DeviceIoStats>, ContainerErr> {
+    let path = cgroup.join("io.stat");
+    let partitions = std::fs::read_to_string(PARTITIONS_PATH).unwrap_or_default();
+    let mut devices = Vec::new();
+    for (device, counters) in read_nested_keyed_file(&path)? {
+        let Some((major, minor)) = device.split_once(':') else {
+            continue;
+        };
+        let (major, minor) = (parse(&path, major)?, parse(&path, minor)?);
+        let get = |key: &str| match counters.get(key) {
+            Some(value) => parse(&path, value),
+            None => Ok(0),
+        };
+        let (rbytes, wbytes, dbytes) = (get("rbytes")?, get("wbytes")?, get("dbytes")?);
+        devices.push(DeviceIoStats {
+            major,
+            minor,
+            device: device_name(major, minor, &partitions),
+            rbytes,
+            wbytes,
+            rios: get("rios")?,
+            wios: get("wios")?,
+            dbytes,
+            dios: get("dios")?,
+            human: HumanIo {
+                rbytes: human_bytes(rbytes),
+                wbytes: human_bytes(wbytes),
+                dbytes: human_bytes(dbytes),
+            },
+        });
+    }
+    devices.sort_by_key(|device| (device.major, device.minor));
+    Ok(devices)
+}
+
+/// The name of a block device, by the link to it in /sys/dev/block or else by
+/// /proc/partitions, whose lines are `major minor #blocks name`.
+fn device_name(major: u64, minor: u64, partitions: &str) -> Option<String> {
+    let link = Path::new(SYS_DEV_BLOCK).join(format!("{}:{}", major, minor));
+    if let Ok(target) = std::fs::read_link(link) {
+        if let Some(name) = target.file_name() {
+            return Some(name.to_string_lossy().into_owned());
+        }
+    }
+    partitions.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            [maj, min, _, name] if maj.parse() == Ok(major) && min.parse() == Ok(minor) => {
+                Some(name.to_string())
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Bytes in binary units with a decimal, e.g. `512 B` or `1.5 KiB`.
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Every page size has its own files, e.g. hugetlb.2MB.current.
+fn read_hugetlb(cgroup: &Path) -> Result<BTreeMap<String, HugetlbStats>, ContainerErr> {
+    let mut hugetlb = BTreeMap::new();
+    for entry in std::fs::read_dir(cgroup).map_err(ContainerErr::IO)? {
+        let name = entry.map_err(ContainerErr::IO)?.file_name();
+        let Some(size) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("hugetlb."))
+            .and_then(|name| name.strip_suffix(".current"))
+        else {
+            continue;
+        };
+        // hugetlb.2MB.rsvd.current counts reservations.
+        if size.contains('.') {
+            continue;
+        }
+        let file = |suffix: &str| cgroup.join(format!("hugetlb.{}.{}", size, suffix));
+        let events = read_counters(&file("events"))?;
+        let stats = HugetlbStats {
+            current: read_value(&file("current"))?,
+            max: read_limit(&file("max"))?,
+            events: events.get("max").copied().unwrap_or_default(),
+        };
+        hugetlb.insert(size.to_string(), stats);
+    }
+    Ok(hugetlb)
+}
+
+/// How many of the cgroup's processes the OOM killer killed, the oom_kill count of
+/// memory.events. None without the memory controller.
+pub fn read_oom_kills<P: AsRef<Path>>(cgroup: P) -> Result<Option<u64>, ContainerErr> {
+    let path = cgroup.as_ref().join("memory.events");
+    let Some(counters) = optional(read_counters(&path))? else {
+        return Ok(None);
+    };
+    Ok(Some(counters.get("oom_kill").copied().unwrap_or(0)))
+}
+
+/// None for a file which doesn't exist, the controller isn't enabled then.
+fn optional<T>(result: Result<T, ContainerErr>) -> Result<Option<T>, ContainerErr> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(ContainerErr::IO(e)) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn parse(path: &Path, value: &str) -> Result<u64, ContainerErr> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ContainerErr::Cgroup(format!("Unexpected value in {:?}: {}", path, value)))
+}
+
+fn read_value(path: &Path) -> Result<u64, ContainerErr> {
+    let value = std::fs::read_to_string(path).map_err(ContainerErr::IO)?;
+    parse(path, &value)
+}
+
+/// A limit, None for `max`.
+fn read_limit(path: &Path) -> Result<Option<u64>, ContainerErr> {
+    let value = std::fs::read_to_string(path).map_err(ContainerErr::IO)?;
+    match value.trim() {
+        "max" => Ok(None),
+        value => parse(path, value).map(Some),
+    }
+}
+
+/// A flat keyed file of counters, like cpu.stat.
+fn read_counters(path: &Path) -> Result<BTreeMap<String, u64>, ContainerErr> {
+    read_flat_keyed_file(path)?
+        .into_iter()
+        .map(|(key, value)| Ok((key, parse(path, &value)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_read_stats() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let cgroup = PathBuf::from(format!("/tmp/stats_{}", time));
+        fs::create_dir(&cgroup).unwrap();
+        for (name, data) in [
+            (
+                "cpu.stat",
+                "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n",
+            ),
+            ("memory.current", "4096\n"),
+            ("memory.max", "max\n"),
+            ("memory.stat", "anon 1024\nfile 2048\n"),
+            (
+                "io.stat",
+                "259:0 rbytes=1572864 wbytes=512 rios=3 wios=1 dbytes=0 dios=0\n\
+                 8:0 rbytes=0 wbytes=0 rios=0 wios=0 dbytes=0 dios=0\n",
+            ),
+            ("hugetlb.2MB.current", "4194304\n"),
+            ("hugetlb.2MB.max", "8388608\n"),
+            ("hugetlb.2MB.events", "max 3\n"),
+            ("hugetlb.2MB.rsvd.current", "0\n"),
+            ("hugetlb.1GB.current", "0\n"),
+            ("hugetlb.1GB.max", "max\n"),
+            ("hugetlb.1GB.events", "max 0\n"),
+            (
+                "memory.pressure",
+                "some avg10=20.00 avg60=5.00 avg300=1.00 total=100\n\
+                 full avg10=10.00 avg60=2.00 avg300=0.50 total=50\n",
+            ),
+        ] {
+            fs::write(cgroup.join(name), data).unwrap();
+        }
+
+        let stats = read_stats(&cgroup).unwrap();
+        let cpu = stats.cpu.unwrap();
+        assert_eq!(1500, cpu.usage_usec);
+        assert_eq!(0, cpu.nr_throttled);
+        let memory = stats.memory.unwrap();
+        assert_eq!(4096, memory.usage);
+        assert_eq!(None, memory.limit);
+        assert_eq!(None, memory.swap_usage);
+        assert_eq!(Some(&2048), memory.stat.get("file"));
+        // No pids controller.
+        assert!(stats.pids.is_none());
+        assert_eq!(vec!["1GB", "2MB"], stats.hugetlb.keys().collect::<Vec<_>>());
+        assert_eq!(
+            HugetlbStats {
+                current: 4194304,
+                max: Some(8388608),
+                events: 3,
+            },
+            stats.hugetlb["2MB"]
+        );
+        assert_eq!(None, stats.hugetlb["1GB"].max);
+        let devices: Vec<_> = stats.io.iter().map(|d| (d.major, d.minor)).collect();
+        assert_eq!(vec![(8, 0), (259, 0)], devices);
+        assert_eq!(1572864, stats.io[1].rbytes);
+        assert_eq!("1.5 MiB", stats.io[1].human.rbytes);
+        assert_eq!("512 B", stats.io[1].human.wbytes);
+        let pressure: Vec<_> = stats.pressure.iter().collect();
+        assert_eq!(1, pressure.len());
+        assert_eq!("memory", pressure[0].0);
+        assert_eq!(20.0, pressure[0].1.some.avg10);
+
+        assert!(stats.network.is_empty());
+
+        assert_eq!(None, read_oom_kills(&cgroup).unwrap());
+        fs::write(
+            cgroup.join("memory.events"),
+            "low 0\nhigh 0\nmax 4\noom 2\noom_kill 1\n",
+        )
+        .unwrap();
+        assert_eq!(Some(1), read_oom_kills(&cgroup).unwrap());
+
+        fs::write(cgroup.join("pids.current"), "many\n").unwrap();
+        assert!(read_stats(&cgroup).is_err());
+
+        fs::remove_dir_all(&cgroup).unwrap();
+    }
+
+    #[test]
+    fn test_parse_net_dev() {
+        let data = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:    1000      10    0    0    0     0          0         0     1000      10    0    0    0     0       0          0
+  eth0: 5242880    4096    1    2    0     0          0         3  1048576    1024    4    5    0     0       0          0
+";
+        let interfaces = parse_net_dev(data).unwrap();
+        assert_eq!(vec!["eth0"], interfaces.keys().collect::<Vec<_>>());
+        assert_eq!(
+            InterfaceStats {
+                rx_bytes: 5242880,
+                rx_packets: 4096,
+                rx_errors: 1,
+                rx_dropped: 2,
+                tx_bytes: 1048576,
+                tx_packets: 1024,
+                tx_errors: 4,
+                tx_dropped: 5,
+            },
+            interfaces["eth0"]
+        );
+        assert!(parse_net_dev("header\nheader\neth0: 1 2 3\n").is_none());
+
+        // This process' own interfaces.
+        assert!(read_network(std::process::id()).is_ok());
+    }
+
+    #[test]
+    fn test_device_name() {
+        let partitions = "\
+major minor  #blocks  name
+
+ 259        0  500107608 nvme0n1
+ 259        1     524288 nvme0n1p1
+";
+        // Devices which don't exist here are only in the partitions given.
+        assert_eq!(
+            Some(String::from("nvme0n1p1")),
+            device_name(
+                259,
+                4096,
+                &partitions.replace("259        1", "259     4096")
+            )
+        );
+        assert_eq!(None, device_name(4095, 4095, partitions));
+
+        assert_eq!("0 B", human_bytes(0));
+        assert_eq!("1023 B", human_bytes(1023));
+        assert_eq!("1.0 KiB", human_bytes(1024));
+        assert_eq!("2.0 GiB", human_bytes(2 << 30));
+    }
+}
@@ -113,50 +113,42 @@ pub fn read_nested_keyed_file<P: AsRef<Path>>(
     Ok(data)
 }
 
-/// Writes to a cgroup interface file with a nested keyed format.
+/// Writes to a cgroup interface file with a nested keyed format. The kernel takes a
+/// key per write, so each line is written on its own.
 pub fn write_nested_keyed_file<P: AsRef<Path>>(
     path: P,
     data: HashMap<String, HashMap<String, String>>,
 ) -> Result<(), ContainerErr> {
-    let mut s = String::new();
-    for (k, v) in data.iter() {
-        s += k;
-        s += " ";
-        for (sk, sv) in v.iter() {
-            let pair = format!("{}={} ", &sk, &sv);
-            s += &pair;
-        }
-        s.remove(s.len() - 1);
-    }
-
     let mut f = OpenOptions::new()
         .write(true)
         .truncate(true)
         .open(path)
         .map_err(ContainerErr::IO)?;
-    f.write_all(s.as_bytes()).map_err(ContainerErr::IO)?;
+    for (k, v) in data.iter() {
+        let mut line = k.clone();
+        for (sk, sv) in v.iter() {
+            line += &format!(" {}={}", &sk, &sv);
+        }
+        line += "\n";
+        f.write_all(line.as_bytes()).map_err(ContainerErr::IO)?;
+    }
     Ok(())
 }
 
-/// Write to a cgroup interface file with a flat keyed format.
+/// Write to a cgroup interface file with a flat keyed format, a line per write.
 pub fn write_flat_keyed_file<P: AsRef<Path>>(
     path: P,
     data: HashMap<String, String>,
 ) -> Result<(), ContainerErr> {
-    let mut s = String::new();
-    for (k, v) in data.iter() {
-        s += k;
-        s += " ";
-        s += v;
-        s += "\n";
-    }
-
     let mut f = OpenOptions::new()
         .write(true)
         .truncate(true)
         .open(path)
         .map_err(ContainerErr::IO)?;
-    f.write_all(s.as_bytes()).map_err(ContainerErr::IO)?;
+    for (k, v) in data.iter() {
+        f.write_all(format!("{} {}\n", k, v).as_bytes())
+            .map_err(ContainerErr::IO)?;
+    }
     Ok(())
 }
 
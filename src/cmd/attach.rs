@@ -0,0 +1,238 @@
+use super::wait::wait_exit;
+use crate::attach::{read_frame, write_frame, FrameKind};
+use crate::container::validate_id;
+use crate::ctx::{setup_ctx, Ctx};
+use crate::error::ContainerErr;
+use crate::signal;
+use crate::state::Status;
+use crate::store::StateStore;
+use libc::c_int;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// ctrl-p, ctrl-q like docker.
+const DEFAULT_DETACH_KEYS: [u8; 2] = [0x10, 0x11];
+/// Signals which are sent on to the container's process rather than handled by us.
+const FORWARDED_SIGNALS: [c_int; 6] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+/// Options for the attach command
+#[derive(Debug)]
+pub struct AttachOpts {
+    /// Input which detaches from the container instead of being sent to it, none if
+    /// empty.
+    pub detach_keys: Vec<u8>,
+    /// Only print the container's output, leave its stdin alone.
+    pub no_stdin: bool,
+}
+
+impl Default for AttachOpts {
+    fn default() -> Self {
+        Self {
+            detach_keys: DEFAULT_DETACH_KEYS.to_vec(),
+            no_stdin: false,
+        }
+    }
+}
+
+/// Parses a detach sequence like docker's `--detach-keys`: comma separated keys, each a
+/// single character or `ctrl-<key>`.
+pub fn parse_detach_keys(keys: &str) -> Result<Vec<u8>, ContainerErr> {
+    let invalid = || ContainerErr::invalid_args(&format!("Invalid detach keys: {}", keys));
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+    keys.split(',')
+        .map(|key| match key.strip_prefix("ctrl-") {
+            Some(key) => match key.as_bytes() {
+                [b @ (b'a'..=b'z' | b'@' | b'[' | b'\\' | b']' | b'^' | b'_')] => Ok(b & 0x1f),
+                _ => Err(invalid()),
+            },
+            None => match key.as_bytes() {
+                [b] if b.is_ascii() => Ok(*b),
+                _ => Err(invalid()),
+            },
+        })
+        .collect()
+}
+
+/// Attaches to the container's stdio through its monitor: prints its output, sends it our
+/// stdin, closing the container's once ours is, and forwards the signals we get to its process. Returns the process' exit code
+/// once it exited, or 0 when detached with the detach keys.
+pub fn attach(container_id: String, opts: AttachOpts) -> Result<i32, ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    let state = ctx.store().load(&container_id)?;
+    if !matches!(state.status(), Status::Created | Status::Running) {
+        return Err(ContainerErr::State(format!(
+            "Container: {} is not running, status is {:?}",
+            &container_id,
+            state.status()
+        )));
+    }
+
+    let socket = ctx.container_dirs(&container_id).attach_socket();
+    let mut stream = UnixStream::connect(socket).map_err(ContainerErr::IO)?;
+    let writer = Arc::new(Mutex::new(stream.try_clone().map_err(ContainerErr::IO)?));
+    let detached = Arc::new(AtomicBool::new(false));
+
+    let mut signals = signal::notify(&FORWARDED_SIGNALS)?;
+    let signal_writer = writer.clone();
+    thread::spawn(move || {
+        let mut buf = [0u8; 16];
+        while let Ok(n @ 1..) = signals.read(&mut buf) {
+            for sig in &buf[..n] {
+                let payload = (*sig as u32).to_be_bytes();
+                let mut writer = signal_writer.lock().unwrap();
+                if write_frame(&mut *writer, FrameKind::Signal, &payload).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    if !opts.no_stdin {
+        let keys = DetachKeys::new(opts.detach_keys);
+        let stdin_detached = detached.clone();
+        // Reading our stdin blocks, the thread goes away with the process.
+        thread::spawn(move || forward_stdin(&writer, keys, &stdin_detached));
+    }
+
+    let mut stdout = io::stdout();
+    let mut stderr = io::stderr();
+    while let Some((kind, data)) = read_frame(&mut stream).map_err(ContainerErr::IO)? {
+        let output: &mut dyn Write = match kind {
+            FrameKind::Stdout => &mut stdout,
+            FrameKind::Stderr => &mut stderr,
+            _ => continue,
+        };
+        output.write_all(&data).map_err(ContainerErr::IO)?;
+        output.flush().map_err(ContainerErr::IO)?;
+    }
+    if detached.load(Ordering::Relaxed) {
+        return Ok(0);
+    }
+    // The monitor only goes away once the container has exited for good.
+    wait_exit(&ctx, &container_id, || Ok(()))
+}
+
+/// Sends our stdin to the container, for `run`. Our end of the connection only writes, the
+/// output is read from the container's log.
+pub(super) fn send_stdin(ctx: &Ctx, container_id: &str) -> Result<(), ContainerErr> {
+    let socket = ctx.container_dirs(container_id).attach_socket();
+    let stream = UnixStream::connect(socket).map_err(ContainerErr::IO)?;
+    // Rather than the monitor blocking on output nobody reads.
+    stream.shutdown(Shutdown::Read).map_err(ContainerErr::IO)?;
+    thread::spawn(move || {
+        let no_keys = DetachKeys::new(Vec::new());
+        forward_stdin(&Mutex::new(stream), no_keys, &AtomicBool::new(false))
+    });
+    Ok(())
+}
+
+/// Sends our stdin to the container until it's closed, which closes the container's, or
+/// the detach keys are typed, which disconnects us.
+fn forward_stdin(
+    stream: &Mutex<UnixStream>,
+    mut keys: DetachKeys,
+    detached: &AtomicBool,
+) -> io::Result<()> {
+    let mut stdin = io::stdin();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stdin.read(&mut buf)?;
+        if n == 0 {
+            return write_frame(&mut *stream.lock().unwrap(), FrameKind::Stdin, &[]);
+        }
+        let (input, detach) = keys.scan(&buf[..n]);
+        let mut stream = stream.lock().unwrap();
+        if !input.is_empty() {
+            write_frame(&mut *stream, FrameKind::Stdin, &input)?;
+        }
+        if detach {
+            detached.store(true, Ordering::Relaxed);
+            return stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Watches input for the detach sequence.
+struct DetachKeys {
+    keys: Vec<u8>,
+    /// How much of the sequence the input ended with so far.
+    matched: usize,
+}
+
+impl DetachKeys {
+    fn new(keys: Vec<u8>) -> Self {
+        Self { keys, matched: 0 }
+    }
+
+    /// Returns the input to send on, and whether it completed the sequence. Input which
+    /// may be the start of the sequence is held back until it turns out not to be.
+    fn scan(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        if self.keys.is_empty() {
+            return (input.to_vec(), false);
+        }
+        let mut out = Vec::new();
+        for b in input {
+            if *b != self.keys[self.matched] {
+                out.extend_from_slice(&self.keys[..self.matched]);
+                self.matched = 0;
+            }
+            if *b == self.keys[self.matched] {
+                self.matched += 1;
+                if self.matched == self.keys.len() {
+                    return (out, true);
+                }
+            } else {
+                out.push(*b);
+            }
+        }
+        (out, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_detach_keys() {
+        assert_eq!(
+            vec![0x10, 0x11],
+            parse_detach_keys("ctrl-p,ctrl-q").unwrap()
+        );
+        assert_eq!(
+            vec![0x00, b'x', 0x1b],
+            parse_detach_keys("ctrl-@,x,ctrl-[").unwrap()
+        );
+        assert_eq!(Vec::<u8>::new(), parse_detach_keys("").unwrap());
+        for invalid in ["ctrl-", "ctrl-1", "xy", "ctrl-p,", "ctrl-P"] {
+            assert!(parse_detach_keys(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_detach_keys() {
+        let mut keys = DetachKeys::new(vec![0x10, 0x11]);
+        assert_eq!((b"ls\n".to_vec(), false), keys.scan(b"ls\n"));
+        // The start of the sequence is held back until the next key.
+        assert_eq!((b"a".to_vec(), false), keys.scan(b"a\x10"));
+        assert_eq!((b"\x10b".to_vec(), false), keys.scan(b"b"));
+        assert_eq!((b"\x10".to_vec(), false), keys.scan(b"\x10\x10"));
+        assert_eq!((Vec::new(), true), keys.scan(b"\x11rest"));
+
+        let mut none = DetachKeys::new(Vec::new());
+        assert_eq!((b"\x10\x11".to_vec(), false), none.scan(b"\x10\x11"));
+    }
+}
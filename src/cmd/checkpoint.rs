@@ -0,0 +1,61 @@
+//! Checkpoint cmd, dumps a running container with CRIU.
+
+use crate::container::validate_id;
+use crate::criu::{self, Dump};
+use crate::ctx::setup_ctx;
+use crate::error::ContainerErr;
+use crate::state::Status;
+use crate::store::StateStore;
+use std::path::PathBuf;
+
+/// Options for the checkpoint command
+#[derive(Debug, Default)]
+pub struct CheckpointOpts {
+    /// Directory the images are written to
+    pub image_path: PathBuf,
+    /// Directory for CRIU's log and scratch files, the image dir if unset
+    pub work_path: Option<PathBuf>,
+    /// Images of an earlier pre-dump to build on, relative to `image_path`
+    pub parent_path: Option<PathBuf>,
+    /// Only dump memory and keep the container running, for a later dump to build on
+    pub pre_dump: bool,
+    /// Keep the container running after dumping it
+    pub leave_running: bool,
+    /// Dump established TCP connections
+    pub tcp_established: bool,
+    /// Namespace types whose contents aren't dumped, e.g. `network`
+    pub empty_namespaces: Vec<String>,
+}
+
+/// Checkpoints the running container `container_id` into `opts.image_path`. Unless told
+/// to leave it running, or pre-dumping, the container's processes are gone afterwards.
+pub fn checkpoint(container_id: String, opts: CheckpointOpts) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    let store = ctx.store();
+    let _lock = store.lock(&container_id)?;
+    let state = store.load(&container_id)?;
+    if *state.status() != Status::Running {
+        return Err(ContainerErr::State(format!(
+            "Container: {} cannot be checkpointed, status is {:?}",
+            &container_id,
+            state.status()
+        )));
+    }
+    let config = ctx.container_dirs(&container_id).load_config()?;
+    let root = criu::root_dir(&config, state.bundle())?;
+
+    let dump = Dump {
+        pid: state.pid(),
+        root: &root,
+        images_dir: &opts.image_path,
+        work_dir: opts.work_path.as_deref(),
+        parent: opts.parent_path.as_deref(),
+        pre_dump: opts.pre_dump,
+        leave_running: opts.leave_running,
+        tcp_established: opts.tcp_established,
+        shell_job: config.process().is_some_and(|process| process.terminal),
+        empty_namespaces: &opts.empty_namespaces,
+    };
+    criu::dump(&dump, &config)
+}
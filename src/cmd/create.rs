@@ -1,100 +1,581 @@
 //! Create cmd
 
-use crate::cgroup::{create_cgroup, detect_cgroup_version};
-use crate::config::Config;
-use crate::container::Container;
-use crate::ctx::{setup_ctx, Ctx};
+use crate::cgroup::{
+    create_cgroup, detect_cgroup_version, kill_cgroup, pids_limit_reached, remove_cgroup,
+    state_cgroup_path, swap_unenforceable,
+};
+use crate::config::{Config, ProcessOverrides, Vars};
+use crate::container::{validate_id, Container};
+use crate::ctx::{setup_ctx, trace_output, Ctx, ResourcePolicy, RuntimeConfig, TraceOutput};
+use crate::dirs::ContainerDirs;
 use crate::error::ContainerErr;
+use crate::etc_files;
+use crate::events::{self, Event};
+use crate::extensions::{Extensions, Registry};
+use crate::features;
+use crate::gpu::{apply_gpus, Gpus};
+use crate::hooks::{run_hooks, HookPhase};
 use crate::init::{init, InitArgs};
-use crate::namespaces::{clone_namespace_flags, namespaces_to_join};
-use crate::process::clone3;
-use crate::state::Status;
-use libc::{__errno_location, c_int, mkfifo, read, EINTR};
-use log::debug;
-use std::ffi::{c_void, CString};
-use std::fs::OpenOptions;
+use crate::journal::Journal;
+use crate::lock::ContainerLock;
+use crate::loopdev;
+use crate::monitor::{self, ContainerStdio, Progress};
+use crate::mount::{check_mounts, resolve_bind_sources, unmount_targets};
+use crate::namespaces::{clone_namespace_flags, namespaces_to_join, resolve_container_paths};
+use crate::pod::Pod;
+use crate::portforward::PortForwards;
+use crate::process::{add_default_env, clone_into_cgroup, wait_exit_code};
+use crate::restart::RestartPolicy;
+use crate::rootfs::{host_mounts, RootfsLayout};
+use crate::signal::stop_signal;
+use crate::start_signal::StartListener;
+use crate::state::{Pid, Status};
+use crate::store::StateStore;
+use crate::sync::{read_sync, write_sync, SyncMsg};
+use crate::trace;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs::{self, OpenOptions};
 use std::io::{ErrorKind, Read};
 use std::os::fd::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::pipe::{PipeReader, PipeWriter};
 use std::process::exit;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug_span;
+
+/// State annotation listing the resource settings which were skipped, see
+/// `ResourcePolicy::Permissive`.
+pub const RESOURCE_WARNINGS_ANNOTATION: &str = "org.beersonthewall.runtime.resource-warnings";
+
+/// How long to wait for a failed container's cgroup to empty before giving up on it.
+const ROLLBACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Options for the create command
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CreateOpts {
+    /// Ports to publish, `host:container[/protocol]`
+    pub publish: Vec<String>,
+    /// Reject unknown fields and out of range values in config.json
+    pub strict: bool,
+    /// Give up on creating the container after this long, waits forever if unset
+    pub timeout: Option<Duration>,
+    /// Fill in masked and readonly paths, capabilities and a seccomp profile where the
+    /// bundle doesn't configure them
+    pub secure_defaults: bool,
+    /// Number of descriptors after stderr the container's process inherits
+    pub preserve_fds: u32,
+    /// Where the container's output goes, overrides the log-driver annotation
+    pub log_driver: Option<LogDriver>,
+    /// Pod to create the container in
+    pub pod: Option<String>,
+    /// When the monitor restarts the container, overrides the restart annotation
+    pub restart: Option<RestartPolicy>,
+    /// Keep the container's stdin open for `attach`, instead of /dev/null
+    pub interactive: bool,
+    /// Labels to find the container by with `list --filter`, kept in the state's
+    /// annotations
+    pub labels: Vec<(String, String)>,
+    /// Record how long each phase took in the state dir and print them once created
+    pub time_report: bool,
+    /// Changes to config.json's process env, args and cwd
+    pub process: ProcessOverrides,
+    /// Whether resource settings the host can't apply fail the create, overrides the
+    /// runtime's config file
+    pub resource_policy: Option<ResourcePolicy>,
+    /// GPUs to bind into the container
+    pub gpus: Option<Gpus>,
+}
+
+/// Where the monitor sends the container's stdout and stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogDriver {
+    /// container.log in the state dir.
+    #[default]
+    File,
+    /// The systemd journal, tagged with the container id.
+    Journald,
+}
+
+impl FromStr for LogDriver {
+    type Err = ContainerErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(LogDriver::File),
+            "journald" => Ok(LogDriver::Journald),
+            _ => Err(ContainerErr::invalid_args(&format!(
+                "Unknown log driver: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Env var carrying `create_async`'s request to the helper process, see
+/// `run_create_helper`.
+#[cfg(feature = "tokio")]
+const CREATE_HELPER_ENV: &str = "CONTAINER_RUNTIME_CREATE_REQUEST";
+
+/// The create `create_async` asks its helper for, with the global options in effect and
+/// the descriptor the helper reports the outcome on.
+#[cfg(feature = "tokio")]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateRequest {
+    container_id: String,
+    bundle_path: String,
+    opts: CreateOpts,
+    global_opts: Option<crate::ctx::GlobalOpts>,
+    result_fd: std::os::fd::RawFd,
+}
+
+/// The `CreateRequest` for the helper, as JSON.
+#[cfg(feature = "tokio")]
+fn create_request(
+    container_id: &str,
+    bundle_path: &str,
+    opts: &CreateOpts,
+    result_fd: std::os::fd::RawFd,
+) -> serde_json::Value {
+    json!({
+        "containerId": container_id,
+        "bundlePath": bundle_path,
+        "opts": opts,
+        "globalOpts": crate::ctx::global_opts(),
+        "resultFd": result_fd,
+    })
+}
+
+/// Like `create`, but without blocking the calling thread. Forking the monitor from a
+/// thread of the caller's runtime could leave it waiting on a lock another thread held,
+/// so the create runs in a helper process re-executing /proc/self/exe, which is awaited.
+///
+/// The binary has to call `run_create_helper` first thing in main, before it starts
+/// threads, and the binary must still be there to be executed again. The helper gets
+/// the caller's environment and descriptors without FD_CLOEXEC, the ones `preserve_fds`
+/// passes on included. Dropping the future leaves the helper to finish the create.
+#[cfg(feature = "tokio")]
+pub async fn create_async(
+    container_id: String,
+    bundle_path: String,
+    opts: CreateOpts,
+) -> Result<(), ContainerErr> {
+    use std::os::unix::process::CommandExt;
+    use tokio::io::unix::AsyncFd;
+
+    validate_id(&container_id)?;
+    let (mut reader, writer) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+    let result_fd = writer.as_raw_fd();
+    let request = create_request(&container_id, &bundle_path, &opts, result_fd);
+    let mut command = std::process::Command::new("/proc/self/exe");
+    command.env(CREATE_HELPER_ENV, request.to_string());
+    // Only in the helper, not in whatever else the caller spawns meanwhile.
+    unsafe { command.pre_exec(move || crate::syscalls::clear_cloexec(result_fd)) };
+    let mut helper = command.spawn().map_err(ContainerErr::IO)?;
+    drop(writer);
+    debug!("create helper pid: {}", helper.id());
+
+    let pidfd = crate::process::pidfd_open(helper.id()).map_err(ContainerErr::IO)?;
+    let pidfd = AsyncFd::new(pidfd).map_err(ContainerErr::IO)?;
+    let _exited = pidfd.readable().await.map_err(ContainerErr::IO)?;
+    let status = helper.wait().map_err(ContainerErr::IO)?;
+
+    // The monitor inherits the writer, there's no end of file before the container exits.
+    crate::syscalls::set_nonblocking(reader.as_raw_fd()).map_err(ContainerErr::IO)?;
+    let mut report = Vec::new();
+    match reader.read_to_end(&mut report) {
+        Err(e) if e.kind() != ErrorKind::WouldBlock => return Err(ContainerErr::IO(e)),
+        _ => {}
+    }
+    let report = String::from_utf8_lossy(&report);
+    if status.success() && report == "ok" {
+        return Ok(());
+    }
+    match report.strip_prefix("error ") {
+        Some(err) => Err(ContainerErr::Monitor(err.to_string())),
+        None => Err(ContainerErr::Monitor(format!(
+            "create helper exited with {}",
+            status
+        ))),
+    }
+}
+
+/// Does the create `create_async` asked for and exits, if this process is its helper.
+/// Returns right away otherwise. Binaries using `create_async` call it first thing in
+/// main.
+#[cfg(feature = "tokio")]
+pub fn run_create_helper() {
+    use std::io::Write;
+    use std::os::fd::FromRawFd;
+
+    let Some(request) = std::env::var_os(CREATE_HELPER_ENV) else {
+        return;
+    };
+    // Neither hooks nor processes the runtime runs need it.
+    std::env::remove_var(CREATE_HELPER_ENV);
+    let request: CreateRequest = match serde_json::from_str(&request.to_string_lossy()) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("invalid create request: {}", e);
+            exit(1);
+        }
+    };
+    let _ = crate::syscalls::set_cloexec(request.result_fd);
+    let mut result = unsafe { fs::File::from_raw_fd(request.result_fd) };
+    if let Some(global_opts) = request.global_opts {
+        crate::ctx::set_global_opts(global_opts);
+    }
+    let (report, code) = match create(request.container_id, request.bundle_path, request.opts) {
+        Ok(()) => (String::from("ok"), 0),
+        Err(e) => (format!("error {:?}", e), 1),
+    };
+    let _ = result.write_all(report.as_bytes());
+    exit(code);
+}
 
 /// Creates a new container from the OCI bundle located at bundle_path
-pub fn create(container_id: String, bundle_path: String) -> Result<(), ContainerErr> {
+pub fn create(
+    container_id: String,
+    bundle_path: String,
+    mut opts: CreateOpts,
+) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
     let bundle_path = PathBuf::from(bundle_path);
-    let config = Config::load(&bundle_path)?;
+    let started = Instant::now();
+    if opts.time_report {
+        trace::record();
+    }
+    let span = debug_span!("load-config", container_id = container_id).entered();
+    let mut config = Config::load_with(&bundle_path, opts.strict, &opts.process)?;
+    if opts.secure_defaults {
+        config.apply_secure_defaults();
+    }
+    drop(span);
     let ctx = setup_ctx()?;
-
-    let mut c = Container::new(container_id.clone(), bundle_path.clone(), config);
-    if c.exists(&ctx) {
-        return Err(ContainerErr::State(format!(
-            "Container: {} already exists.",
-            &container_id
+    // Before anything looks at the mounts, hooks or annotations.
+    let bundle = std::path::absolute(&bundle_path).map_err(ContainerErr::IO)?;
+    let vars = Vars::new(&bundle, &ctx.state_dir(&container_id), &container_id);
+    config.expand_vars(&vars)?;
+    if let Some(mounts) = config.mounts_mut() {
+        resolve_bind_sources(mounts, &bundle);
+    }
+    let runtime_config = ctx.runtime_config()?;
+    apply_hardening_profiles(&mut config, &runtime_config)?;
+    if let Some(gpus) = &opts.gpus {
+        apply_gpus(
+            &mut config,
+            gpus,
+            Path::new("/dev"),
+            &runtime_config.gpu_library_dirs,
+        )?;
+    }
+    let skipped = config.disable_controllers(&ctx.disabled_controllers()?)?;
+    if opts.strict && !skipped.is_empty() {
+        return Err(ContainerErr::Cgroup(format!(
+            "linux.resources has settings for {}, disabled on this host",
+            skipped.join(", ")
         )));
     }
+    let hostname = config.hostname().unwrap_or(&container_id).to_string();
+    if let Some(process) = config.process_mut() {
+        add_default_env(process, &hostname);
+    }
+    let pod = opts
+        .pod
+        .as_deref()
+        .map(|name| Pod::load(&ctx, name))
+        .transpose()?;
+    if let Some(pod) = &pod {
+        if !pod.is_running(&ctx) {
+            return Err(ContainerErr::Pod(format!(
+                "pod {} isn't running",
+                pod.name()
+            )));
+        }
+        pod.share_namespaces(&mut config)?;
+    }
+    if let Some(namespaces) = config.linux_namespaces_mut() {
+        resolve_container_paths(&ctx, namespaces)?;
+    }
+    // A missing kernel feature is better reported now than as a failed syscall later.
+    features::check(&config, ctx.cgroups_root())?;
+    // The container process could only fail at mounting them.
+    check_mounts(&config)?;
 
-    c.write_state(&ctx)?;
+    // Fail fast if the entrypoint is missing, once we're in the container process the
+    // only thing we can report is a failed exec. Bundles without a process can be
+    // created but not started.
+    if let Some(process) = config.process() {
+        RootfsLayout::detect(&config, &bundle_path)?.find_executable(process)?;
+    }
 
-    // Create container ready pipe. This is used for the container process to notify us
-    // when it's ready to execute.
-    let (rdy_pipe_reader, rdy_pipe_writer) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+    let ext = parse_extensions(&config, opts.strict)?;
+    let ports = PortForwards::from_requested(&opts.publish, &ext.publish)?;
+    opts.log_driver = opts.log_driver.or(ext.log_driver);
+    opts.restart = opts.restart.or(ext.restart);
 
-    // Create FIFO used by container process to block until we send a signal to exec
-    // the entrypoint process.
-    let fifo_path = ctx.state_dir.join(&container_id).join("exec_fifo");
-    fifo(&fifo_path)?;
-
-    init_container_proc(
-        fifo_path,
-        rdy_pipe_reader,
-        rdy_pipe_writer,
-        c.clone(),
-        ctx.clone(),
-        bundle_path,
-    )?;
+    let mut c = Container::new(container_id.clone(), bundle_path.clone(), Arc::new(config));
+    let signal = stop_signal(c.config())?;
+    c.state_mut().set_stop_signal(signal);
+    for (key, value) in &opts.labels {
+        c.state_mut().set_annotation(key.clone(), value.clone());
+    }
+    if let Some(pod) = &pod {
+        let cgroup_path = pod.container_cgroup_path(c.config(), &container_id);
+        c.state_mut().set_cgroup(cgroup_path, ctx.cgroup_manager());
+        c.state_mut().set_pod(pod.name().to_string());
+    }
+    // Held until the container is created, anyone else operating on it waits for us.
+    let store = ctx.store();
+    let lock = store.lock(&container_id)?;
+    c.reserve(&store)?;
+    if opts.time_report {
+        trace::record_to(&ctx.container_dirs(&container_id))?;
+    }
 
-    c.update_status(Status::Created);
-    c.write_state(&ctx)?;
+    // Everything set up from here on is undone if creating the container fails, so that
+    // a failed create can be retried with the same id.
+    let mut rollback = Rollback {
+        dirs: Some(ctx.container_dirs(&container_id)),
+        cgroup: None,
+        loop_device: None,
+        mounts: Vec::new(),
+    };
+    let result = create_container(&ctx, c, bundle_path, &ports, &opts, &lock, &mut rollback);
+    if let Err(e) = &result {
+        debug!("create failed, rolling back: {:?}", e);
+        rollback.run();
+    }
+    result?;
+    let dirs = ctx.container_dirs(&container_id);
+    for controller in skipped {
+        let message = format!(
+            "{} settings are not applied, the controller is disabled",
+            controller
+        );
+        let event = Event::new("warning", &container_id, json!({ "message": message }));
+        events::emit(&dirs, &event)?;
+    }
 
+    if opts.time_report {
+        let timings = trace::load_timings(&ctx.container_dirs(&container_id))?;
+        eprint!("{}", trace::report(&timings, started.elapsed()));
+    }
     Ok(())
 }
 
-/// Creates a FIFO
-fn fifo<P: AsRef<Path>>(path: P) -> Result<(), ContainerErr> {
-    debug!("creating fifo");
-    let path = if let Some(path) = path.as_ref().to_str() {
-        path
-    } else {
-        debug!("fifo path: {:?}", path.as_ref());
-        return Err(ContainerErr::Fifo(String::from(
-            "Fifo path not valid unicode",
+/// Parses the runtime's annotations. Unknown ones under its prefix are likely typos, with
+/// --strict they're rejected like unknown config fields.
+fn parse_extensions(config: &Config, strict: bool) -> Result<Extensions, ContainerErr> {
+    let registry = Registry::default();
+    let unknown = registry.unknown(config);
+    if strict && !unknown.is_empty() {
+        return Err(ContainerErr::InvalidConfig(unknown));
+    }
+    for violation in unknown {
+        warn!("{}", violation);
+    }
+    registry.parse(config)
+}
+
+/// Merges the host's hardening profiles into the config, but for those the bundle opts out
+/// of by name. Naming a profile which doesn't exist is an error, it's likely a typo.
+fn apply_hardening_profiles(
+    config: &mut Config,
+    runtime_config: &RuntimeConfig,
+) -> Result<(), ContainerErr> {
+    let profiles = &runtime_config.hardening_profiles;
+    let opt_out = Extensions::parse(config)?.hardening_opt_out;
+    if let Some(name) = opt_out.iter().find(|name| !profiles.contains_key(*name)) {
+        return Err(ContainerErr::Options(format!(
+            "no hardening profile {} to opt out of",
+            name
         )));
-    };
+    }
+    for (name, profile) in profiles {
+        if opt_out.contains(name) {
+            info!(
+                "not applying hardening profile {}, the bundle opts out",
+                name
+            );
+            continue;
+        }
+        config.apply_hardening_profile(name, profile)?;
+    }
+    Ok(())
+}
 
-    debug!("path: {}", path);
+/// Artifacts of a container which is being created, or restored.
+pub(super) struct Rollback {
+    pub(super) dirs: Option<ContainerDirs>,
+    pub(super) cgroup: Option<PathBuf>,
+    pub(super) loop_device: Option<PathBuf>,
+    /// Mounts the container's init may have made outside its own mount namespace.
+    pub(super) mounts: Vec<PathBuf>,
+}
 
-    let path =
-        CString::new(path).map_err(|_| ContainerErr::Fifo(String::from("Invalid FIFO path")))?;
-    let err = unsafe { mkfifo(path.as_c_str().as_ptr(), 0o622) };
-    if err < 0 {
-        debug!("{:?}", err);
-        unsafe { debug!("errno {:?}", *__errno_location()) };
-        return Err(ContainerErr::Fifo(String::from("Failed to create fifo.")));
+impl Rollback {
+    /// Kills the container's processes and removes everything created for it.
+    pub(super) fn run(self) {
+        if let Some(cgroup) = &self.cgroup {
+            // The container process is in here, if it got as far as being cloned.
+            if let Err(e) = kill_cgroup(cgroup) {
+                warn!("failed to kill processes in {:?}: {:?}", cgroup, e);
+            }
+            if let Err(e) = remove_cgroup(cgroup, ROLLBACK_TIMEOUT) {
+                warn!("failed to remove cgroup {:?}: {:?}", cgroup, e);
+            }
+        }
+        // Before the loop device, an image rootfs may be mounted from it.
+        if let Err(e) = unmount_targets(&self.mounts) {
+            warn!("failed to unmount {:?}: {:?}", self.mounts, e);
+        }
+        if let Some(device) = &self.loop_device {
+            if let Err(e) = loopdev::detach(device) {
+                warn!("failed to detach {:?}: {:?}", device, e);
+            }
+        }
+        // The FIFO is in the state dir.
+        if let Some(dirs) = &self.dirs {
+            if let Err(e) = dirs.remove() {
+                warn!("failed to remove state dir {:?}: {:?}", dirs.dir(), e);
+            }
+        }
     }
+}
 
-    debug!("done creating fifo");
-    Ok(())
+fn create_container(
+    ctx: &Ctx,
+    mut c: Container,
+    bundle_path: PathBuf,
+    ports: &PortForwards,
+    opts: &CreateOpts,
+    lock: &ContainerLock,
+    rollback: &mut Rollback,
+) -> Result<(), ContainerErr> {
+    let container_id = c.state().id().to_string();
+    // Containers in a pod have theirs set already, below the pod's cgroup.
+    let cgroup_path = state_cgroup_path(ctx, c.state(), c.config())?;
+    c.state_mut()
+        .set_cgroup(cgroup_path.clone(), ctx.cgroup_manager());
+    // Image rootfs are mounted from a loop device, which delete detaches again. It's set
+    // up here so it's recorded in the state.
+    if let RootfsLayout::Image { path, .. } = RootfsLayout::detect(c.config(), &bundle_path)? {
+        let device = loopdev::attach(&path)?;
+        rollback.loop_device = Some(device.clone());
+        c.state_mut().set_loop_device(device);
+    }
+    let dirs = ctx.container_dirs(&container_id);
+    let mounts = host_mounts(c.config(), &bundle_path, &dirs)?;
+    if !mounts.is_empty() {
+        debug!(
+            "no mount namespace of its own, recording mounts {:?}",
+            mounts
+        );
+    }
+    rollback.mounts = mounts.clone();
+    c.state_mut().set_host_mounts(mounts);
+    c.write_state(&ctx.store())?;
+    // Keep a copy of the config, later commands shouldn't depend on the bundle.
+    dirs.write_config(c.config())?;
+    // Port forwarding is set up by start, once the container's network is configured.
+    ports.write(&dirs)?;
+    etc_files::write_files(c.config(), &dirs, &container_id)?;
+
+    // Create the cgroup before the container process. We're going to use CLONE_INTO_CGROUP
+    // flag for clone3 to join the group. If we create the process and only then create/join
+    // the cgroup the child is automatically a part of the parent process' cgroup and we'd
+    // need to handle migrating the child process to the new cgroup. Which is annoying :/
+    info!("create {}: creating cgroup", &container_id);
+    let span = debug_span!("cgroup-create", container_id = container_id).entered();
+    detect_cgroup_version(ctx.cgroups_root())?;
+    if fs::metadata(&cgroup_path).is_ok() {
+        return Err(ContainerErr::Cgroup(format!(
+            "{:?} already exists",
+            cgroup_path
+        )));
+    }
+    rollback.cgroup = Some(cgroup_path.clone());
+    let policy = match opts.resource_policy {
+        Some(policy) => policy,
+        None => ctx.runtime_config()?.resource_policy,
+    };
+    let warnings = create_cgroup(&cgroup_path, c.config(), policy)?;
+    drop(span);
+    // Saved with the state once the container process is up.
+    if !warnings.is_empty() {
+        let value =
+            serde_json::to_string(&warnings).map_err(|e| ContainerErr::State(e.to_string()))?;
+        c.state_mut()
+            .set_annotation(RESOURCE_WARNINGS_ANNOTATION.to_string(), value);
+    }
+    // The limit was skipped rather than failing the create, the caller should know.
+    if let Some(swap) = c.config().cgroup_memory().and_then(|memory| memory.swap) {
+        if let Some(reason) = swap_unenforceable(&cgroup_path) {
+            let message = format!("swap limit {} is not enforced, {}", swap, reason);
+            let event = Event::new("warning", &container_id, json!({ "message": message }));
+            events::emit(&dirs, &event)?;
+        }
+    }
+
+    // The rest happens in the monitor, which becomes the parent of the container process.
+    let monitor_ctx = ctx.clone();
+    // Connected up front, a missing journald fails create rather than losing the output.
+    let journal = match opts.log_driver.unwrap_or_default() {
+        LogDriver::File => None,
+        LogDriver::Journald => Some(Journal::connect(&container_id)?),
+    };
+    let preserve_fds = opts.preserve_fds;
+    monitor::spawn(
+        ctx,
+        &container_id,
+        opts.timeout,
+        journal,
+        opts.restart.unwrap_or_default(),
+        opts.interactive,
+        move |stdio, progress| {
+            lock.release_inherited()?;
+            let pid = init_container_proc(
+                stdio,
+                progress,
+                c.clone(),
+                monitor_ctx.clone(),
+                bundle_path.clone(),
+                preserve_fds,
+            )?;
+
+            progress.phase("writing state");
+            c.state_mut().set_pid(pid);
+            c.update_status(Status::Created);
+            c.write_state(&monitor_ctx.store())?;
+            Ok(pid)
+        },
+    )
 }
 
 /// Clones container child process
 fn init_container_proc(
-    fifo_path: PathBuf,
-    rdy_pipe_reader: PipeReader,
-    rdy_pipe_writer: PipeWriter,
+    stdio: ContainerStdio,
+    progress: &mut Progress,
     container: Container,
     ctx: Ctx,
     bundle_path: PathBuf,
-) -> Result<(), ContainerErr> {
+    preserve_fds: u32,
+) -> Result<Pid, ContainerErr> {
+    // Create container ready pipe. This is used for the container process to notify us
+    // when it's ready to execute.
+    let (rdy_pipe_reader, rdy_pipe_writer) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+
+    // Create the start signal used by container process to block until we send a signal
+    // to exec the entrypoint process.
+    let start_listener = StartListener::new(
+        &ctx.container_dirs(container.state().id()),
+        container.config().host_root_ids(),
+    )?;
+
     let mut flags = 0;
     if let Some(ns) = &container.config().linux_namespaces() {
         flags |= clone_namespace_flags(ns);
@@ -106,59 +587,105 @@ fn init_container_proc(
         Vec::new()
     };
 
-    // Create the cgroup in the parent process. We're going to use CLONE_INTO_CGROUP flag
-    // for clone3 to join the group. If we create the process and only then create/join the
-    // cgroup the child is automatically a part of the parent process' cgroup and we'd need
-    // to handle migrating the child process to the new cgroup. Which is annoying :/
+    let cgroup_path = state_cgroup_path(&ctx, container.state(), container.config())?;
 
-    if let Err(e) = detect_cgroup_version(ctx.cgroups_root()) {
-        debug!("detect_cgroup_version {:?}", e);
-        exit(1);
-    }
-    let cgroup_path = ctx.cgroups_root().join(container.state().id());
-    create_cgroup(&cgroup_path, container.config())?;
+    // Create hooks pipe. This is used to tell the container process we're done running
+    // the hooks which run in the runtime namespace.
+    let (hooks_pipe_reader, hooks_pipe_writer) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+    // Where the container process' json spans go once we return, see init.
+    let trace_log = match trace_output() {
+        TraceOutput::Json => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(ctx.container_dirs(container.state().id()).monitor_log())
+                .map_err(ContainerErr::IO)?,
+        ),
+        TraceOutput::Log => None,
+    };
 
     let init_args = InitArgs {
         bundle_path,
-        fifo_path: fifo_path.clone(),
+        start_listener,
         rdy_pipe_write_fd: rdy_pipe_writer.as_raw_fd(),
+        hooks_pipe_read_fd: hooks_pipe_reader.as_raw_fd(),
+        stdio,
         container,
         ctx,
         join_ns,
+        preserve_fds,
+        trace_log,
     };
 
-    debug!("cloning child process");
+    progress.phase("cloning the container process");
+    let span = debug_span!("clone", container_id = init_args.container.state().id()).entered();
     log::logger().flush();
-    let cgroup_file = OpenOptions::new()
-        .read(true)
-        .open(&cgroup_path)
-        .map_err(ContainerErr::IO)?;
-    let pid = clone3(flags, cgroup_file.as_raw_fd())?;
-    debug!("PID: {}", pid);
+    let pid = clone_into_cgroup(flags, &cgroup_path).map_err(|e| {
+        // A bare EAGAIN otherwise.
+        match pids_limit_reached(&cgroup_path, init_args.ctx.cgroups_root()) {
+            Some(full) if full == cgroup_path => {
+                ContainerErr::Cgroup(format!("pids limit of {:?} reached", full))
+            }
+            Some(full) => {
+                ContainerErr::Cgroup(format!("pids limit reached in parent cgroup {:?}", full))
+            }
+            None => e,
+        }
+    })?;
     if pid == 0 {
-        // child process
-        init(init_args)?;
+        // child process, only returns if initializing or the exec failed. Never return
+        // into the monitor's code from here.
+        if let Err(e) = init(init_args) {
+            eprintln!("container init failed: {:?}", e);
+        }
+        log::logger().flush();
+        exit(1);
     } else {
         // parent
+        drop(span);
+        debug!("PID: {}", pid);
+        // Only the child writes to the ready pipe. Without our copy of the write end the
+        // pipe is closed if the child dies, rather than leaving us waiting for it.
+        drop(rdy_pipe_writer);
         // Read child process ready status
-        let mut ret: c_int = 0;
         debug!("waiting for container ready status... {}", pid);
+        let mut state = init_args.container.state().clone();
+        state.set_pid(pid);
+        progress.phase("waiting for the container process to set up mounts");
 
-        unsafe {
-            while read(
-                rdy_pipe_reader.as_raw_fd(),
-                &raw mut ret as *mut c_void,
-                size_of_val(&ret),
-            ) == -1
-                && *libc::__errno_location() == EINTR
-            {}
-        }
-
-        if ret > 0 {
-            return Err(ContainerErr::Init("Error initializing container process"));
+        loop {
+            let msg = read_sync(rdy_pipe_reader.as_raw_fd())
+                .map_err(|e| exited_early(pid).unwrap_or(e))?;
+            match msg {
+                SyncMsg::CreateRuntimeHooks => {
+                    progress.phase("running prestart and createRuntime hooks");
+                    let span = debug_span!("prestart-hooks", container_id = state.id()).entered();
+                    let config = init_args.container.config();
+                    run_hooks(config, HookPhase::Prestart, &state)?;
+                    run_hooks(config, HookPhase::CreateRuntime, &state)?;
+                    drop(span);
+                    write_sync(hooks_pipe_writer.as_raw_fd(), SyncMsg::HooksDone)?;
+                    progress.phase("waiting for the createContainer hooks and pivot_root");
+                }
+                SyncMsg::Ready => break,
+                _ => return Err(ContainerErr::Init("Error initializing container process")),
+            }
         }
     }
-    Ok(())
+    Ok(pid)
+}
+
+/// The error for a container process which closed the ready pipe without saying it's
+/// ready, i.e. died. None if we couldn't find out how it exited.
+fn exited_early(pid: Pid) -> Option<ContainerErr> {
+    let exit_code = wait_exit_code(pid).ok()?;
+    Some(ContainerErr::Child((
+        exit_code,
+        format!(
+            "container process {} exited with {} before it was ready",
+            pid, exit_code
+        ),
+    )))
 }
 
 /// Reads from a pipe and retries interrupted reads until sucessful or encounters
@@ -175,3 +702,102 @@ fn read_pipe_retry_temp_fail<P: AsRef<Path>>(pipe: P) -> Result<Vec<u8>, std::io
 
     Ok(buffer)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_create_request() {
+        let opts = CreateOpts {
+            timeout: Some(Duration::from_millis(1500)),
+            restart: Some(RestartPolicy::OnFailure {
+                max_restarts: Some(3),
+            }),
+            labels: vec![(String::from("app"), String::from("web"))],
+            gpus: Some(Gpus::Indices(vec![0, 2])),
+            log_driver: Some(LogDriver::Journald),
+            ..Default::default()
+        };
+        let request = create_request("foo", "/bundle", &opts, 7);
+        let request: CreateRequest = serde_json::from_value(request).unwrap();
+        assert_eq!("foo", request.container_id);
+        assert_eq!("/bundle", request.bundle_path);
+        assert_eq!(7, request.result_fd);
+        assert_eq!(opts.timeout, request.opts.timeout);
+        assert_eq!(opts.restart, request.opts.restart);
+        assert_eq!(opts.labels, request.opts.labels);
+        assert_eq!(opts.gpus, request.opts.gpus);
+        assert_eq!(opts.log_driver, request.opts.log_driver);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/rollback_state_{}", time)));
+        let cgroup = PathBuf::from(format!("/tmp/rollback_cgroup_{}", time));
+        dirs.create().unwrap();
+        fs::write(dirs.state(), b"{}").unwrap();
+        fs::create_dir(&cgroup).unwrap();
+
+        let rollback = Rollback {
+            dirs: Some(dirs.clone()),
+            cgroup: Some(cgroup.clone()),
+            loop_device: None,
+            mounts: vec![PathBuf::from(format!("/tmp/rollback_unmounted_{}", time))],
+        };
+        rollback.run();
+        assert!(fs::metadata(dirs.dir()).is_err());
+        assert!(fs::metadata(&cgroup).is_err());
+    }
+
+    #[test]
+    fn test_apply_hardening_profiles() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let path = PathBuf::from(format!("/tmp/runtime_config_{}.json", time));
+        assert_eq!(
+            RuntimeConfig::default(),
+            RuntimeConfig::load(&path).unwrap()
+        );
+        fs::write(
+            &path,
+            r#"{"hardeningProfiles": {
+                "kcore": {"maskedPaths": ["/proc/kcore"]},
+                "pids": {"maxPids": 100}
+            }}"#,
+        )
+        .unwrap();
+        let runtime_config = RuntimeConfig::load(&path).unwrap();
+        fs::write(&path, r#"{"hardening": {}}"#).unwrap();
+        assert!(RuntimeConfig::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+
+        let config = |opt_out: &str| -> Config {
+            serde_json::from_value(serde_json::json!({
+                "ociVersion": "1.0.1",
+                "root": {"path": "rootfs", "readonly": false},
+                "annotations": {"org.beersonthewall.runtime.hardening-opt-out": opt_out},
+            }))
+            .unwrap()
+        };
+        let mut all = config("");
+        apply_hardening_profiles(&mut all, &runtime_config).unwrap();
+        assert_eq!(["/proc/kcore"], all.masked_paths());
+        assert_eq!(100, all.pids().unwrap().limit);
+
+        let mut opted_out = config("pids");
+        apply_hardening_profiles(&mut opted_out, &runtime_config).unwrap();
+        assert_eq!(["/proc/kcore"], opted_out.masked_paths());
+        assert!(opted_out.pids().is_none());
+
+        assert!(apply_hardening_profiles(&mut config("pid"), &runtime_config).is_err());
+    }
+}
@@ -1,23 +1,111 @@
-use crate::{ctx::setup_ctx, error::ContainerErr};
-use log::debug;
+use super::stop::{stop_container, StopOpts};
+use crate::cgroup::state_cgroup_path;
+use crate::container::validate_id;
+use crate::ctx::{setup_ctx, Ctx};
+use crate::error::ContainerErr;
+use crate::hooks::{run_hooks, HookPhase};
+use crate::lock::ContainerLock;
+use crate::loopdev;
+use crate::monitor::ExitStatus;
+use crate::mount::unmount_targets;
+use crate::portforward::PortForwards;
+use crate::state::{State, Status};
+use crate::store::StateStore;
+use log::{debug, warn};
 use std::fs;
+use std::path::{Path, PathBuf};
 
-pub fn delete(container_id: String) -> Result<(), ContainerErr> {
+/// Options for the delete command
+#[derive(Debug, Default)]
+pub struct DeleteOpts {
+    /// Stop the container first if it's still created or running.
+    pub force: bool,
+}
+
+pub fn delete(container_id: String, opts: DeleteOpts) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
     let ctx = setup_ctx()?;
+    let store = ctx.store();
+    let lock = store.lock(&container_id)?;
 
-    // Cleanup state directory
-    let container_state_dir = ctx.state_dir(&container_id);
-    if fs::metadata(&container_state_dir).is_ok() {
-        debug!("deleting state directory");
-        fs::remove_dir_all(&container_state_dir).map_err(ContainerErr::IO)?;
+    if opts.force {
+        let running = store
+            .load(&container_id)
+            .is_ok_and(|state| matches!(state.status(), Status::Created | Status::Running));
+        if running {
+            debug!("stopping container");
+            stop_container(&ctx, &container_id, StopOpts::default().timeout)?;
+        }
+    }
+    delete_container(&ctx, &container_id, &lock)?;
+    Ok(())
+}
+
+/// Removes everything of a container which has no processes left, with its lock held.
+/// Returns what was removed: its state dir, cgroup, mounts and loop device.
+pub(super) fn delete_container(
+    ctx: &Ctx,
+    container_id: &str,
+    lock: &ContainerLock,
+) -> Result<Vec<PathBuf>, ContainerErr> {
+    let store = ctx.store();
+    let dirs = ctx.container_dirs(container_id);
+    let mut removed = Vec::new();
+    // Needed for the poststop hooks once everything else is gone.
+    let state = store.load(container_id).ok();
+    let config = dirs.load_config();
+    // The monitor runs the poststop hooks when the container exits and then records the
+    // exit status.
+    let poststop_done = ExitStatus::load(&dirs).is_ok();
+
+    // Cleanup port forwarding, this has to happen before the state dir is removed.
+    if let Some(mut ports) = PortForwards::load(&dirs)? {
+        debug!("removing port forwarding");
+        ports.teardown();
     }
 
+    // Cleanup state directory
+    debug!("deleting state directory");
+    store.delete(container_id)?;
+    removed.push(dirs.dir().to_path_buf());
+
     // Cleanup cgroup
-    let cgroup_path = ctx.cgroups_root().join(&container_id);
+    let cgroup_path = match (&state, &config) {
+        (Some(state), Ok(config)) => state_cgroup_path(ctx, state, config)?,
+        (Some(state), Err(_)) => state
+            .cgroup_path()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| ctx.cgroups_root().join(container_id)),
+        (None, _) => ctx.cgroups_root().join(container_id),
+    };
     if fs::metadata(&cgroup_path).is_ok() {
         debug!("cleaning up cgroup",);
         fs::remove_dir(&cgroup_path).map_err(ContainerErr::IO)?;
+        removed.push(cgroup_path);
     }
 
-    Ok(())
+    // The container's mounts are gone with its processes, unless it had no mount namespace
+    // of its own. Then the image isn't in use anymore.
+    if let Some(state) = &state {
+        for target in unmount_targets(state.host_mounts())? {
+            debug!("unmounted {:?}", target);
+            removed.push(target);
+        }
+    }
+    if let Some(device) = state.as_ref().and_then(State::loop_device) {
+        debug!("detaching loop device {:?}", device);
+        loopdev::detach(device)?;
+        removed.push(device.to_path_buf());
+    }
+
+    lock.remove()?;
+
+    if let Some(mut state) = state.filter(|_| !poststop_done) {
+        state.update_status(Status::Stopped);
+        match config {
+            Ok(config) => run_hooks(&config, HookPhase::Poststop, &state)?,
+            Err(e) => warn!("skipping poststop hooks, failed to load config: {:?}", e),
+        }
+    }
+    Ok(removed)
 }
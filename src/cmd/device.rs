@@ -0,0 +1,220 @@
+//! Device cmd, adds host devices to a running container and removes them again.
+//!
+//! The node is made inside the container's mount namespace, by a child which joins only
+//! that one, so the path is resolved in the container's rootfs rather than the host's.
+//! The container's config gets the device and, if its linux.resources.devices restricts
+//! devices, a rule allowing it, and the cgroup's device filter is replaced by one from
+//! the new rules. Removing a device revokes the access before the node goes. Containers
+//! without rules have no filter, nothing stops them from making the node again then.
+
+use crate::cgroup::{set_cgroup_devices, state_cgroup_path};
+use crate::config::{Config, DeviceNode};
+use crate::container::validate_id;
+use crate::ctx::{setup_ctx, Ctx};
+use crate::error::ContainerErr;
+use crate::namespaces::join_process_namespaces;
+use crate::process::wait_exit_code;
+use crate::state::{Pid, State, Status};
+use crate::store::StateStore;
+use crate::syscalls;
+use log::debug;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::{chown, FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+/// Options for the device add command
+#[derive(Debug)]
+pub struct DeviceOpts {
+    /// What the container may do with the device, some of "rwm".
+    pub permissions: String,
+}
+
+impl Default for DeviceOpts {
+    fn default() -> Self {
+        Self {
+            permissions: String::from("rwm"),
+        }
+    }
+}
+
+/// Makes the host device `host_path` at the same path in the container.
+pub fn device_add(
+    container_id: String,
+    host_path: String,
+    opts: DeviceOpts,
+) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    validate_permissions(&opts.permissions)?;
+    let mut node = DeviceNode::stat(&host_path)?;
+    node.file_mode = node_mode(node.file_mode, &opts.permissions);
+
+    let ctx = setup_ctx()?;
+    let _lock = ctx.store().lock(&container_id)?;
+    let (state, mut config) = running(&ctx, &container_id)?;
+    debug!("adding {:?} to {}", node, container_id);
+    in_mount_namespace(state.pid(), &config, || make_node(&node))?;
+    config.add_device(&node, &opts.permissions);
+    if let Err(e) = set_device_filter(&ctx, &state, &config) {
+        let _ = in_mount_namespace(state.pid(), &config, || remove_node(&node.path));
+        return Err(e);
+    }
+    ctx.container_dirs(&container_id).write_config(&config)
+}
+
+/// Removes the device at `path` from the container.
+pub fn device_remove(container_id: String, path: String) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    let _lock = ctx.store().lock(&container_id)?;
+    let (state, mut config) = running(&ctx, &container_id)?;
+    debug!("removing {} from {}", path, container_id);
+    if !config.remove_device(&path) {
+        return Err(ContainerErr::Device(format!(
+            "{} has no device at {}",
+            container_id, path
+        )));
+    }
+    set_device_filter(&ctx, &state, &config)?;
+    ctx.container_dirs(&container_id).write_config(&config)?;
+    in_mount_namespace(state.pid(), &config, || remove_node(&path))
+}
+
+/// Replaces the device filter of the container's cgroup by one for the config's rules.
+fn set_device_filter(ctx: &Ctx, state: &State, config: &Config) -> Result<(), ContainerErr> {
+    let Some(devices) = config.allowed_devices() else {
+        return Ok(());
+    };
+    let cgroup = state_cgroup_path(ctx, state, config)?;
+    set_cgroup_devices(cgroup, devices, &config.device_nodes())
+        .map_err(|e| ContainerErr::Device(format!("replacing the device filter: {:?}", e)))
+}
+
+/// The container's state and config, if it's created or running.
+fn running(ctx: &Ctx, container_id: &str) -> Result<(State, Config), ContainerErr> {
+    let state = ctx.store().load(container_id)?;
+    if !matches!(state.status(), Status::Created | Status::Running) {
+        return Err(ContainerErr::State(format!(
+            "Container: {} is not running, status is {:?}",
+            container_id,
+            state.status()
+        )));
+    }
+    let config = ctx.container_dirs(container_id).load_config()?;
+    Ok((state, config))
+}
+
+fn validate_permissions(permissions: &str) -> Result<(), ContainerErr> {
+    if permissions.is_empty() || !permissions.chars().all(|c| "rwm".contains(c)) {
+        return Err(ContainerErr::invalid_args(&format!(
+            "Invalid device permissions: {}, expected some of rwm",
+            permissions
+        )));
+    }
+    Ok(())
+}
+
+/// The host node's `mode` without the read or write bits the permissions don't grant.
+fn node_mode(mode: u32, permissions: &str) -> u32 {
+    let mut mode = mode;
+    if !permissions.contains('r') {
+        mode &= !0o444;
+    }
+    if !permissions.contains('w') {
+        mode &= !0o222;
+    }
+    mode
+}
+
+fn make_node(node: &DeviceNode) -> Result<(), String> {
+    let path = Path::new(&node.path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("{:?}: {}", parent, e))?;
+    }
+    remove_node(&node.path)?;
+    let kind = if node.typ == 'b' {
+        libc::S_IFBLK
+    } else {
+        libc::S_IFCHR
+    };
+    let dev = libc::makedev(node.major as u32, node.minor as u32);
+    syscalls::mknod(path, kind | node.file_mode, dev).map_err(|e| e.to_string())?;
+    // mknod's mode is subject to our umask.
+    fs::set_permissions(path, fs::Permissions::from_mode(node.file_mode))
+        .map_err(|e| format!("{:?}: {}", path, e))?;
+    chown(path, Some(node.uid), Some(node.gid)).map_err(|e| format!("{:?}: {}", path, e))
+}
+
+/// Removes the device node at `path`, if there's one. Refuses to remove anything else,
+/// the container may have put it there.
+fn remove_node(path: &str) -> Result<(), String> {
+    let file_type = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata.file_type(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("{}: {}", path, e)),
+    };
+    if !file_type.is_char_device() && !file_type.is_block_device() {
+        return Err(format!("{} is not a device node", path));
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("{}: {}", path, e)),
+        _ => Ok(()),
+    }
+}
+
+/// Runs `f` in a child which joined the mount namespace of `pid`, setns can't be undone.
+/// The child reports what went wrong over a pipe.
+fn in_mount_namespace<F>(pid: Pid, config: &Config, f: F) -> Result<(), ContainerErr>
+where
+    F: FnOnce() -> Result<(), String>,
+{
+    let Some(mount) = config
+        .linux_namespaces()
+        .and_then(|namespaces| namespaces.iter().find(|ns| ns.typ == "mount"))
+    else {
+        return Err(ContainerErr::Device(String::from(
+            "the container has no mount namespace of its own",
+        )));
+    };
+    let mount = [mount.clone()];
+    let (mut reader, mut writer) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+    let child = unsafe { syscalls::fork() }.map_err(ContainerErr::IO)?;
+    if child == 0 {
+        drop(reader);
+        let result = join_process_namespaces(pid, &mount)
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|_| f());
+        let code = match result {
+            Ok(()) => 0,
+            Err(msg) => {
+                let _ = writer.write_all(msg.as_bytes());
+                1
+            }
+        };
+        // Never return into the parent's code from the child.
+        unsafe { libc::_exit(code) };
+    }
+
+    drop(writer);
+    let mut msg = String::new();
+    let _ = reader.read_to_string(&mut msg);
+    match wait_exit_code(child as Pid)? {
+        0 => Ok(()),
+        _ => Err(ContainerErr::Device(msg)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_mode() {
+        assert!(validate_permissions("rw").is_ok());
+        assert!(validate_permissions("").is_err());
+        assert!(validate_permissions("rwx").is_err());
+        assert_eq!(0o666, node_mode(0o666, "rwm"));
+        assert_eq!(0o444, node_mode(0o666, "rm"));
+        assert_eq!(0o220, node_mode(0o660, "w"));
+    }
+}
@@ -0,0 +1,240 @@
+//! Events cmd, reports the resource usage of a running container, like runc's `events`.
+//!
+//! With a PSI threshold, a `pressure` event is printed too whenever the share of time
+//! some of the container's tasks were stalled on a resource, averaged over the last 10
+//! seconds, goes above the threshold, and again once it's back below.
+
+use crate::cgroup::{read_network, read_stats, state_cgroup_path, wait_empty, PressureStats};
+use crate::container::validate_id;
+use crate::ctx::{setup_ctx, Ctx};
+use crate::error::ContainerErr;
+use crate::events::Event;
+use crate::state::{Pid, Status};
+use crate::store::StateStore;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Options for the events command
+#[derive(Debug)]
+pub struct EventsOpts {
+    /// Print the stats once rather than until the container stops.
+    pub stats: bool,
+    /// How often the stats are printed.
+    pub interval: Duration,
+    /// Print `pressure` events when a pressure average crosses this percentage.
+    pub psi_threshold: Option<f64>,
+}
+
+impl Default for EventsOpts {
+    fn default() -> Self {
+        Self {
+            stats: false,
+            interval: Duration::from_secs(5),
+            psi_threshold: None,
+        }
+    }
+}
+
+/// Prints `stats` events with the container's resource usage as json, a line every
+/// interval until the container stops, or once with `stats`.
+pub fn events(container_id: String, opts: EventsOpts) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    let (cgroup, _, netns_pid) = running_container(&ctx, &container_id)?;
+    let mut watch = opts.psi_threshold.map(PressureWatch::new);
+    loop {
+        emit_stats(&container_id, &cgroup, netns_pid, &mut watch, print_event)?;
+        if opts.stats {
+            return Ok(());
+        }
+        thread::sleep(opts.interval);
+        if wait_empty(&cgroup, Duration::ZERO)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Like `events`, but hands the events to `on_event` rather than printing them, and
+/// sleeps on tokio's timer. Returns as soon as the init process exits, its pidfd is
+/// awaited along with the interval.
+#[cfg(feature = "tokio")]
+pub async fn events_async<F>(
+    container_id: String,
+    opts: EventsOpts,
+    mut on_event: F,
+) -> Result<(), ContainerErr>
+where
+    F: FnMut(&Event) -> Result<(), ContainerErr>,
+{
+    use crate::process::pidfd_open;
+    use tokio::io::unix::AsyncFd;
+
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    let (cgroup, pid, netns_pid) = running_container(&ctx, &container_id)?;
+    let pidfd = match pidfd_open(pid) {
+        Ok(pidfd) => AsyncFd::new(pidfd).map_err(ContainerErr::IO)?,
+        // Gone since its state was read.
+        Err(e) if e.raw_os_error() == Some(libc::ESRCH) => return Ok(()),
+        Err(e) => return Err(ContainerErr::IO(e)),
+    };
+    let mut watch = opts.psi_threshold.map(PressureWatch::new);
+    loop {
+        emit_stats(&container_id, &cgroup, netns_pid, &mut watch, &mut on_event)?;
+        if opts.stats {
+            return Ok(());
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(opts.interval) => {}
+            exited = pidfd.readable() => return exited.map(|_| ()).map_err(ContainerErr::IO),
+        }
+        if wait_empty(&cgroup, Duration::ZERO)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the container's stats and hands them to `on_event`, preceded by the pressure
+/// events they cause.
+fn emit_stats<F>(
+    container_id: &str,
+    cgroup: &Path,
+    netns_pid: Option<Pid>,
+    watch: &mut Option<PressureWatch>,
+    mut on_event: F,
+) -> Result<(), ContainerErr>
+where
+    F: FnMut(&Event) -> Result<(), ContainerErr>,
+{
+    let mut stats = read_stats(cgroup)?;
+    if let Some(pid) = netns_pid {
+        stats.network = read_network(pid)?;
+    }
+    if let Some(watch) = watch {
+        for data in watch.crossings(&stats.pressure) {
+            on_event(&Event::new("pressure", container_id, data))?;
+        }
+    }
+    let data = serde_json::to_value(stats).map_err(|e| ContainerErr::State(e.to_string()))?;
+    on_event(&Event::new("stats", container_id, data))
+}
+
+/// Which resources' pressure is above the threshold.
+struct PressureWatch {
+    threshold: f64,
+    above: Vec<&'static str>,
+}
+
+impl PressureWatch {
+    fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            above: Vec::new(),
+        }
+    }
+
+    /// The data of the events for the resources whose pressure crossed the threshold
+    /// since the last stats.
+    fn crossings(&mut self, pressure: &PressureStats) -> Vec<Value> {
+        let mut crossings = Vec::new();
+        for (resource, pressure) in pressure.iter() {
+            let avg10 = pressure.some.avg10;
+            let above = avg10 > self.threshold;
+            if above == self.above.contains(&resource) {
+                continue;
+            }
+            if above {
+                self.above.push(resource);
+            } else {
+                self.above.retain(|r| *r != resource);
+            }
+            crossings.push(json!({
+                "resource": resource,
+                "avg10": avg10,
+                "threshold": self.threshold,
+                "above": above,
+            }));
+        }
+        crossings
+    }
+}
+
+/// The cgroup and init process of a running container, the latter again if the container
+/// has a network namespace of its own. The host's interfaces aren't the container's.
+fn running_container(
+    ctx: &Ctx,
+    container_id: &str,
+) -> Result<(PathBuf, Pid, Option<Pid>), ContainerErr> {
+    let state = ctx.store().load(container_id)?;
+    if !matches!(state.status(), Status::Created | Status::Running) {
+        return Err(ContainerErr::State(format!(
+            "Container: {} is not running, status is {:?}",
+            container_id,
+            state.status()
+        )));
+    }
+    let config = ctx.container_dirs(container_id).load_config()?;
+    let netns = config
+        .linux_namespaces()
+        .unwrap_or_default()
+        .iter()
+        .any(|ns| ns.typ == "network");
+    let pid = netns.then(|| state.pid());
+    Ok((state_cgroup_path(ctx, &state, &config)?, state.pid(), pid))
+}
+
+fn print_event(event: &Event) -> Result<(), ContainerErr> {
+    let json = serde_json::to_string(event).map_err(|e| ContainerErr::State(e.to_string()))?;
+    println!("{}", json);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_pressure_watch() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let cgroup = PathBuf::from(format!("/tmp/pressure_watch_{}", time));
+        fs::create_dir(&cgroup).unwrap();
+        let stats = |cpu: f64, io: f64| {
+            for (name, avg10) in [("cpu.pressure", cpu), ("io.pressure", io)] {
+                let line = format!("some avg10={:.2} avg60=0.00 avg300=0.00 total=0\n", avg10);
+                fs::write(cgroup.join(name), line).unwrap();
+            }
+            read_stats(&cgroup).unwrap().pressure
+        };
+
+        let mut watch = PressureWatch::new(10.0);
+        assert!(watch.crossings(&stats(5.0, 5.0)).is_empty());
+
+        let crossings = watch.crossings(&stats(25.0, 5.0));
+        assert_eq!(1, crossings.len());
+        assert_eq!("cpu", crossings[0]["resource"]);
+        assert_eq!(true, crossings[0]["above"]);
+        // Only once while it stays above.
+        assert!(watch.crossings(&stats(30.0, 5.0)).is_empty());
+
+        let crossings = watch.crossings(&stats(2.0, 50.0));
+        assert_eq!(
+            vec![("cpu", false), ("io", true)],
+            crossings
+                .iter()
+                .map(|c| (
+                    c["resource"].as_str().unwrap(),
+                    c["above"].as_bool().unwrap()
+                ))
+                .collect::<Vec<_>>()
+        );
+
+        fs::remove_dir_all(&cgroup).unwrap();
+    }
+}
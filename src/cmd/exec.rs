@@ -0,0 +1,262 @@
+//! Exec cmd, runs an additional process inside a running container.
+
+use crate::cgroup::state_cgroup_path;
+use crate::config::{Config, LinuxSeccomp, Process};
+use crate::container::validate_id;
+use crate::ctx::setup_ctx;
+use crate::error::ContainerErr;
+use crate::extensions::Extensions;
+use crate::fds::close_inherited;
+use crate::namespaces::join_process_namespaces;
+use crate::process::{
+    add_default_env, apply_process_spec, build_args, build_env, find_executable, wait_exit_code,
+};
+use crate::sched::share_core_cookie;
+use crate::state::{Pid, Status};
+use crate::store::StateStore;
+use crate::syscalls::{self, execve};
+use crate::tty::{forward_stdio, send_console, set_controlling_terminal, set_size, Pty};
+use crate::users::{resolve_user, split_user};
+use log::debug;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Exit code of the exec'd process if it couldn't be started, same as a shell's for a
+/// command which can't be executed.
+const EXEC_FAILED: i32 = 127;
+
+/// Options for the exec command, layered over the container's process.
+#[derive(Debug, Default)]
+pub struct ExecOpts {
+    /// Additional `KEY=VALUE` environment variables, overriding the container's.
+    pub env: Vec<String>,
+    /// Working directory inside the container.
+    pub cwd: Option<String>,
+    /// `user[:group]` to run as, names or ids. Names are those of the container's
+    /// /etc/passwd and /etc/group.
+    pub user: Option<String>,
+    /// Allocate a pty for the process.
+    pub tty: bool,
+    /// Unix socket to send the pty's master to, instead of forwarding our stdio to it.
+    pub console_socket: Option<PathBuf>,
+    /// Don't wait for the process to exit.
+    pub detach: bool,
+    /// File to write the process' pid to.
+    pub pid_file: Option<PathBuf>,
+    /// OCI process document to run instead of the container's process, `-p`.
+    pub process: Option<PathBuf>,
+    /// Number of descriptors after stderr the process inherits.
+    pub preserve_fds: u32,
+}
+
+/// Executes `command` in the running container `container_id`. Returns the exit code of the
+/// process, or 0 right away if detached. With a process document `command` may be empty,
+/// otherwise it replaces the document's args.
+pub fn exec(
+    container_id: String,
+    command: Vec<String>,
+    opts: ExecOpts,
+) -> Result<i32, ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    let state = ctx.store().load(&container_id)?;
+    if *state.status() != Status::Running {
+        return Err(ContainerErr::State(format!(
+            "Container: {} is not running, status is {:?}",
+            &container_id,
+            state.status()
+        )));
+    }
+
+    let config = ctx.container_dirs(&container_id).load_config()?;
+    let mut process = exec_process(&config, command, &opts)?;
+    add_default_env(&mut process, config.hostname().unwrap_or(&container_id));
+    // Like the container's init, unless told otherwise or given a whole process.
+    let user = match (&opts.user, &opts.process) {
+        (Some(user), _) => Some(user.clone()),
+        (None, None) => Extensions::parse(&config)?.user,
+        (None, Some(_)) => None,
+    };
+    // Without a console socket nobody would be on the other end of the terminal.
+    if process.terminal && opts.detach && opts.console_socket.is_none() {
+        return Err(ContainerErr::invalid_args(
+            "a terminal can't be used with --detach without --console-socket",
+        ));
+    }
+    if opts.console_socket.is_some() && !process.terminal {
+        return Err(ContainerErr::invalid_args("--console-socket needs --tty"));
+    }
+
+    // Everything on the host side is opened before joining the container's namespaces.
+    let mut pid_file = match &opts.pid_file {
+        Some(path) => Some(File::create(path).map_err(ContainerErr::IO)?),
+        None => None,
+    };
+    let cgroup_procs = OpenOptions::new()
+        .write(true)
+        .open(state_cgroup_path(&ctx, &state, &config)?.join("cgroup.procs"))
+        .map_err(ContainerErr::IO)?;
+    let pty = if process.terminal {
+        let pty = Pty::open()?;
+        if let Some((rows, cols)) = process.console_size() {
+            set_size(pty.master.as_raw_fd(), rows, cols)?;
+        }
+        Some(pty)
+    } else {
+        None
+    };
+
+    // From the host's pid namespace, the fork inherits it.
+    if Extensions::parse(&config)?.sched_core {
+        share_core_cookie(state.pid())?;
+    }
+    if let Some(namespaces) = config.linux_namespaces() {
+        join_process_namespaces(state.pid(), namespaces)?;
+    }
+    // In the container's mount namespace our root is its rootfs, names are looked up there.
+    if let Some(user) = &user {
+        resolve_user(Path::new("/"), user)?.apply(&mut process.user);
+    }
+
+    // Forking after joining the namespaces puts the child in the container's pid namespace.
+    let pid = unsafe { syscalls::fork() }.map_err(ContainerErr::IO)?;
+    if pid == 0 {
+        // Only returns if the exec failed.
+        if let Err(e) = exec_child(
+            &process,
+            config.seccomp(),
+            &cgroup_procs,
+            pty.as_ref(),
+            opts.preserve_fds,
+        ) {
+            eprintln!("exec failed: {:?}", e);
+        }
+        std::process::exit(EXEC_FAILED);
+    }
+    debug!("exec'd process pid: {}", pid);
+
+    if let Some(f) = &mut pid_file {
+        f.write_all(pid.to_string().as_bytes())
+            .map_err(ContainerErr::IO)?;
+    }
+    if let Some(pty) = pty {
+        if let Some(socket) = &opts.console_socket {
+            send_console(socket, &pty)?;
+        } else if !opts.detach {
+            drop(pty.slave);
+            forward_stdio(pty.master)?;
+        }
+    }
+    if opts.detach {
+        return Ok(0);
+    }
+    wait_exit_code(pid as Pid)
+}
+
+/// The container's process with the exec options applied.
+fn exec_process(
+    config: &Config,
+    command: Vec<String>,
+    opts: &ExecOpts,
+) -> Result<Process, ContainerErr> {
+    let mut process = match &opts.process {
+        Some(path) => Process::load(path)?,
+        None => {
+            let mut process = config.process().cloned().unwrap_or_default();
+            // The container's terminal setting is for its init process.
+            process.terminal = false;
+            process
+        }
+    };
+    if !command.is_empty() {
+        process.args = Some(command);
+    }
+    if opts.tty {
+        process.terminal = true;
+    }
+    if !opts.env.is_empty() {
+        process
+            .env
+            .get_or_insert_with(Vec::new)
+            .extend(opts.env.iter().cloned());
+    }
+    if let Some(cwd) = &opts.cwd {
+        process.cwd = cwd.clone();
+    }
+    // Resolved once in the container, only the syntax can be checked here.
+    if let Some(user) = &opts.user {
+        split_user(user)?;
+    }
+    Ok(process)
+}
+
+/// Runs in the forked child, won't return on success.
+fn exec_child(
+    process: &Process,
+    seccomp: Option<&LinuxSeccomp>,
+    mut cgroup_procs: &File,
+    pty: Option<&Pty>,
+    preserve_fds: u32,
+) -> Result<(), ContainerErr> {
+    // Writing 0 moves the writing process.
+    cgroup_procs.write_all(b"0").map_err(ContainerErr::IO)?;
+    if let Some(pty) = pty {
+        set_controlling_terminal(pty)?;
+    }
+
+    let argv = build_args(process)?;
+    let envp = build_env(process)?;
+    std::env::set_current_dir(&process.cwd).map_err(ContainerErr::IO)?;
+    let path = find_executable("/", process)?;
+
+    close_inherited(preserve_fds)?;
+    // Exec'd processes are confined like the container's init.
+    apply_process_spec(process, seccomp)?;
+
+    let err = execve(&path, &argv, &envp);
+    Err(ContainerErr::Entrypoint(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_exec_process() {
+        let config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "process": {
+                "terminal": true,
+                "user": {"uid": 1000, "gid": 100},
+                "args": ["sh"],
+                "cwd": "/",
+            },
+        }))
+        .unwrap();
+        let opts = ExecOpts {
+            user: Some(String::from("www:log")),
+            ..Default::default()
+        };
+        let process = exec_process(&config, vec![String::from("ls")], &opts).unwrap();
+        assert_eq!(Some(vec![String::from("ls")]), process.args);
+        assert!(!process.terminal);
+        // Names are resolved in the container, later.
+        assert_eq!((1000, 100), (process.user.uid, process.user.gid));
+
+        for user in ["", "www:", ":log"] {
+            let opts = ExecOpts {
+                user: Some(String::from(user)),
+                ..Default::default()
+            };
+            assert!(
+                exec_process(&config, Vec::new(), &opts).is_err(),
+                "{}",
+                user
+            );
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use crate::cgroup::{host_has_swap, swap_accounting};
+use crate::error::ContainerErr;
+use crate::features::{available, Feature};
+use serde_json::{json, Map, Value};
+
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+
+/// Prints the kernel features the runtime probed for as json, whether each is available
+/// and the kernel version it needs, and whether the cgroups can enforce swap limits.
+pub fn features() -> Result<(), ContainerErr> {
+    println!("{}", report());
+    Ok(())
+}
+
+fn report() -> Value {
+    let kernel: Map<String, Value> = Feature::ALL
+        .into_iter()
+        .map(|feature| {
+            let probed = json!({
+                "available": available(feature),
+                "minKernel": feature.min_kernel(),
+            });
+            (feature.name().to_string(), probed)
+        })
+        .collect();
+    json!({
+        "kernel": kernel,
+        "cgroup": {
+            "swapAccounting": swap_accounting(CGROUP_MOUNT),
+            "swap": host_has_swap(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let report = report();
+        let kernel = report["kernel"].as_object().unwrap();
+        assert_eq!(Feature::ALL.len(), kernel.len());
+        assert_eq!("5.7", kernel["cloneIntoCgroup"]["minKernel"]);
+        assert!(kernel["pidfd"]["available"].is_boolean());
+        assert!(report["cgroup"]["swapAccounting"].is_boolean());
+    }
+}
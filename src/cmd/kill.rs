@@ -1,5 +1,54 @@
+use crate::cgroup::{kill_cgroup, signal_cgroup, state_cgroup_path};
+use crate::container::validate_id;
+use crate::ctx::setup_ctx;
 use crate::error::ContainerErr;
+use crate::process::{pidfd_open, pidfd_send_signal};
+use crate::signal::parse_signal;
+use crate::state::Status;
+use crate::store::StateStore;
+use log::debug;
+use std::fs;
 
-pub fn kill(_container_id: String, _signal: String) -> Result<(), ContainerErr> {
-    todo!("implement kill")
+/// Options for the kill command
+#[derive(Debug, Default)]
+pub struct KillOpts {
+    /// Signal every process in the container's cgroup, not only its init process.
+    pub all: bool,
+}
+
+/// Sends the signal, given by name or number, to the container's init process. SIGKILL,
+/// and any signal with `all`, goes to every process in the container.
+pub fn kill(container_id: String, signal: String, opts: KillOpts) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    let sig = parse_signal(&signal)?;
+    let ctx = setup_ctx()?;
+    let state = ctx.store().load(&container_id)?;
+    match state.status() {
+        Status::Created | Status::Running => {}
+        status => {
+            return Err(ContainerErr::State(format!(
+                "Container: {} cannot be signalled, status is {:?}",
+                container_id, status
+            )))
+        }
+    }
+
+    if sig == libc::SIGKILL || opts.all {
+        let config = ctx.container_dirs(&container_id).load_config()?;
+        let cgroup = state_cgroup_path(&ctx, &state, &config)?;
+        // Without a cgroup of its own there's only the init process to signal.
+        if fs::metadata(&cgroup).is_ok() {
+            debug!("sending signal {} to everything in {:?}", sig, cgroup);
+            return if sig == libc::SIGKILL {
+                kill_cgroup(&cgroup)
+            } else {
+                signal_cgroup(&cgroup, sig)
+            };
+        }
+    }
+    // Through a pidfd, in case the process exited and its pid was reused since the state
+    // was written.
+    let pidfd = pidfd_open(state.pid()).map_err(ContainerErr::IO)?;
+    debug!("sending signal {} to {}", sig, state.pid());
+    pidfd_send_signal(&pidfd, sig).map_err(ContainerErr::IO)
 }
@@ -0,0 +1,205 @@
+//! List cmd, prints the containers, optionally only those matching filters.
+
+use crate::ctx::{setup_ctx, Ctx};
+use crate::error::ContainerErr;
+use crate::state::{State, Status};
+use crate::store::StateStore;
+use crate::users;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::str::FromStr;
+
+/// Names of the statuses, as given to `--filter status=` and printed.
+const STATUSES: [(&str, Status); 4] = [
+    ("creating", Status::Creating),
+    ("created", Status::Created),
+    ("running", Status::Running),
+    ("stopped", Status::Stopped),
+];
+
+/// Options for the list command
+#[derive(Debug, Default)]
+pub struct ListOpts {
+    /// Containers have to match all of them to be listed.
+    pub filters: Vec<Filter>,
+}
+
+/// Narrows down the containers `list` prints.
+#[derive(Debug, PartialEq)]
+pub enum Filter {
+    /// `label=<key>` or `label=<key>=<value>`, matched against the state's annotations,
+    /// which hold the labels given to create.
+    Label { key: String, value: Option<String> },
+    /// `status=<status>`, e.g. `status=running`.
+    Status(Status),
+}
+
+impl FromStr for Filter {
+    type Err = ContainerErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ContainerErr::invalid_args(&format!("Invalid filter: {}", s));
+        match s.split_once('=').ok_or_else(invalid)? {
+            ("label", label) => {
+                let (key, value) = match label.split_once('=') {
+                    Some((key, value)) => (key, Some(value.to_string())),
+                    None => (label, None),
+                };
+                if key.is_empty() {
+                    return Err(invalid());
+                }
+                Ok(Filter::Label {
+                    key: key.to_string(),
+                    value,
+                })
+            }
+            ("status", status) => STATUSES
+                .iter()
+                .find(|(name, _)| *name == status)
+                .map(|(_, status)| Filter::Status(status.clone()))
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Filter {
+    fn matches(&self, state: &State) -> bool {
+        match self {
+            Filter::Label { key, value } => state
+                .annotations()
+                .get(key)
+                .is_some_and(|v| value.as_ref().is_none_or(|value| value == v)),
+            Filter::Status(status) => state.status() == status,
+        }
+    }
+}
+
+/// Prints a table of the containers matching the filters.
+pub fn list(opts: ListOpts) -> Result<(), ContainerErr> {
+    let ctx = setup_ctx()?;
+    let mut containers = Vec::new();
+    for state in ctx.store().list()? {
+        if opts.filters.iter().all(|f| f.matches(&state)) {
+            let owner = owner(&ctx, &state)?;
+            containers.push((state, owner));
+        }
+    }
+    print!("{}", table(&containers));
+    Ok(())
+}
+
+/// Name of the user who created the container, the owner of its state dir. Falls back
+/// to the uid for users the host doesn't know.
+fn owner(ctx: &Ctx, state: &State) -> Result<String, ContainerErr> {
+    let uid = match fs::metadata(ctx.state_dir(state.id())) {
+        Ok(metadata) => metadata.uid(),
+        // Deleted since it was listed.
+        Err(_) => return Ok(String::new()),
+    };
+    Ok(users::user_by_uid(uid)?.map_or_else(|| uid.to_string(), |user| user.name))
+}
+
+fn status_name(status: &Status) -> &'static str {
+    STATUSES
+        .iter()
+        .find(|(_, s)| s == status)
+        .map(|(name, _)| *name)
+        .unwrap_or_default()
+}
+
+/// Lays out the states and their owners in columns, like runc's list.
+fn table(containers: &[(State, String)]) -> String {
+    let mut rows = vec![[
+        String::from("ID"),
+        String::from("PID"),
+        String::from("STATUS"),
+        String::from("OWNER"),
+        String::from("BUNDLE"),
+    ]];
+    for (state, owner) in containers {
+        rows.push([
+            state.id().to_string(),
+            state.pid().to_string(),
+            status_name(state.status()).to_string(),
+            owner.clone(),
+            state.bundle().display().to_string(),
+        ]);
+    }
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in rows {
+        for (width, cell) in widths.iter().zip(&row) {
+            table.push_str(&format!("{:<width$}   ", cell, width = width));
+        }
+        table.push_str(&row[4]);
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn state(id: &str, status: Status) -> State {
+        let mut state = State::new(
+            id.to_string(),
+            PathBuf::from(format!("/bundles/{}", id)),
+            String::from("1.0.1"),
+        );
+        state.update_status(status);
+        state
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut web = state("web", Status::Running);
+        web.set_annotation(String::from("app"), String::from("shop"));
+        web.set_annotation(String::from("tier"), String::from("frontend"));
+        let db = state("db", Status::Stopped);
+
+        let filter = |f: &str| f.parse::<Filter>().unwrap();
+        assert!(filter("label=app").matches(&web));
+        assert!(filter("label=app=shop").matches(&web));
+        assert!(!filter("label=app=blog").matches(&web));
+        assert!(!filter("label=app").matches(&db));
+        assert!(filter("status=running").matches(&web));
+        assert!(filter("status=stopped").matches(&db));
+        // Values may contain =.
+        assert_eq!(
+            Filter::Label {
+                key: String::from("query"),
+                value: Some(String::from("a=b")),
+            },
+            filter("label=query=a=b")
+        );
+
+        for invalid in ["label", "label=", "status=paused", "name=web", ""] {
+            assert!(invalid.parse::<Filter>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_table() {
+        let mut web = state("web", Status::Running);
+        web.set_pid(4242);
+        let containers = [
+            (state("database", Status::Stopped), String::from("root")),
+            (web, String::from("1000")),
+        ];
+        assert_eq!(
+            "ID         PID    STATUS    OWNER   BUNDLE\n\
+             database   0      stopped   root    /bundles/database\n\
+             web        4242   running   1000    /bundles/web\n",
+            table(&containers)
+        );
+    }
+}
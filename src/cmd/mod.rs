@@ -1,11 +1,57 @@
+mod attach;
+mod checkpoint;
 mod create;
 mod delete;
+mod device;
+mod events;
+mod exec;
+mod features;
 mod kill;
+mod list;
+mod pod;
+mod prune;
+mod resize;
+mod restore;
+mod run;
+mod selftest;
 mod start;
 mod state;
+mod stop;
+mod update;
+mod wait;
 
-pub use create::create;
-pub use delete::delete;
-pub use kill::kill;
-pub use start::start;
-pub use state::state;
+pub use crate::config::ProcessOverrides;
+pub use crate::container::validate_id;
+pub use crate::ctx::{set_global_opts, CgroupManager, GlobalOpts, ResourcePolicy, TraceOutput};
+#[cfg(feature = "tokio")]
+pub use crate::events::Event;
+pub use crate::gpu::Gpus;
+pub use attach::{attach, parse_detach_keys, AttachOpts};
+pub use checkpoint::{checkpoint, CheckpointOpts};
+pub use create::{create, CreateOpts, LogDriver};
+#[cfg(feature = "tokio")]
+pub use create::{create_async, run_create_helper};
+pub use delete::{delete, DeleteOpts};
+pub use device::{device_add, device_remove, DeviceOpts};
+#[cfg(feature = "tokio")]
+pub use events::events_async;
+pub use events::{events, EventsOpts};
+pub use exec::{exec, ExecOpts};
+pub use features::features;
+pub use kill::{kill, KillOpts};
+pub use list::{list, Filter, ListOpts};
+pub use pod::{pod_create, pod_delete, pod_inspect};
+pub use prune::{prune, PruneOpts};
+pub use resize::resize;
+pub use restore::{restore, RestoreOpts};
+pub use run::{run, RunOpts};
+pub use selftest::selftest;
+#[cfg(feature = "tokio")]
+pub use start::start_async;
+pub use start::{start, StartOpts};
+pub use state::{state, StateOpts};
+pub use stop::{stop, StopOpts};
+pub use update::{update, UpdateOpts};
+pub use wait::wait;
+#[cfg(feature = "tokio")]
+pub use wait::wait_async;
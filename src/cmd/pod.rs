@@ -0,0 +1,43 @@
+//! Pod cmds, creating, inspecting and deleting pods.
+
+use crate::ctx::setup_ctx;
+use crate::error::ContainerErr;
+use crate::pod::Pod;
+use serde::Serialize;
+
+/// What `pod inspect` prints.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PodInfo<'a> {
+    #[serde(flatten)]
+    pod: &'a Pod,
+    running: bool,
+    containers: Vec<String>,
+}
+
+/// Creates the pod `name`, ready for `create --pod` to put containers in.
+pub fn pod_create(name: String) -> Result<(), ContainerErr> {
+    let ctx = setup_ctx()?;
+    Pod::create(&ctx, &name)?;
+    Ok(())
+}
+
+/// Prints the pod's state and containers as json.
+pub fn pod_inspect(name: String) -> Result<(), ContainerErr> {
+    let ctx = setup_ctx()?;
+    let pod = Pod::load(&ctx, &name)?;
+    let info = PodInfo {
+        pod: &pod,
+        running: pod.is_running(&ctx),
+        containers: pod.containers(&ctx)?,
+    };
+    let json = serde_json::to_string(&info).map_err(|e| ContainerErr::Pod(e.to_string()))?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Deletes the pod `name`, its containers have to be deleted first.
+pub fn pod_delete(name: String) -> Result<(), ContainerErr> {
+    let ctx = setup_ctx()?;
+    Pod::load(&ctx, &name)?.delete(&ctx)
+}
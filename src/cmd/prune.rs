@@ -0,0 +1,202 @@
+//! Prune cmd, removes containers whose processes are gone but which were never deleted,
+//! e.g. because the runtime or the host crashed.
+
+use super::delete::delete_container;
+use crate::cgroup::{systemd_scopes, wait_empty};
+use crate::ctx::{setup_ctx, Ctx};
+use crate::error::ContainerErr;
+use crate::lock::ContainerLock;
+use crate::process::pidfd_open;
+use crate::state::Status;
+use crate::store::StateStore;
+use log::{debug, warn};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Options for the prune command
+#[derive(Debug, Default)]
+pub struct PruneOpts {
+    /// Only prune containers whose state last changed at least this long ago.
+    pub age: Option<Duration>,
+}
+
+/// Removes the state dirs, cgroups and lock files of containers without processes, and
+/// prints what was removed.
+pub fn prune(opts: PruneOpts) -> Result<(), ContainerErr> {
+    let ctx = setup_ctx()?;
+    for path in prune_containers(&ctx, &opts)? {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+fn prune_containers(ctx: &Ctx, opts: &PruneOpts) -> Result<Vec<PathBuf>, ContainerErr> {
+    let mut removed = Vec::new();
+    let entries = match fs::read_dir(&ctx.state_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(removed),
+        Err(e) => return Err(ContainerErr::IO(e)),
+    };
+    for entry in entries {
+        let entry = entry.map_err(ContainerErr::IO)?;
+        let id = entry.file_name().to_string_lossy().into_owned();
+        // The locks and pods dirs aren't containers.
+        if id.starts_with('.') {
+            continue;
+        }
+        // Something else is working on it, so it isn't abandoned.
+        let Some(lock) = ContainerLock::try_acquire(ctx, &id)? else {
+            debug!("skipping {}, it's locked", id);
+            continue;
+        };
+        if !is_orphan(ctx, &id, opts.age)? {
+            continue;
+        }
+        debug!("pruning {}", id);
+        match delete_container(ctx, &id, &lock) {
+            Ok(paths) => removed.extend(paths),
+            Err(e) => warn!("failed to prune {}: {:?}", id, e),
+        }
+    }
+
+    // Lock files outlive containers whose delete didn't get that far.
+    if let Ok(entries) = fs::read_dir(ctx.locks_dir()) {
+        for entry in entries {
+            let entry = entry.map_err(ContainerErr::IO)?;
+            let id = entry.file_name().to_string_lossy().into_owned();
+            if ctx.state_dir(&id).exists() || changed_within(&entry.path(), opts.age)? {
+                continue;
+            }
+            if let Some(lock) = ContainerLock::try_acquire(ctx, &id)? {
+                lock.remove()?;
+                removed.push(entry.path());
+            }
+        }
+    }
+
+    // As do the scopes of the systemd cgroup manager.
+    for (id, scope) in systemd_scopes(ctx)? {
+        if ctx.state_dir(&id).exists() || !wait_empty(&scope, Duration::ZERO)? {
+            continue;
+        }
+        match fs::remove_dir(&scope) {
+            Ok(()) => removed.push(scope),
+            Err(e) => warn!("failed to remove {:?}: {}", scope, e),
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether the container has no processes left and, with `age`, its state hasn't changed
+/// for that long. State which can't be loaded is left by a create which never finished.
+fn is_orphan(ctx: &Ctx, container_id: &str, age: Option<Duration>) -> Result<bool, ContainerErr> {
+    let dirs = ctx.container_dirs(container_id);
+    // A create which crashed before reserving leaves only the dir.
+    let changed = if dirs.state().exists() {
+        dirs.state()
+    } else {
+        dirs.dir().to_path_buf()
+    };
+    if changed_within(&changed, age)? {
+        return Ok(false);
+    }
+
+    let Ok(state) = ctx.store().load(container_id) else {
+        return Ok(true);
+    };
+    let cgroup = state
+        .cgroup_path()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| ctx.cgroups_root().join(container_id));
+    if !wait_empty(&cgroup, Duration::ZERO)? {
+        return Ok(false);
+    }
+    let alive =
+        *state.status() != Status::Stopped && state.pid() != 0 && pidfd_open(state.pid()).is_ok();
+    Ok(!alive)
+}
+
+/// Whether `path` was modified less than `age` ago, never without an age.
+fn changed_within(path: &Path, age: Option<Duration>) -> Result<bool, ContainerErr> {
+    let Some(age) = age else {
+        return Ok(false);
+    };
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(ContainerErr::IO)?;
+    let elapsed = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+    Ok(elapsed < age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ctx::CgroupManager;
+    use crate::state::State;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_prune() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let mut ctx = Ctx::default();
+        ctx.state_dir = PathBuf::from(format!("/tmp/prune_{}", time));
+        let store = ctx.store();
+
+        // Stopped, with a cgroup left behind.
+        let cgroup = PathBuf::from(format!("/tmp/prune_cgroup_{}", time));
+        fs::create_dir(&cgroup).unwrap();
+        let mut dead = State::new(String::from("dead"), PathBuf::from("/"), String::new());
+        dead.update_status(Status::Stopped);
+        dead.set_cgroup(cgroup.clone(), CgroupManager::Cgroupfs);
+        store.save(&dead).unwrap();
+        drop(store.lock("dead").unwrap());
+
+        // Running as this process.
+        let mut alive = State::new(String::from("alive"), PathBuf::from("/"), String::new());
+        alive.update_status(Status::Running);
+        alive.set_pid(std::process::id());
+        store.save(&alive).unwrap();
+
+        // Reserved by a create which crashed.
+        let reserved = State::new(String::from("reserved"), PathBuf::from("/"), String::new());
+        store.reserve(&reserved).unwrap();
+
+        // Busy, whatever its state.
+        let busy = State::new(String::from("busy"), PathBuf::from("/"), String::new());
+        store.reserve(&busy).unwrap();
+        let busy_lock = store.lock("busy").unwrap();
+
+        // Deleted, but its lock file wasn't.
+        drop(store.lock("gone").unwrap());
+
+        // Too recent.
+        let opts = PruneOpts {
+            age: Some(Duration::from_secs(3600)),
+        };
+        assert!(prune_containers(&ctx, &opts).unwrap().is_empty());
+
+        let mut removed = prune_containers(&ctx, &PruneOpts::default()).unwrap();
+        removed.sort();
+        let mut expected = vec![
+            ctx.state_dir("dead"),
+            cgroup,
+            ctx.state_dir("reserved"),
+            ctx.locks_dir().join("gone"),
+        ];
+        expected.sort();
+        assert_eq!(expected, removed);
+        assert!(ctx.state_dir("alive").exists());
+        assert!(ctx.state_dir("busy").exists());
+        assert!(!ctx.locks_dir().join("dead").exists());
+
+        drop(busy_lock);
+        fs::remove_dir_all(&ctx.state_dir).unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+use crate::container::validate_id;
+use crate::ctx::setup_ctx;
+use crate::error::ContainerErr;
+use crate::state::Status;
+use crate::store::StateStore;
+use crate::tty::set_size;
+use std::fs::OpenOptions;
+use std::os::fd::AsRawFd;
+
+/// Resizes the terminal of the container's process. The size is set on the terminal
+/// itself rather than on the pty master, so this works whoever holds the master.
+pub fn resize(container_id: String, rows: u16, cols: u16) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    let state = ctx.store().load(&container_id)?;
+    if !matches!(state.status(), Status::Created | Status::Running) {
+        return Err(ContainerErr::State(format!(
+            "Container: {} is not running, status is {:?}",
+            &container_id,
+            state.status()
+        )));
+    }
+
+    // The process' stdin is its terminal if it has one.
+    let terminal = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(format!("/proc/{}/fd/0", state.pid()))
+        .map_err(ContainerErr::IO)?;
+    if unsafe { libc::isatty(terminal.as_raw_fd()) } != 1 {
+        return Err(ContainerErr::State(format!(
+            "Container: {} has no terminal",
+            &container_id
+        )));
+    }
+    set_size(terminal.as_raw_fd(), rows, cols)
+}
@@ -0,0 +1,186 @@
+//! Restore cmd, recreates a container from a checkpoint.
+
+use super::create::Rollback;
+use crate::cgroup::{container_cgroup_path, create_cgroup, detect_cgroup_version};
+use crate::config::{Config, Vars};
+use crate::container::{validate_id, Container};
+use crate::criu::{self, Restore, NETNS_KEY};
+use crate::ctx::{setup_ctx, Ctx};
+use crate::error::ContainerErr;
+use crate::lock::ContainerLock;
+use crate::monitor;
+use crate::mount::resolve_bind_sources;
+use crate::restart::RestartPolicy;
+use crate::rootfs::host_mounts;
+use crate::signal::stop_signal;
+use crate::state::Status;
+use crate::store::StateStore;
+use log::{debug, warn};
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Options for the restore command
+#[derive(Debug, Default)]
+pub struct RestoreOpts {
+    /// Directory the checkpoint's images are in
+    pub image_path: PathBuf,
+    /// Directory for CRIU's log and scratch files, the image dir if unset
+    pub work_path: Option<PathBuf>,
+    /// Network namespace to restore the container into, e.g. one prepared by a CNI
+    /// plugin. The bundle's network namespace path if unset.
+    pub netns: Option<PathBuf>,
+    /// Bind mount sources which moved, as old and new path
+    pub mount_map: Vec<(PathBuf, PathBuf)>,
+    /// The checkpoint has established TCP connections
+    pub tcp_established: bool,
+}
+
+/// Restores the checkpoint in `opts.image_path` as the container `container_id`, from
+/// the bundle it was created from. The container is running afterwards, supervised by
+/// a monitor like a created one.
+pub fn restore(
+    container_id: String,
+    bundle_path: String,
+    opts: RestoreOpts,
+) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    let bundle_path = PathBuf::from(bundle_path);
+    let mut config = Config::load(&bundle_path)?;
+    let ctx = setup_ctx()?;
+    // Expanded the same as when the container was created.
+    let bundle = std::path::absolute(&bundle_path).map_err(ContainerErr::IO)?;
+    let vars = Vars::new(&bundle, &ctx.state_dir(&container_id), &container_id);
+    config.expand_vars(&vars)?;
+    if let Some(mounts) = config.mounts_mut() {
+        resolve_bind_sources(mounts, &bundle);
+    }
+    for controller in config.disable_controllers(&ctx.disabled_controllers()?)? {
+        warn!(
+            "{} settings are not applied, the controller is disabled",
+            controller
+        );
+    }
+    let root = criu::root_dir(&config, &bundle_path)?;
+
+    let mut c = Container::new(container_id.clone(), bundle_path, Arc::new(config));
+    let signal = stop_signal(c.config())?;
+    c.state_mut().set_stop_signal(signal);
+    let store = ctx.store();
+    let lock = store.lock(&container_id)?;
+    c.reserve(&store)?;
+
+    let mut rollback = Rollback {
+        dirs: Some(ctx.container_dirs(&container_id)),
+        cgroup: None,
+        loop_device: None,
+        mounts: Vec::new(),
+    };
+    let result = restore_container(&ctx, c, &root, &opts, &lock, &mut rollback);
+    if let Err(e) = &result {
+        debug!("restore failed, rolling back: {:?}", e);
+        rollback.run();
+    }
+    result
+}
+
+fn restore_container(
+    ctx: &Ctx,
+    mut c: Container,
+    root: &Path,
+    opts: &RestoreOpts,
+    lock: &ContainerLock,
+    rollback: &mut Rollback,
+) -> Result<(), ContainerErr> {
+    let container_id = c.state().id().to_string();
+    let images_dir = fs::canonicalize(&opts.image_path).map_err(ContainerErr::IO)?;
+    let descriptors = criu::read_descriptors(&images_dir)?;
+
+    let cgroup_path = container_cgroup_path(ctx, c.config(), &container_id)?;
+    c.state_mut()
+        .set_cgroup(cgroup_path.clone(), ctx.cgroup_manager());
+    // CRIU mounts the rootfs like the container's init did.
+    let dirs = ctx.container_dirs(&container_id);
+    let mounts = host_mounts(c.config(), c.state().bundle(), &dirs)?;
+    rollback.mounts = mounts.clone();
+    c.state_mut().set_host_mounts(mounts);
+    c.write_state(&ctx.store())?;
+    ctx.container_dirs(&container_id).write_config(c.config())?;
+
+    detect_cgroup_version(ctx.cgroups_root())?;
+    if fs::metadata(&cgroup_path).is_ok() {
+        return Err(ContainerErr::Cgroup(format!(
+            "{:?} already exists",
+            cgroup_path
+        )));
+    }
+    rollback.cgroup = Some(cgroup_path.clone());
+    // Restoring doesn't change the container's settings, they're skipped as before.
+    let policy = ctx.runtime_config()?.resource_policy;
+    create_cgroup(&cgroup_path, c.config(), policy)?;
+    let cgroup = cgroup_path
+        .strip_prefix(ctx.cgroups_root())
+        .map_err(|_| {
+            ContainerErr::Cgroup(format!("{:?} is outside the cgroup mount", cgroup_path))
+        })?
+        .to_path_buf();
+
+    let netns_path = opts.netns.clone().or_else(|| {
+        let namespaces = c.config().linux_namespaces()?;
+        let network = namespaces.iter().find(|ns| ns.typ == "network")?;
+        network.path.as_ref().map(PathBuf::from)
+    });
+    let netns = netns_path
+        .map(|path| File::open(path).map_err(ContainerErr::IO))
+        .transpose()?;
+    let mounts = criu::bind_mount_sources(c.config(), &opts.mount_map);
+    let shell_job = c.config().process().is_some_and(|process| process.terminal);
+
+    let monitor_ctx = ctx.clone();
+    // The monitor can't create the container again from the checkpoint, so restored
+    // containers aren't restarted.
+    let restart = RestartPolicy::Never;
+    monitor::spawn(
+        ctx,
+        &container_id,
+        None,
+        None,
+        restart,
+        false,
+        move |stdio, progress| {
+            lock.release_inherited()?;
+            // The dumped stdout and stderr were pipes to the old monitor, CRIU puts ours in
+            // their place.
+            let mut inherit: Vec<_> = stdio
+                .fds()
+                .into_iter()
+                .zip(descriptors.iter().skip(1))
+                .filter(|(_, dumped)| dumped.starts_with("pipe:"))
+                .map(|(fd, dumped)| (fd, dumped.clone()))
+                .collect();
+            if let Some(netns) = &netns {
+                inherit.push((netns.as_raw_fd(), NETNS_KEY.to_string()));
+            }
+
+            progress.phase("restoring the container with criu");
+            let pid = criu::restore(&Restore {
+                root,
+                images_dir: &images_dir,
+                work_dir: opts.work_path.as_deref(),
+                cgroup: &cgroup,
+                tcp_established: opts.tcp_established,
+                shell_job,
+                mounts: mounts.clone(),
+                inherit,
+            })?;
+            drop(stdio);
+
+            progress.phase("writing state");
+            c.state_mut().set_pid(pid);
+            c.update_status(Status::Running);
+            c.write_state(&monitor_ctx.store())?;
+            Ok(pid)
+        },
+    )
+}
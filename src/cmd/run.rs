@@ -0,0 +1,61 @@
+use super::attach::send_stdin;
+use super::create::{create, CreateOpts};
+use super::delete::{delete, DeleteOpts};
+use super::start::{start, StartOpts};
+use super::wait::wait_exit;
+use crate::container::validate_id;
+use crate::ctx::setup_ctx;
+use crate::error::ContainerErr;
+use std::fs::File;
+use std::io::{self, Write};
+
+/// Options for the run command
+#[derive(Debug, Default)]
+pub struct RunOpts {
+    pub create: CreateOpts,
+    /// Don't send our stdin to the container, its stdin is /dev/null unless it's
+    /// interactive.
+    pub no_stdin: bool,
+}
+
+/// Creates and starts the container and stays in the foreground until its process exits,
+/// sending it our stdin and copying the container's log to stdout. The container is
+/// deleted afterwards, like runc's run does. Returns the process' exit code, see `wait`.
+pub fn run(container_id: String, bundle_path: String, opts: RunOpts) -> Result<i32, ContainerErr> {
+    validate_id(&container_id)?;
+    let mut create_opts = opts.create;
+    // Our stdin goes through the monitor, like attach's.
+    create_opts.interactive |= !opts.no_stdin;
+    create(container_id.clone(), bundle_path, create_opts)?;
+    let stdin = match opts.no_stdin {
+        true => Ok(()),
+        false => setup_ctx().and_then(|ctx| send_stdin(&ctx, &container_id)),
+    };
+    let result = stdin
+        .and_then(|()| start(container_id.clone(), StartOpts::default()))
+        .and_then(|()| follow(&container_id));
+    // Also stops the container if starting it failed halfway.
+    let deleted = delete(container_id, DeleteOpts { force: true });
+    let exit_code = result?;
+    deleted?;
+    Ok(exit_code)
+}
+
+/// Waits for the container to exit, copying what it writes to its log to stdout.
+fn follow(container_id: &str) -> Result<i32, ContainerErr> {
+    let ctx = setup_ctx()?;
+    // There's no log with the journald log driver.
+    let mut log = File::open(ctx.container_dirs(container_id).container_log()).ok();
+    let mut copy_log = || {
+        if let Some(log) = &mut log {
+            let mut stdout = io::stdout().lock();
+            io::copy(log, &mut stdout).map_err(ContainerErr::IO)?;
+            stdout.flush().map_err(ContainerErr::IO)?;
+        }
+        Ok(())
+    };
+    let exit_code = wait_exit(&ctx, container_id, &mut copy_log)?;
+    // Whatever the monitor copied after the process exited.
+    copy_log()?;
+    Ok(exit_code)
+}
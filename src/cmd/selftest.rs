@@ -0,0 +1,265 @@
+//! Selftest cmd, runs containers through their whole lifecycle on this host to see which
+//! of the kernel subsystems the runtime relies on work here.
+//!
+//! The containers need no image: their rootfs is an empty dir with the host's /usr, /bin
+//! and /lib bind mounted read-only, so they run the host's sh.
+
+use super::create::{create, CreateOpts};
+use super::delete::{delete, DeleteOpts};
+use super::exec::{exec, ExecOpts};
+use super::kill::{kill, KillOpts};
+use super::start::{start, StartOpts};
+use crate::ctx::setup_ctx;
+use crate::error::ContainerErr;
+use crate::store::StateStore;
+use serde_json::{json, Value};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+use std::process;
+
+/// Host dirs the containers get, those which are symlinks, as with merged /usr, are
+/// copied as symlinks.
+const HOST_DIRS: [&str; 5] = ["/usr", "/bin", "/sbin", "/lib", "/lib64"];
+const PIDS_LIMIT: i64 = 64;
+/// Outside the rootfs, the container mustn't see it.
+const MARKER_FILENAME: &str = "marker";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Subsystem {
+    Cgroups,
+    PivotRoot,
+    Userns,
+    Seccomp,
+}
+
+impl Subsystem {
+    const ALL: [Subsystem; 4] = [
+        Subsystem::Cgroups,
+        Subsystem::PivotRoot,
+        Subsystem::Userns,
+        Subsystem::Seccomp,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Subsystem::Cgroups => "cgroups",
+            Subsystem::PivotRoot => "pivot_root",
+            Subsystem::Userns => "userns",
+            Subsystem::Seccomp => "seccomp",
+        }
+    }
+
+    /// The container's config, every one limits its pids and has a new root.
+    fn config(self, bundle: &Bundle) -> Value {
+        let mut mounts = vec![json!({"destination": "/proc", "type": "proc", "source": "proc"})];
+        for dir in &bundle.bind_dirs {
+            mounts.push(json!({
+                "destination": dir,
+                "type": "bind",
+                "source": dir,
+                "options": ["rbind", "ro"],
+            }));
+        }
+        let mut config = json!({
+            "ociVersion": "1.0.2",
+            "process": {
+                "args": ["sleep", "3600"],
+                "env": ["PATH=/usr/sbin:/usr/bin:/sbin:/bin"],
+                "cwd": "/",
+            },
+            "root": {"path": "rootfs", "readonly": true},
+            "mounts": mounts,
+            "linux": {
+                "namespaces": [
+                    {"type": "pid"},
+                    {"type": "mount"},
+                    {"type": "ipc"},
+                    {"type": "uts"},
+                ],
+                "resources": {"pids": {"limit": PIDS_LIMIT}},
+            },
+        });
+        let linux = &mut config["linux"];
+        match self {
+            Subsystem::Cgroups | Subsystem::PivotRoot => {}
+            Subsystem::Userns => {
+                linux["namespaces"]
+                    .as_array_mut()
+                    .unwrap()
+                    .push(json!({"type": "user"}));
+                linux["uidMappings"] = json!([{"containerID": 0, "hostID": 100000, "size": 65536}]);
+                linux["gidMappings"] = json!([{"containerID": 0, "hostID": 100000, "size": 65536}]);
+            }
+            Subsystem::Seccomp => {
+                linux["seccomp"] = json!({
+                    "defaultAction": "SCMP_ACT_ALLOW",
+                    "syscalls": [{"names": ["uname"], "action": "SCMP_ACT_ERRNO"}],
+                });
+            }
+        }
+        config
+    }
+
+    /// Checks the subsystem does its job in the running container.
+    fn verify(self, container_id: &str, bundle: &Bundle) -> Result<(), ContainerErr> {
+        let script = match self {
+            Subsystem::Cgroups => {
+                let state = setup_ctx()?.store().load(container_id)?;
+                let cgroup = state
+                    .cgroup_path()
+                    .ok_or_else(|| ContainerErr::Cgroup(String::from("no cgroup recorded")))?;
+                let max = fs::read_to_string(cgroup.join("pids.max")).map_err(ContainerErr::IO)?;
+                if max.trim() != PIDS_LIMIT.to_string() {
+                    return Err(ContainerErr::Cgroup(format!(
+                        "pids.max is {}, expected {}",
+                        max.trim(),
+                        PIDS_LIMIT
+                    )));
+                }
+                String::from("true")
+            }
+            Subsystem::PivotRoot => format!("test ! -e {}", bundle.marker().display()),
+            Subsystem::Userns => String::from("grep -q 100000 /proc/self/uid_map"),
+            Subsystem::Seccomp => String::from("! uname"),
+        };
+        let command = vec![String::from("sh"), String::from("-c"), script.clone()];
+        match exec(container_id.to_string(), command, ExecOpts::default())? {
+            0 => Ok(()),
+            code => Err(ContainerErr::State(format!(
+                "Container: {} `{}` exited with {}",
+                container_id, script, code
+            ))),
+        }
+    }
+}
+
+/// The bundle the containers are created from, removed when dropped.
+struct Bundle {
+    dir: PathBuf,
+    /// The HOST_DIRS which are dirs, bind mounted.
+    bind_dirs: Vec<String>,
+}
+
+impl Bundle {
+    fn create(dir: PathBuf) -> Result<Self, ContainerErr> {
+        let rootfs = dir.join("rootfs");
+        fs::create_dir_all(rootfs.join("proc")).map_err(ContainerErr::IO)?;
+        fs::write(dir.join(MARKER_FILENAME), "").map_err(ContainerErr::IO)?;
+        let mut bind_dirs = Vec::new();
+        for host_dir in HOST_DIRS {
+            let Ok(metadata) = fs::symlink_metadata(host_dir) else {
+                continue;
+            };
+            let target = rootfs.join(host_dir.trim_start_matches('/'));
+            if metadata.is_symlink() {
+                let link = fs::read_link(host_dir).map_err(ContainerErr::IO)?;
+                symlink(link, target).map_err(ContainerErr::IO)?;
+            } else if metadata.is_dir() {
+                fs::create_dir(target).map_err(ContainerErr::IO)?;
+                bind_dirs.push(host_dir.to_string());
+            }
+        }
+        Ok(Self { dir, bind_dirs })
+    }
+
+    fn marker(&self) -> PathBuf {
+        self.dir.join(MARKER_FILENAME)
+    }
+
+    fn write_config(&self, config: &Value) -> Result<(), ContainerErr> {
+        let json = serde_json::to_vec(config).map_err(|e| ContainerErr::Bundle(e.to_string()))?;
+        fs::write(self.dir.join("config.json"), json).map_err(ContainerErr::IO)
+    }
+}
+
+impl Drop for Bundle {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Creates, starts, execs in, kills and deletes a container for each subsystem, and prints
+/// which work. Fails if any doesn't, the output is meant for bug reports.
+pub fn selftest() -> Result<(), ContainerErr> {
+    let dir = std::env::temp_dir().join(format!("container_runtime-selftest-{}", process::id()));
+    let bundle = Bundle::create(dir)?;
+    let mut failed = 0;
+    for subsystem in Subsystem::ALL {
+        match run_container(&bundle, subsystem) {
+            Ok(()) => println!("{}: ok", subsystem.name()),
+            Err(e) => {
+                println!("{}: failed, {:?}", subsystem.name(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(ContainerErr::UnsupportedPlatform(format!(
+            "{} of {} subsystems don't work on this host",
+            failed,
+            Subsystem::ALL.len()
+        )));
+    }
+    Ok(())
+}
+
+fn run_container(bundle: &Bundle, subsystem: Subsystem) -> Result<(), ContainerErr> {
+    let container_id = format!("selftest-{}-{}", subsystem.name(), process::id());
+    bundle.write_config(&subsystem.config(bundle))?;
+    let bundle_path = bundle.dir.to_string_lossy().into_owned();
+    create(container_id.clone(), bundle_path, CreateOpts::default())?;
+    let result = start(container_id.clone(), StartOpts::default())
+        .and_then(|()| subsystem.verify(&container_id, bundle))
+        .and_then(|()| {
+            kill(
+                container_id.clone(),
+                String::from("SIGKILL"),
+                KillOpts::default(),
+            )
+        });
+    // Also stops the container if something failed halfway.
+    let deleted = delete(container_id, DeleteOpts { force: true });
+    result?;
+    deleted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::path::Path;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_configs() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let bundle = Bundle::create(PathBuf::from(format!("/tmp/selftest_{}", time))).unwrap();
+        assert!(bundle.dir.join("rootfs/usr").is_dir());
+        assert!(bundle.bind_dirs.contains(&String::from("/usr")));
+
+        for subsystem in Subsystem::ALL {
+            bundle.write_config(&subsystem.config(&bundle)).unwrap();
+            let config = Config::load(&bundle.dir).unwrap();
+            let user_ns = config
+                .linux_namespaces()
+                .unwrap()
+                .iter()
+                .any(|ns| ns.typ == "user");
+            assert_eq!(subsystem == Subsystem::Userns, user_ns, "{:?}", subsystem);
+            assert_eq!(
+                subsystem == Subsystem::Seccomp,
+                config.seccomp().is_some(),
+                "{:?}",
+                subsystem
+            );
+        }
+
+        let dir = bundle.dir.clone();
+        drop(bundle);
+        assert!(!Path::new(&dir).exists());
+    }
+}
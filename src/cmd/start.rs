@@ -1,20 +1,184 @@
-use crate::ctx::setup_ctx;
+use crate::cgroup::{process_cgroup, state_cgroup_path};
+use crate::config::Config;
+use crate::container::validate_id;
+use crate::ctx::{setup_ctx, Ctx};
+use crate::dirs::ContainerDirs;
 use crate::error::ContainerErr;
+use crate::hooks::{run_hooks, HookPhase};
+use crate::lock::ContainerLock;
+use crate::portforward::PortForwards;
+use crate::process::{pidfd_is_alive, pidfd_open};
+use crate::start_signal::send_start;
+#[cfg(feature = "tokio")]
+use crate::start_signal::send_start_async;
+use crate::state::{State, Status};
+use crate::store::StateStore;
+use crate::trace;
+use libc::ESRCH;
 use log::debug;
-use std::fs::OpenOptions;
+use std::os::fd::OwnedFd;
+use std::time::Duration;
+use tracing::debug_span;
+
+/// Options for the start command
+#[derive(Debug)]
+pub struct StartOpts {
+    /// How long to wait for the container process to pick up the start signal.
+    pub timeout: Duration,
+}
+
+impl Default for StartOpts {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
 
 /// Starts the container process.
-pub fn start(container_id: String) -> Result<(), ContainerErr> {
+pub fn start(container_id: String, opts: StartOpts) -> Result<(), ContainerErr> {
+    let starting = begin_start(container_id)?;
+    let span = debug_span!("start", container_id = starting.container_id).entered();
+    let started = send_start(&starting.dirs, opts.timeout);
+    drop(span);
+    starting.finish(started)
+}
+
+/// Like `start`, but awaits the container process picking up the start signal instead of
+/// blocking the thread. The poststart hooks run on tokio's blocking threads.
+#[cfg(feature = "tokio")]
+pub async fn start_async(container_id: String, opts: StartOpts) -> Result<(), ContainerErr> {
+    use tracing::Instrument;
+
+    let starting = begin_start(container_id)?;
+    let span = debug_span!("start", container_id = starting.container_id);
+    let started = send_start_async(&starting.dirs, opts.timeout)
+        .instrument(span)
+        .await;
+    match tokio::task::spawn_blocking(move || starting.finish(started)).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// A created container about to be sent the start signal.
+struct Starting {
+    ctx: Ctx,
+    container_id: String,
+    dirs: ContainerDirs,
+    /// Held until the container is started, anyone else operating on it waits for us.
+    _lock: ContainerLock,
+    state: State,
+    config: Config,
+    pidfd: OwnedFd,
+}
+
+impl Starting {
+    /// Records the container running once the signal was `started`, and runs the
+    /// poststart hooks.
+    fn finish(mut self, started: Result<(), ContainerErr>) -> Result<(), ContainerErr> {
+        let store = self.ctx.store();
+        if let Err(e) = started {
+            if !pidfd_is_alive(&self.pidfd) {
+                return Err(mark_stopped(
+                    &self.ctx,
+                    &mut self.state,
+                    "init process exited before it could be started",
+                ));
+            }
+            if let ContainerErr::AlreadyStarted(_) = e {
+                // An earlier start got as far as sending the signal but not saving the state.
+                self.state.update_status(Status::Running);
+                store.save(&self.state)?;
+            }
+            return Err(e);
+        }
+
+        self.state.update_status(Status::Running);
+        store.save(&self.state)?;
+
+        run_hooks(&self.config, HookPhase::Poststart, &self.state)
+    }
+}
+
+/// Checks the container can be started and sets up its port forwarding.
+fn begin_start(container_id: String) -> Result<Starting, ContainerErr> {
+    validate_id(&container_id)?;
     let ctx = setup_ctx()?;
-    let state_dir = ctx.state_dir(&container_id);
-    let fifo_path = state_dir.join("exec_fifo");
+    let dirs = ctx.container_dirs(&container_id);
+    let store = ctx.store();
+    let lock = store.lock(&container_id)?;
+    let mut state = store.load(&container_id)?;
+
+    if *state.status() != Status::Created {
+        return Err(ContainerErr::State(format!(
+            "Container: {} cannot be started, status is {:?}",
+            &container_id,
+            state.status()
+        )));
+    }
 
-    debug!("opening FIFO");
-    let _ = OpenOptions::new()
-        .append(true)
-        .open(&fifo_path)
-        .map_err(|e| ContainerErr::Fifo(format!("err: {:?}", e)))?;
-    debug!("done with fifo");
+    let config = dirs.load_config()?;
+    let args = config.process().and_then(|p| p.args.as_ref());
+    if args.is_none_or(|args| args.is_empty()) {
+        return Err(ContainerErr::State(format!(
+            "Container: {} has no process to execute",
+            &container_id
+        )));
+    }
+
+    // Hold on to a pidfd so the checks below refer to the process create cloned, even if
+    // it exits and the pid gets reused while we're starting it.
+    let pidfd = match pidfd_open(state.pid()) {
+        Ok(pidfd) => pidfd,
+        Err(e) if state.pid() == 0 || e.raw_os_error() == Some(ESRCH) => {
+            return Err(mark_stopped(
+                &ctx,
+                &mut state,
+                "init process no longer exists",
+            ));
+        }
+        Err(e) => return Err(ContainerErr::IO(e)),
+    };
+
+    let expected_cgroup = state_cgroup_path(&ctx, &state, &config)?;
+    let actual_cgroup = process_cgroup(state.pid(), ctx.cgroups_root())?;
+    if actual_cgroup != expected_cgroup {
+        let reason = format!(
+            "pid {} is in cgroup {:?} instead of {:?}, the init process is gone",
+            state.pid(),
+            actual_cgroup,
+            expected_cgroup
+        );
+        return Err(mark_stopped(&ctx, &mut state, &reason));
+    }
+
+    if let Some(mut ports) = PortForwards::load(&dirs)? {
+        debug!("setting up port forwarding");
+        let result = ports.apply(state.pid());
+        // Record whatever was set up, even on failure, so delete can clean it up.
+        ports.write(&dirs)?;
+        result?;
+    }
+
+    trace::resume(&dirs)?;
+    Ok(Starting {
+        ctx,
+        container_id,
+        dirs,
+        _lock: lock,
+        state,
+        config,
+        pidfd,
+    })
+}
 
-    Ok(())
+/// Records that the container's init process is gone and builds the error to report.
+fn mark_stopped(ctx: &Ctx, state: &mut State, reason: &str) -> ContainerErr {
+    debug!("marking container stopped: {}", reason);
+    state.update_status(Status::Stopped);
+    if let Err(e) = ctx.store().save(state) {
+        debug!("failed to write state: {:?}", e);
+    }
+    ContainerErr::State(format!("Container: {} {}", state.id(), reason))
 }
@@ -1,5 +1,114 @@
+use crate::container::validate_id;
+use crate::ctx::{setup_ctx, Ctx};
 use crate::error::ContainerErr;
+use crate::state::{Pid, State, Status};
+use crate::store::StateStore;
+use crate::watch::{Change, StateWatcher};
 
-pub fn state(_container_id: String) -> Result<(), ContainerErr> {
-    todo!("implement state cmd");
+/// Options for the state command
+#[derive(Debug, Default)]
+pub struct StateOpts {
+    /// Keep printing the state whenever its status changes, until the container is
+    /// deleted.
+    pub watch: bool,
+}
+
+/// Prints the state of the container as json.
+pub fn state(container_id: String, opts: StateOpts) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    if opts.watch {
+        return watch(&ctx, &container_id);
+    }
+    let state = ctx.store().load(&container_id)?;
+    print_state(&state)
+}
+
+fn print_state(state: &State) -> Result<(), ContainerErr> {
+    let json = serde_json::to_string(state).map_err(|e| ContainerErr::State(e.to_string()))?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Prints the state, a line each time its status or init process changes, until the
+/// container is deleted.
+fn watch(ctx: &Ctx, container_id: &str) -> Result<(), ContainerErr> {
+    let store = ctx.store();
+    // Fails for containers which don't exist, the same as loading their state.
+    store.load(container_id)?;
+    // Watching before loading the state again, so no change goes unnoticed.
+    let mut watcher = StateWatcher::new(&ctx.container_dirs(container_id))?;
+    let mut transitions = Transitions::default();
+    loop {
+        // Fails while the container is being deleted, the watcher tells us when it's gone.
+        if let Ok(mut state) = store.load(container_id) {
+            let alive = watcher.follow(state.pid())?;
+            // Nothing may be left to record the exit, if the monitor went away.
+            if !alive && matches!(state.status(), Status::Created | Status::Running) {
+                state.update_status(Status::Stopped);
+            }
+            if transitions.changed(&state) {
+                print_state(&state)?;
+            }
+        }
+        if watcher.wait()? == Change::Deleted {
+            return Ok(());
+        }
+    }
+}
+
+/// Tells apart the states worth a line from states written for other reasons, like a
+/// health check.
+#[derive(Default)]
+struct Transitions {
+    last: Option<(Status, Pid)>,
+}
+
+impl Transitions {
+    fn changed(&mut self, state: &State) -> bool {
+        let current = Some((state.status().clone(), state.pid()));
+        if current == self.last {
+            return false;
+        }
+        self.last = current;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_transitions() {
+        let mut state = State::new(
+            String::from("watched"),
+            PathBuf::from("/bundle"),
+            String::from("1.0.1"),
+        );
+        let mut transitions = Transitions::default();
+        assert!(transitions.changed(&state));
+        assert!(!transitions.changed(&state));
+
+        state.set_pid(42);
+        state.update_status(Status::Created);
+        assert!(transitions.changed(&state));
+        state.update_status(Status::Running);
+        assert!(transitions.changed(&state));
+        state.set_restart_count(1);
+        assert!(!transitions.changed(&state));
+        // A restart gives the container a new init process.
+        state.set_pid(43);
+        assert!(transitions.changed(&state));
+    }
+
+    #[test]
+    fn test_state_invalid_id() {
+        // Rejected before any path is made from it, not only by the CLI.
+        assert!(matches!(
+            state(String::from("../etc"), StateOpts::default()),
+            Err(ContainerErr::InvalidId(_))
+        ));
+    }
 }
@@ -0,0 +1,100 @@
+use crate::cgroup::{kill_cgroup, state_cgroup_path, wait_empty};
+use crate::container::validate_id;
+use crate::ctx::{setup_ctx, Ctx};
+use crate::error::ContainerErr;
+use crate::process::{pidfd_open, pidfd_send_signal, pidfd_wait_exit};
+use crate::state::Status;
+use crate::store::StateStore;
+use libc::ESRCH;
+use log::debug;
+use std::fs;
+use std::time::Duration;
+
+/// How long to wait for the cgroup to empty once its processes are killed.
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options for the stop command
+#[derive(Debug)]
+pub struct StopOpts {
+    /// How long the init process gets to exit after the stop signal before everything in
+    /// the container is killed.
+    pub timeout: Duration,
+}
+
+impl Default for StopOpts {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Stops the container: sends its init process its stop signal, and once it exited or
+/// the timeout passed kills whatever is left in its cgroup. A stopped container isn't
+/// restarted by its monitor.
+pub fn stop(container_id: String, opts: StopOpts) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    let _lock = ctx.store().lock(&container_id)?;
+    stop_container(&ctx, &container_id, opts.timeout)
+}
+
+/// Stops the container, the caller holds its lock.
+pub(crate) fn stop_container(
+    ctx: &Ctx,
+    container_id: &str,
+    timeout: Duration,
+) -> Result<(), ContainerErr> {
+    let store = ctx.store();
+    let mut state = store.load(container_id)?;
+
+    // Recorded before signalling anything, so the monitor sees it when the process exits.
+    state.request_stop();
+    store.save(&state)?;
+    match state.status() {
+        Status::Created | Status::Running => {}
+        // The monitor may be about to restart it, the request above prevents that.
+        Status::Stopped => return Ok(()),
+        status => {
+            return Err(ContainerErr::State(format!(
+                "Container: {} cannot be stopped, status is {:?}",
+                container_id, status
+            )))
+        }
+    }
+
+    let signal = state.stop_signal();
+    match pidfd_open(state.pid()) {
+        Ok(pidfd) => {
+            debug!("sending signal {} to {}", signal, state.pid());
+            match pidfd_send_signal(&pidfd, signal) {
+                Err(e) if e.raw_os_error() != Some(ESRCH) => return Err(ContainerErr::IO(e)),
+                _ => {}
+            }
+            if !pidfd_wait_exit(&pidfd, timeout)? {
+                debug!("init process didn't exit within {:?}", timeout);
+            }
+        }
+        Err(e) if e.raw_os_error() == Some(ESRCH) => debug!("init process already exited"),
+        Err(e) => return Err(ContainerErr::IO(e)),
+    }
+
+    // Anything still running, like exec'd processes or the init process if it ignored
+    // the signal, is killed.
+    let config = ctx.container_dirs(container_id).load_config()?;
+    let cgroup = state_cgroup_path(ctx, &state, &config)?;
+    if fs::metadata(&cgroup).is_ok() {
+        kill_cgroup(&cgroup)?;
+        if !wait_empty(&cgroup, KILL_TIMEOUT)? {
+            return Err(ContainerErr::State(format!(
+                "Container: {} still has processes in {:?}",
+                container_id, cgroup
+            )));
+        }
+    }
+
+    // The monitor may have updated the state in the meantime.
+    let mut state = store.load(container_id)?;
+    state.update_status(Status::Stopped);
+    store.save(&state)
+}
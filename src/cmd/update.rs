@@ -0,0 +1,200 @@
+//! Update cmd, changes the resources of a running container.
+//!
+//! `--resources` takes linux.resources as in config.json, the controllers it has
+//! settings for replace the container's. Memory limits with checkBeforeUpdate are refused
+//! if the container already uses more.
+//!
+//! With `--auto-cpu` it keeps running, and tunes the container's cpu quota by its
+//! cpu.pressure: a stalled container gets more of the cpu, up to the maximum, and an idle
+//! one gives it back, down to the minimum. What's left up to the maximum is the burst, so
+//! short spikes aren't throttled in the meantime.
+
+use crate::cgroup::{
+    read_cpu_max, read_pressure, set_cpu_quota, state_cgroup_path, update_cgroup, wait_empty,
+};
+use crate::config::Config;
+use crate::container::validate_id;
+use crate::ctx::{setup_ctx, Ctx};
+use crate::error::ContainerErr;
+use crate::events::{self, Event};
+use crate::state::Status;
+use crate::store::StateStore;
+use log::{debug, warn};
+use serde_json::json;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// Above this share of stalled time, in percent, the quota goes up.
+const PRESSURE_HIGH: f64 = 10.0;
+/// Below it, the quota goes down.
+const PRESSURE_LOW: f64 = 1.0;
+/// The quota moves in this many steps between its bounds.
+const QUOTA_STEPS: u64 = 4;
+
+/// Options for the update command
+#[derive(Debug)]
+pub struct UpdateOpts {
+    /// linux.resources to merge into the container's, see `Config::update_resources`.
+    pub resources: Option<PathBuf>,
+    /// Tune the cpu quota until the container stops.
+    pub auto_cpu: bool,
+    /// The quota's lower bound, in microseconds per period. The container's quota by
+    /// default.
+    pub cpu_quota_min: Option<u64>,
+    /// The quota's upper bound. All of the host's cpus by default.
+    pub cpu_quota_max: Option<u64>,
+    /// How often the pressure is checked.
+    pub interval: Duration,
+}
+
+impl Default for UpdateOpts {
+    fn default() -> Self {
+        Self {
+            resources: None,
+            auto_cpu: false,
+            cpu_quota_min: None,
+            cpu_quota_max: None,
+            // The shortest of the pressure averages.
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Updates the container's resources.
+pub fn update(container_id: String, opts: UpdateOpts) -> Result<(), ContainerErr> {
+    validate_id(&container_id)?;
+    if opts.resources.is_none() && !opts.auto_cpu {
+        return Err(ContainerErr::invalid_args("Nothing to update"));
+    }
+    let ctx = setup_ctx()?;
+    if let Some(path) = &opts.resources {
+        let _lock = ctx.store().lock(&container_id)?;
+        let (cgroup, mut config) = running_cgroup(&ctx, &container_id)?;
+        config.update_resources(path)?;
+        for controller in config.disable_controllers(&ctx.disabled_controllers()?)? {
+            warn!(
+                "{} settings are not applied, the controller is disabled",
+                controller
+            );
+        }
+        let policy = ctx.runtime_config()?.resource_policy;
+        for message in update_cgroup(&cgroup, &config, policy)? {
+            let event = Event::new("warning", &container_id, json!({ "message": message }));
+            events::emit(&ctx.container_dirs(&container_id), &event)?;
+        }
+        // Later commands go by the copy of the config.
+        ctx.container_dirs(&container_id).write_config(&config)?;
+    }
+    if opts.auto_cpu {
+        tune_cpu(&ctx, &container_id, &opts)?;
+    }
+    Ok(())
+}
+
+/// The cgroup and config of a container which is running.
+fn running_cgroup(ctx: &Ctx, container_id: &str) -> Result<(PathBuf, Config), ContainerErr> {
+    let state = ctx.store().load(container_id)?;
+    if !matches!(state.status(), Status::Created | Status::Running) {
+        return Err(ContainerErr::State(format!(
+            "Container: {} is not running, status is {:?}",
+            container_id,
+            state.status()
+        )));
+    }
+    let config = ctx.container_dirs(container_id).load_config()?;
+    Ok((state_cgroup_path(ctx, &state, &config)?, config))
+}
+
+/// Adjusts the cpu quota by the pressure until the container stops.
+fn tune_cpu(ctx: &Ctx, container_id: &str, opts: &UpdateOpts) -> Result<(), ContainerErr> {
+    let (cgroup, _) = running_cgroup(ctx, container_id)?;
+    let dirs = ctx.container_dirs(container_id);
+    let cpu_max = read_cpu_max(&cgroup)?;
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get() as u64);
+    let bounds = QuotaBounds::new(
+        opts.cpu_quota_min.or(cpu_max.quota),
+        opts.cpu_quota_max.unwrap_or(cpu_max.period * cpus),
+    )?;
+    debug!("tuning cpu quota of {} within {:?}", container_id, bounds);
+
+    while !wait_empty(&cgroup, Duration::ZERO)? {
+        let cpu_max = read_cpu_max(&cgroup)?;
+        let pressure = read_pressure(&cgroup, "cpu.pressure")?.some.avg10;
+        let quota = bounds.next_quota(cpu_max.quota, pressure);
+        if cpu_max.quota != Some(quota) {
+            let burst = bounds.burst(quota);
+            set_cpu_quota(&cgroup, cpu_max, quota, burst)?;
+            let data = json!({"quota": quota, "burst": burst, "pressure": pressure});
+            events::emit(&dirs, &Event::new("cpu_quota", container_id, data))?;
+        }
+        thread::sleep(opts.interval);
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct QuotaBounds {
+    min: u64,
+    max: u64,
+}
+
+impl QuotaBounds {
+    fn new(min: Option<u64>, max: u64) -> Result<Self, ContainerErr> {
+        let min = min.ok_or_else(|| {
+            ContainerErr::invalid_args("The container has no cpu quota, --cpu-quota-min is needed")
+        })?;
+        if min == 0 || min > max {
+            return Err(ContainerErr::invalid_args(&format!(
+                "Invalid cpu quota bounds: {} to {}",
+                min, max
+            )));
+        }
+        Ok(Self { min, max })
+    }
+
+    /// The quota after a period with `pressure`, one step up or down. A quota outside
+    /// the bounds, or none, is brought within them first.
+    fn next_quota(&self, quota: Option<u64>, pressure: f64) -> u64 {
+        let quota = quota.unwrap_or(self.max).clamp(self.min, self.max);
+        let step = ((self.max - self.min) / QUOTA_STEPS).max(1);
+        if pressure > PRESSURE_HIGH {
+            (quota + step).min(self.max)
+        } else if pressure < PRESSURE_LOW {
+            quota.saturating_sub(step).max(self.min)
+        } else {
+            quota
+        }
+    }
+
+    /// The headroom up to the maximum, the kernel caps the burst at the quota.
+    fn burst(&self, quota: u64) -> u64 {
+        (self.max - quota).min(quota)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_bounds() {
+        assert!(QuotaBounds::new(None, 100_000).is_err());
+        assert!(QuotaBounds::new(Some(0), 100_000).is_err());
+        assert!(QuotaBounds::new(Some(200_000), 100_000).is_err());
+
+        let bounds = QuotaBounds::new(Some(100_000), 500_000).unwrap();
+        assert_eq!(200_000, bounds.next_quota(Some(100_000), 25.0));
+        assert_eq!(500_000, bounds.next_quota(Some(450_000), 25.0));
+        assert_eq!(300_000, bounds.next_quota(Some(300_000), 5.0));
+        assert_eq!(200_000, bounds.next_quota(Some(300_000), 0.0));
+        assert_eq!(100_000, bounds.next_quota(Some(120_000), 0.0));
+        // Unlimited, or out of bounds.
+        assert_eq!(500_000, bounds.next_quota(None, 5.0));
+        assert_eq!(100_000, bounds.next_quota(Some(10_000), 5.0));
+
+        assert_eq!(100_000, bounds.burst(100_000));
+        assert_eq!(200_000, bounds.burst(300_000));
+        assert_eq!(0, bounds.burst(500_000));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::container::validate_id;
+use crate::ctx::{setup_ctx, Ctx};
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use crate::monitor::ExitStatus;
+#[cfg(feature = "tokio")]
+use crate::syscalls;
+use std::fs;
+#[cfg(feature = "tokio")]
+use std::fs::File;
+use std::io::ErrorKind;
+#[cfg(feature = "tokio")]
+use std::os::fd::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+/// How often to check whether the container has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Waits for the container's process to exit, prints its exit code and returns it. The
+/// code is 128 + the signal number if the process was killed by a signal, like a shell's.
+pub fn wait(container_id: String) -> Result<i32, ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    let exit_code = wait_exit(&ctx, &container_id, || Ok(()))?;
+    println!("{}", exit_code);
+    Ok(exit_code)
+}
+
+/// Waits for the monitor to record the container's exit status, calling `on_poll` in
+/// between checks. Returns the exit code.
+pub(crate) fn wait_exit<F>(
+    ctx: &Ctx,
+    container_id: &str,
+    mut on_poll: F,
+) -> Result<i32, ContainerErr>
+where
+    F: FnMut() -> Result<(), ContainerErr>,
+{
+    let dirs = ctx.container_dirs(container_id);
+    loop {
+        on_poll()?;
+        if let Some(exit_code) = exit_code(&dirs, container_id)? {
+            return Ok(exit_code);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Like `wait`, but awaits the monitor recording the exit status, with inotify on the
+/// state dir, instead of polling for it. Returns the exit code without printing it.
+#[cfg(feature = "tokio")]
+pub async fn wait_async(container_id: String) -> Result<i32, ContainerErr> {
+    validate_id(&container_id)?;
+    let ctx = setup_ctx()?;
+    wait_exit_async(&ctx, &container_id).await
+}
+
+#[cfg(feature = "tokio")]
+async fn wait_exit_async(ctx: &Ctx, container_id: &str) -> Result<i32, ContainerErr> {
+    use libc::{IN_CLOEXEC, IN_DELETE_SELF, IN_MOVED_TO, IN_NONBLOCK, IN_ONLYDIR};
+    use std::io::Read;
+    use tokio::io::unix::AsyncFd;
+
+    let dirs = ctx.container_dirs(container_id);
+    // Watching before the first look, the exit status may be written in between. The
+    // monitor renames it into place.
+    let inotify = syscalls::inotify_init1(IN_CLOEXEC | IN_NONBLOCK).map_err(ContainerErr::IO)?;
+    let mask = IN_MOVED_TO | IN_DELETE_SELF | IN_ONLYDIR;
+    if let Err(e) = syscalls::inotify_add_watch(inotify.as_raw_fd(), dirs.dir(), mask) {
+        return match e.kind() {
+            ErrorKind::NotFound => Err(gone(container_id)),
+            _ => Err(ContainerErr::IO(e)),
+        };
+    }
+    let inotify = AsyncFd::new(File::from(inotify)).map_err(ContainerErr::IO)?;
+    let mut buf = [0u8; 4096];
+    loop {
+        if let Some(exit_code) = exit_code(&dirs, container_id)? {
+            return Ok(exit_code);
+        }
+        let mut guard = inotify.readable().await.map_err(ContainerErr::IO)?;
+        // Only that something changed matters, the events are read to rearm.
+        loop {
+            match guard.try_io(|inotify| inotify.get_ref().read(&mut buf)) {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => return Err(ContainerErr::IO(e)),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// The container's exit code once the monitor recorded it.
+fn exit_code(dirs: &ContainerDirs, container_id: &str) -> Result<Option<i32>, ContainerErr> {
+    match ExitStatus::load(dirs) {
+        Ok(status) => return Ok(Some(status.exit_code)),
+        Err(ContainerErr::IO(e)) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    if fs::metadata(dirs.dir()).is_err() {
+        return Err(gone(container_id));
+    }
+    Ok(None)
+}
+
+fn gone(container_id: &str) -> ContainerErr {
+    ContainerErr::State(format!("Container: {} no longer exists", container_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_wait_exit() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let mut ctx = Ctx::default();
+        ctx.state_dir = PathBuf::from(format!("/tmp/wait_{}", time));
+        let dirs = ctx.container_dirs("foo");
+        dirs.create().unwrap();
+
+        let exit_file = dirs.exit_status();
+        let mut polls = 0;
+        let exit_code = wait_exit(&ctx, "foo", || {
+            polls += 1;
+            if polls == 3 {
+                let status = r#"{"exitCode":137,"createdAt":1,"exitedAt":2}"#;
+                fs::write(&exit_file, status).map_err(ContainerErr::IO)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(137, exit_code);
+        assert_eq!(3, polls);
+
+        assert!(wait_exit(&ctx, "bar", || Ok(())).is_err());
+        fs::remove_dir_all(&ctx.state_dir).unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_wait_async() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let mut ctx = Ctx::default();
+        ctx.state_dir = PathBuf::from(format!("/tmp/wait_async_{}", time));
+        let dirs = ctx.container_dirs("foo");
+        dirs.create().unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // Written the way the monitor does, renamed into place.
+        let exit_file = dirs.exit_status();
+        let writing = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let tmp = exit_file.with_extension("json.tmp");
+            fs::write(&tmp, r#"{"exitCode":137,"createdAt":1,"exitedAt":2}"#).unwrap();
+            fs::rename(&tmp, &exit_file).unwrap();
+        });
+        assert_eq!(137, runtime.block_on(wait_exit_async(&ctx, "foo")).unwrap());
+        writing.join().unwrap();
+
+        assert!(runtime.block_on(wait_exit_async(&ctx, "bar")).is_err());
+        let deleting = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            dirs.remove().unwrap();
+        });
+        fs::remove_file(ctx.container_dirs("foo").exit_status()).unwrap();
+        let result = runtime.block_on(wait_exit_async(&ctx, "foo"));
+        assert!(matches!(result, Err(ContainerErr::State(_))));
+        deleting.join().unwrap();
+        fs::remove_dir_all(&ctx.state_dir).unwrap();
+    }
+}
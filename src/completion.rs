@@ -0,0 +1,231 @@
+//! Shell completion scripts and a JSON description of the CLI, both generated from
+//! `args::COMMANDS` so they can't fall behind the parser.
+
+use crate::args::{CommandSpec, COMMANDS, GLOBAL_FLAGS};
+use container_runtime_lib::error::ContainerErr;
+use serde_json::{json, Value};
+use std::fmt::Write;
+use std::str::FromStr;
+
+const BIN: &str = "container_runtime";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = ContainerErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(ContainerErr::invalid_args(&format!(
+                "Unsupported shell, expected bash, zsh or fish: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// The completion script for `shell`, to be sourced by it.
+pub fn script(shell: Shell) -> String {
+    match shell {
+        Shell::Bash => bash(),
+        Shell::Zsh => zsh(),
+        Shell::Fish => fish(),
+    }
+}
+
+fn flags(spec: &CommandSpec) -> String {
+    let flags: Vec<&str> = spec
+        .value_flags
+        .iter()
+        .chain(spec.switches)
+        .copied()
+        .collect();
+    flags.join(" ")
+}
+
+fn command_names() -> String {
+    let names: Vec<&str> = COMMANDS.iter().map(|spec| spec.name).collect();
+    names.join(" ")
+}
+
+/// The loop finding the command in the words before the cursor, skipping global flags and
+/// their values. Shared by bash and zsh.
+fn find_command(words: &str, first: usize, current: &str) -> String {
+    format!(
+        r#"    local i cmd=
+    for ((i = {first}; i < {current}; i++)); do
+        case ${{{words}[i]}} in
+            {global}) ((i++)) ;;
+            -*) ;;
+            *) cmd=${{{words}[i]}}; break ;;
+        esac
+    done
+"#,
+        first = first,
+        current = current,
+        words = words,
+        global = GLOBAL_FLAGS.join("|"),
+    )
+}
+
+fn bash() -> String {
+    let mut script = format!(
+        "_{bin}() {{\n    local cur=${{COMP_WORDS[COMP_CWORD]}}\n{find}    case $cmd in\n",
+        bin = BIN,
+        find = find_command("COMP_WORDS", 1, "COMP_CWORD"),
+    );
+    let _ = writeln!(
+        script,
+        "        '') COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\")) ;;",
+        GLOBAL_FLAGS.join(" "),
+        command_names()
+    );
+    for spec in COMMANDS {
+        let _ = writeln!(
+            script,
+            "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")) ;;",
+            spec.name,
+            flags(spec)
+        );
+    }
+    let _ = write!(
+        script,
+        "    esac\n}}\ncomplete -o default -F _{bin} {bin}\n",
+        bin = BIN
+    );
+    script
+}
+
+fn zsh() -> String {
+    // words[1] is the binary.
+    let mut script = format!(
+        "#compdef {bin}\n\n_{bin}() {{\n{find}    case $cmd in\n",
+        bin = BIN,
+        find = find_command("words", 2, "CURRENT"),
+    );
+    let _ = writeln!(
+        script,
+        "        '') compadd -- {} {} ;;",
+        GLOBAL_FLAGS.join(" "),
+        command_names()
+    );
+    for spec in COMMANDS {
+        let _ = writeln!(
+            script,
+            "        {}) compadd -- {}; _files ;;",
+            spec.name,
+            flags(spec)
+        );
+    }
+    let _ = write!(script, "    esac\n}}\n\n_{bin} \"$@\"\n", bin = BIN);
+    script
+}
+
+/// A fish `complete` option for `flag`, long or short.
+fn fish_flag(flag: &str) -> String {
+    match flag.strip_prefix("--") {
+        Some(long) => format!("-l {}", long),
+        None => format!("-s {}", flag.trim_start_matches('-')),
+    }
+}
+
+fn fish() -> String {
+    let mut script = String::new();
+    let _ = writeln!(
+        script,
+        "complete -c {} -n __fish_use_subcommand -f -a \"{}\"",
+        BIN,
+        command_names()
+    );
+    for flag in GLOBAL_FLAGS {
+        let _ = writeln!(
+            script,
+            "complete -c {} -n __fish_use_subcommand {} -r",
+            BIN,
+            fish_flag(flag)
+        );
+    }
+    for spec in COMMANDS {
+        let condition = format!("\"__fish_seen_subcommand_from {}\"", spec.name);
+        for flag in spec.value_flags {
+            let _ = writeln!(
+                script,
+                "complete -c {} -n {} {} -r",
+                BIN,
+                condition,
+                fish_flag(flag)
+            );
+        }
+        for flag in spec.switches {
+            let _ = writeln!(
+                script,
+                "complete -c {} -n {} {}",
+                BIN,
+                condition,
+                fish_flag(flag)
+            );
+        }
+    }
+    script
+}
+
+/// The commands with their arguments and flags, for `--help-json`.
+pub fn help_json() -> Value {
+    let flag = |name: &&str, takes_value: bool| json!({"name": name, "takesValue": takes_value});
+    let commands: Vec<Value> = COMMANDS
+        .iter()
+        .map(|spec| {
+            let flags: Vec<Value> = spec
+                .value_flags
+                .iter()
+                .map(|name| flag(name, true))
+                .chain(spec.switches.iter().map(|name| flag(name, false)))
+                .collect();
+            json!({"name": spec.name, "args": spec.args, "flags": flags})
+        })
+        .collect();
+    let global: Vec<Value> = GLOBAL_FLAGS.iter().map(|name| flag(name, true)).collect();
+    json!({"globalFlags": global, "commands": commands})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripts() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = script(shell);
+            for spec in COMMANDS {
+                assert!(script.contains(spec.name), "{:?} {}", shell, spec.name);
+            }
+        }
+        assert!(bash().contains("        exec) COMPREPLY=($(compgen -W \"--env --cwd --user"));
+        assert!(fish().contains(
+            "complete -c container_runtime -n \"__fish_seen_subcommand_from exec\" -s p -r\n"
+        ));
+        assert!("csh".parse::<Shell>().is_err());
+    }
+
+    #[test]
+    fn test_help_json() {
+        let help = help_json();
+        let commands = help["commands"].as_array().unwrap();
+        assert_eq!(COMMANDS.len(), commands.len());
+        let kill = commands.iter().find(|c| c["name"] == "kill").unwrap();
+        assert_eq!(json!(["<container-id>", "<signal>"]), kill["args"]);
+        assert_eq!(
+            json!([{"name": "--all", "takesValue": false}]),
+            kill["flags"]
+        );
+        assert_eq!("--cgroup-manager", help["globalFlags"][0]["name"]);
+    }
+}
@@ -0,0 +1,145 @@
+//! Linux capabilities
+//! https://github.com/opencontainers/runtime-spec/blob/main/config.md#linux-process
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// Capability sets of the container process
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct LinuxCapabilities {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounding: Option<Vec<Capability>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective: Option<Vec<Capability>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inheritable: Option<Vec<Capability>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permitted: Option<Vec<Capability>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ambient: Option<Vec<Capability>>,
+
+    #[serde(flatten)]
+    pub(super) unknown: Map<String, Value>,
+}
+
+macro_rules! capabilities {
+    ($($variant:ident = $value:literal => $name:literal,)*) => {
+        /// A capability, numbered as in linux/capability.h
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        #[repr(u8)]
+        pub enum Capability {
+            $($variant = $value,)*
+        }
+
+        impl Capability {
+            /// The capability's name as used in config.json, e.g. `CAP_KILL`
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Capability::$variant => $name,)*
+                }
+            }
+        }
+
+        impl FromStr for Capability {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($name => Ok(Capability::$variant),)*
+                    _ => Err(format!("unknown capability: {}", s)),
+                }
+            }
+        }
+    };
+}
+
+capabilities! {
+    Chown = 0 => "CAP_CHOWN",
+    DacOverride = 1 => "CAP_DAC_OVERRIDE",
+    DacReadSearch = 2 => "CAP_DAC_READ_SEARCH",
+    Fowner = 3 => "CAP_FOWNER",
+    Fsetid = 4 => "CAP_FSETID",
+    Kill = 5 => "CAP_KILL",
+    Setgid = 6 => "CAP_SETGID",
+    Setuid = 7 => "CAP_SETUID",
+    Setpcap = 8 => "CAP_SETPCAP",
+    LinuxImmutable = 9 => "CAP_LINUX_IMMUTABLE",
+    NetBindService = 10 => "CAP_NET_BIND_SERVICE",
+    NetBroadcast = 11 => "CAP_NET_BROADCAST",
+    NetAdmin = 12 => "CAP_NET_ADMIN",
+    NetRaw = 13 => "CAP_NET_RAW",
+    IpcLock = 14 => "CAP_IPC_LOCK",
+    IpcOwner = 15 => "CAP_IPC_OWNER",
+    SysModule = 16 => "CAP_SYS_MODULE",
+    SysRawio = 17 => "CAP_SYS_RAWIO",
+    SysChroot = 18 => "CAP_SYS_CHROOT",
+    SysPtrace = 19 => "CAP_SYS_PTRACE",
+    SysPacct = 20 => "CAP_SYS_PACCT",
+    SysAdmin = 21 => "CAP_SYS_ADMIN",
+    SysBoot = 22 => "CAP_SYS_BOOT",
+    SysNice = 23 => "CAP_SYS_NICE",
+    SysResource = 24 => "CAP_SYS_RESOURCE",
+    SysTime = 25 => "CAP_SYS_TIME",
+    SysTtyConfig = 26 => "CAP_SYS_TTY_CONFIG",
+    Mknod = 27 => "CAP_MKNOD",
+    Lease = 28 => "CAP_LEASE",
+    AuditWrite = 29 => "CAP_AUDIT_WRITE",
+    AuditControl = 30 => "CAP_AUDIT_CONTROL",
+    Setfcap = 31 => "CAP_SETFCAP",
+    MacOverride = 32 => "CAP_MAC_OVERRIDE",
+    MacAdmin = 33 => "CAP_MAC_ADMIN",
+    Syslog = 34 => "CAP_SYSLOG",
+    WakeAlarm = 35 => "CAP_WAKE_ALARM",
+    BlockSuspend = 36 => "CAP_BLOCK_SUSPEND",
+    AuditRead = 37 => "CAP_AUDIT_READ",
+    Perfmon = 38 => "CAP_PERFMON",
+    Bpf = 39 => "CAP_BPF",
+    CheckpointRestore = 40 => "CAP_CHECKPOINT_RESTORE",
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Names are validated when the config is parsed, so a typo fails create instead of
+// being silently dropped when capabilities are applied.
+impl<'de> Deserialize<'de> for Capability {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Capability {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capability_names() {
+        let caps: LinuxCapabilities = serde_json::from_str(
+            r#"{"bounding": ["CAP_NET_BIND_SERVICE", "CAP_CHECKPOINT_RESTORE"]}"#,
+        )
+        .unwrap();
+        let bounding = caps.bounding.unwrap();
+        assert_eq!(
+            vec![Capability::NetBindService, Capability::CheckpointRestore],
+            bounding
+        );
+        assert_eq!(10, bounding[0] as u8);
+
+        let err = serde_json::from_str::<LinuxCapabilities>(r#"{"ambient": ["CAP_NET_BIND"]}"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown capability: CAP_NET_BIND"));
+    }
+}
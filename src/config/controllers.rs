@@ -0,0 +1,85 @@
+//! Controllers disabled on the host, e.g. in VM images whose cgroup root doesn't expose io
+//! or hugetlb. Their settings are left out rather than failing every container setting
+//! them.
+
+use super::*;
+
+/// The controllers linux.resources has settings for.
+const CONTROLLERS: [&str; 6] = ["cpu", "hugetlb", "io", "memory", "pids", "rdma"];
+
+impl Config {
+    /// Drops the settings of the `disabled` controllers from linux.resources. Returns the
+    /// controllers which had any, in order.
+    pub fn disable_controllers(
+        &mut self,
+        disabled: &[String],
+    ) -> Result<Vec<&'static str>, ContainerErr> {
+        if let Some(unknown) = disabled
+            .iter()
+            .find(|name| !CONTROLLERS.contains(&name.as_str()))
+        {
+            return Err(ContainerErr::Options(format!(
+                "Unknown controller {}, expected one of {}",
+                unknown,
+                CONTROLLERS.join(", ")
+            )));
+        }
+        let Some(resources) = self.linux.as_mut().and_then(|l| l.resources.as_mut()) else {
+            return Ok(Vec::new());
+        };
+        let mut skipped = Vec::new();
+        for controller in CONTROLLERS {
+            if !disabled.iter().any(|name| name == controller) {
+                continue;
+            }
+            let had = match controller {
+                "cpu" => resources.cpu.take().is_some(),
+                "hugetlb" => resources.hugepage_limits.take().is_some(),
+                "io" => resources.block_io.take().is_some(),
+                "memory" => resources.memory.take().is_some(),
+                "pids" => resources.pids.take().is_some(),
+                _ => resources.rdma.take().is_some(),
+            };
+            if had {
+                skipped.push(controller);
+            }
+        }
+        Ok(skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_disable_controllers() {
+        let mut config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "linux": {"namespaces": [], "resources": {
+                "memory": {"limit": 1048576},
+                "pids": {"limit": 64},
+                "cpu": {"quota": 50000},
+            }},
+        }))
+        .unwrap();
+        let disabled = [
+            String::from("memory"),
+            String::from("hugetlb"),
+            String::from("cpu"),
+        ];
+        assert_eq!(
+            vec!["cpu", "memory"],
+            config.disable_controllers(&disabled).unwrap()
+        );
+        assert!(config.cgroup_cpu().is_none());
+        assert!(config.cgroup_memory().is_none());
+        assert_eq!(64, config.pids().unwrap().limit);
+
+        assert!(config
+            .disable_controllers(&[String::from("cpuset")])
+            .is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! Hardened defaults for bundles which don't configure them, see `create --secure-defaults`.
+//!
+//! The defaults follow docker's: a set of masked and readonly paths under /proc and /sys,
+//! a reduced set of capabilities, and a seccomp profile refusing the syscalls a container
+//! shouldn't need unless it's been granted the capability covering them.
+
+use super::*;
+
+/// Paths hidden from the container. Files are covered with /dev/null, directories with
+/// an empty read-only tmpfs.
+const MASKED_PATHS: [&str; 12] = [
+    "/proc/acpi",
+    "/proc/asound",
+    "/proc/interrupts",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/sys/devices/virtual/powercap",
+    "/sys/firmware",
+];
+
+/// Paths remounted read-only in the container.
+const READONLY_PATHS: [&str; 5] = [
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+/// Capabilities granted to the container process.
+const CAPABILITIES: [Capability; 14] = [
+    Capability::AuditWrite,
+    Capability::Chown,
+    Capability::DacOverride,
+    Capability::Fowner,
+    Capability::Fsetid,
+    Capability::Kill,
+    Capability::Mknod,
+    Capability::NetBindService,
+    Capability::NetRaw,
+    Capability::Setfcap,
+    Capability::Setgid,
+    Capability::Setpcap,
+    Capability::Setuid,
+    Capability::SysChroot,
+];
+
+/// Syscalls refused with EPERM, unless the process' bounding set has the capability
+/// next to them. Those without a capability are always refused.
+const DENIED_SYSCALLS: [(Option<Capability>, &[&str]); 12] = [
+    (
+        None,
+        &[
+            "_sysctl",
+            "add_key",
+            "create_module",
+            "get_kernel_syms",
+            "kexec_file_load",
+            "kexec_load",
+            "keyctl",
+            "nfsservctl",
+            "query_module",
+            "request_key",
+            "sysfs",
+            "userfaultfd",
+            "uselib",
+            "ustat",
+        ],
+    ),
+    (
+        Some(Capability::SysAdmin),
+        &[
+            "bpf",
+            "fanotify_init",
+            "fsconfig",
+            "fsmount",
+            "fsopen",
+            "fspick",
+            "lookup_dcookie",
+            "mount",
+            "move_mount",
+            "name_to_handle_at",
+            "open_tree",
+            "perf_event_open",
+            "quotactl",
+            "setns",
+            "swapoff",
+            "swapon",
+            "umount2",
+            "unshare",
+        ],
+    ),
+    (Some(Capability::DacReadSearch), &["open_by_handle_at"]),
+    (Some(Capability::SysBoot), &["reboot"]),
+    (
+        Some(Capability::SysModule),
+        &["delete_module", "finit_module", "init_module"],
+    ),
+    (
+        Some(Capability::SysNice),
+        &["get_mempolicy", "mbind", "move_pages", "set_mempolicy"],
+    ),
+    (Some(Capability::SysPacct), &["acct"]),
+    (
+        Some(Capability::SysPtrace),
+        &[
+            "kcmp",
+            "pidfd_getfd",
+            "process_madvise",
+            "process_vm_readv",
+            "process_vm_writev",
+            "ptrace",
+        ],
+    ),
+    (Some(Capability::SysRawio), &["ioperm", "iopl"]),
+    (
+        Some(Capability::SysTime),
+        &["clock_adjtime", "clock_settime", "settimeofday"],
+    ),
+    (Some(Capability::SysTtyConfig), &["vhangup"]),
+    (Some(Capability::Syslog), &["syslog"]),
+];
+
+impl Config {
+    /// Fills in the hardened defaults for whatever the bundle leaves unset. Anything the
+    /// bundle configures itself, even as an empty list, is kept as is.
+    pub fn apply_secure_defaults(&mut self) {
+        if let Some(process) = &mut self.process {
+            process
+                .capabilities
+                .get_or_insert_with(|| LinuxCapabilities {
+                    bounding: Some(CAPABILITIES.to_vec()),
+                    effective: Some(CAPABILITIES.to_vec()),
+                    permitted: Some(CAPABILITIES.to_vec()),
+                    ..Default::default()
+                });
+        }
+        let bounding = self
+            .process
+            .as_ref()
+            .and_then(|process| process.capabilities.as_ref()?.bounding.clone())
+            .unwrap_or_default();
+
+        let linux = self.linux.get_or_insert_with(Linux::default);
+        linux
+            .masked_paths
+            .get_or_insert_with(|| MASKED_PATHS.iter().map(|p| p.to_string()).collect());
+        linux
+            .readonly_paths
+            .get_or_insert_with(|| READONLY_PATHS.iter().map(|p| p.to_string()).collect());
+        linux
+            .seccomp
+            .get_or_insert_with(|| default_seccomp(&bounding));
+    }
+}
+
+/// Allows everything but the denied syscalls whose capability isn't in `bounding`.
+fn default_seccomp(bounding: &[Capability]) -> LinuxSeccomp {
+    let names: Vec<&str> = DENIED_SYSCALLS
+        .iter()
+        .filter(|(cap, _)| cap.is_none_or(|cap| !bounding.contains(&cap)))
+        .flat_map(|(_, names)| names.iter().copied())
+        .collect();
+    let mut seccomp = LinuxSeccomp::new("SCMP_ACT_ALLOW");
+    seccomp.syscalls = Some(vec![LinuxSyscall::new(
+        &names,
+        "SCMP_ACT_ERRNO",
+        Some(libc::EPERM as u32),
+    )]);
+    seccomp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn denied(config: &Config) -> Vec<String> {
+        config.seccomp().unwrap().syscalls.as_ref().unwrap()[0]
+            .names
+            .clone()
+    }
+
+    #[test]
+    fn test_secure_defaults() {
+        let mut config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "process": {"args": ["sh"]},
+        }))
+        .unwrap();
+        config.apply_secure_defaults();
+
+        assert_eq!(MASKED_PATHS.len(), config.masked_paths().len());
+        assert!(config.readonly_paths().iter().any(|p| p == "/proc/sys"));
+        let caps = config.process().unwrap().capabilities.as_ref().unwrap();
+        assert_eq!(Some(CAPABILITIES.to_vec()), caps.bounding);
+        assert!(caps.ambient.is_none());
+        let seccomp = config.seccomp().unwrap();
+        assert_eq!("SCMP_ACT_ALLOW", seccomp.default_action);
+        assert!(denied(&config).iter().any(|name| name == "mount"));
+        assert!(denied(&config).iter().any(|name| name == "kexec_load"));
+    }
+
+    #[test]
+    fn test_secure_defaults_keep_bundle() {
+        let mut config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "process": {
+                "args": ["sh"],
+                "capabilities": {"bounding": ["CAP_SYS_ADMIN"]},
+            },
+            "linux": {"namespaces": [], "maskedPaths": []},
+        }))
+        .unwrap();
+        config.apply_secure_defaults();
+
+        assert!(config.masked_paths().is_empty());
+        assert_eq!(READONLY_PATHS.len(), config.readonly_paths().len());
+        let caps = config.process().unwrap().capabilities.as_ref().unwrap();
+        assert_eq!(Some(vec![Capability::SysAdmin]), caps.bounding);
+        // Granted CAP_SYS_ADMIN, so mounting is allowed. Loading kernels never is.
+        assert!(!denied(&config).iter().any(|name| name == "mount"));
+        assert!(denied(&config).iter().any(|name| name == "kexec_load"));
+    }
+}
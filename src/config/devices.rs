@@ -0,0 +1,224 @@
+//! Devices added to and removed from a running container, kept in its copy of the config
+//! so it lists the container's devices as they are, and host devices bound into it.
+
+use super::*;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+/// A device node, as found on the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceNode {
+    pub path: String,
+    /// 'c' or 'b'.
+    pub typ: char,
+    pub major: i64,
+    pub minor: i64,
+    pub file_mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl DeviceNode {
+    /// The host's device node at `path`.
+    pub fn stat(path: &str) -> Result<Self, ContainerErr> {
+        let metadata = fs::metadata(path).map_err(ContainerErr::IO)?;
+        let typ = if metadata.file_type().is_char_device() {
+            'c'
+        } else if metadata.file_type().is_block_device() {
+            'b'
+        } else {
+            return Err(ContainerErr::Device(format!("{} is not a device", path)));
+        };
+        let rdev = metadata.rdev();
+        Ok(Self {
+            path: path.to_string(),
+            typ,
+            major: (((rdev >> 32) & 0xffff_f000) | ((rdev >> 8) & 0xfff)) as i64,
+            minor: (((rdev >> 12) & 0xffff_ff00) | (rdev & 0xff)) as i64,
+            file_mode: metadata.mode() & 0o777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        })
+    }
+}
+
+impl Config {
+    /// The char and block devices of linux.devices. Fifos have no major and minor.
+    pub fn device_nodes(&self) -> Vec<DeviceNode> {
+        let devices = self.linux.as_ref().and_then(|linux| linux.devices.as_ref());
+        devices
+            .into_iter()
+            .flatten()
+            .filter_map(|device| {
+                let typ = match device.typ.as_str() {
+                    "c" | "u" => 'c',
+                    "b" => 'b',
+                    _ => return None,
+                };
+                Some(DeviceNode {
+                    path: device.path.clone(),
+                    typ,
+                    major: device.major?,
+                    minor: device.minor?,
+                    file_mode: device.file_mode.unwrap_or(0o666),
+                    uid: device.uid.unwrap_or(0),
+                    gid: device.gid.unwrap_or(0),
+                })
+            })
+            .collect()
+    }
+
+    /// Adds `node` to linux.devices and a rule allowing `access` to it, some of "rwm", to
+    /// linux.resources.devices. Replaces what the config had for its path.
+    pub fn add_device(&mut self, node: &DeviceNode, access: &str) {
+        self.remove_device(&node.path);
+        let linux = self.linux.get_or_insert_with(Linux::default);
+        linux.devices.get_or_insert_with(Vec::new).push(Device {
+            typ: node.typ.to_string(),
+            path: node.path.clone(),
+            major: Some(node.major),
+            minor: Some(node.minor),
+            file_mode: Some(node.file_mode),
+            uid: Some(node.uid),
+            gid: Some(node.gid),
+            unknown: Map::new(),
+        });
+        self.allow_device(node, access);
+    }
+
+    /// Binds the host's device `node` at the same path, and allows `access` to it.
+    pub fn bind_device(&mut self, node: &DeviceNode, access: &str) {
+        self.add_bind_mount(&node.path, &node.path, &["bind", "nosuid", "noexec"]);
+        self.allow_device(node, access);
+    }
+
+    /// Adds a rule allowing `access` to `node` to linux.resources.devices. Without rules
+    /// there, no device filter is installed and every device is allowed already.
+    fn allow_device(&mut self, node: &DeviceNode, access: &str) {
+        let Some(devices) = self
+            .linux
+            .as_mut()
+            .and_then(|linux| linux.resources.as_mut())
+            .and_then(|resources| resources.devices.as_mut())
+        else {
+            return;
+        };
+        devices.push(AllowedDevice {
+            allow: true,
+            typ: Some(match node.typ {
+                'b' => DeviceType::Block,
+                _ => DeviceType::Char,
+            }),
+            major: Some(node.major),
+            minor: Some(node.minor),
+            access: Some(access.to_string()),
+            unknown: Map::new(),
+        });
+    }
+
+    /// Removes the device at `path` from linux.devices, and the rule `add_device` allowed
+    /// it with from linux.resources.devices. Returns whether the config had it.
+    pub fn remove_device(&mut self, path: &str) -> bool {
+        let Some(linux) = &mut self.linux else {
+            return false;
+        };
+        let Some(devices) = &mut linux.devices else {
+            return false;
+        };
+        let Some(i) = devices.iter().position(|device| device.path == path) else {
+            return false;
+        };
+        let device = devices.remove(i);
+        let typ = match device.typ.as_str() {
+            "b" => DeviceType::Block,
+            _ => DeviceType::Char,
+        };
+        if let Some(rules) = linux.resources.as_mut().and_then(|r| r.devices.as_mut()) {
+            // allow_device appended it, rules of the bundle for the same device stay.
+            if let Some(i) = rules.iter().rposition(|rule| {
+                rule.allow
+                    && rule.typ == Some(typ)
+                    && rule.major == device.major
+                    && rule.minor == device.minor
+            }) {
+                rules.remove(i);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_stat() {
+        let null = DeviceNode::stat("/dev/null").unwrap();
+        assert_eq!(('c', 1, 3), (null.typ, null.major, null.minor));
+        assert!(DeviceNode::stat("/dev").is_err());
+    }
+
+    #[test]
+    fn test_add_device() {
+        let mut config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "linux": {"namespaces": [], "resources": {
+                "devices": [
+                    {"allow": false, "access": "rwm"},
+                    {"allow": true, "type": "c", "major": 195, "minor": 0, "access": "r"},
+                    {"allow": true, "type": "b", "major": 195, "minor": 0, "access": "r"},
+                ],
+            }},
+        }))
+        .unwrap();
+        let node = DeviceNode {
+            path: String::from("/dev/nvidia0"),
+            typ: 'c',
+            major: 195,
+            minor: 0,
+            file_mode: 0o666,
+            uid: 0,
+            gid: 0,
+        };
+        config.add_device(&node, "rw");
+        config.add_device(&node, "rwm");
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(
+            json!([{"type": "c", "path": "/dev/nvidia0", "major": 195, "minor": 0,
+                    "fileMode": 0o666, "uid": 0, "gid": 0}]),
+            value["linux"]["devices"]
+        );
+        assert_eq!(
+            json!([{"allow": false, "access": "rwm"},
+                   {"allow": true, "type": "c", "major": 195, "minor": 0, "access": "r"},
+                   {"allow": true, "type": "b", "major": 195, "minor": 0, "access": "r"},
+                   {"allow": true, "type": "c", "major": 195, "minor": 0, "access": "rwm"}]),
+            value["linux"]["resources"]["devices"]
+        );
+        assert_eq!(vec![node.clone()], config.device_nodes());
+
+        // The bundle's own rules for the device stay.
+        assert!(config.remove_device("/dev/nvidia0"));
+        assert!(!config.remove_device("/dev/nvidia0"));
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(json!([]), value["linux"]["devices"]);
+        assert_eq!(
+            json!([{"allow": false, "access": "rwm"},
+                   {"allow": true, "type": "c", "major": 195, "minor": 0, "access": "r"},
+                   {"allow": true, "type": "b", "major": 195, "minor": 0, "access": "r"}]),
+            value["linux"]["resources"]["devices"]
+        );
+
+        // Without rules every device is allowed already.
+        let mut config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "linux": {"namespaces": []},
+        }))
+        .unwrap();
+        config.add_device(&node, "rwm");
+        assert!(config.allowed_devices().is_none());
+    }
+}
@@ -0,0 +1,55 @@
+//! Landlock ruleset of the process, as in the runtime-spec's proposed process.landlock
+//! https://github.com/opencontainers/runtime-spec/pull/1111
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Filesystem access the process keeps, everything else it's denied
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct LinuxLandlock {
+    pub ruleset: LandlockRuleset,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<LandlockRules>,
+    /// Fail rather than leave out access rights the kernel doesn't know.
+    #[serde(default)]
+    pub disable_best_effort: bool,
+
+    #[serde(flatten)]
+    pub(super) unknown: Map<String, Value>,
+}
+
+/// Access rights the ruleset handles, denied but where a rule allows them
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct LandlockRuleset {
+    #[serde(rename = "handledAccessFS")]
+    pub handled_access_fs: Vec<String>,
+
+    #[serde(flatten)]
+    pub(super) unknown: Map<String, Value>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct LandlockRules {
+    #[serde(default)]
+    pub path_beneath: Vec<LandlockPathBeneath>,
+
+    #[serde(flatten)]
+    pub(super) unknown: Map<String, Value>,
+}
+
+/// Access rights allowed for the files beneath the paths
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct LandlockPathBeneath {
+    pub allowed_access: Vec<String>,
+    pub paths: Vec<String>,
+
+    #[serde(flatten)]
+    pub(super) unknown: Map<String, Value>,
+}
@@ -0,0 +1,1096 @@
+use crate::error::ContainerErr;
+use log::debug;
+use serde::de::IgnoredAny;
+use serde::{self, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+mod capabilities;
+mod controllers;
+mod defaults;
+mod devices;
+mod landlock;
+mod overrides;
+mod profile;
+mod rlimit;
+mod seccomp;
+mod strict;
+mod vars;
+
+pub use capabilities::{Capability, LinuxCapabilities};
+pub use devices::DeviceNode;
+pub use landlock::LinuxLandlock;
+pub use overrides::ProcessOverrides;
+pub use profile::HardeningProfile;
+pub use rlimit::RLimit;
+pub use seccomp::{LinuxSeccomp, LinuxSyscall};
+pub use strict::Violation;
+pub use vars::Vars;
+
+/// A container's config.json
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct Config {
+    pub oci_version: String,
+    pub root: Root,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mounts: Option<Vec<Mount>>,
+    // Optional for bundles which are only ever created, e.g. externally managed
+    // pause containers. Start fails without it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    process: Option<Process>,
+
+    // Hostname
+    // https://github.com/opencontainers/runtime-spec/blob/main/config.md#hostname
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+
+    // Domainname
+    // https://github.com/opencontainers/runtime-spec/blob/main/config.md#domainname
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domainname: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    linux: Option<Linux>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hooks: Option<Hooks>,
+
+    // Other platforms' sections, kept opaque. Only linux is supported, see `check_platform`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    windows: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    solaris: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vm: Option<Value>,
+
+    // Annotations
+    // https://github.com/opencontainers/runtime-spec/blob/main/config.md#annotations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<HashMap<String, String>>,
+
+    /// Fields not known to this runtime, kept so the config round-trips. Every config
+    /// struct has one of these so nothing is lost when the config is written back out.
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+impl Config {
+    /// Writes the config as config.json into `dir`
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> Result<(), ContainerErr> {
+        let f = File::create(dir.as_ref().join("config.json")).map_err(ContainerErr::IO)?;
+        serde_json::to_writer(f, self).map_err(|e| ContainerErr::Bundle(e.to_string()))
+    }
+
+    pub fn annotations(&self) -> Option<&HashMap<String, String>> {
+        self.annotations.as_ref()
+    }
+
+    /// Reads config.json from the bundle_path, and parses the json
+    pub fn load<P: AsRef<Path>>(bundle_path: P) -> Result<Self, ContainerErr> {
+        Self::load_with(bundle_path, false, &ProcessOverrides::default())
+    }
+
+    /// Like `load`, but with `strict` set fields unknown to this runtime and out of range
+    /// values are rejected. Every violation is reported, not just the first one. The
+    /// overrides are applied before any of the checks.
+    pub fn load_with<P: AsRef<Path>>(
+        bundle_path: P,
+        strict: bool,
+        overrides: &ProcessOverrides,
+    ) -> Result<Self, ContainerErr> {
+        debug!("loading config.json");
+        // Get path to config.json
+        let mut pb = PathBuf::new();
+        pb.push(bundle_path);
+        pb.push("config.json");
+
+        // Parsed straight from the bytes, serde_json checks the strings are utf8 as it goes.
+        let buf = fs::read(pb).map_err(|e| ContainerErr::Bundle(e.to_string()))?;
+        // Before the full parse, other platforms' configs may not have fields we require.
+        check_platform(&buf)?;
+        let mut config: Self =
+            serde_json::from_slice(&buf).map_err(|e| ContainerErr::Bundle(e.to_string()))?;
+        overrides.apply(&mut config)?;
+        if strict {
+            let violations = strict::check(&config);
+            if !violations.is_empty() {
+                return Err(ContainerErr::InvalidConfig(violations));
+            }
+        }
+        // Without --strict too, it'd only fail once we're in the container.
+        if let Some(process) = &config.process {
+            process.check_rlimits()?;
+        }
+        if !config.valid_spec() {
+            return Err(ContainerErr::Bundle(String::new()));
+        }
+
+        debug!("config.json loaded");
+        Ok(config)
+    }
+
+    /// Host uid and gid the container's root is mapped to, if the container gets a new
+    /// user namespace with mappings for it.
+    pub fn host_root_ids(&self) -> Option<(u32, u32)> {
+        let linux = self.linux.as_ref()?;
+        if !linux
+            .namespaces
+            .iter()
+            .any(|ns| ns.typ == "user" && ns.path.is_none())
+        {
+            return None;
+        }
+        let root = |mappings: &Option<Vec<IdMapping>>| {
+            mappings
+                .iter()
+                .flatten()
+                .find(|m| m.container_id == 0 && m.size > 0)
+                .map(|m| m.host_id)
+        };
+        Some((root(&linux.uid_mappings)?, root(&linux.gid_mappings)?))
+    }
+
+    pub fn linux_namespaces(&self) -> Option<&[Namespace]> {
+        if let Some(linux) = &self.linux {
+            Some(&linux.namespaces)
+        } else {
+            None
+        }
+    }
+
+    pub fn linux_namespaces_mut(&mut self) -> Option<&mut [Namespace]> {
+        self.linux
+            .as_mut()
+            .map(|linux| linux.namespaces.as_mut_slice())
+    }
+
+    /// Joins the `typ` namespace at `path` rather than creating one, adding the namespace
+    /// if the config doesn't list it.
+    pub fn set_namespace_path(&mut self, typ: &str, path: String) {
+        let namespaces = &mut self.linux.get_or_insert_with(Linux::default).namespaces;
+        match namespaces.iter_mut().find(|ns| ns.typ == typ) {
+            Some(ns) => ns.path = Some(path),
+            None => namespaces.push(Namespace {
+                typ: typ.to_string(),
+                path: Some(path),
+                unknown: Map::new(),
+            }),
+        }
+    }
+
+    /// Merges the linux.resources at `path`, as given to `update`, into the config. Each
+    /// controller they have settings for replaces the config's, the others are kept.
+    pub fn update_resources<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ContainerErr> {
+        let f = File::open(path).map_err(|e| ContainerErr::Bundle(e.to_string()))?;
+        let update: Resources =
+            serde_json::from_reader(f).map_err(|e| ContainerErr::Bundle(e.to_string()))?;
+        let resources = self
+            .linux
+            .get_or_insert_with(Linux::default)
+            .resources
+            .get_or_insert_with(Resources::default);
+        resources.memory = update.memory.or(resources.memory.take());
+        resources.devices = update.devices.or(resources.devices.take());
+        resources.cpu = update.cpu.or(resources.cpu.take());
+        resources.block_io = update.block_io.or(resources.block_io.take());
+        resources.hugepage_limits = update.hugepage_limits.or(resources.hugepage_limits.take());
+        resources.network = update.network.or(resources.network.take());
+        resources.pids = update.pids.or(resources.pids.take());
+        resources.rdma = update.rdma.or(resources.rdma.take());
+        resources.unified = update.unified.or(resources.unified.take());
+        resources.unknown.extend(update.unknown);
+        Ok(())
+    }
+
+    pub fn cgroup_memory(&self) -> Option<&Memory> {
+        if let Some(linux) = &self.linux {
+            if let Some(resources) = &linux.resources {
+                if let Some(memory) = &resources.memory {
+                    return Some(memory);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn cgroup_cpu(&self) -> Option<&Cpu> {
+        if let Some(linux) = &self.linux {
+            if let Some(resources) = &linux.resources {
+                if let Some(cpu) = &resources.cpu {
+                    return Some(cpu);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn blockio(&self) -> Option<&BlockIO> {
+        if let Some(linux) = &self.linux {
+            if let Some(resources) = &linux.resources {
+                if let Some(block_io) = &resources.block_io {
+                    return Some(block_io);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn hugepage_limits(&self) -> Option<&[HugePageLimits]> {
+        if let Some(linux) = &self.linux {
+            if let Some(resources) = &linux.resources {
+                if let Some(hpl) = &resources.hugepage_limits {
+                    return Some(hpl);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn rdma(&self) -> Option<std::collections::hash_map::Iter<'_, String, Rdma>> {
+        if let Some(linux) = &self.linux {
+            if let Some(resources) = &linux.resources {
+                if let Some(rdma) = &resources.rdma {
+                    return Some(rdma.iter());
+                }
+            }
+        }
+        None
+    }
+
+    pub fn allowed_devices(&self) -> Option<&[AllowedDevice]> {
+        if let Some(linux) = &self.linux {
+            if let Some(resources) = &linux.resources {
+                if let Some(devices) = &resources.devices {
+                    return Some(devices);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn pids(&self) -> Option<&Pids> {
+        if let Some(linux) = &self.linux {
+            if let Some(resources) = &linux.resources {
+                if let Some(p) = &resources.pids {
+                    return Some(p);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn mounts(&self) -> Option<&[Mount]> {
+        if let Some(mounts) = &self.mounts {
+            return Some(mounts);
+        }
+        None
+    }
+
+    pub fn mounts_mut(&mut self) -> Option<&mut [Mount]> {
+        self.mounts.as_deref_mut()
+    }
+
+    /// Appends a bind mount of the host's `source` at `destination`.
+    pub fn add_bind_mount(&mut self, source: &str, destination: &str, options: &[&str]) {
+        self.mounts.get_or_insert_with(Vec::new).push(Mount {
+            destination: destination.to_string(),
+            source: Some(source.to_string()),
+            options: Some(options.iter().map(|option| option.to_string()).collect()),
+            typ: Some(String::from("bind")),
+            uid_mappings: None,
+            gid_mappings: None,
+            unknown: Map::new(),
+        });
+    }
+
+    pub fn cgroups_path(&self) -> Option<&str> {
+        if let Some(linux) = &self.linux {
+            if let Some(path) = &linux.cgroups_path {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    pub fn seccomp(&self) -> Option<&LinuxSeccomp> {
+        self.linux.as_ref()?.seccomp.as_ref()
+    }
+
+    pub fn masked_paths(&self) -> &[String] {
+        self.linux
+            .as_ref()
+            .and_then(|linux| linux.masked_paths.as_deref())
+            .unwrap_or_default()
+    }
+
+    pub fn readonly_paths(&self) -> &[String] {
+        self.linux
+            .as_ref()
+            .and_then(|linux| linux.readonly_paths.as_deref())
+            .unwrap_or_default()
+    }
+
+    pub fn hooks(&self) -> Option<&Hooks> {
+        self.hooks.as_ref()
+    }
+
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    pub fn annotation(&self, key: &str) -> Option<&String> {
+        if let Some(annotations) = &self.annotations {
+            return annotations.get(key);
+        }
+        None
+    }
+
+    pub fn process(&self) -> Option<&Process> {
+        self.process.as_ref()
+    }
+
+    pub fn process_mut(&mut self) -> Option<&mut Process> {
+        self.process.as_mut()
+    }
+
+    fn valid_spec(&self) -> bool {
+        if let Some(process) = &self.process {
+            let cwd = Path::new(&process.cwd);
+            return cwd.is_absolute();
+        }
+        true
+    }
+}
+
+/// Which platform specific sections a config has, skipped over rather than parsed.
+/// Generic tooling sometimes emits sections this runtime can't run alongside a linux
+/// section, they only matter if there's no linux section.
+#[derive(Deserialize)]
+struct Platforms {
+    linux: Option<IgnoredAny>,
+    windows: Option<IgnoredAny>,
+    solaris: Option<IgnoredAny>,
+    vm: Option<IgnoredAny>,
+}
+
+/// Rejects configs whose active platform isn't linux.
+fn check_platform(buf: &[u8]) -> Result<(), ContainerErr> {
+    let platforms: Platforms =
+        serde_json::from_slice(buf).map_err(|e| ContainerErr::Bundle(e.to_string()))?;
+    if platforms.linux.is_some() {
+        return Ok(());
+    }
+    let unsupported = [
+        ("windows", platforms.windows.is_some()),
+        ("solaris", platforms.solaris.is_some()),
+        ("vm", platforms.vm.is_some()),
+    ];
+    for (platform, present) in unsupported {
+        if present {
+            return Err(ContainerErr::UnsupportedPlatform(format!(
+                "{} platform not supported by this runtime",
+                platform
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Root configuration
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md#root
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct Root {
+    pub path: String,
+    pub readonly: bool,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// Mount configuration
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md#mounts
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct Mount {
+    pub destination: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Vec<String>>,
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid_mappings: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gid_mappings: Option<Vec<String>>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// Process configuration
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md#process
+/// Missing fields take the defaults from `Process::default`.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase", default)]
+#[repr(C)]
+pub struct Process {
+    pub terminal: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    console_size: Option<ConsoleSize>,
+    pub cwd: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_line: Option<String>,
+    pub user: User,
+
+    // POSIX process fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rlimits: Option<Vec<RLimit>>,
+
+    // Linux process fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apparmor_profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<LinuxCapabilities>,
+    pub no_new_privileges: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oom_score_adj: Option<isize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheduler: Option<LinuxScheduler>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selinux_label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub io_priority: Option<LinuxIOPriority>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub landlock: Option<LinuxLandlock>,
+
+    #[serde(rename = "execCPUAffinity")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exec_cpu_affinity: Option<ExecCPUAffinity>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+impl Process {
+    /// Reads a standalone process document, e.g. the process.json given to exec.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ContainerErr> {
+        let f = File::open(path).map_err(|e| ContainerErr::Bundle(e.to_string()))?;
+        let process: Self =
+            serde_json::from_reader(f).map_err(|e| ContainerErr::Bundle(e.to_string()))?;
+        if !Path::new(&process.cwd).is_absolute() {
+            return Err(ContainerErr::Bundle(format!(
+                "process cwd must be absolute: {}",
+                process.cwd
+            )));
+        }
+        process.check_rlimits()?;
+        Ok(process)
+    }
+
+    /// The initial size of the process' terminal as rows and columns.
+    pub fn console_size(&self) -> Option<(u16, u16)> {
+        let size = self.console_size.as_ref()?;
+        let clamp = |n: usize| n.min(u16::MAX as usize) as u16;
+        Some((clamp(size.height), clamp(size.width)))
+    }
+
+    fn check_rlimits(&self) -> Result<(), ContainerErr> {
+        self.rlimits.iter().flatten().try_for_each(RLimit::check)
+    }
+}
+
+impl Default for Process {
+    fn default() -> Self {
+        Self {
+            terminal: false,
+            console_size: None,
+            cwd: String::from("/"),
+            env: None,
+            args: None,
+            command_line: None,
+            user: User::default(),
+            rlimits: None,
+            apparmor_profile: None,
+            capabilities: None,
+            no_new_privileges: false,
+            oom_score_adj: None,
+            scheduler: None,
+            selinux_label: None,
+            io_priority: None,
+            landlock: None,
+            exec_cpu_affinity: None,
+            unknown: Map::new(),
+        }
+    }
+}
+
+/// Console Size configuration
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+struct ConsoleSize {
+    height: usize,
+    width: usize,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// A Process' user configuration
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md#user
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct User {
+    pub uid: isize,
+    pub gid: isize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub umask: Option<isize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub additional_gids: Option<Vec<isize>>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+// Linux platform structs
+
+// Linux platform specific configuration
+// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#linux-container-configuration
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+struct Linux {
+    namespaces: Vec<Namespace>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid_mappings: Option<Vec<IdMapping>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gid_mappings: Option<Vec<IdMapping>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_offsets: Option<HashMap<String, TimeOffsets>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    devices: Option<Vec<Device>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cgroups_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resources: Option<Resources>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seccomp: Option<LinuxSeccomp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    masked_paths: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    readonly_paths: Option<Vec<String>>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// Linux process configuration for the scheduler
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md#linux-process
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct LinuxScheduler {
+    pub policy: String,
+    #[serde(default)]
+    pub nice: i32,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// Linux process exec CPU affinity
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md#linux-process
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+struct ExecCPUAffinity {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    initial: Option<String>,
+    #[serde(rename = "final")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fnl: Option<String>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// Linux process IO priority configuration
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md#linux-process
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct LinuxIOPriority {
+    pub class: String,
+    pub priority: i32,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// Linux Namespace configuration
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#namespaces
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct Namespace {
+    // TODO: make this an enum?
+    #[serde(rename = "type")]
+    pub typ: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// User namespace mappings
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#user-namespace-mappings
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+struct IdMapping {
+    #[serde(rename = "containerID")]
+    container_id: u32,
+
+    #[serde(rename = "hostID")]
+    host_id: u32,
+
+    size: u32,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// Offset for Time Namespace
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#offset-for-time-namespace
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+struct TimeOffsets {
+    secs: i64,
+    nanosecs: u32,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// Linux device configuration
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#devices
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+struct Device {
+    #[serde(rename = "type")]
+    typ: String,
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    major: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    minor: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_mode: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gid: Option<u32>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+// Hooks structs
+
+/// POSIX platform hooks
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md#posix-platform-hooks
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct Hooks {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prestart: Option<Vec<Hook>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_runtime: Option<Vec<Hook>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_container: Option<Vec<Hook>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_container: Option<Vec<Hook>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poststart: Option<Vec<Hook>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poststop: Option<Vec<Hook>>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// A single Hook configuration
+/// https://github.com/opencontainers/runtime-spec/blob/main/config.md#posix-platform-hooks
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct Hook {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<usize>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// Cgroup resource configuration
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#cgroup-ownership
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+#[repr(C)]
+struct Resources {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<Memory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    devices: Option<Vec<AllowedDevice>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpu: Option<Cpu>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block_io: Option<BlockIO>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hugepage_limits: Option<Vec<HugePageLimits>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    network: Option<Network>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pids: Option<Pids>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rdma: Option<HashMap<String, Rdma>>,
+    /// cgroup v2 parameters
+    /// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#unified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unified: Option<HashMap<String, String>>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// cgroup subsystem memory
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#memory
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct Memory {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reservation: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<i64>,
+    #[serde(rename = "kernelTCP")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel_tcp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swappiness: Option<u64>,
+    #[serde(rename = "disableOOMKiller")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_oom_killer: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_hierarchy: Option<bool>,
+    #[serde(rename = "checkBeforeUpdate")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_before_update: Option<bool>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// cgroup allowed devices
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#allowed-device-list
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct AllowedDevice {
+    pub allow: bool,
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<DeviceType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub major: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minor: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<String>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum DeviceType {
+    #[serde(rename = "a")]
+    All,
+    #[serde(rename = "c")]
+    Char,
+    #[serde(rename = "b")]
+    Block,
+}
+
+/// cgroup subsystems cpu and cpusets
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#cpu
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct Cpu {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shares: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub realtime_runtime: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub realtime_period: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mems: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle: Option<i64>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct BlockIO {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leaf_weight: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight_device: Option<Vec<WeightDevice>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttle_read_bps_device: Option<Vec<DevThrottle>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttle_write_bps_device: Option<Vec<DevThrottle>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttle_read_iops_device: Option<Vec<DevThrottle>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttle_write_iops_device: Option<Vec<DevThrottle>>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct WeightDevice {
+    pub major: i64,
+    pub minor: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leaf_weight: Option<u16>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct DevThrottle {
+    pub major: i64,
+    pub minor: i64,
+    pub rate: u64,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct HugePageLimits {
+    pub page_size: String,
+    pub limit: u64,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// cgroup subsystem network
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#network
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+struct Network {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    class_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priorities: Option<Vec<Prio>>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+struct Prio {
+    name: String,
+    priority: u32,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// cgroup subsystem pids
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#pids
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct Pids {
+    pub limit: i64,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+/// cgroup subsystem rdma
+/// https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#rdma
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct Rdma {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hca_handles: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hca_objects: Option<u32>,
+
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let raw = std::fs::read_to_string("test_configs/config.json").unwrap();
+        let config: Config = serde_json::from_str(&raw).unwrap();
+
+        let expected: Value = serde_json::from_str(&raw).unwrap();
+        let actual = serde_json::to_value(&config).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_check_platform() {
+        // Windows configs don't require root, the platform must be rejected first.
+        let windows = serde_json::json!({"ociVersion": "1.0.1", "windows": {"layerFolders": []}});
+        assert!(matches!(
+            check_platform(windows.to_string().as_bytes()),
+            Err(ContainerErr::UnsupportedPlatform(_))
+        ));
+
+        let both = serde_json::json!({"ociVersion": "1.0.1", "linux": {}, "vm": {}});
+        assert!(check_platform(both.to_string().as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_process_optional() {
+        let config: Config = serde_json::from_str(
+            r#"{"ociVersion": "1.0.1", "root": {"path": "rootfs", "readonly": false}}"#,
+        )
+        .unwrap();
+        assert!(config.process().is_none());
+        assert!(config.valid_spec());
+    }
+
+    #[test]
+    fn test_process_defaults() {
+        let config: Config = serde_json::from_str(
+            r#"{"ociVersion": "1.0.1", "root": {"path": "rootfs", "readonly": false}, "process": {"args": ["sh"]}}"#,
+        )
+        .unwrap();
+        let process = config.process().unwrap();
+        assert_eq!("/", process.cwd);
+        assert!(process.env.is_none());
+        assert!(!process.terminal);
+    }
+
+    #[test]
+    fn test_update_resources() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "linux": {
+                "namespaces": [],
+                "resources": {"memory": {"limit": 1024}, "pids": {"limit": 64}},
+            },
+        }))
+        .unwrap();
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let path = PathBuf::from(format!("/tmp/resources_{}.json", time));
+        fs::write(
+            &path,
+            r#"{"memory": {"limit": 512, "checkBeforeUpdate": true}, "cpu": {"quota": 50000}}"#,
+        )
+        .unwrap();
+        config.update_resources(&path).unwrap();
+        let memory = config.cgroup_memory().unwrap();
+        assert_eq!(Some(512), memory.limit);
+        assert_eq!(Some(true), memory.check_before_update);
+        assert_eq!(Some(50000), config.cgroup_cpu().unwrap().quota);
+        assert_eq!(64, config.pids().unwrap().limit);
+
+        fs::write(&path, r#"{"memory": {"limit": "lots"}}"#).unwrap();
+        assert!(config.update_resources(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_host_root_ids() {
+        let mut raw = serde_json::json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "linux": {
+                "namespaces": [{"type": "user"}],
+                "uidMappings": [{"containerID": 0, "hostID": 100000, "size": 65536}],
+                "gidMappings": [{"containerID": 0, "hostID": 200000, "size": 65536}],
+            },
+        });
+        let config: Config = serde_json::from_value(raw.clone()).unwrap();
+        assert_eq!(Some((100000, 200000)), config.host_root_ids());
+
+        // Without a new user namespace the mappings don't apply.
+        raw["linux"]["namespaces"] =
+            serde_json::json!([{"type": "user", "path": "/proc/1/ns/user"}]);
+        let config: Config = serde_json::from_value(raw).unwrap();
+        assert_eq!(None, config.host_root_ids());
+    }
+}
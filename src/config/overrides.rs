@@ -0,0 +1,151 @@
+//! Changes to the container's process given on the command line, see `create --env`.
+
+use super::*;
+
+/// Changes to config.json's process. They're applied as the config is loaded, before it's
+/// validated, so the result is checked like any other config.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProcessOverrides {
+    /// `KEY=VALUE`, replacing the variable if the config sets it, added otherwise.
+    pub env: Vec<String>,
+    /// Replaces the args.
+    pub args: Option<Vec<String>>,
+    /// Added after the args.
+    pub append_args: Vec<String>,
+    pub cwd: Option<String>,
+}
+
+impl ProcessOverrides {
+    fn is_empty(&self) -> bool {
+        self.env.is_empty()
+            && self.args.is_none()
+            && self.append_args.is_empty()
+            && self.cwd.is_none()
+    }
+
+    pub(super) fn apply(&self, config: &mut Config) -> Result<(), ContainerErr> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let process = config.process.as_mut().ok_or_else(|| {
+            ContainerErr::Bundle(String::from("config.json has no process to override"))
+        })?;
+
+        for var in &self.env {
+            let key = match var.split_once('=') {
+                Some((key, _)) if !key.is_empty() => key,
+                _ => {
+                    return Err(ContainerErr::invalid_args(&format!(
+                        "Invalid env, expected KEY=VALUE: {}",
+                        var
+                    )))
+                }
+            };
+            let env = process.env.get_or_insert_with(Vec::new);
+            let existing = env
+                .iter_mut()
+                .find(|v| v.split_once('=').is_some_and(|(k, _)| k == key));
+            match existing {
+                Some(existing) => existing.clone_from(var),
+                None => env.push(var.clone()),
+            }
+        }
+        if let Some(args) = &self.args {
+            process.args = Some(args.clone());
+        }
+        if !self.append_args.is_empty() {
+            process
+                .args
+                .get_or_insert_with(Vec::new)
+                .extend(self.append_args.iter().cloned());
+        }
+        if let Some(cwd) = &self.cwd {
+            process.cwd = cwd.clone();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> Config {
+        serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "process": {
+                "args": ["sh", "-c", "env"],
+                "env": ["PATH=/bin", "TERM=xterm"],
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut config = config();
+        let overrides = ProcessOverrides {
+            env: vec![String::from("TERM=dumb"), String::from("DEBUG=1")],
+            append_args: vec![String::from("extra")],
+            cwd: Some(String::from("/tmp")),
+            ..Default::default()
+        };
+        overrides.apply(&mut config).unwrap();
+        let process = config.process().unwrap();
+        assert_eq!(
+            Some(vec![
+                String::from("PATH=/bin"),
+                String::from("TERM=dumb"),
+                String::from("DEBUG=1"),
+            ]),
+            process.env
+        );
+        assert_eq!(
+            Some(vec![
+                String::from("sh"),
+                String::from("-c"),
+                String::from("env"),
+                String::from("extra"),
+            ]),
+            process.args
+        );
+        assert_eq!("/tmp", process.cwd);
+
+        // Replaced args are appended to as well.
+        let overrides = ProcessOverrides {
+            args: Some(vec![String::from("true")]),
+            append_args: vec![String::from("--verbose")],
+            ..Default::default()
+        };
+        overrides.apply(&mut config).unwrap();
+        assert_eq!(
+            Some(vec![String::from("true"), String::from("--verbose")]),
+            config.process().unwrap().args
+        );
+    }
+
+    #[test]
+    fn test_apply_invalid() {
+        for env in ["NOEQUALS", "=value"] {
+            let overrides = ProcessOverrides {
+                env: vec![String::from(env)],
+                ..Default::default()
+            };
+            assert!(overrides.apply(&mut config()).is_err(), "{}", env);
+        }
+
+        let mut config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+        }))
+        .unwrap();
+        ProcessOverrides::default().apply(&mut config).unwrap();
+        let overrides = ProcessOverrides {
+            cwd: Some(String::from("/")),
+            ..Default::default()
+        };
+        assert!(overrides.apply(&mut config).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Hardening profiles, host-wide hardening an admin sets up in the runtime's config file
+//! (see `ctx::RuntimeConfig`) for every container, whatever its bundle configures.
+//!
+//! A profile only ever adds restrictions: its paths are masked or made read-only on top
+//! of the bundle's, mounts using one of its denied options are rejected and the pids
+//! limit is capped. Bundles can only get out of a profile by naming it in the
+//! `hardening-opt-out` annotation.
+
+use super::*;
+
+/// A profile as written in the runtime's config file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct HardeningProfile {
+    /// Masked besides linux.maskedPaths.
+    #[serde(default)]
+    pub masked_paths: Vec<String>,
+    /// Read-only besides linux.readonlyPaths.
+    #[serde(default)]
+    pub readonly_paths: Vec<String>,
+    /// Mount options no mount may use, e.g. `suid` or `dev`.
+    #[serde(default)]
+    pub denied_mount_options: Vec<String>,
+    /// Upper bound of linux.resources.pids.limit, which is set to it if the bundle has no
+    /// limit.
+    #[serde(default)]
+    pub max_pids: Option<i64>,
+}
+
+impl Config {
+    /// Merges the profile `name` into the config. Fails with every mount option the
+    /// profile denies, nothing is changed then.
+    pub fn apply_hardening_profile(
+        &mut self,
+        name: &str,
+        profile: &HardeningProfile,
+    ) -> Result<(), ContainerErr> {
+        let mut violations = Vec::new();
+        for (i, mount) in self.mounts.iter().flatten().enumerate() {
+            for (j, option) in mount.options.iter().flatten().enumerate() {
+                if profile.denied_mount_options.contains(option) {
+                    violations.push(Violation {
+                        pointer: format!("/mounts/{}/options/{}", i, j),
+                        message: format!("{} is denied by hardening profile {}", option, name),
+                    });
+                }
+            }
+        }
+        if !violations.is_empty() {
+            return Err(ContainerErr::InvalidConfig(violations));
+        }
+
+        let linux = self.linux.get_or_insert_with(Linux::default);
+        extend_unique(&mut linux.masked_paths, &profile.masked_paths);
+        extend_unique(&mut linux.readonly_paths, &profile.readonly_paths);
+        if let Some(max) = profile.max_pids {
+            let resources = linux.resources.get_or_insert_with(Resources::default);
+            match &mut resources.pids {
+                // 0 and below mean no limit.
+                Some(pids) if pids.limit > 0 && pids.limit <= max => {}
+                Some(pids) => pids.limit = max,
+                None => {
+                    resources.pids = Some(Pids {
+                        limit: max,
+                        unknown: Map::new(),
+                    })
+                }
+            }
+        }
+        debug!("applied hardening profile {}", name);
+        Ok(())
+    }
+}
+
+fn extend_unique(paths: &mut Option<Vec<String>>, extra: &[String]) {
+    if extra.is_empty() {
+        return;
+    }
+    let paths = paths.get_or_insert_with(Vec::new);
+    for path in extra {
+        if !paths.contains(path) {
+            paths.push(path.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(value: Value) -> Config {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_apply_hardening_profile() {
+        let profile: HardeningProfile = serde_json::from_value(json!({
+            "maskedPaths": ["/proc/kcore", "/sys/firmware"],
+            "readonlyPaths": ["/proc/sys"],
+            "deniedMountOptions": ["suid", "dev"],
+            "maxPids": 512,
+        }))
+        .unwrap();
+
+        let mut c = config(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "mounts": [{"destination": "/tmp", "type": "tmpfs", "options": ["nosuid"]}],
+            "linux": {"namespaces": [], "maskedPaths": ["/proc/kcore"]},
+        }));
+        c.apply_hardening_profile("site", &profile).unwrap();
+        assert_eq!(["/proc/kcore", "/sys/firmware"], c.masked_paths());
+        assert_eq!(["/proc/sys"], c.readonly_paths());
+        assert_eq!(512, c.pids().unwrap().limit);
+
+        // Limits are only ever lowered.
+        for (limit, capped) in [(100, 100), (1000, 512), (-1, 512), (0, 512)] {
+            let mut c = config(json!({
+                "ociVersion": "1.0.1",
+                "root": {"path": "rootfs", "readonly": false},
+                "linux": {"namespaces": [], "resources": {"pids": {"limit": limit}}},
+            }));
+            c.apply_hardening_profile("site", &profile).unwrap();
+            assert_eq!(capped, c.pids().unwrap().limit, "{}", limit);
+        }
+
+        let mut c = config(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "mounts": [
+                {"destination": "/proc", "type": "proc", "source": "proc"},
+                {"destination": "/data", "type": "bind", "options": ["rbind", "suid", "dev"]},
+            ],
+        }));
+        let Err(ContainerErr::InvalidConfig(violations)) =
+            c.apply_hardening_profile("site", &profile)
+        else {
+            panic!("denied mount options were accepted");
+        };
+        let pointers: Vec<&str> = violations.iter().map(|v| v.pointer.as_str()).collect();
+        assert_eq!(vec!["/mounts/1/options/1", "/mounts/1/options/2"], pointers);
+        assert!(c.masked_paths().is_empty());
+
+        assert!(serde_json::from_value::<HardeningProfile>(json!({"maxPid": 1})).is_err());
+    }
+}
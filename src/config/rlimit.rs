@@ -0,0 +1,178 @@
+//! POSIX process resource limits
+//! https://github.com/opencontainers/runtime-spec/blob/main/config.md#posix-process
+
+use crate::error::ContainerErr;
+use crate::libc_compat::RlimitResource;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// The limit value for no limit, RLIM_INFINITY.
+const RLIM_INFINITY: u64 = u64::MAX;
+
+/// A resource limit of the container process. `soft` and `hard` also accept -1,
+/// "unlimited" and "infinity" for no limit.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[repr(C)]
+pub struct RLimit {
+    #[serde(rename = "type")]
+    pub typ: RlimitType,
+    #[serde(deserialize_with = "limit")]
+    pub soft: u64,
+    #[serde(deserialize_with = "limit")]
+    pub hard: u64,
+
+    #[serde(flatten)]
+    pub(super) unknown: Map<String, Value>,
+}
+
+macro_rules! rlimit_types {
+    ($($variant:ident => $name:literal = $resource:ident,)*) => {
+        /// A resource, named as in config.json. Unknown names fail when the config is
+        /// parsed rather than when the limits are set in the container.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum RlimitType {
+            $($variant,)*
+        }
+
+        impl RlimitType {
+            /// The resource's name as used in config.json, e.g. `RLIMIT_NOFILE`
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(RlimitType::$variant => $name,)*
+                }
+            }
+
+            /// The resource argument for getrlimit and setrlimit.
+            pub fn resource(&self) -> RlimitResource {
+                match self {
+                    $(RlimitType::$variant => libc::$resource,)*
+                }
+            }
+        }
+
+        impl FromStr for RlimitType {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($name => Ok(RlimitType::$variant),)*
+                    _ => Err(format!("unknown rlimit: {}", s)),
+                }
+            }
+        }
+    };
+}
+
+rlimit_types! {
+    As => "RLIMIT_AS" = RLIMIT_AS,
+    Core => "RLIMIT_CORE" = RLIMIT_CORE,
+    Cpu => "RLIMIT_CPU" = RLIMIT_CPU,
+    Data => "RLIMIT_DATA" = RLIMIT_DATA,
+    Fsize => "RLIMIT_FSIZE" = RLIMIT_FSIZE,
+    Locks => "RLIMIT_LOCKS" = RLIMIT_LOCKS,
+    Memlock => "RLIMIT_MEMLOCK" = RLIMIT_MEMLOCK,
+    Msgqueue => "RLIMIT_MSGQUEUE" = RLIMIT_MSGQUEUE,
+    Nice => "RLIMIT_NICE" = RLIMIT_NICE,
+    Nofile => "RLIMIT_NOFILE" = RLIMIT_NOFILE,
+    Nproc => "RLIMIT_NPROC" = RLIMIT_NPROC,
+    Rss => "RLIMIT_RSS" = RLIMIT_RSS,
+    Rtprio => "RLIMIT_RTPRIO" = RLIMIT_RTPRIO,
+    Rttime => "RLIMIT_RTTIME" = RLIMIT_RTTIME,
+    Sigpending => "RLIMIT_SIGPENDING" = RLIMIT_SIGPENDING,
+    Stack => "RLIMIT_STACK" = RLIMIT_STACK,
+}
+
+impl fmt::Display for RlimitType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for RlimitType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for RlimitType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl RLimit {
+    /// Rejects a soft limit above the hard one, which setrlimit would only refuse once
+    /// we're in the container.
+    pub fn check(&self) -> Result<(), ContainerErr> {
+        if self.soft > self.hard {
+            return Err(ContainerErr::Rlimit(format!(
+                "{}: soft limit {} exceeds hard limit {}",
+                self.typ, self.soft, self.hard
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawLimit {
+    Value(u64),
+    Negative(i64),
+    Name(String),
+}
+
+fn limit<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match RawLimit::deserialize(deserializer)? {
+        RawLimit::Value(value) => Ok(value),
+        RawLimit::Negative(-1) => Ok(RLIM_INFINITY),
+        RawLimit::Name(name) if name == "unlimited" || name == "infinity" => Ok(RLIM_INFINITY),
+        RawLimit::Negative(value) => Err(serde::de::Error::custom(format!(
+            "invalid limit: {}",
+            value
+        ))),
+        RawLimit::Name(name) => Err(serde::de::Error::custom(format!(
+            "invalid limit: {:?}",
+            name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rlimit() {
+        let rlimit: RLimit =
+            serde_json::from_value(json!({"type": "RLIMIT_NOFILE", "soft": 1024, "hard": -1}))
+                .unwrap();
+        assert_eq!(RlimitType::Nofile, rlimit.typ);
+        assert_eq!(libc::RLIMIT_NOFILE, rlimit.typ.resource());
+        assert_eq!((1024, RLIM_INFINITY), (rlimit.soft, rlimit.hard));
+        assert!(rlimit.check().is_ok());
+
+        let rlimit: RLimit = serde_json::from_value(
+            json!({"type": "RLIMIT_CORE", "soft": "unlimited", "hard": "infinity"}),
+        )
+        .unwrap();
+        assert_eq!((RLIM_INFINITY, RLIM_INFINITY), (rlimit.soft, rlimit.hard));
+
+        let rlimit: RLimit =
+            serde_json::from_value(json!({"type": "RLIMIT_CPU", "soft": "unlimited", "hard": 10}))
+                .unwrap();
+        assert!(matches!(rlimit.check(), Err(ContainerErr::Rlimit(_))));
+
+        for invalid in [
+            json!({"type": "RLIMIT_BOGUS", "soft": 1, "hard": 1}),
+            json!({"type": "RLIMIT_CORE", "soft": -2, "hard": 1}),
+            json!({"type": "RLIMIT_CORE", "soft": "lots", "hard": 1}),
+        ] {
+            assert!(serde_json::from_value::<RLimit>(invalid).is_err());
+        }
+    }
+}
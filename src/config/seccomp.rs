@@ -0,0 +1,79 @@
+//! Seccomp profile
+//! https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#seccomp
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Seccomp filter for the container process
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct LinuxSeccomp {
+    pub default_action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_errno_ret: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub architectures: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub syscalls: Option<Vec<LinuxSyscall>>,
+
+    #[serde(flatten)]
+    pub(super) unknown: Map<String, Value>,
+}
+
+/// Action for a set of syscalls, overriding the profile's default action
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct LinuxSyscall {
+    pub names: Vec<String>,
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errno_ret: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<LinuxSeccompArg>>,
+
+    #[serde(flatten)]
+    pub(super) unknown: Map<String, Value>,
+}
+
+/// Condition on a syscall argument
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[repr(C)]
+pub struct LinuxSeccompArg {
+    pub index: u32,
+    pub value: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_two: Option<u64>,
+    pub op: String,
+
+    #[serde(flatten)]
+    pub(super) unknown: Map<String, Value>,
+}
+
+impl LinuxSeccomp {
+    /// A profile with `default_action` for every syscall.
+    pub fn new(default_action: &str) -> Self {
+        Self {
+            default_action: default_action.to_string(),
+            default_errno_ret: None,
+            architectures: None,
+            syscalls: None,
+            unknown: Map::new(),
+        }
+    }
+}
+
+impl LinuxSyscall {
+    /// `action` for the syscalls in `names`, without argument conditions.
+    pub fn new(names: &[&str], action: &str, errno_ret: Option<u32>) -> Self {
+        Self {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            action: action.to_string(),
+            errno_ret,
+            args: None,
+            unknown: Map::new(),
+        }
+    }
+}
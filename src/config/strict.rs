@@ -0,0 +1,402 @@
+//! Strict validation of a parsed config.
+//!
+//! Regular loading is lenient: fields this runtime doesn't know are kept around and
+//! otherwise ignored, and values are only checked once they're applied. Strict mode
+//! reports all of these problems up front, each with a JSON pointer (RFC 6901) to the
+//! offending value so bundle authors can find them without creating a container.
+
+use super::*;
+use std::fmt;
+
+/// A single problem found in a config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// JSON pointer to the offending value, e.g. `/process/rlimits/0/soft`
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.pointer, self.message)
+    }
+}
+
+const NAMESPACE_TYPES: [&str; 8] = [
+    "pid", "network", "mount", "ipc", "uts", "user", "cgroup", "time",
+];
+const DEVICE_TYPES: [&str; 4] = ["c", "b", "u", "p"];
+const IOPRIO_CLASSES: [&str; 3] = ["IOPRIO_CLASS_RT", "IOPRIO_CLASS_BE", "IOPRIO_CLASS_IDLE"];
+/// Seccomp actions this runtime can compile, SCMP_ACT_NOTIFY needs a listener.
+const SECCOMP_ACTIONS: [&str; 8] = [
+    "SCMP_ACT_KILL",
+    "SCMP_ACT_KILL_PROCESS",
+    "SCMP_ACT_KILL_THREAD",
+    "SCMP_ACT_TRAP",
+    "SCMP_ACT_ERRNO",
+    "SCMP_ACT_TRACE",
+    "SCMP_ACT_ALLOW",
+    "SCMP_ACT_LOG",
+];
+
+/// Returns every violation found in `config`.
+pub fn check(config: &Config) -> Vec<Violation> {
+    let mut c = Checker::default();
+    c.unknown("", &config.unknown);
+    c.unknown("/root", &config.root.unknown);
+    for (i, mount) in config.mounts.iter().flatten().enumerate() {
+        c.unknown(&format!("/mounts/{}", i), &mount.unknown);
+    }
+    if let Some(process) = &config.process {
+        c.process(process);
+    }
+    if let Some(linux) = &config.linux {
+        c.linux(linux);
+    }
+    if let Some(hooks) = &config.hooks {
+        c.hooks(hooks);
+    }
+    c.violations
+}
+
+#[derive(Default)]
+struct Checker {
+    violations: Vec<Violation>,
+}
+
+impl Checker {
+    fn report(&mut self, pointer: String, message: String) {
+        self.violations.push(Violation { pointer, message });
+    }
+
+    fn unknown(&mut self, pointer: &str, unknown: &Map<String, Value>) {
+        for key in unknown.keys() {
+            self.report(
+                format!("{}/{}", pointer, escape(key)),
+                String::from("unknown or unsupported field"),
+            );
+        }
+    }
+
+    fn range<T: PartialOrd + fmt::Display>(&mut self, pointer: String, value: T, min: T, max: T) {
+        if value < min || value > max {
+            self.report(
+                pointer,
+                format!("{} is out of range {}..={}", value, min, max),
+            );
+        }
+    }
+
+    fn one_of(&mut self, pointer: String, value: &str, allowed: &[&str]) {
+        if !allowed.contains(&value) {
+            self.report(
+                pointer,
+                format!("{:?} is not one of {}", value, allowed.join(", ")),
+            );
+        }
+    }
+
+    fn process(&mut self, process: &Process) {
+        self.unknown("/process", &process.unknown);
+        self.unknown("/process/user", &process.user.unknown);
+        if let Some(console_size) = &process.console_size {
+            self.unknown("/process/consoleSize", &console_size.unknown);
+        }
+        if let Some(capabilities) = &process.capabilities {
+            self.unknown("/process/capabilities", &capabilities.unknown);
+        }
+        for (i, rlimit) in process.rlimits.iter().flatten().enumerate() {
+            let pointer = format!("/process/rlimits/{}", i);
+            self.unknown(&pointer, &rlimit.unknown);
+            if rlimit.soft > rlimit.hard {
+                self.report(
+                    format!("{}/soft", pointer),
+                    format!(
+                        "soft limit {} exceeds hard limit {}",
+                        rlimit.soft, rlimit.hard
+                    ),
+                );
+            }
+        }
+        if let Some(oom_score_adj) = process.oom_score_adj {
+            self.range(
+                String::from("/process/oomScoreAdj"),
+                oom_score_adj,
+                -1000,
+                1000,
+            );
+        }
+        if let Some(scheduler) = &process.scheduler {
+            self.unknown("/process/scheduler", &scheduler.unknown);
+            self.range(
+                String::from("/process/scheduler/nice"),
+                scheduler.nice,
+                -20,
+                19,
+            );
+        }
+        if let Some(io_priority) = &process.io_priority {
+            self.unknown("/process/ioPriority", &io_priority.unknown);
+            self.one_of(
+                String::from("/process/ioPriority/class"),
+                &io_priority.class,
+                &IOPRIO_CLASSES,
+            );
+            self.range(
+                String::from("/process/ioPriority/priority"),
+                io_priority.priority,
+                0,
+                7,
+            );
+        }
+        if let Some(landlock) = &process.landlock {
+            self.unknown("/process/landlock", &landlock.unknown);
+            self.unknown("/process/landlock/ruleset", &landlock.ruleset.unknown);
+            if let Some(rules) = &landlock.rules {
+                self.unknown("/process/landlock/rules", &rules.unknown);
+                for (i, rule) in rules.path_beneath.iter().enumerate() {
+                    let pointer = format!("/process/landlock/rules/pathBeneath/{}", i);
+                    self.unknown(&pointer, &rule.unknown);
+                }
+            }
+        }
+        if let Some(affinity) = &process.exec_cpu_affinity {
+            self.unknown("/process/execCPUAffinity", &affinity.unknown);
+        }
+    }
+
+    fn linux(&mut self, linux: &Linux) {
+        self.unknown("/linux", &linux.unknown);
+        for (i, ns) in linux.namespaces.iter().enumerate() {
+            let pointer = format!("/linux/namespaces/{}", i);
+            self.unknown(&pointer, &ns.unknown);
+            self.one_of(format!("{}/type", pointer), &ns.typ, &NAMESPACE_TYPES);
+        }
+        for (key, mappings) in [
+            ("uidMappings", &linux.uid_mappings),
+            ("gidMappings", &linux.gid_mappings),
+        ] {
+            for (i, mapping) in mappings.iter().flatten().enumerate() {
+                let pointer = format!("/linux/{}/{}", key, i);
+                self.unknown(&pointer, &mapping.unknown);
+                if mapping.size == 0 {
+                    self.report(
+                        format!("{}/size", pointer),
+                        String::from("must be greater than zero"),
+                    );
+                }
+            }
+        }
+        for (clock, offsets) in linux.time_offsets.iter().flatten() {
+            let pointer = format!("/linux/timeOffsets/{}", escape(clock));
+            self.unknown(&pointer, &offsets.unknown);
+            self.range(
+                format!("{}/nanosecs", pointer),
+                offsets.nanosecs,
+                0,
+                999_999_999,
+            );
+        }
+        for (i, device) in linux.devices.iter().flatten().enumerate() {
+            let pointer = format!("/linux/devices/{}", i);
+            self.unknown(&pointer, &device.unknown);
+            self.one_of(format!("{}/type", pointer), &device.typ, &DEVICE_TYPES);
+        }
+        if let Some(resources) = &linux.resources {
+            self.resources(resources);
+        }
+        if let Some(seccomp) = &linux.seccomp {
+            self.seccomp(seccomp);
+        }
+    }
+
+    fn seccomp(&mut self, seccomp: &LinuxSeccomp) {
+        let pointer = "/linux/seccomp";
+        self.unknown(pointer, &seccomp.unknown);
+        self.one_of(
+            format!("{}/defaultAction", pointer),
+            &seccomp.default_action,
+            &SECCOMP_ACTIONS,
+        );
+        for (i, syscall) in seccomp.syscalls.iter().flatten().enumerate() {
+            let pointer = format!("{}/syscalls/{}", pointer, i);
+            self.unknown(&pointer, &syscall.unknown);
+            self.one_of(
+                format!("{}/action", pointer),
+                &syscall.action,
+                &SECCOMP_ACTIONS,
+            );
+            if syscall.args.as_ref().is_some_and(|args| !args.is_empty()) {
+                self.report(
+                    format!("{}/args", pointer),
+                    String::from("argument conditions are not supported"),
+                );
+            }
+        }
+    }
+
+    fn resources(&mut self, resources: &Resources) {
+        let pointer = "/linux/resources";
+        self.unknown(pointer, &resources.unknown);
+        if let Some(memory) = &resources.memory {
+            self.unknown(&format!("{}/memory", pointer), &memory.unknown);
+            if let Some(swappiness) = memory.swappiness {
+                self.range(format!("{}/memory/swappiness", pointer), swappiness, 0, 100);
+            }
+        }
+        for (i, device) in resources.devices.iter().flatten().enumerate() {
+            self.unknown(&format!("{}/devices/{}", pointer, i), &device.unknown);
+        }
+        if let Some(cpu) = &resources.cpu {
+            self.unknown(&format!("{}/cpu", pointer), &cpu.unknown);
+            if let Some(shares) = cpu.shares {
+                self.range(format!("{}/cpu/shares", pointer), shares, 2, 262_144);
+            }
+        }
+        if let Some(block_io) = &resources.block_io {
+            let pointer = format!("{}/blockIO", pointer);
+            self.unknown(&pointer, &block_io.unknown);
+            self.blkio_weights(&pointer, block_io.weight, block_io.leaf_weight);
+            for (i, device) in block_io.weight_device.iter().flatten().enumerate() {
+                let pointer = format!("{}/weightDevice/{}", pointer, i);
+                self.unknown(&pointer, &device.unknown);
+                self.blkio_weights(&pointer, device.weight, device.leaf_weight);
+            }
+            let throttles = [
+                ("throttleReadBpsDevice", &block_io.throttle_read_bps_device),
+                (
+                    "throttleWriteBpsDevice",
+                    &block_io.throttle_write_bps_device,
+                ),
+                (
+                    "throttleReadIOPSDevice",
+                    &block_io.throttle_read_iops_device,
+                ),
+                (
+                    "throttleWriteIOPSDevice",
+                    &block_io.throttle_write_iops_device,
+                ),
+            ];
+            for (name, devices) in throttles {
+                for (i, device) in devices.iter().flatten().enumerate() {
+                    self.unknown(&format!("{}/{}/{}", pointer, name, i), &device.unknown);
+                }
+            }
+        }
+        for (i, limit) in resources.hugepage_limits.iter().flatten().enumerate() {
+            self.unknown(&format!("{}/hugepageLimits/{}", pointer, i), &limit.unknown);
+        }
+        if let Some(network) = &resources.network {
+            self.unknown(&format!("{}/network", pointer), &network.unknown);
+            for (i, prio) in network.priorities.iter().flatten().enumerate() {
+                self.unknown(
+                    &format!("{}/network/priorities/{}", pointer, i),
+                    &prio.unknown,
+                );
+            }
+        }
+        if let Some(pids) = &resources.pids {
+            self.unknown(&format!("{}/pids", pointer), &pids.unknown);
+        }
+        for (device, rdma) in resources.rdma.iter().flatten() {
+            self.unknown(
+                &format!("{}/rdma/{}", pointer, escape(device)),
+                &rdma.unknown,
+            );
+        }
+    }
+
+    fn blkio_weights(&mut self, pointer: &str, weight: Option<u16>, leaf_weight: Option<u16>) {
+        if let Some(weight) = weight {
+            self.range(format!("{}/weight", pointer), weight, 10, 1000);
+        }
+        if let Some(leaf_weight) = leaf_weight {
+            self.range(format!("{}/leafWeight", pointer), leaf_weight, 10, 1000);
+        }
+    }
+
+    fn hooks(&mut self, hooks: &Hooks) {
+        self.unknown("/hooks", &hooks.unknown);
+        let phases = [
+            ("prestart", &hooks.prestart),
+            ("createRuntime", &hooks.create_runtime),
+            ("createContainer", &hooks.create_container),
+            ("startContainer", &hooks.start_container),
+            ("poststart", &hooks.poststart),
+            ("poststop", &hooks.poststop),
+        ];
+        for (name, phase_hooks) in phases {
+            for (i, hook) in phase_hooks.iter().flatten().enumerate() {
+                let pointer = format!("/hooks/{}/{}", name, i);
+                self.unknown(&pointer, &hook.unknown);
+                if !Path::new(&hook.path).is_absolute() {
+                    self.report(
+                        format!("{}/path", pointer),
+                        String::from("must be an absolute path"),
+                    );
+                }
+                if hook.timeout == Some(0) {
+                    self.report(
+                        format!("{}/timeout", pointer),
+                        String::from("must be greater than zero"),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Escapes a key for use as a JSON pointer reference token.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pointers(raw: &str) -> Vec<String> {
+        let config: Config = serde_json::from_str(raw).unwrap();
+        check(&config).into_iter().map(|v| v.pointer).collect()
+    }
+
+    #[test]
+    fn test_check_unknown_fields() {
+        let raw = r#"{
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false, "bogus": 1},
+            "process": {"args": ["sh"], "user": {"uid": 0, "gid": 0, "a/b": true}},
+            "hooks": {"poststop": [{"path": "/bin/true", "extra": []}]}
+        }"#;
+        assert_eq!(
+            vec![
+                "/root/bogus",
+                "/process/user/a~1b",
+                "/hooks/poststop/0/extra"
+            ],
+            pointers(raw)
+        );
+    }
+
+    #[test]
+    fn test_check_ranges() {
+        let raw = r#"{
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "process": {
+                "args": ["sh"],
+                "oomScoreAdj": 1001,
+                "rlimits": [{"type": "RLIMIT_NOFILE", "soft": 2048, "hard": 1024}]
+            },
+            "linux": {"namespaces": [{"type": "pid"}, {"type": "net"}]}
+        }"#;
+        assert_eq!(
+            vec![
+                "/process/rlimits/0/soft",
+                "/process/oomScoreAdj",
+                "/linux/namespaces/1/type"
+            ],
+            pointers(raw)
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! `${VAR}` expansion in config.json, so bundles can be reused as templates.
+//!
+//! Only a fixed set of variables is known, all about the container being created: the
+//! runtime's own environment doesn't leak into the container's config.
+
+use super::*;
+
+/// The variables and their values for one container.
+pub struct Vars {
+    vars: [(&'static str, String); 3],
+}
+
+impl Vars {
+    pub fn new(bundle: &Path, state_dir: &Path, container_id: &str) -> Self {
+        Self {
+            vars: [
+                ("BUNDLE", bundle.to_string_lossy().into_owned()),
+                ("STATE_DIR", state_dir.to_string_lossy().into_owned()),
+                ("CONTAINER_ID", container_id.to_string()),
+            ],
+        }
+    }
+
+    /// Replaces every `${NAME}` in `s`. Unknown names are an error rather than left as
+    /// they are, they're most likely typos.
+    fn expand(&self, s: &str) -> Result<String, ContainerErr> {
+        let mut expanded = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("${") {
+            expanded.push_str(&rest[..start]);
+            let var = &rest[start + 2..];
+            let end = var
+                .find('}')
+                .ok_or_else(|| ContainerErr::Bundle(format!("Unterminated variable in {}", s)))?;
+            let (_, value) = self
+                .vars
+                .iter()
+                .find(|(name, _)| *name == &var[..end])
+                .ok_or_else(|| {
+                    ContainerErr::Bundle(format!(
+                        "Unknown variable ${{{}}} in {}, expected BUNDLE, STATE_DIR or CONTAINER_ID",
+                        &var[..end],
+                        s
+                    ))
+                })?;
+            expanded.push_str(value);
+            rest = &var[end + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+
+    fn expand_in_place(&self, s: &mut String) -> Result<(), ContainerErr> {
+        if s.contains("${") {
+            *s = self.expand(s)?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Expands the variables in mount sources, hook paths and args, and annotation
+    /// values.
+    pub fn expand_vars(&mut self, vars: &Vars) -> Result<(), ContainerErr> {
+        for mount in self.mounts.iter_mut().flatten() {
+            if let Some(source) = &mut mount.source {
+                vars.expand_in_place(source)?;
+            }
+        }
+        if let Some(hooks) = &mut self.hooks {
+            let phases = [
+                &mut hooks.prestart,
+                &mut hooks.create_runtime,
+                &mut hooks.create_container,
+                &mut hooks.start_container,
+                &mut hooks.poststart,
+                &mut hooks.poststop,
+            ];
+            for hook in phases.into_iter().flatten().flatten() {
+                vars.expand_in_place(&mut hook.path)?;
+                for arg in hook.args.iter_mut().flatten() {
+                    vars.expand_in_place(arg)?;
+                }
+            }
+        }
+        for value in self.annotations.iter_mut().flat_map(|a| a.values_mut()) {
+            vars.expand_in_place(value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vars() -> Vars {
+        Vars::new(
+            Path::new("/bundles/web"),
+            Path::new("/run/runtime/web"),
+            "web",
+        )
+    }
+
+    #[test]
+    fn test_expand() {
+        let vars = vars();
+        assert_eq!("/bundles/web/data", vars.expand("${BUNDLE}/data").unwrap());
+        assert_eq!(
+            "web-/run/runtime/web",
+            vars.expand("${CONTAINER_ID}-${STATE_DIR}").unwrap()
+        );
+        // Only ${NAME} is special.
+        assert_eq!("$HOME {}", vars.expand("$HOME {}").unwrap());
+        assert!(vars.expand("${HOME}").is_err());
+        assert!(vars.expand("${BUNDLE").is_err());
+    }
+
+    #[test]
+    fn test_expand_vars() {
+        let mut config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "mounts": [
+                {"destination": "/data", "source": "${BUNDLE}/data", "options": ["bind"]},
+                {"destination": "/proc", "type": "proc"},
+            ],
+            "hooks": {
+                "poststop": [{"path": "${BUNDLE}/cleanup.sh", "args": ["cleanup.sh", "${CONTAINER_ID}"]}],
+            },
+            "annotations": {"log": "${STATE_DIR}/app.log"},
+        }))
+        .unwrap();
+        config.expand_vars(&vars()).unwrap();
+
+        let mounts = config.mounts.as_ref().unwrap();
+        assert_eq!(Some("/bundles/web/data"), mounts[0].source.as_deref());
+        assert_eq!(None, mounts[1].source);
+        let hook = &config.hooks.as_ref().unwrap().poststop.as_ref().unwrap()[0];
+        assert_eq!("/bundles/web/cleanup.sh", hook.path);
+        assert_eq!(
+            Some(vec![String::from("cleanup.sh"), String::from("web")]),
+            hook.args
+        );
+        assert_eq!(
+            Some(&String::from("/run/runtime/web/app.log")),
+            config.annotation("log")
+        );
+    }
+}
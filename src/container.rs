@@ -1,25 +1,27 @@
 use crate::state::Status;
 
 use super::config::Config;
-use super::ctx::Ctx;
 use super::error::ContainerErr;
 use super::state::State;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+use super::store::StateStore;
 use std::path::PathBuf;
+use std::sync::Arc;
 
+/// A container being created. Clones share the config, which is parsed once and handed
+/// on to the monitor and the container process as is.
 #[derive(Clone)]
 pub struct Container {
     state: State,
-    config: Config,
+    config: Arc<Config>,
 }
 
 impl Container {
-    pub fn new(container_id: String, bundle_path: PathBuf, config: Config) -> Self {
-        Self {
-            state: State::new(container_id, bundle_path, config.oci_version.clone()),
-            config,
+    pub fn new(container_id: String, bundle_path: PathBuf, config: Arc<Config>) -> Self {
+        let mut state = State::new(container_id, bundle_path, config.oci_version.clone());
+        if let Some(annotations) = config.annotations() {
+            state.set_annotations(annotations.clone());
         }
+        Self { state, config }
     }
 
     pub fn state(&self) -> &State {
@@ -34,35 +36,65 @@ impl Container {
         &mut self.state
     }
 
-    /// Writes container state to <ctx.state_dir>/<container_id>/state.json
-    pub fn write_state(&self, ctx: &Ctx) -> Result<(), ContainerErr> {
-        let raw_state =
-            serde_json::to_string(&self.state).map_err(|e| ContainerErr::State(e.to_string()))?;
-        let container_dir = ctx.state_dir(self.state.id());
-        let container_state_path = container_dir.join("state.json");
-
-        if fs::metadata(&container_dir).is_err() {
-            fs::create_dir(&container_dir).map_err(ContainerErr::IO)?;
-        }
-
-        let mut f = OpenOptions::new()
-            .truncate(true)
-            .create(true)
-            .write(true)
-            .open(container_state_path)
-            .map_err(ContainerErr::IO)?;
-
-        f.write_all(raw_state.as_bytes())
-            .map_err(ContainerErr::IO)?;
-        Ok(())
+    /// Saves the container's state in `store`.
+    pub fn write_state<S: StateStore>(&self, store: &S) -> Result<(), ContainerErr> {
+        store.save(&self.state)
     }
 
-    /// Checks if the container state already exists on the filesystem
-    pub fn exists(&self, ctx: &Ctx) -> bool {
-        fs::metadata(ctx.state_path_for(self.state.id())).is_ok()
+    /// Claims the container id in `store`, failing if the container already exists.
+    pub fn reserve<S: StateStore>(&self, store: &S) -> Result<(), ContainerErr> {
+        store.reserve(&self.state)
     }
 
     pub fn config(&self) -> &Config {
         &self.config
     }
 }
+
+/// Longest container id, well below NAME_MAX so the id fits in the names derived from it,
+/// e.g. its systemd scope.
+const MAX_ID_LEN: usize = 128;
+
+/// Checks a container id given to a command, every cmd entry point does before using it.
+/// Ids become path components of the state dir and the cgroup, so only letters, digits,
+/// '_', '+', '-' and '.' are allowed, like runc, and "." and ".." are rejected.
+pub fn validate_id(id: &str) -> Result<(), ContainerErr> {
+    let invalid = |reason: &str| Err(ContainerErr::InvalidId(format!("{:?}: {}", id, reason)));
+    if id.is_empty() {
+        return invalid("empty");
+    }
+    if id.len() > MAX_ID_LEN {
+        return invalid(&format!("longer than {} characters", MAX_ID_LEN));
+    }
+    if id == "." || id == ".." {
+        return invalid("not a name");
+    }
+    if let Some(c) = id
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.')))
+    {
+        return invalid(&format!("contains {:?}", c));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_id() {
+        let longest = "a".repeat(MAX_ID_LEN);
+        for id in ["web", "web-1.2_a+b", "..hidden", &longest] {
+            assert!(validate_id(id).is_ok(), "{}", id);
+        }
+        let too_long = "a".repeat(MAX_ID_LEN + 1);
+        for id in ["", ".", "..", "../foo", "a/b", "a b", "ü", &too_long] {
+            assert!(
+                matches!(validate_id(id), Err(ContainerErr::InvalidId(_))),
+                "{}",
+                id
+            );
+        }
+    }
+}
@@ -0,0 +1,438 @@
+//! Checkpointing containers with CRIU, by running the criu binary.
+//!
+//! Bind mounts are dumped as external mounts keyed by their destination, their sources
+//! are on the host and have to be provided again on restore. Namespaces the container
+//! joined rather than created are external too, keyed like runc does so images can be
+//! moved between the two. The container's stdio are pipes to its monitor, which CRIU
+//! can't dump, their inodes are recorded in `descriptors.json` in the image dir so a
+//! restore can hand CRIU new pipes in their place.
+
+use crate::config::{Config, Mount};
+use crate::error::ContainerErr;
+use crate::rootfs::RootfsLayout;
+use crate::state::Pid;
+use crate::syscalls;
+use log::debug;
+use std::ffi::OsString;
+use std::fs;
+use std::os::fd::RawFd;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const CRIU: &str = "criu";
+const DUMP_LOG: &str = "dump.log";
+const RESTORE_LOG: &str = "restore.log";
+const RESTORE_PIDFILE: &str = "restore.pid";
+const DESCRIPTORS_FILENAME: &str = "descriptors.json";
+/// The key the network namespace of a container which joined one is dumped with.
+pub const NETNS_KEY: &str = "extRootNetNS";
+
+/// Namespaces CRIU can treat as external, by config type, CRIU's name and the key the
+/// namespace is dumped with.
+const EXTERNAL_NAMESPACES: [(&str, &str, &str); 2] = [
+    ("network", "net", NETNS_KEY),
+    ("pid", "pid", "extRootPidNS"),
+];
+
+/// What to dump, and how.
+#[derive(Debug)]
+pub struct Dump<'a> {
+    pub pid: Pid,
+    /// The container's root directory on the host, of the dumped or restored container.
+    pub root: &'a Path,
+    pub images_dir: &'a Path,
+    /// Where CRIU writes its log and other scratch files, the image dir by default.
+    pub work_dir: Option<&'a Path>,
+    /// Images of an earlier pre-dump, only memory changed since is dumped.
+    pub parent: Option<&'a Path>,
+    /// Only dump memory, leaving the container running, for a later dump to build on.
+    pub pre_dump: bool,
+    pub leave_running: bool,
+    pub tcp_established: bool,
+    /// The process is attached to a terminal.
+    pub shell_job: bool,
+    /// Namespace types whose contents aren't dumped, restore sets them up empty.
+    pub empty_namespaces: &'a [String],
+}
+
+/// Runs `criu dump` or `criu pre-dump` for the container described by `config`.
+pub fn dump(dump: &Dump, config: &Config) -> Result<(), ContainerErr> {
+    fs::create_dir_all(dump.images_dir).map_err(ContainerErr::IO)?;
+    if !dump.pre_dump {
+        write_descriptors(dump.pid, dump.images_dir)?;
+    }
+    let args = dump_args(dump, config)?;
+    debug!("running {} {:?}", CRIU, args);
+    let status = Command::new(CRIU)
+        .args(&args)
+        .status()
+        .map_err(|e| ContainerErr::Checkpoint(format!("failed to run {}: {}", CRIU, e)))?;
+    if !status.success() {
+        let log = dump.work_dir.unwrap_or(dump.images_dir).join(DUMP_LOG);
+        return Err(ContainerErr::Checkpoint(format!(
+            "{} failed with {}, see {:?}",
+            CRIU, status, log
+        )));
+    }
+    Ok(())
+}
+
+fn dump_args(dump: &Dump, config: &Config) -> Result<Vec<OsString>, ContainerErr> {
+    let command = if dump.pre_dump { "pre-dump" } else { "dump" };
+    let mut args: Vec<OsString> = vec![
+        command.into(),
+        "--tree".into(),
+        dump.pid.to_string().into(),
+        "--images-dir".into(),
+        dump.images_dir.into(),
+    ];
+    if let Some(work_dir) = dump.work_dir {
+        args.push("--work-dir".into());
+        args.push(work_dir.into());
+    }
+    args.push("--root".into());
+    args.push(dump.root.into());
+    for a in [
+        "--log-file",
+        DUMP_LOG,
+        "-v4",
+        "--manage-cgroups",
+        "--ext-unix-sk",
+        "--file-locks",
+    ] {
+        args.push(a.into());
+    }
+    if let Some(parent) = dump.parent {
+        args.push("--prev-images-dir".into());
+        args.push(parent.into());
+    }
+    if dump.pre_dump || dump.parent.is_some() {
+        args.push("--track-mem".into());
+    }
+    if dump.leave_running && !dump.pre_dump {
+        args.push("--leave-running".into());
+    }
+    if dump.tcp_established {
+        args.push("--tcp-established".into());
+    }
+    if dump.shell_job {
+        args.push("--shell-job".into());
+    }
+    for typ in dump.empty_namespaces {
+        // CRIU can only leave out the network namespace.
+        if typ != "network" {
+            return Err(ContainerErr::Checkpoint(format!(
+                "can't leave out the {} namespace",
+                typ
+            )));
+        }
+        args.push("--empty-ns".into());
+        args.push("net".into());
+    }
+    for external in external_namespaces(dump.pid, config, dump.empty_namespaces)? {
+        args.push("--external".into());
+        args.push(external.into());
+    }
+    for mount in bind_mounts(config) {
+        args.push("--external".into());
+        let destination = &mount.destination;
+        args.push(format!("mnt[{}]:{}", destination, destination).into());
+    }
+    Ok(args)
+}
+
+/// `--external` values for the namespaces the container joined by path, other than
+/// those left out entirely.
+fn external_namespaces(
+    pid: Pid,
+    config: &Config,
+    empty: &[String],
+) -> Result<Vec<String>, ContainerErr> {
+    let mut externals = Vec::new();
+    for ns in config.linux_namespaces().into_iter().flatten() {
+        if ns.path.is_none() || empty.contains(&ns.typ) {
+            continue;
+        }
+        let Some((_, name, key)) = EXTERNAL_NAMESPACES.iter().find(|(t, _, _)| *t == ns.typ) else {
+            continue;
+        };
+        let inode = fs::metadata(format!("/proc/{}/ns/{}", pid, name))
+            .map_err(ContainerErr::IO)?
+            .ino();
+        externals.push(format!("{}[{}]:{}", name, inode, key));
+    }
+    Ok(externals)
+}
+
+/// The config's bind mounts.
+fn bind_mounts(config: &Config) -> Vec<&Mount> {
+    config
+        .mounts()
+        .into_iter()
+        .flatten()
+        .filter(|mount| {
+            mount.typ.as_deref() == Some("bind")
+                || mount
+                    .options
+                    .iter()
+                    .flatten()
+                    .any(|o| o == "bind" || o == "rbind")
+        })
+        .collect()
+}
+
+/// Records what the container's stdin, stdout and stderr are, e.g. `pipe:[1234]`.
+fn write_descriptors(pid: Pid, images_dir: &Path) -> Result<(), ContainerErr> {
+    let mut descriptors = Vec::new();
+    for fd in 0..=2 {
+        let target = fs::read_link(format!("/proc/{}/fd/{}", pid, fd))
+            .map(|target| target.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        descriptors.push(target);
+    }
+    let raw =
+        serde_json::to_string(&descriptors).map_err(|e| ContainerErr::Checkpoint(e.to_string()))?;
+    fs::write(images_dir.join(DESCRIPTORS_FILENAME), raw).map_err(ContainerErr::IO)
+}
+
+/// What to restore, and how.
+#[derive(Debug)]
+pub struct Restore<'a> {
+    pub root: &'a Path,
+    pub images_dir: &'a Path,
+    pub work_dir: Option<&'a Path>,
+    /// The restored container's cgroup, relative to the cgroup mount.
+    pub cgroup: &'a Path,
+    pub tcp_established: bool,
+    pub shell_job: bool,
+    /// Bind mounts by destination and the source to use for them on this host.
+    pub mounts: Vec<(String, PathBuf)>,
+    /// Descriptors CRIU puts in place of what was dumped as the key, e.g. `pipe:[1234]`
+    /// or `extRootNetNS`.
+    pub inherit: Vec<(RawFd, String)>,
+}
+
+/// Runs `criu restore`. The restored process becomes our child, CRIU exits once it's
+/// running. Returns its pid.
+pub fn restore(restore: &Restore) -> Result<Pid, ContainerErr> {
+    let work_dir = restore.work_dir.unwrap_or(restore.images_dir);
+    let pidfile = work_dir.join(RESTORE_PIDFILE);
+    let args = restore_args(restore, &pidfile);
+    debug!("running {} {:?}", CRIU, args);
+    let inherit: Vec<RawFd> = restore.inherit.iter().map(|(fd, _)| *fd).collect();
+    let mut command = Command::new(CRIU);
+    command.args(&args);
+    // Only clear close-on-exec in the child, the descriptors stay ours otherwise.
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &inherit {
+                syscalls::clear_cloexec(*fd)?;
+            }
+            Ok(())
+        });
+    }
+    let status = command
+        .status()
+        .map_err(|e| ContainerErr::Checkpoint(format!("failed to run {}: {}", CRIU, e)))?;
+    if !status.success() {
+        return Err(ContainerErr::Checkpoint(format!(
+            "{} failed with {}, see {:?}",
+            CRIU,
+            status,
+            work_dir.join(RESTORE_LOG)
+        )));
+    }
+    let pid = fs::read_to_string(&pidfile).map_err(ContainerErr::IO)?;
+    pid.trim()
+        .parse()
+        .map_err(|_| ContainerErr::Checkpoint(format!("invalid pid in {:?}: {}", pidfile, pid)))
+}
+
+fn restore_args(restore: &Restore, pidfile: &Path) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec![
+        "restore".into(),
+        "--images-dir".into(),
+        restore.images_dir.into(),
+    ];
+    if let Some(work_dir) = restore.work_dir {
+        args.push("--work-dir".into());
+        args.push(work_dir.into());
+    }
+    args.push("--root".into());
+    args.push(restore.root.into());
+    args.push("--pidfile".into());
+    args.push(pidfile.into());
+    args.push("--cgroup-root".into());
+    args.push(Path::new("/").join(restore.cgroup).into());
+    for a in [
+        "--log-file",
+        RESTORE_LOG,
+        "-v4",
+        "--restore-detached",
+        "--restore-sibling",
+        "--manage-cgroups",
+        "--ext-unix-sk",
+        "--file-locks",
+    ] {
+        args.push(a.into());
+    }
+    if restore.tcp_established {
+        args.push("--tcp-established".into());
+    }
+    if restore.shell_job {
+        args.push("--shell-job".into());
+    }
+    for (destination, source) in &restore.mounts {
+        let mut external = OsString::from(format!("mnt[{}]:", destination));
+        external.push(source);
+        args.push("--external".into());
+        args.push(external);
+    }
+    for (fd, key) in &restore.inherit {
+        args.push("--inherit-fd".into());
+        args.push(format!("fd[{}]:{}", fd, key).into());
+    }
+    args
+}
+
+/// The config's bind mounts by destination and source. Sources under the first path of
+/// an entry in `mount_map` are moved under its second path, for mounts whose source is
+/// somewhere else on this host than where the container was dumped.
+pub fn bind_mount_sources(
+    config: &Config,
+    mount_map: &[(PathBuf, PathBuf)],
+) -> Vec<(String, PathBuf)> {
+    bind_mounts(config)
+        .into_iter()
+        .filter_map(|mount| {
+            let source = Path::new(mount.source.as_deref()?);
+            let source = mount_map
+                .iter()
+                .find_map(|(old, new)| Some(new.join(source.strip_prefix(old).ok()?)))
+                .unwrap_or_else(|| source.to_path_buf());
+            Some((mount.destination.clone(), source))
+        })
+        .collect()
+}
+
+/// What the dumped container's stdin, stdout and stderr were, see `write_descriptors`.
+pub fn read_descriptors(images_dir: &Path) -> Result<Vec<String>, ContainerErr> {
+    let f = fs::File::open(images_dir.join(DESCRIPTORS_FILENAME)).map_err(ContainerErr::IO)?;
+    serde_json::from_reader(f).map_err(|e| ContainerErr::Checkpoint(e.to_string()))
+}
+
+/// The container's root directory on the host. Overlays and images are only mounted in
+/// the container's mount namespace, CRIU needs a plain directory.
+pub fn root_dir(config: &Config, bundle: &Path) -> Result<PathBuf, ContainerErr> {
+    match RootfsLayout::detect(config, bundle)? {
+        RootfsLayout::Directory(root) => Ok(root),
+        layout => Err(ContainerErr::Checkpoint(format!(
+            "only directory rootfs can be checkpointed: {:?}",
+            layout
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_dump_args() {
+        let config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "mounts": [
+                {"destination": "/proc", "type": "proc", "source": "proc"},
+                {"destination": "/data", "source": "/srv/data", "options": ["rbind", "rw"]},
+            ],
+        }))
+        .unwrap();
+        let mut dump = Dump {
+            pid: 42,
+            root: Path::new("/bundle/rootfs"),
+            images_dir: Path::new("/images/2"),
+            work_dir: None,
+            parent: Some(Path::new("../1")),
+            pre_dump: false,
+            leave_running: true,
+            tcp_established: true,
+            shell_job: false,
+            empty_namespaces: &[],
+        };
+        let args = dump_args(&dump, &config).unwrap();
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(["dump", "--tree", "42"], args[..3]);
+        for expected in [
+            "--leave-running",
+            "--tcp-established",
+            "--track-mem",
+            "../1",
+            "mnt[/data]:/data",
+        ] {
+            assert!(
+                args.contains(&expected),
+                "{} missing from {:?}",
+                expected,
+                args
+            );
+        }
+        assert!(!args.iter().any(|a| a.contains("/proc")));
+
+        dump.pre_dump = true;
+        let args = dump_args(&dump, &config).unwrap();
+        assert_eq!("pre-dump", args[0]);
+        assert!(!args.contains(&OsString::from("--leave-running")));
+
+        let empty = [String::from("ipc")];
+        dump.empty_namespaces = &empty;
+        assert!(dump_args(&dump, &config).is_err());
+    }
+
+    #[test]
+    fn test_restore_args() {
+        let config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "mounts": [
+                {"destination": "/data", "source": "/srv/data", "options": ["rbind"]},
+                {"destination": "/cache", "source": "/var/cache/app", "type": "bind"},
+            ],
+        }))
+        .unwrap();
+        let mount_map = [(PathBuf::from("/srv"), PathBuf::from("/mnt/new"))];
+        let restore = Restore {
+            root: Path::new("/bundle/rootfs"),
+            images_dir: Path::new("/images"),
+            work_dir: None,
+            cgroup: Path::new("pod-web/app"),
+            tcp_established: false,
+            shell_job: false,
+            mounts: bind_mount_sources(&config, &mount_map),
+            inherit: vec![
+                (5, String::from("pipe:[123]")),
+                (7, String::from(NETNS_KEY)),
+            ],
+        };
+        let args = restore_args(&restore, Path::new("/images/restore.pid"));
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        for expected in [
+            "/pod-web/app",
+            "mnt[/data]:/mnt/new/data",
+            "mnt[/cache]:/var/cache/app",
+            "fd[5]:pipe:[123]",
+            "fd[7]:extRootNetNS",
+            "--restore-sibling",
+        ] {
+            assert!(
+                args.contains(&expected),
+                "{} missing from {:?}",
+                expected,
+                args
+            );
+        }
+    }
+}
@@ -1,21 +1,178 @@
 //! Settings/Context for the container runtime itself.
 
+use crate::config::HardeningProfile;
+use crate::dirs::ContainerDirs;
 use crate::error::ContainerErr;
+use crate::store::FileStore;
+use crate::trace;
 use log::debug;
+use serde::{Deserialize, Serialize};
 use std::{
-    fs,
+    collections::BTreeMap,
+    fs::{self, DirBuilder},
     io::ErrorKind,
+    os::unix::fs::DirBuilderExt,
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
 };
 
-pub const STATE_FILENAME: &str = "state.json";
+/// State dirs hold the container's state and config, they're root's business. Opening
+/// one is what lets `start` start the container, see `start_signal`.
+pub const STATE_DIR_MODE: u32 = 0o700;
+const LOCKS_DIR: &str = ".locks";
+const PODS_DIR: &str = ".pods";
 const BASE_DIR: &str = "/run/generic_brand_container_runtime";
+const RUNTIME_CONFIG_PATH: &str = "/etc/generic_brand_container_runtime/config.json";
+
+static GLOBAL_OPTS: OnceLock<GlobalOpts> = OnceLock::new();
+
+/// How container cgroups are laid out, like runc's `--systemd-cgroup`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupManager {
+    /// cgroupsPath is a path relative to the cgroup mount, `<id>` by default.
+    #[default]
+    Cgroupfs,
+    /// cgroupsPath is `slice:prefix:name`, placed where systemd would put the scope.
+    Systemd,
+}
+
+impl FromStr for CgroupManager {
+    type Err = ContainerErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cgroupfs" => Ok(CgroupManager::Cgroupfs),
+            "systemd" => Ok(CgroupManager::Systemd),
+            _ => Err(ContainerErr::invalid_args(&format!(
+                "Unknown cgroup manager: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// What happens to resource settings the host can't apply, e.g. for a controller its
+/// cgroup root doesn't have or a file its kernel doesn't know.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourcePolicy {
+    /// They fail the create.
+    #[default]
+    Strict,
+    /// They're skipped with a warning, recorded in the container's state.
+    Permissive,
+}
+
+impl FromStr for ResourcePolicy {
+    type Err = ContainerErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(ResourcePolicy::Strict),
+            "permissive" => Ok(ResourcePolicy::Permissive),
+            _ => Err(ContainerErr::invalid_args(&format!(
+                "Unknown resource policy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Where the timings of lifecycle phases go, see `trace`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TraceOutput {
+    /// A debug log line per phase.
+    #[default]
+    Log,
+    /// A JSON object per phase on the runtime's stderr.
+    Json,
+}
+
+impl FromStr for TraceOutput {
+    type Err = ContainerErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(TraceOutput::Log),
+            "json" => Ok(TraceOutput::Json),
+            _ => Err(ContainerErr::invalid_args(&format!(
+                "Unknown trace output: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Options given before the command, they apply to every command.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GlobalOpts {
+    pub cgroup_manager: CgroupManager,
+    pub trace_output: TraceOutput,
+    /// Controllers whose settings are left out, see `Config::disable_controllers`.
+    pub disabled_controllers: Vec<String>,
+}
+
+/// Sets the global options, has to happen before any command runs.
+pub fn set_global_opts(opts: GlobalOpts) {
+    trace::init(opts.trace_output);
+    let _ = GLOBAL_OPTS.set(opts);
+}
+
+/// The global options in effect, if they were set.
+#[cfg(feature = "tokio")]
+pub(crate) fn global_opts() -> Option<&'static GlobalOpts> {
+    GLOBAL_OPTS.get()
+}
+
+/// The `--trace-output` in effect.
+pub fn trace_output() -> TraceOutput {
+    GLOBAL_OPTS
+        .get()
+        .map(|opts| opts.trace_output)
+        .unwrap_or_default()
+}
+
+/// The runtime's own config file, host-wide settings for every container.
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// Merged into every container's config, unless its bundle opts out, by name.
+    #[serde(default)]
+    pub hardening_profiles: BTreeMap<String, HardeningProfile>,
+    /// Left out of every container's resources, like `--disable-controller`.
+    #[serde(default)]
+    pub disabled_controllers: Vec<String>,
+    /// Unless `create --resource-policy` says otherwise.
+    #[serde(default)]
+    pub resource_policy: ResourcePolicy,
+    /// Driver library directories `create --gpus` binds into the container, read-only at
+    /// the same path.
+    #[serde(default)]
+    pub gpu_library_dirs: Vec<PathBuf>,
+}
+
+impl RuntimeConfig {
+    /// Loads the config file at `path`, the defaults if there's none.
+    pub fn load(path: &Path) -> Result<Self, ContainerErr> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(ContainerErr::IO(e)),
+        };
+        serde_json::from_str(&contents)
+            .map_err(|e| ContainerErr::Options(format!("{}: {}", path.display(), e)))
+    }
+}
 
 /// Container runtime settings
 #[derive(Clone)]
 pub struct Ctx {
     pub state_dir: PathBuf,
     cgroups_root: PathBuf,
+    cgroup_manager: CgroupManager,
+    disabled_controllers: Vec<String>,
 }
 
 impl Default for Ctx {
@@ -23,6 +180,8 @@ impl Default for Ctx {
         Self {
             state_dir: PathBuf::from(BASE_DIR),
             cgroups_root: PathBuf::from("/sys/fs/cgroup"),
+            cgroup_manager: CgroupManager::default(),
+            disabled_controllers: Vec::new(),
         }
     }
 }
@@ -32,24 +191,63 @@ impl Ctx {
         &self.cgroups_root
     }
 
+    pub fn cgroup_manager(&self) -> CgroupManager {
+        self.cgroup_manager
+    }
+
+    /// The runtime's config file, see `RuntimeConfig`.
+    pub fn runtime_config(&self) -> Result<RuntimeConfig, ContainerErr> {
+        RuntimeConfig::load(Path::new(RUNTIME_CONFIG_PATH))
+    }
+
+    /// The controllers disabled by `--disable-controller` and the runtime's config file.
+    pub fn disabled_controllers(&self) -> Result<Vec<String>, ContainerErr> {
+        let mut disabled = self.runtime_config()?.disabled_controllers;
+        disabled.extend(self.disabled_controllers.iter().cloned());
+        Ok(disabled)
+    }
+
     pub fn state_dir(&self, container_id: &str) -> PathBuf {
         self.state_dir.join(container_id)
     }
 
-    pub fn state_path_for(&self, container_id: &str) -> PathBuf {
-        self.state_dir.join(container_id).join(STATE_FILENAME)
+    /// The files in the container's state dir.
+    pub fn container_dirs(&self, container_id: &str) -> ContainerDirs {
+        ContainerDirs::new(self.state_dir(container_id))
+    }
+
+    /// Directory holding the per-container lock files, see `lock::ContainerLock`.
+    pub fn locks_dir(&self) -> PathBuf {
+        self.state_dir.join(LOCKS_DIR)
+    }
+
+    /// The containers' states, kept in their state dirs.
+    pub fn store(&self) -> FileStore<'_> {
+        FileStore::new(self)
+    }
+
+    /// Directory holding the state of pod `name`, see `pod::Pod`.
+    pub fn pod_dir(&self, name: &str) -> PathBuf {
+        self.state_dir.join(PODS_DIR).join(name)
     }
 }
 
 /// Sets up context (creates state dir if it doesn't exist)
 pub fn setup_ctx() -> Result<Ctx, ContainerErr> {
     debug!("setting up context...");
-    let ctx = Ctx::default();
+    let mut ctx = Ctx::default();
+    if let Some(opts) = GLOBAL_OPTS.get() {
+        ctx.cgroup_manager = opts.cgroup_manager;
+        ctx.disabled_controllers = opts.disabled_controllers.clone();
+    }
 
     if let Err(e) = fs::metadata(&ctx.state_dir) {
         if e.kind() == ErrorKind::NotFound {
             debug!("state dir not found, creating...");
-            fs::create_dir(&ctx.state_dir).map_err(ContainerErr::IO)?;
+            DirBuilder::new()
+                .mode(STATE_DIR_MODE)
+                .create(&ctx.state_dir)
+                .map_err(ContainerErr::IO)?;
         } else {
             return Err(ContainerErr::IO(e));
         }
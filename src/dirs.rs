@@ -0,0 +1,169 @@
+//! The layout of a container's state dir, everything the runtime keeps on disk for it.
+//!
+//! Files are only ever reached through [`ContainerDirs`], so there's one place to see
+//! what a state dir holds, and removing the dir removes all of it.
+
+use crate::config::Config;
+use crate::ctx::STATE_DIR_MODE;
+use crate::error::ContainerErr;
+use std::fs::{self, DirBuilder};
+use std::io::ErrorKind;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+const STATE_FILENAME: &str = "state.json";
+pub const FIFO_FILENAME: &str = "exec_fifo";
+const SOCKET_FILENAME: &str = "attach.sock";
+const LOG_FILENAME: &str = "container.log";
+const MONITOR_LOG_FILENAME: &str = "monitor.log";
+const EXIT_FILENAME: &str = "exit.json";
+const EVENTS_FILENAME: &str = "events.jsonl";
+const TIMINGS_FILENAME: &str = "timings.jsonl";
+const PORTS_FILENAME: &str = "ports.json";
+const ETC_DIR: &str = "etc";
+/// Mount points for image rootfs. The image is mounted on `image/`, a tmpfs for writes on
+/// `rw/` and the two are combined on `rootfs/`.
+const IMAGE_DIR: &str = "image";
+const RW_DIR: &str = "rw";
+const ROOTFS_DIR: &str = "rootfs";
+
+/// A container's state dir, see `Ctx::container_dirs`.
+#[derive(Clone, Debug)]
+pub struct ContainerDirs {
+    dir: PathBuf,
+}
+
+impl ContainerDirs {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Creates the state dir, and its parents, if it doesn't exist yet.
+    pub fn create(&self) -> Result<(), ContainerErr> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(STATE_DIR_MODE)
+            .create(&self.dir)
+            .map_err(ContainerErr::IO)
+    }
+
+    /// Removes the state dir with everything in it, fine if it's gone already.
+    pub fn remove(&self) -> Result<(), ContainerErr> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(ContainerErr::IO(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes what a run of the container left which would get in the way of creating
+    /// it again: its exit status and start signal.
+    pub fn remove_run_files(&self) -> Result<(), ContainerErr> {
+        for path in [self.exit_status(), self.exec_fifo()] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(ContainerErr::IO(e)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> PathBuf {
+        self.dir.join(STATE_FILENAME)
+    }
+
+    /// The copy of the bundle's config.json, later commands don't depend on the bundle.
+    pub fn load_config(&self) -> Result<Config, ContainerErr> {
+        Config::load(&self.dir)
+    }
+
+    pub fn write_config(&self, config: &Config) -> Result<(), ContainerErr> {
+        config.write(&self.dir)
+    }
+
+    /// The legacy start signal, see `start_signal`.
+    pub fn exec_fifo(&self) -> PathBuf {
+        self.dir.join(FIFO_FILENAME)
+    }
+
+    pub fn attach_socket(&self) -> PathBuf {
+        self.dir.join(SOCKET_FILENAME)
+    }
+
+    /// The container's stdout and stderr.
+    pub fn container_log(&self) -> PathBuf {
+        self.dir.join(LOG_FILENAME)
+    }
+
+    pub fn monitor_log(&self) -> PathBuf {
+        self.dir.join(MONITOR_LOG_FILENAME)
+    }
+
+    pub fn exit_status(&self) -> PathBuf {
+        self.dir.join(EXIT_FILENAME)
+    }
+
+    pub fn events(&self) -> PathBuf {
+        self.dir.join(EVENTS_FILENAME)
+    }
+
+    pub fn timings(&self) -> PathBuf {
+        self.dir.join(TIMINGS_FILENAME)
+    }
+
+    pub fn ports(&self) -> PathBuf {
+        self.dir.join(PORTS_FILENAME)
+    }
+
+    /// The generated /etc files, see `etc_files`.
+    pub fn etc_dir(&self) -> PathBuf {
+        self.dir.join(ETC_DIR)
+    }
+
+    /// Where an image rootfs is mounted, see `rootfs::setup_rootfs`.
+    pub fn image_dir(&self) -> PathBuf {
+        self.dir.join(IMAGE_DIR)
+    }
+
+    pub fn image_rw_dir(&self) -> PathBuf {
+        self.dir.join(RW_DIR)
+    }
+
+    pub fn image_rootfs_dir(&self) -> PathBuf {
+        self.dir.join(ROOTFS_DIR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_create_remove() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/dirs_{}/web", time)));
+        dirs.create().unwrap();
+        dirs.create().unwrap();
+        fs::write(dirs.exit_status(), "{}").unwrap();
+        fs::write(dirs.exec_fifo(), "").unwrap();
+        fs::write(dirs.container_log(), "hello\n").unwrap();
+
+        dirs.remove_run_files().unwrap();
+        assert!(!dirs.exit_status().exists());
+        assert!(!dirs.exec_fifo().exists());
+        assert!(dirs.container_log().exists());
+
+        fs::create_dir(dirs.etc_dir()).unwrap();
+        dirs.remove().unwrap();
+        assert!(!dirs.dir().exists());
+        dirs.remove().unwrap();
+        fs::remove_dir(dirs.dir().parent().unwrap()).unwrap();
+    }
+}
@@ -1,27 +1,47 @@
 use libc::c_int;
 
+use crate::config::Violation;
 use crate::mount::MountErr;
 
 #[derive(Debug)]
 pub enum ContainerErr {
     Args(String),
+    InvalidId(String),
     Bundle(String),
+    InvalidConfig(Vec<Violation>),
+    UnsupportedPlatform(String),
     IO(std::io::Error),
     Cgroup(String),
     State(String),
     Pipe(String),
     Fifo(String),
+    StartSignal(String),
+    AlreadyStarted(String),
     Init(&'static str),
+    Monitor(String),
+    Timeout(String),
     Rlimit(String),
     IoPriority(String),
+    Scheduler(String),
     InvalidNamespace(String),
     JoinNamespace(String),
     Clone(String),
     RootFs(String),
+    Entrypoint(String),
     Mount(MountErr),
     MountType(String),
     Options(String),
     Child((c_int, String)),
+    Hook(String),
+    Capabilities(String),
+    AppArmor(String),
+    Seccomp(String),
+    Procfs(String),
+    PortForward(String),
+    Pod(String),
+    Checkpoint(String),
+    Device(String),
+    Landlock(String),
 }
 
 impl ContainerErr {
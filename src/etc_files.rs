@@ -0,0 +1,192 @@
+//! /etc/resolv.conf, /etc/hostname and /etc/hosts generated by the runtime.
+//!
+//! With the etc-files annotation set, create writes the files to the container's state dir
+//! and init binds them read-only into the rootfs, so the bundle doesn't need the
+//! container's network identity baked into it. Files the bundle mounts itself are left
+//! alone.
+
+use crate::config::Config;
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use crate::extensions::Extensions;
+use crate::mount::{create_mount_point, mount};
+use libc::{MS_BIND, MS_RDONLY, MS_REMOUNT};
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const HOST_RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// The generated files, by name in the state dir and destination in the container.
+const FILES: [(&str, &str); 3] = [
+    ("resolv.conf", "/etc/resolv.conf"),
+    ("hostname", "/etc/hostname"),
+    ("hosts", "/etc/hosts"),
+];
+
+/// Writes the files for the container to its state dir, if the config asks for them.
+pub fn write_files(
+    config: &Config,
+    dirs: &ContainerDirs,
+    container_id: &str,
+) -> Result<(), ContainerErr> {
+    let ext = Extensions::parse(config)?;
+    if !ext.etc_files {
+        return Ok(());
+    }
+    let host_resolv_conf = match fs::read_to_string(HOST_RESOLV_CONF) {
+        Ok(contents) => contents,
+        Err(e) => {
+            debug!("not using the host's resolv.conf: {}", e);
+            String::new()
+        }
+    };
+    let hostname = config.hostname().unwrap_or(container_id);
+    let dir = dirs.etc_dir();
+    fs::create_dir_all(&dir).map_err(ContainerErr::IO)?;
+    let contents = [
+        resolv_conf(&ext, &host_resolv_conf),
+        format!("{}\n", hostname),
+        hosts(&ext, hostname),
+    ];
+    for ((name, _), contents) in FILES.iter().zip(contents) {
+        fs::write(dir.join(name), contents).map_err(ContainerErr::IO)?;
+    }
+    Ok(())
+}
+
+/// Binds the files written by `write_files` read-only into the rootfs, before it becomes
+/// the container's root.
+pub fn mount_files(
+    config: &Config,
+    dirs: &ContainerDirs,
+    rootfs: &Path,
+) -> Result<(), ContainerErr> {
+    if !Extensions::parse(config)?.etc_files {
+        return Ok(());
+    }
+    let dir = dirs.etc_dir();
+    for (name, destination) in FILES {
+        let configured = config
+            .mounts()
+            .is_some_and(|mounts| mounts.iter().any(|m| m.destination == destination));
+        if configured {
+            debug!(
+                "{} is mounted by the bundle, not mounting the generated one",
+                destination
+            );
+            continue;
+        }
+        let src = dir.join(name);
+        let target: PathBuf = rootfs.join(destination.trim_start_matches('/'));
+        create_mount_point(&target, true)?;
+        // The read-only flag is ignored when creating a bind mount, it takes a remount.
+        mount(&src, &target, c"", MS_BIND, None)
+            .and_then(|_| mount(&src, &target, c"", MS_BIND | MS_REMOUNT | MS_RDONLY, None))
+            .map_err(|e| {
+                ContainerErr::RootFs(format!("failed to mount {}: {:?}", destination, e))
+            })?;
+    }
+    Ok(())
+}
+
+/// resolv.conf from the dns annotations. Settings which aren't annotated come from the
+/// host's, except loopback nameservers which don't answer in the container's network
+/// namespace. The host's nameservers are assumed to be valid.
+fn resolv_conf(ext: &Extensions, host: &str) -> String {
+    let host_values = |key: &str| -> Vec<String> {
+        host.lines()
+            .filter_map(|line| line.trim().strip_prefix(key))
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .flat_map(str::split_whitespace)
+            .map(String::from)
+            .collect()
+    };
+
+    let nameservers = match &ext.dns {
+        Some(dns) => dns.clone(),
+        None => host_values("nameserver")
+            .iter()
+            .filter_map(|ns| ns.parse().ok())
+            .collect(),
+    };
+    let mut contents = String::new();
+    for ip in nameservers {
+        if ip.is_loopback() {
+            debug!("skipping loopback nameserver {}", ip);
+            continue;
+        }
+        contents.push_str(&format!("nameserver {}\n", ip));
+    }
+    let search = ext
+        .dns_search
+        .clone()
+        .unwrap_or_else(|| host_values("search"));
+    if !search.is_empty() {
+        contents.push_str(&format!("search {}\n", search.join(" ")));
+    }
+    let options = ext
+        .dns_options
+        .clone()
+        .unwrap_or_else(|| host_values("options"));
+    if !options.is_empty() {
+        contents.push_str(&format!("options {}\n", options.join(" ")));
+    }
+    contents
+}
+
+/// /etc/hosts with the loopback entries, the container's hostname and the add-hosts
+/// annotation's entries.
+fn hosts(ext: &Extensions, hostname: &str) -> String {
+    let mut contents = format!(
+        "127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n127.0.1.1\t{}\n",
+        hostname
+    );
+    for (name, ip) in &ext.add_hosts {
+        contents.push_str(&format!("{}\t{}\n", ip, name));
+    }
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolv_conf() {
+        let host = "# generated\nnameserver 127.0.0.53\nnameserver 10.0.0.1\nsearch example.com\noptions edns0 trust-ad\n";
+        assert_eq!(
+            "nameserver 10.0.0.1\nsearch example.com\noptions edns0 trust-ad\n",
+            resolv_conf(&Extensions::default(), host)
+        );
+
+        let ext = Extensions {
+            dns: Some(vec![
+                "1.1.1.1".parse().unwrap(),
+                "2606:4700:4700::1111".parse().unwrap(),
+            ]),
+            dns_options: Some(vec![String::from("ndots:2")]),
+            ..Default::default()
+        };
+        assert_eq!(
+            "nameserver 1.1.1.1\nnameserver 2606:4700:4700::1111\nsearch example.com\noptions ndots:2\n",
+            resolv_conf(&ext, host)
+        );
+    }
+
+    #[test]
+    fn test_hosts() {
+        let ext = Extensions {
+            add_hosts: vec![
+                (String::from("db"), "10.0.0.2".parse().unwrap()),
+                (String::from("cache"), "fd00::3".parse().unwrap()),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            "127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n127.0.1.1\tweb\n\
+             10.0.0.2\tdb\nfd00::3\tcache\n",
+            hosts(&ext, "web")
+        );
+    }
+}
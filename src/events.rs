@@ -0,0 +1,88 @@
+//! Container events, like health status changes.
+//!
+//! Events are appended to `events.jsonl` in the container's state dir, one json object per
+//! line in the shape of runc's events: `{"type": ..., "id": ..., "data": ...}` plus the
+//! time they happened at. Appends of a single line don't interleave, so the monitor's
+//! threads and other commands can emit events without coordinating.
+
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    /// Seconds since the epoch.
+    pub time: u64,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub data: Value,
+}
+
+impl Event {
+    pub fn new(typ: &str, container_id: &str, data: Value) -> Self {
+        Self {
+            typ: typ.to_string(),
+            id: container_id.to_string(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            data,
+        }
+    }
+}
+
+/// Appends `event` to the container's events.
+pub fn emit(dirs: &ContainerDirs, event: &Event) -> Result<(), ContainerErr> {
+    let mut line = serde_json::to_vec(event).map_err(|e| ContainerErr::State(e.to_string()))?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dirs.events())
+        .and_then(|mut f| f.write_all(&line))
+        .map_err(ContainerErr::IO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_emit() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/events_{}", time)));
+        dirs.create().unwrap();
+
+        let first = Event::new("health_status", "foo", json!({"status": "healthy"}));
+        let second = Event::new("oom", "foo", Value::Null);
+        emit(&dirs, &first).unwrap();
+        emit(&dirs, &second).unwrap();
+
+        let raw = fs::read_to_string(dirs.events()).unwrap();
+        let events: Vec<Event> = raw
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(vec![first, second], events);
+        assert!(raw
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("{\"type\":\"oom\",\"id\":\"foo\""));
+
+        dirs.remove().unwrap();
+    }
+}
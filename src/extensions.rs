@@ -0,0 +1,372 @@
+//! Runtime extensions, configured with annotations under `org.beersonthewall.runtime.`.
+//!
+//! Features which aren't part of the OCI spec, or are still experimental, are switched
+//! on and configured through annotations instead of new config fields. Each extension
+//! registers a parser for its annotation which fills in its typed options in
+//! [`Extensions`]. create parses them up front so bad values fail it, later steps parse
+//! the config's copy again rather than reading the annotations themselves.
+
+use crate::cmd::LogDriver;
+use crate::config::{Config, Violation};
+use crate::error::ContainerErr;
+use crate::mount::parse_size;
+use crate::portforward::PortMapping;
+use crate::restart::RestartPolicy;
+use crate::signal::parse_signal;
+use crate::users::split_user;
+use libc::c_int;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Namespace of the runtime's annotations.
+pub const PREFIX: &str = "org.beersonthewall.runtime.";
+
+/// Parses an annotation's value into the extensions' options.
+pub type Parser = fn(&mut Extensions, &str) -> Result<(), ContainerErr>;
+
+/// Typed options of the runtime's extensions, parsed from a config's annotations.
+#[derive(Debug, Default)]
+pub struct Extensions {
+    /// Size of the container's /dev/shm in bytes.
+    pub shm_size: Option<u64>,
+    /// Where the container's output goes, unless create is told otherwise.
+    pub log_driver: Option<LogDriver>,
+    /// Ports to publish, besides those given to create.
+    pub publish: Vec<PortMapping>,
+    /// Shell command the monitor runs once the container has exited.
+    pub cleanup: Option<String>,
+    /// Whether the runtime generates /etc/resolv.conf, /etc/hostname and /etc/hosts.
+    pub etc_files: bool,
+    pub dns: Option<Vec<IpAddr>>,
+    pub dns_search: Option<Vec<String>>,
+    pub dns_options: Option<Vec<String>>,
+    /// Extra /etc/hosts entries, by name.
+    pub add_hosts: Vec<(String, IpAddr)>,
+    /// When the monitor restarts the container, unless create is told otherwise.
+    pub restart: Option<RestartPolicy>,
+    /// Shell command checking the container's health, see `health`.
+    pub health_cmd: Option<String>,
+    pub health_interval: Option<Duration>,
+    pub health_timeout: Option<Duration>,
+    pub health_retries: Option<u32>,
+    /// Signal `stop` sends first, see `signal::stop_signal`.
+    pub stop_signal: Option<c_int>,
+    /// Hardening profiles of the runtime's config file the container opts out of.
+    pub hardening_opt_out: Vec<String>,
+    /// `user[:group]`, names or ids, the process runs as instead of process.user's ids.
+    /// Names are looked up in the rootfs, see `users::resolve_user`.
+    pub user: Option<String>,
+    /// Give the container's processes a core-scheduling cookie, see
+    /// `sched::create_core_cookie`.
+    pub sched_core: bool,
+    /// Whether the OOM killer takes out the whole container, true unless set.
+    pub oom_group: Option<bool>,
+}
+
+impl Extensions {
+    /// Parses the config's annotations with the runtime's extensions.
+    pub fn parse(config: &Config) -> Result<Self, ContainerErr> {
+        Registry::default().parse(config)
+    }
+}
+
+/// Parsers by annotation name, without the prefix.
+pub struct Registry {
+    parsers: Vec<(&'static str, Parser)>,
+}
+
+impl Registry {
+    /// A registry without any extensions.
+    pub fn new() -> Self {
+        Self {
+            parsers: Vec::new(),
+        }
+    }
+
+    /// Registers the parser for the annotation `PREFIX` + `name`, replacing the one
+    /// registered before.
+    pub fn register(&mut self, name: &'static str, parser: Parser) -> &mut Self {
+        self.parsers.retain(|(n, _)| *n != name);
+        self.parsers.push((name, parser));
+        self
+    }
+
+    /// Parses the annotations of the registered extensions. Those under the prefix no
+    /// extension knows about are skipped, see `unknown`.
+    pub fn parse(&self, config: &Config) -> Result<Extensions, ContainerErr> {
+        let mut extensions = Extensions::default();
+        for (name, parser) in &self.parsers {
+            if let Some(value) = config.annotation(&format!("{}{}", PREFIX, name)) {
+                parser(&mut extensions, value).map_err(|e| {
+                    ContainerErr::Options(format!("annotation {}{}: {:?}", PREFIX, name, e))
+                })?;
+            }
+        }
+        Ok(extensions)
+    }
+
+    /// Annotations under the prefix which none of the extensions handle, likely typos.
+    pub fn unknown(&self, config: &Config) -> Vec<Violation> {
+        let mut unknown: Vec<Violation> = config
+            .annotations()
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, _)| key.strip_prefix(PREFIX).map(|name| (key, name)))
+            .filter(|(_, name)| !self.parsers.iter().any(|(n, _)| n == name))
+            .map(|(key, _)| Violation {
+                pointer: format!("/annotations/{}", key),
+                message: String::from("unknown runtime extension"),
+            })
+            .collect();
+        unknown.sort_by(|a, b| a.pointer.cmp(&b.pointer));
+        unknown
+    }
+}
+
+impl Default for Registry {
+    /// The runtime's extensions.
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry
+            .register("shm-size", |ext, value| {
+                ext.shm_size = Some(parse_size(value)?);
+                Ok(())
+            })
+            .register("log-driver", |ext, value| {
+                ext.log_driver = Some(value.trim().parse()?);
+                Ok(())
+            })
+            .register("publish", |ext, value| {
+                ext.publish = list(value).map(str::parse).collect::<Result<_, _>>()?;
+                Ok(())
+            })
+            .register("cleanup", |ext, value| {
+                ext.cleanup = Some(value.to_string());
+                Ok(())
+            })
+            .register("etc-files", |ext, value| {
+                ext.etc_files = parse_bool(value)?;
+                Ok(())
+            })
+            .register("dns", |ext, value| {
+                let dns = list(value).map(parse_ip).collect::<Result<_, _>>()?;
+                ext.dns = Some(dns);
+                Ok(())
+            })
+            .register("dns-search", |ext, value| {
+                ext.dns_search = Some(list(value).map(String::from).collect());
+                Ok(())
+            })
+            .register("dns-options", |ext, value| {
+                ext.dns_options = Some(list(value).map(String::from).collect());
+                Ok(())
+            })
+            .register("add-hosts", |ext, value| {
+                ext.add_hosts = list(value).map(parse_host).collect::<Result<_, _>>()?;
+                Ok(())
+            })
+            .register("restart", |ext, value| {
+                ext.restart = Some(value.trim().parse()?);
+                Ok(())
+            })
+            .register("health-cmd", |ext, value| {
+                ext.health_cmd = Some(value.to_string());
+                Ok(())
+            })
+            .register("health-interval", |ext, value| {
+                ext.health_interval = Some(parse_duration(value)?);
+                Ok(())
+            })
+            .register("health-timeout", |ext, value| {
+                ext.health_timeout = Some(parse_duration(value)?);
+                Ok(())
+            })
+            .register("health-retries", |ext, value| {
+                let retries = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| ContainerErr::Options(format!("invalid number: {:?}", value)))?;
+                ext.health_retries = Some(retries);
+                Ok(())
+            })
+            .register("stop-signal", |ext, value| {
+                ext.stop_signal = Some(parse_signal(value.trim())?);
+                Ok(())
+            })
+            .register("hardening-opt-out", |ext, value| {
+                ext.hardening_opt_out = list(value).map(String::from).collect();
+                Ok(())
+            })
+            .register("user", |ext, value| {
+                split_user(value.trim())?;
+                ext.user = Some(value.trim().to_string());
+                Ok(())
+            })
+            .register("sched-core", |ext, value| {
+                ext.sched_core = parse_bool(value)?;
+                Ok(())
+            })
+            .register("oom-group", |ext, value| {
+                ext.oom_group = Some(parse_bool(value)?);
+                Ok(())
+            });
+        registry
+    }
+}
+
+/// Splits a comma separated annotation, skipping empty entries.
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|v| !v.is_empty())
+}
+
+fn parse_bool(value: &str) -> Result<bool, ContainerErr> {
+    match value.trim() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(ContainerErr::Options(format!(
+            "expected true or false: {:?}",
+            value
+        ))),
+    }
+}
+
+/// Parses a non-zero duration in seconds, or with an ms, s or m suffix.
+fn parse_duration(value: &str) -> Result<Duration, ContainerErr> {
+    let invalid = || ContainerErr::Options(format!("invalid duration: {:?}", value));
+    let value = value.trim();
+    let (n, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let n: u64 = n.parse().map_err(|_| invalid())?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(n),
+        "s" => Duration::from_secs(n),
+        "m" => Duration::from_secs(n.checked_mul(60).ok_or_else(invalid)?),
+        _ => return Err(invalid()),
+    };
+    if duration.is_zero() {
+        return Err(invalid());
+    }
+    Ok(duration)
+}
+
+fn parse_ip(value: &str) -> Result<IpAddr, ContainerErr> {
+    value
+        .parse()
+        .map_err(|_| ContainerErr::Options(format!("invalid address: {:?}", value)))
+}
+
+/// A `name=ip` hosts entry.
+fn parse_host(entry: &str) -> Result<(String, IpAddr), ContainerErr> {
+    let invalid = || ContainerErr::Options(format!("invalid hosts entry: {:?}", entry));
+    let (name, ip) = entry.split_once('=').ok_or_else(invalid)?;
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(invalid());
+    }
+    Ok((
+        name.to_string(),
+        parse_ip(ip.trim()).map_err(|_| invalid())?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(annotations: serde_json::Value) -> Config {
+        serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "annotations": annotations,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let ext = Extensions::parse(&config(json!({
+            "org.beersonthewall.runtime.shm-size": "1m",
+            "org.beersonthewall.runtime.log-driver": "journald",
+            "org.beersonthewall.runtime.publish": "8080:80, 5353:53/udp",
+            "org.beersonthewall.runtime.add-hosts": "db=10.0.0.2,cache = fd00::3",
+            "org.beersonthewall.runtime.stop-signal": "SIGQUIT",
+            "org.beersonthewall.runtime.user": "www:log",
+            "org.beersonthewall.runtime.sched-core": "true",
+            "org.beersonthewall.runtime.oom-group": "false",
+            "org.example.other": "ignored",
+        })))
+        .unwrap();
+        assert_eq!(Some(1 << 20), ext.shm_size);
+        assert_eq!(Some(LogDriver::Journald), ext.log_driver);
+        assert_eq!(2, ext.publish.len());
+        assert!(!ext.etc_files);
+        assert_eq!(None, ext.dns);
+        assert_eq!(
+            vec![
+                (String::from("db"), "10.0.0.2".parse().unwrap()),
+                (String::from("cache"), "fd00::3".parse().unwrap()),
+            ],
+            ext.add_hosts
+        );
+        assert_eq!(Some(libc::SIGQUIT), ext.stop_signal);
+        assert_eq!(Some(String::from("www:log")), ext.user);
+        assert!(ext.sched_core);
+        assert_eq!(Some(false), ext.oom_group);
+
+        for (name, value) in [
+            ("shm-size", "lots"),
+            ("log-driver", "syslog"),
+            ("publish", "8080"),
+            ("etc-files", "yes"),
+            ("dns", "dns.example"),
+            ("add-hosts", "a b=10.0.0.2"),
+            ("restart", "on-failure:x"),
+            ("health-interval", "0s"),
+            ("health-timeout", "5h"),
+            ("health-retries", "-1"),
+            ("stop-signal", "SIGNOPE"),
+            ("user", "www:"),
+            ("sched-core", "on"),
+        ] {
+            let key = format!("{}{}", PREFIX, name);
+            assert!(Extensions::parse(&config(json!({key: value}))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Duration::from_secs(30), parse_duration("30").unwrap());
+        assert_eq!(Duration::from_secs(30), parse_duration("30s").unwrap());
+        assert_eq!(Duration::from_millis(500), parse_duration("500ms").unwrap());
+        assert_eq!(Duration::from_secs(120), parse_duration("2m").unwrap());
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("1.5s").is_err());
+    }
+
+    #[test]
+    fn test_registry() {
+        let config = config(json!({
+            "org.beersonthewall.runtime.shm-size": "1m",
+            "org.beersonthewall.runtime.experiment": "on",
+        }));
+        let unknown = Registry::default().unknown(&config);
+        assert_eq!(1, unknown.len());
+        assert_eq!(
+            "/annotations/org.beersonthewall.runtime.experiment",
+            unknown[0].pointer
+        );
+
+        // Registering a parser claims the annotation.
+        let mut registry = Registry::new();
+        registry.register("experiment", |ext, value| {
+            ext.etc_files = value == "on";
+            Ok(())
+        });
+        assert!(registry.parse(&config).unwrap().etc_files);
+        assert_eq!(1, registry.unknown(&config).len());
+    }
+}
@@ -0,0 +1,82 @@
+//! File descriptors the container's processes inherit.
+//!
+//! Everything the runtime opens itself is close-on-exec, std opens files and pipes that
+//! way. What isn't is whatever our caller left open, which the process would inherit
+//! along with stdio, e.g. a descriptor of the caller's state dir. So right before exec
+//! everything above stderr is marked close-on-exec, except the descriptors the caller
+//! passes on with `--preserve-fds`. The environment needs no such care, the process only
+//! gets process.env, never the runtime's own.
+
+use crate::error::ContainerErr;
+use crate::syscalls::{close_range, set_cloexec};
+use libc::c_uint;
+use log::debug;
+use std::fs;
+use std::os::fd::RawFd;
+
+/// The first descriptor after stdin, stdout and stderr.
+const FIRST_FD: c_uint = 3;
+
+/// Marks every descriptor from 3 + `preserve` on close-on-exec, so only stdio and the
+/// `preserve` descriptors after it make it past exec.
+pub fn close_inherited(preserve: u32) -> Result<(), ContainerErr> {
+    let first = FIRST_FD.saturating_add(preserve);
+    match close_range(first, c_uint::MAX, libc::CLOSE_RANGE_CLOEXEC) {
+        Ok(()) => Ok(()),
+        // Kernels before 5.11 don't have close_range or its CLOEXEC flag.
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EINVAL)) => {
+            debug!(
+                "close_range unavailable, going through /proc/self/fd: {}",
+                e
+            );
+            cloexec_from(first as RawFd)
+        }
+        Err(e) => Err(ContainerErr::IO(e)),
+    }
+}
+
+fn cloexec_from(first: RawFd) -> Result<(), ContainerErr> {
+    for entry in fs::read_dir("/proc/self/fd").map_err(ContainerErr::IO)? {
+        let entry = entry.map_err(ContainerErr::IO)?;
+        let Some(fd) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        if fd < first {
+            continue;
+        }
+        match set_cloexec(fd) {
+            // Closed since it was listed.
+            Err(e) if e.raw_os_error() == Some(libc::EBADF) => {}
+            result => result.map_err(ContainerErr::IO)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+
+    fn cloexec(fd: RawFd) -> bool {
+        unsafe { libc::fcntl(fd, libc::F_GETFD) & libc::FD_CLOEXEC != 0 }
+    }
+
+    #[test]
+    fn test_cloexec_from() {
+        // dup doesn't set close-on-exec, like the descriptors a caller leaves open.
+        let null = File::open("/dev/null").unwrap();
+        let kept = unsafe { libc::dup(null.as_raw_fd()) };
+        let leaked = unsafe { libc::dup(null.as_raw_fd()) };
+        assert!(kept < leaked && !cloexec(leaked));
+
+        cloexec_from(leaked).unwrap();
+        assert!(!cloexec(kept));
+        assert!(cloexec(leaked));
+        unsafe {
+            libc::close(kept);
+            libc::close(leaked);
+        }
+    }
+}
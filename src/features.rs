@@ -0,0 +1,225 @@
+//! Probing the kernel for the features the runtime uses, see the `features` command.
+//!
+//! Syscalls are probed by calling them with arguments every kernel having them rejects:
+//! ENOSYS means the syscall is missing, any other error that it's there. Nothing is
+//! created along the way. The results are cached for the life of the process.
+
+use crate::cgroup::{detect_cgroup_version, CgroupVersion};
+use crate::config::Config;
+use crate::error::ContainerErr;
+use crate::process::pidfd_open;
+use crate::syscalls;
+use libc::{c_long, clone_args, syscall, ENOSYS};
+use std::io;
+use std::path::Path;
+use std::ptr;
+use std::sync::OnceLock;
+
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+const TIME_NS_PATH: &str = "/proc/self/ns/time";
+/// The seccomp action behind SCMP_ACT_NOTIFY, which libc doesn't have.
+const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc00000;
+
+/// A kernel feature, and the kernel version which brought it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    Clone3,
+    CloneIntoCgroup,
+    Cgroup2,
+    Openat2,
+    MountSetattr,
+    SeccompUserNotif,
+    Pidfd,
+    TimeNamespace,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 8] = [
+        Feature::Clone3,
+        Feature::CloneIntoCgroup,
+        Feature::Cgroup2,
+        Feature::Openat2,
+        Feature::MountSetattr,
+        Feature::SeccompUserNotif,
+        Feature::Pidfd,
+        Feature::TimeNamespace,
+    ];
+
+    /// The feature's key in the `features` output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Clone3 => "clone3",
+            Feature::CloneIntoCgroup => "cloneIntoCgroup",
+            Feature::Cgroup2 => "cgroup2",
+            Feature::Openat2 => "openat2",
+            Feature::MountSetattr => "mountSetattr",
+            Feature::SeccompUserNotif => "seccompUserNotify",
+            Feature::Pidfd => "pidfd",
+            Feature::TimeNamespace => "timeNamespace",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Feature::Clone3 => "clone3",
+            Feature::CloneIntoCgroup => "CLONE_INTO_CGROUP",
+            Feature::Cgroup2 => "cgroup v2",
+            Feature::Openat2 => "openat2",
+            Feature::MountSetattr => "mount_setattr",
+            Feature::SeccompUserNotif => "seccomp user notification",
+            Feature::Pidfd => "pidfd",
+            Feature::TimeNamespace => "time namespaces",
+        }
+    }
+
+    /// The first kernel version with the feature.
+    pub fn min_kernel(self) -> &'static str {
+        match self {
+            Feature::Clone3 => "5.3",
+            Feature::CloneIntoCgroup => "5.7",
+            Feature::Cgroup2 => "4.5",
+            Feature::Openat2 => "5.6",
+            Feature::MountSetattr => "5.12",
+            Feature::SeccompUserNotif => "5.0",
+            Feature::Pidfd => "5.3",
+            Feature::TimeNamespace => "5.6",
+        }
+    }
+
+    fn probe(self) -> bool {
+        match self {
+            Feature::Clone3 => syscall_exists(unsafe {
+                syscall(libc::SYS_clone3, ptr::null_mut::<clone_args>(), 0usize)
+            }),
+            Feature::CloneIntoCgroup => probe_clone_into_cgroup(),
+            Feature::Cgroup2 => {
+                matches!(detect_cgroup_version(CGROUP_MOUNT), Ok(CgroupVersion::V2))
+            }
+            Feature::Openat2 => syscall_exists(unsafe {
+                syscall(
+                    libc::SYS_openat2,
+                    -1,
+                    ptr::null::<u8>(),
+                    ptr::null::<u8>(),
+                    0usize,
+                )
+            }),
+            Feature::MountSetattr => syscall_exists(unsafe {
+                syscall(
+                    libc::SYS_mount_setattr,
+                    -1,
+                    ptr::null::<u8>(),
+                    0,
+                    ptr::null::<u8>(),
+                    0usize,
+                )
+            }),
+            Feature::SeccompUserNotif => {
+                let action = SECCOMP_RET_USER_NOTIF;
+                let ret = unsafe {
+                    syscall(
+                        libc::SYS_seccomp,
+                        libc::SECCOMP_GET_ACTION_AVAIL,
+                        0,
+                        &action as *const u32,
+                    )
+                };
+                ret == 0
+            }
+            Feature::Pidfd => pidfd_open(std::process::id()).is_ok(),
+            Feature::TimeNamespace => Path::new(TIME_NS_PATH).exists(),
+        }
+    }
+}
+
+/// Whether a syscall exists, given what it returned for arguments it rejects. The probes
+/// pass sizes as usize, the syscalls read a full register for them.
+fn syscall_exists(ret: c_long) -> bool {
+    ret >= 0 || io::Error::last_os_error().raw_os_error() != Some(ENOSYS)
+}
+
+/// clone3 with CLONE_INTO_CGROUP and a bad cgroup fd. Kernels knowing the flag fail with
+/// EBADF, older ones reject the flag or the larger clone_args.
+// libc warns CLONE_INTO_CGROUP overflows a c_int, it's only ever a clone_args flag here.
+#[allow(deprecated)]
+fn probe_clone_into_cgroup() -> bool {
+    let mut args = unsafe { std::mem::zeroed::<clone_args>() };
+    args.flags = libc::CLONE_INTO_CGROUP as u64;
+    args.cgroup = i32::MAX as u64;
+    match unsafe { syscalls::clone3(&mut args) } {
+        Ok(0) => unsafe { libc::_exit(0) },
+        // Can't happen, the fd isn't open. Don't leave a zombie behind if it does.
+        Ok(pid) => {
+            let _ = syscalls::waitpid(pid);
+            true
+        }
+        Err(e) => syscalls::errno(&e) == Some(libc::EBADF),
+    }
+}
+
+/// Whether the kernel has `feature`, probed the first time any feature is asked about.
+pub fn available(feature: Feature) -> bool {
+    static AVAILABLE: OnceLock<[bool; Feature::ALL.len()]> = OnceLock::new();
+    let available = AVAILABLE.get_or_init(|| Feature::ALL.map(Feature::probe));
+    available[feature as usize]
+}
+
+fn lacks(feature: Feature) -> ContainerErr {
+    ContainerErr::UnsupportedPlatform(format!(
+        "kernel lacks {}, need ≥ {}",
+        feature.description(),
+        feature.min_kernel()
+    ))
+}
+
+/// Fails unless the kernel has `feature`.
+pub fn require(feature: Feature) -> Result<(), ContainerErr> {
+    if available(feature) {
+        Ok(())
+    } else {
+        Err(lacks(feature))
+    }
+}
+
+/// Checks up front that the kernel has what creating a container from `config` needs,
+/// rather than failing halfway through. Features with a fallback aren't required.
+pub fn check(config: &Config, cgroups_root: &Path) -> Result<(), ContainerErr> {
+    // The default mount is what's probed, the runtime may be pointed elsewhere.
+    if !matches!(detect_cgroup_version(cgroups_root)?, CgroupVersion::V2) {
+        return Err(lacks(Feature::Cgroup2));
+    }
+    // The monitor, start, kill and stop all track the container process by pidfd.
+    require(Feature::Pidfd)?;
+    let new_time_ns = config
+        .linux_namespaces()
+        .unwrap_or_default()
+        .iter()
+        .any(|ns| ns.typ == "time" && ns.path.is_none());
+    if new_time_ns {
+        require(Feature::TimeNamespace)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probes() {
+        for feature in Feature::ALL {
+            // Probing twice gives the cached result.
+            assert_eq!(available(feature), available(feature), "{:?}", feature);
+        }
+        // pidfds are older than the kernels the tests run on.
+        assert!(available(Feature::Pidfd));
+    }
+
+    #[test]
+    fn test_lacks() {
+        assert_eq!(
+            "UnsupportedPlatform(\"kernel lacks time namespaces, need ≥ 5.6\")",
+            format!("{:?}", lacks(Feature::TimeNamespace))
+        );
+    }
+}
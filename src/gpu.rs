@@ -0,0 +1,181 @@
+//! GPUs for `create --gpus`: the host's NVIDIA device nodes and DRM render nodes are bound
+//! into the container, allowed by its device rules, and so are the driver library directories
+//! listed in the runtime's config file (see `ctx::RuntimeConfig`), read-only.
+
+use crate::config::{Config, DeviceNode};
+use crate::error::ContainerErr;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// NVIDIA's device nodes shared by all of its GPUs.
+const NVIDIA_CONTROL_DEVICES: [&str; 4] = [
+    "nvidiactl",
+    "nvidia-uvm",
+    "nvidia-uvm-tools",
+    "nvidia-modeset",
+];
+
+/// Which of the host's GPUs a container gets, parsed from `all` or a list of indices like
+/// `0,2`. An index picks /dev/nvidia<index> and the render node in the same position.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Gpus {
+    All,
+    Indices(Vec<u32>),
+}
+
+impl FromStr for Gpus {
+    type Err = ContainerErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(Gpus::All);
+        }
+        s.split(',')
+            .map(|index| index.parse::<u32>())
+            .collect::<Result<_, _>>()
+            .map(Gpus::Indices)
+            .map_err(|_| {
+                ContainerErr::invalid_args(&format!(
+                    "Invalid gpus: {}, expected all or indices like 0,1",
+                    s
+                ))
+            })
+    }
+}
+
+impl Gpus {
+    fn has(&self, index: usize) -> bool {
+        match self {
+            Gpus::All => true,
+            Gpus::Indices(indices) => indices.contains(&(index as u32)),
+        }
+    }
+}
+
+/// Binds the `gpus` found under `dev` into the container, and the `library_dirs`.
+pub fn apply_gpus(
+    config: &mut Config,
+    gpus: &Gpus,
+    dev: &Path,
+    library_dirs: &[PathBuf],
+) -> Result<(), ContainerErr> {
+    let devices = select_devices(gpus, &list_dir(dev)?, &list_dir(&dev.join("dri"))?)?;
+    for name in devices {
+        let path = dev.join(name);
+        let node = DeviceNode::stat(&path.to_string_lossy())?;
+        debug!("binding gpu device {:?}", node);
+        config.bind_device(&node, "rwm");
+    }
+    for dir in library_dirs {
+        if !dir.is_dir() {
+            return Err(ContainerErr::Device(format!(
+                "gpu library directory {:?} doesn't exist",
+                dir
+            )));
+        }
+        let dir = dir.to_string_lossy();
+        config.add_bind_mount(&dir, &dir, &["rbind", "ro", "nosuid", "nodev"]);
+    }
+    Ok(())
+}
+
+/// The device nodes of the `gpus`, relative to /dev, given the names in /dev and
+/// /dev/dri. Fails if one of the GPUs asked for isn't there.
+fn select_devices(
+    gpus: &Gpus,
+    dev: &[String],
+    dri: &[String],
+) -> Result<Vec<String>, ContainerErr> {
+    let mut nvidia: Vec<u32> = dev
+        .iter()
+        .filter_map(|name| name.strip_prefix("nvidia")?.parse().ok())
+        .collect();
+    nvidia.sort_unstable();
+    let mut render: Vec<u32> = dri
+        .iter()
+        .filter_map(|name| name.strip_prefix("renderD")?.parse().ok())
+        .collect();
+    render.sort_unstable();
+
+    if let Gpus::Indices(indices) = gpus {
+        let count = nvidia.len().max(render.len());
+        if let Some(missing) = indices.iter().find(|index| **index as usize >= count) {
+            return Err(ContainerErr::Device(format!("no gpu {}", missing)));
+        }
+    } else if nvidia.is_empty() && render.is_empty() {
+        return Err(ContainerErr::Device(String::from("no gpus found")));
+    }
+
+    let mut devices = Vec::new();
+    if !nvidia.is_empty() {
+        devices.extend(
+            NVIDIA_CONTROL_DEVICES
+                .iter()
+                .filter(|name| dev.iter().any(|d| d == *name))
+                .map(|name| name.to_string()),
+        );
+    }
+    devices.extend(
+        nvidia
+            .iter()
+            .filter(|index| gpus.has(**index as usize))
+            .map(|index| format!("nvidia{}", index)),
+    );
+    devices.extend(
+        render
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| gpus.has(*i))
+            .map(|(_, minor)| format!("dri/renderD{}", minor)),
+    );
+    Ok(devices)
+}
+
+/// The names in `dir`, none if it doesn't exist.
+fn list_dir(dir: &Path) -> Result<Vec<String>, ContainerErr> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .map(|entry| {
+                entry
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .map_err(ContainerErr::IO)
+            })
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(ContainerErr::IO(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_devices() {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        let dev: Vec<String> = names(&["null", "nvidia0", "nvidia1", "nvidiactl", "nvidia-uvm"]);
+        let dri: Vec<String> = names(&["card0", "renderD129", "renderD128"]);
+
+        assert_eq!(
+            names(&[
+                "nvidiactl",
+                "nvidia-uvm",
+                "nvidia0",
+                "nvidia1",
+                "dri/renderD128",
+                "dri/renderD129"
+            ]) as Vec<String>,
+            select_devices(&Gpus::All, &dev, &dri).unwrap()
+        );
+        assert_eq!(
+            names(&["nvidiactl", "nvidia-uvm", "nvidia1", "dri/renderD129"]) as Vec<String>,
+            select_devices(&"1".parse().unwrap(), &dev, &dri).unwrap()
+        );
+        assert!(select_devices(&"2".parse().unwrap(), &dev, &dri).is_err());
+        assert!(select_devices(&Gpus::All, &names(&["null"]), &[]).is_err());
+        assert!("0,x".parse::<Gpus>().is_err());
+    }
+}
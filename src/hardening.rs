@@ -0,0 +1,60 @@
+//! linux.maskedPaths and linux.readonlyPaths, applied once we're in the container's root.
+
+use crate::error::ContainerErr;
+use crate::mount::mount;
+use libc::{MS_BIND, MS_RDONLY, MS_REC, MS_REMOUNT};
+use log::debug;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Hides each path from the container. Files get /dev/null mounted over them,
+/// directories an empty read-only tmpfs. Paths which don't exist are skipped.
+pub fn mask_paths(paths: &[String]) -> Result<(), ContainerErr> {
+    for path in paths {
+        let Some(metadata) = metadata(path)? else {
+            continue;
+        };
+        debug!("masking {}", path);
+        let result = if metadata.is_dir() {
+            mount("tmpfs", path, c"tmpfs", MS_RDONLY, None)
+        } else {
+            mount("/dev/null", path, c"", MS_BIND, None)
+        };
+        result.map_err(|e| ContainerErr::RootFs(format!("failed to mask {}: {:?}", path, e)))?;
+    }
+    Ok(())
+}
+
+/// Makes each path read-only by bind mounting it onto itself and remounting the bind
+/// read-only. Paths which don't exist are skipped.
+pub fn readonly_paths(paths: &[String]) -> Result<(), ContainerErr> {
+    for path in paths {
+        if metadata(path)?.is_none() {
+            continue;
+        }
+        debug!("making {} read-only", path);
+        mount(path, path, c"", MS_BIND | MS_REC, None)
+            .and_then(|_| {
+                mount(
+                    path,
+                    path,
+                    c"",
+                    MS_BIND | MS_REC | MS_REMOUNT | MS_RDONLY,
+                    None,
+                )
+            })
+            .map_err(|e| {
+                ContainerErr::RootFs(format!("failed to make {} read-only: {:?}", path, e))
+            })?;
+    }
+    Ok(())
+}
+
+fn metadata(path: &str) -> Result<Option<fs::Metadata>, ContainerErr> {
+    match fs::metadata(Path::new(path)) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ContainerErr::IO(e)),
+    }
+}
@@ -0,0 +1,200 @@
+//! Health checks, run by the monitor while the container is running.
+//!
+//! The check is a shell command configured with the health-* annotations. Every interval
+//! the monitor runs it inside the container with the runtime's own `exec`, so it gets the
+//! container's namespaces, cgroup, user and confinement like any exec'd process. The
+//! result goes into the state's `health`, and changes of the status are emitted as
+//! `health_status` events.
+
+use crate::ctx::Ctx;
+use crate::error::ContainerErr;
+use crate::events::{self, Event};
+use crate::extensions::Extensions;
+use crate::state::Status;
+use crate::store::StateStore;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_RETRIES: u32 = 3;
+/// How often a running check is polled for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A container's health check, from its annotations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthCheck {
+    cmd: String,
+    interval: Duration,
+    timeout: Duration,
+    /// Consecutive failures before the container is unhealthy.
+    retries: u32,
+}
+
+impl HealthCheck {
+    /// The check configured by the annotations, if there's a command.
+    pub fn from_extensions(ext: &Extensions) -> Option<Self> {
+        Some(Self {
+            cmd: ext.health_cmd.clone()?,
+            interval: ext.health_interval.unwrap_or(DEFAULT_INTERVAL),
+            timeout: ext.health_timeout.unwrap_or(DEFAULT_TIMEOUT),
+            retries: ext.health_retries.unwrap_or(DEFAULT_RETRIES),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// No check has passed yet, and there haven't been enough failures to give up.
+    #[default]
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+/// The container's health, in its state.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+    pub status: HealthStatus,
+    /// Checks failed in a row.
+    pub failing_streak: u32,
+}
+
+impl Health {
+    /// Records the result of a check, returns whether the status changed.
+    fn record(&mut self, passed: bool, retries: u32) -> bool {
+        let before = self.status;
+        if passed {
+            self.failing_streak = 0;
+            self.status = HealthStatus::Healthy;
+        } else {
+            self.failing_streak += 1;
+            if self.failing_streak >= retries {
+                self.status = HealthStatus::Unhealthy;
+            }
+        }
+        self.status != before
+    }
+}
+
+/// Runs the check every interval until `stop` is dropped or sent to, the monitor does
+/// that when the init process exits. Checks only run while the container is running.
+pub fn run(ctx: Ctx, container_id: String, check: HealthCheck, stop: Receiver<()>) {
+    while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(check.interval) {
+        let running = ctx
+            .store()
+            .load(&container_id)
+            .is_ok_and(|state| *state.status() == Status::Running);
+        if !running {
+            continue;
+        }
+        let passed = match probe(&container_id, &check) {
+            Ok(passed) => passed,
+            Err(e) => {
+                warn!("health check of {} failed to run: {:?}", container_id, e);
+                false
+            }
+        };
+        debug!("health check of {} passed: {}", container_id, passed);
+        if let Err(e) = record(&ctx, &container_id, passed, check.retries) {
+            warn!("failed to record health of {}: {:?}", container_id, e);
+        }
+    }
+}
+
+/// Runs the check once through `exec`, whether it exited 0 within the timeout.
+fn probe(container_id: &str, check: &HealthCheck) -> Result<bool, ContainerErr> {
+    let mut child = Command::new("/proc/self/exe")
+        .args(["exec", container_id, "/bin/sh", "-c", &check.cmd])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(ContainerErr::IO)?;
+    let deadline = Instant::now() + check.timeout;
+    loop {
+        if let Some(status) = child.try_wait().map_err(ContainerErr::IO)? {
+            return Ok(status.success());
+        }
+        if Instant::now() >= deadline {
+            // The exec'd process is left to the container, exec only forwards its exit.
+            debug!("health check of {} timed out", container_id);
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(false);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Updates the health in the container's state, emitting an event if its status
+/// changed. Holds the container's lock so it doesn't race other state updates.
+fn record(ctx: &Ctx, container_id: &str, passed: bool, retries: u32) -> Result<(), ContainerErr> {
+    let store = ctx.store();
+    let _lock = store.lock(container_id)?;
+    let mut state = store.load(container_id)?;
+    // It may have exited while the check ran.
+    if *state.status() != Status::Running {
+        return Ok(());
+    }
+    let mut health = state.health().cloned().unwrap_or_default();
+    let changed = health.record(passed, retries);
+    let status = health.status;
+    state.set_health(health);
+    store.save(&state)?;
+
+    if changed {
+        info!("container {} is {:?}", container_id, status);
+        let event = Event::new("health_status", container_id, json!({ "status": status }));
+        events::emit(&ctx.container_dirs(container_id), &event)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut health = Health::default();
+        assert!(!health.record(false, 2));
+        assert_eq!(HealthStatus::Starting, health.status);
+        assert!(health.record(false, 2));
+        assert_eq!(HealthStatus::Unhealthy, health.status);
+        assert_eq!(2, health.failing_streak);
+
+        assert!(health.record(true, 2));
+        assert_eq!(HealthStatus::Healthy, health.status);
+        assert_eq!(0, health.failing_streak);
+        // A single failure doesn't make a healthy container unhealthy.
+        assert!(!health.record(false, 2));
+        assert_eq!(HealthStatus::Healthy, health.status);
+    }
+
+    #[test]
+    fn test_from_extensions() {
+        assert_eq!(None, HealthCheck::from_extensions(&Extensions::default()));
+        let ext = Extensions {
+            health_cmd: Some(String::from("curl -f localhost")),
+            health_interval: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(HealthCheck {
+                cmd: String::from("curl -f localhost"),
+                interval: Duration::from_secs(5),
+                timeout: DEFAULT_TIMEOUT,
+                retries: DEFAULT_RETRIES,
+            }),
+            HealthCheck::from_extensions(&ext)
+        );
+    }
+}
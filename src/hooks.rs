@@ -0,0 +1,265 @@
+//! POSIX platform hooks
+//! https://github.com/opencontainers/runtime-spec/blob/main/config.md#posix-platform-hooks
+
+use crate::config::{Config, Hook};
+use crate::error::ContainerErr;
+use crate::state::State;
+use crate::syscalls;
+use log::{debug, info, warn};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running hook is polled for completion when it has a timeout.
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long a hook's output is waited for once it exited.
+const HOOK_OUTPUT_GRACE: Duration = Duration::from_millis(100);
+/// How much of a failed hook's stderr goes into the error.
+const HOOK_OUTPUT_KEPT: usize = 4096;
+
+/// Lifecycle phases at which hooks are run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookPhase {
+    /// Deprecated in favour of createRuntime, runs at the same point in the runtime namespace.
+    Prestart,
+    /// After the container's mounts are set up, in the runtime namespace.
+    CreateRuntime,
+    /// After createRuntime, in the container namespace before pivot_root.
+    CreateContainer,
+    /// On start, in the container namespace before the user process is executed.
+    StartContainer,
+    /// After the user process is started, in the runtime namespace.
+    Poststart,
+    /// After the container is deleted, in the runtime namespace.
+    Poststop,
+}
+
+impl HookPhase {
+    /// Per the spec, failures in poststart and poststop hooks are only logged.
+    fn fatal(&self) -> bool {
+        !matches!(self, HookPhase::Poststart | HookPhase::Poststop)
+    }
+}
+
+/// Runs the hooks configured for `phase` in order, passing each the container state on stdin.
+pub fn run_hooks(config: &Config, phase: HookPhase, state: &State) -> Result<(), ContainerErr> {
+    let Some(hooks) = config.hooks() else {
+        return Ok(());
+    };
+
+    let phase_hooks = match phase {
+        HookPhase::Prestart => &hooks.prestart,
+        HookPhase::CreateRuntime => &hooks.create_runtime,
+        HookPhase::CreateContainer => &hooks.create_container,
+        HookPhase::StartContainer => &hooks.start_container,
+        HookPhase::Poststart => &hooks.poststart,
+        HookPhase::Poststop => &hooks.poststop,
+    };
+
+    let raw_state = serde_json::to_string(state).map_err(|e| ContainerErr::State(e.to_string()))?;
+    for hook in phase_hooks.iter().flatten() {
+        debug!("running {:?} hook: {}", phase, hook.path);
+        if let Err(e) = run_hook(hook, phase, &raw_state) {
+            if phase.fatal() {
+                return Err(e);
+            }
+            warn!("{:?} hook {} failed: {:?}", phase, hook.path, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_hook(hook: &Hook, phase: HookPhase, raw_state: &str) -> Result<(), ContainerErr> {
+    let mut cmd = Command::new(&hook.path);
+    // args holds argv including argv[0], same semantics as execv.
+    if let Some(args) = &hook.args {
+        if let Some((arg0, rest)) = args.split_first() {
+            cmd.arg0(arg0).args(rest);
+        }
+    }
+    cmd.env_clear();
+    for var in hook.env.iter().flatten() {
+        if let Some((key, value)) = var.split_once('=') {
+            cmd.env(key, value);
+        }
+    }
+    // Its own process group, so whatever it started goes with it when it times out.
+    if hook.timeout.is_some() {
+        cmd.process_group(0);
+    }
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| ContainerErr::Hook(format!("failed to run {}: {}", hook.path, e)))?;
+    let label = format!("{:?} hook {}", phase, hook.path);
+    let stdout = child
+        .stdout
+        .take()
+        .map(|out| log_output(out, &label, false));
+    let stderr = child.stderr.take().map(|err| log_output(err, &label, true));
+    if let Some(mut stdin) = child.stdin.take() {
+        // The hook may not read its stdin, so ignore broken pipes.
+        let _ = stdin.write_all(raw_state.as_bytes());
+    }
+
+    let status = match hook.timeout {
+        Some(timeout) => {
+            let deadline = Instant::now() + Duration::from_secs(timeout as u64);
+            loop {
+                if let Some(status) = child.try_wait().map_err(ContainerErr::IO)? {
+                    break status;
+                }
+                if Instant::now() >= deadline {
+                    let _ = syscalls::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                    let _ = child.wait();
+                    return Err(ContainerErr::Hook(format!(
+                        "{} timed out after {}s",
+                        hook.path, timeout
+                    )));
+                }
+                thread::sleep(HOOK_POLL_INTERVAL);
+            }
+        }
+        None => child.wait().map_err(ContainerErr::IO)?,
+    };
+
+    // Processes the hook left behind may keep the pipes open, their output isn't waited
+    // for beyond a grace period.
+    let collect = |output: Option<Receiver<String>>| {
+        output
+            .and_then(|output| output.recv_timeout(HOOK_OUTPUT_GRACE).ok())
+            .unwrap_or_default()
+    };
+    collect(stdout);
+    let stderr = collect(stderr);
+    if !status.success() {
+        let stderr = stderr.trim_end();
+        return Err(ContainerErr::Hook(if stderr.is_empty() {
+            format!("{} exited with {}", hook.path, status)
+        } else {
+            format!("{} exited with {}: {}", hook.path, status, stderr)
+        }));
+    }
+    Ok(())
+}
+
+/// Logs a hook's output line by line as it comes in. The receiver gets the last of it,
+/// up to `HOOK_OUTPUT_KEPT` bytes, once the pipe is closed.
+fn log_output<R: Read + Send + 'static>(pipe: R, label: &str, stderr: bool) -> Receiver<String> {
+    let label = label.to_string();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut kept = String::new();
+        for line in BufReader::new(pipe).split(b'\n') {
+            let Ok(line) = line else {
+                break;
+            };
+            let line = String::from_utf8_lossy(&line);
+            if stderr {
+                warn!("{}: {}", label, line);
+            } else {
+                info!("{}: {}", label, line);
+            }
+            kept.push_str(&line);
+            kept.push('\n');
+            if kept.len() > HOOK_OUTPUT_KEPT {
+                let mut cut = kept.len() - HOOK_OUTPUT_KEPT;
+                while !kept.is_char_boundary(cut) {
+                    cut += 1;
+                }
+                kept.drain(..cut);
+            }
+        }
+        let _ = tx.send(kept);
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(path: &str, args: &[&str], timeout: Option<usize>) -> Hook {
+        serde_json::from_value(serde_json::json!({
+            "path": path,
+            "args": args,
+            "env": ["KEY=a=b"],
+            "timeout": timeout,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_run_hook() {
+        // The state is passed on stdin and env values may contain '='.
+        let h = hook(
+            "/bin/sh",
+            &["sh", "-c", "grep -q foobar && [ \"$KEY\" = a=b ]"],
+            None,
+        );
+        assert!(run_hook(&h, HookPhase::Prestart, "{\"id\":\"foobar\"}").is_ok());
+        assert!(run_hook(&h, HookPhase::Prestart, "{\"id\":\"other\"}").is_err());
+    }
+
+    #[test]
+    fn test_run_hook_timeout() {
+        let h = hook("/bin/sh", &["sh", "-c", "sleep 5"], Some(0));
+        let start = Instant::now();
+        assert!(matches!(
+            run_hook(&h, HookPhase::Prestart, "{}"),
+            Err(ContainerErr::Hook(_))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_run_hook_timeout_kills_group() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let pid_file = format!("/tmp/hook_group_{}", time);
+        // The sleep in the background would outlive the hook if only the hook was killed.
+        let script = format!("sleep 30 & echo $! > {}; wait", pid_file);
+        let h = hook("/bin/sh", &["sh", "-c", &script], Some(1));
+        assert!(run_hook(&h, HookPhase::CreateRuntime, "{}").is_err());
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        std::fs::remove_file(&pid_file).unwrap();
+        let stat = format!("/proc/{}/stat", pid.trim());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        // Gone, or a zombie waiting to be reaped.
+        while std::fs::read_to_string(&stat).is_ok_and(|stat| !stat.contains(") Z ")) {
+            assert!(
+                Instant::now() < deadline,
+                "background process still running"
+            );
+            thread::sleep(HOOK_POLL_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn test_run_hook_output() {
+        let h = hook(
+            "/bin/sh",
+            &["sh", "-c", "echo progress; echo no space left >&2; exit 3"],
+            None,
+        );
+        match run_hook(&h, HookPhase::Poststop, "{}") {
+            Err(ContainerErr::Hook(msg)) => {
+                assert!(msg.ends_with(": no space left"), "{}", msg);
+                assert!(!msg.contains("progress"), "{}", msg);
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
@@ -4,80 +4,181 @@ use crate::config::Namespace;
 use crate::container::Container;
 use crate::ctx::Ctx;
 use crate::error::ContainerErr;
-use crate::ioprio::set_iopriority;
+use crate::etc_files;
+use crate::extensions::Extensions;
+use crate::fds::close_inherited;
+use crate::hardening::{mask_paths, readonly_paths};
+use crate::hooks::{run_hooks, HookPhase};
+use crate::monitor::ContainerStdio;
 use crate::mount::setup_mounts;
 use crate::namespaces::join_namspaces;
-use crate::process::{clear_env, populate_env};
-use crate::rlimit::set_rlimits;
-use crate::rootfs::setup_rootfs;
-use libc::{__errno_location, c_int, c_void, write, EINTR};
+use crate::process::{apply_process_spec, build_args, build_env, find_executable};
+use crate::rootfs::{pivot_root, setup_rootfs};
+use crate::sched::create_core_cookie;
+use crate::start_signal::StartListener;
+use crate::state::{Pid, Status};
+use crate::sync::{read_sync, write_sync, SyncMsg};
+use crate::syscalls::execve;
+use crate::trace;
+use crate::users::resolve_user;
+use libc::c_int;
 use log::debug;
-use std::fs::OpenOptions;
+use std::fs::File;
 use std::path::{Path, PathBuf};
+use tracing::debug_span;
 
 /// Init arguments
 pub struct InitArgs {
     pub bundle_path: PathBuf,
-    pub fifo_path: PathBuf,
+    pub start_listener: StartListener,
     pub rdy_pipe_write_fd: c_int,
+    pub hooks_pipe_read_fd: c_int,
+    pub stdio: ContainerStdio,
     pub container: Container,
     pub ctx: Ctx,
     pub join_ns: Vec<Namespace>,
+    pub preserve_fds: u32,
+    /// The monitor's log, see `trace::release_output`. Opened by create, the state dir is
+    /// out of reach after pivot_root.
+    pub trace_log: Option<File>,
 }
 
 /// First thing that runs in a new container process.
+///
+/// The ordering follows the OCI lifecycle: mounts are set up, the runtime runs the
+/// prestart and createRuntime hooks, we run the createContainer hooks and notify the
+/// runtime we're ready. Once started we run the startContainer hooks and exec.
 pub fn init(mut args: InitArgs) -> Result<(), ContainerErr> {
-    let pid = std::process::id();
-    args.container.state_mut().set_pid(pid);
-
-    join_namspaces(&args.join_ns)?;
-
-    clear_env();
-    populate_env(args.container.config());
-
-    set_rlimits(args.container.config())?;
-
-    set_iopriority(args.container.config())?;
-
-    setup_rootfs(args.container.config(), args.bundle_path)?;
-
-    setup_mounts(args.container.config())?;
-
-    // Write exit code to pipe for parent process
-    notify_container_ready(args.rdy_pipe_write_fd);
-
-    // Wait for FIFO to be opened. Then we can exec, at this moment we don't care what's
-    // sent. Opening the fifo is the signal.
-    wait_for_exec(&args.fifo_path);
+    if let Err(e) = prepare(&mut args) {
+        // Let create know instead of leaving it waiting for us.
+        let _ = write_sync(args.rdy_pipe_write_fd, SyncMsg::Error);
+        return Err(e);
+    }
 
-    exec(args.container)?;
+    // create returns once we're ready, its caller's stderr is no place for our spans.
+    if let Some(log) = args.trace_log.take() {
+        trace::release_output(&log);
+    }
+    // Write ready status to pipe for parent process
+    write_sync(args.rdy_pipe_write_fd, SyncMsg::Ready)?;
+
+    // Wait for the start signal. Then we can exec.
+    args.start_listener.wait()?;
+
+    args.container.update_status(Status::Created);
+    let span = debug_span!(
+        "start-container-hooks",
+        container_id = args.container.state().id()
+    )
+    .entered();
+    run_hooks(
+        args.container.config(),
+        HookPhase::StartContainer,
+        args.container.state(),
+    )?;
+    drop(span);
+
+    exec(args.container, args.preserve_fds)?;
 
     debug!("container successfully created");
 
     Ok(())
 }
 
-fn notify_container_ready(fd: c_int) {
-    let ret: c_int = 0;
-    if fd > 0 {
-        unsafe {
-            debug!("writing to ready pipe");
-
-            while write(fd, &raw const ret as *const c_void, size_of_val(&ret)) == -1
-                && *__errno_location() == EINTR
-            {
-                debug!("retrying rdy notif");
-            }
+/// Everything up to the point where the container is ready to be started.
+fn prepare(args: &mut InitArgs) -> Result<(), ContainerErr> {
+    let pid = host_pid();
+    args.container.state_mut().set_pid(pid);
+
+    // From here on our output, and the container's, goes to the monitor.
+    args.stdio.install()?;
+
+    join_namspaces(&args.join_ns)?;
+
+    let id = args.container.state().id().to_string();
+    let span = debug_span!("mounts", container_id = id).entered();
+    let dirs = args.ctx.container_dirs(&id);
+    let rootfs = setup_rootfs(
+        args.container.config(),
+        &args.bundle_path,
+        args.container.state().loop_device(),
+        &dirs,
+    )?;
+
+    setup_mounts(args.container.config(), &rootfs)?;
+    etc_files::mount_files(args.container.config(), &dirs, &rootfs)?;
+    drop(span);
+
+    // The runtime runs the prestart and createRuntime hooks in its own namespaces, wait
+    // for it to finish before continuing.
+    write_sync(args.rdy_pipe_write_fd, SyncMsg::CreateRuntimeHooks)?;
+    match read_sync(args.hooks_pipe_read_fd)? {
+        SyncMsg::HooksDone => {}
+        msg => {
+            return Err(ContainerErr::Pipe(format!(
+                "unexpected sync message: {:?}",
+                msg
+            )))
         }
     }
+
+    let span = debug_span!("create-container-hooks", container_id = id).entered();
+    run_hooks(
+        args.container.config(),
+        HookPhase::CreateContainer,
+        args.container.state(),
+    )?;
+    drop(span);
+
+    let span = debug_span!("pivot", container_id = id).entered();
+    pivot_root(&rootfs)?;
+    drop(span);
+
+    let config = args.container.config();
+    readonly_paths(config.readonly_paths())?;
+    mask_paths(config.masked_paths())
 }
 
-/// Won't return on success.
-fn exec(_container: Container) -> Result<(), ContainerErr> {
-    Ok(())
+/// Our pid as seen from the runtime's pid namespace. /proc is still the runtime's procfs
+/// at this point, so /proc/self resolves to the host pid even in a new pid namespace.
+fn host_pid() -> Pid {
+    std::fs::read_link("/proc/self")
+        .ok()
+        .and_then(|p| p.to_str()?.parse().ok())
+        .unwrap_or_else(std::process::id)
 }
 
-fn wait_for_exec<P: AsRef<Path>>(fifo: P) {
-    debug!("opening fifo");
-    let _ = OpenOptions::new().read(true).open(fifo).unwrap();
+/// Replaces the init process with the container's entrypoint. Won't return on success.
+fn exec(container: Container, preserve_fds: u32) -> Result<(), ContainerErr> {
+    let span = debug_span!("exec", container_id = container.state().id()).entered();
+    let Some(mut process) = container.config().process().cloned() else {
+        return Err(ContainerErr::Entrypoint(String::from(
+            "config has no process section",
+        )));
+    };
+    // The rootfs is our root now, so names are those of the container's /etc/passwd.
+    let extensions = Extensions::parse(container.config())?;
+    if let Some(user) = extensions.user {
+        resolve_user(Path::new("/"), &user)?.apply(&mut process.user);
+    }
+    if extensions.sched_core {
+        create_core_cookie()?;
+    }
+    let process = &process;
+    let argv = build_args(process)?;
+    let envp = build_env(process)?;
+
+    std::env::set_current_dir(&process.cwd).map_err(ContainerErr::IO)?;
+    // The rootfs is our root now, resolve the entrypoint the same way create checked it.
+    let path = find_executable("/", process)?;
+
+    // Before the seccomp filter, which may well not allow close_range.
+    close_inherited(preserve_fds)?;
+    apply_process_spec(process, container.config().seccomp())?;
+
+    debug!("exec {:?}", path);
+    drop(span);
+    log::logger().flush();
+    let err = execve(&path, &argv, &envp);
+    Err(ContainerErr::Entrypoint(err.to_string()))
 }
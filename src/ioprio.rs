@@ -1,22 +1,33 @@
-use crate::{config::Config, error::ContainerErr};
-use libc::{c_int, syscall, SYS_ioprio_set, __errno_location};
+use crate::{config::Process, error::ContainerErr, syscalls::ioprio_set};
+use libc::c_int;
 use log::debug;
 
 /// syscall ioprio_set
-pub fn set_iopriority(config: &Config) -> Result<(), ContainerErr> {
+pub fn set_iopriority(process: &Process) -> Result<(), ContainerErr> {
     // linux header enum, so libc doesn't have this
     // https://github.com/torvalds/linux/blob/059dd502b263d8a4e2a84809cf1068d6a3905e6f/include/uapi/linux/ioprio.h#L53
     const IOPRIO_WHO_PROCESS: c_int = 1;
-    if let Some(prio) = &config.process().io_priority {
+    // The class goes in the bits above the priority, IOPRIO_PRIO_VALUE.
+    const IOPRIO_CLASS_SHIFT: c_int = 13;
+    if let Some(prio) = &process.io_priority {
         debug!("{:?}", prio);
-        let err = unsafe { syscall(SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio.priority) };
-        if err == -1 {
-            let errno = unsafe { *__errno_location() };
-            return Err(ContainerErr::IoPriority(format!(
-                "syscall: ioprio_set failed errno: {}",
-                errno
-            )));
-        }
+        let class = match prio.class.as_str() {
+            "IOPRIO_CLASS_RT" => 1,
+            "IOPRIO_CLASS_BE" => 2,
+            "IOPRIO_CLASS_IDLE" => 3,
+            class => {
+                return Err(ContainerErr::IoPriority(format!(
+                    "unknown class: {}",
+                    class
+                )))
+            }
+        };
+        ioprio_set(
+            IOPRIO_WHO_PROCESS,
+            0,
+            class << IOPRIO_CLASS_SHIFT | prio.priority,
+        )
+        .map_err(|e| ContainerErr::IoPriority(e.to_string()))?;
     }
 
     Ok(())
@@ -0,0 +1,151 @@
+//! Landlock filesystem sandbox of the container process, from process.landlock.
+//!
+//! The ruleset denies the access rights it handles everywhere but beneath the paths of its
+//! rules, which allow some of them back. Unless the config disables best effort, access
+//! rights newer than the kernel's Landlock ABI are left out, and the ruleset isn't
+//! enforced at all on kernels without Landlock.
+
+use crate::config::LinuxLandlock;
+use crate::error::ContainerErr;
+use crate::syscalls;
+use libc::c_int;
+use log::debug;
+use std::fs::OpenOptions;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+
+/// Filesystem access rights from linux/landlock.h, by their names in the config, with the
+/// ABI version which introduced them.
+const ACCESS_FS: [(&str, u64, c_int); 16] = [
+    ("execute", 1 << 0, 1),
+    ("write_file", 1 << 1, 1),
+    ("read_file", 1 << 2, 1),
+    ("read_dir", 1 << 3, 1),
+    ("remove_dir", 1 << 4, 1),
+    ("remove_file", 1 << 5, 1),
+    ("make_char", 1 << 6, 1),
+    ("make_dir", 1 << 7, 1),
+    ("make_reg", 1 << 8, 1),
+    ("make_sock", 1 << 9, 1),
+    ("make_fifo", 1 << 10, 1),
+    ("make_block", 1 << 11, 1),
+    ("make_sym", 1 << 12, 1),
+    ("refer", 1 << 13, 2),
+    ("truncate", 1 << 14, 3),
+    ("ioctl_dev", 1 << 15, 5),
+];
+
+/// Enforces the ruleset on the calling process, which its children inherit.
+pub fn apply_landlock(landlock: &LinuxLandlock) -> Result<(), ContainerErr> {
+    let handled = access_mask(&landlock.ruleset.handled_access_fs)?;
+    let mut rules = Vec::new();
+    for rule in landlock.rules.iter().flat_map(|rules| &rules.path_beneath) {
+        let allowed = access_mask(&rule.allowed_access)?;
+        if allowed & !handled != 0 {
+            return Err(ContainerErr::Landlock(format!(
+                "rule for {:?} allows access the ruleset doesn't handle",
+                rule.paths
+            )));
+        }
+        rules.push((allowed, &rule.paths));
+    }
+
+    let supported = match syscalls::landlock_abi_version() {
+        Ok(version) => supported_access(version),
+        Err(e) if !landlock.disable_best_effort => {
+            debug!("landlock isn't available, not enforcing the ruleset: {}", e);
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(ContainerErr::Landlock(format!(
+                "landlock isn't available: {}",
+                e
+            )))
+        }
+    };
+    if landlock.disable_best_effort && handled & !supported != 0 {
+        return Err(ContainerErr::Landlock(format!(
+            "kernel lacks access rights {}",
+            access_names(handled & !supported).join(", ")
+        )));
+    }
+    let handled = handled & supported;
+    if handled == 0 {
+        debug!("none of the ruleset's access rights are supported, not enforcing it");
+        return Ok(());
+    }
+
+    let ruleset = syscalls::landlock_create_ruleset(handled)
+        .map_err(|e| ContainerErr::Landlock(e.to_string()))?;
+    for (allowed, paths) in rules {
+        let allowed = allowed & handled;
+        if allowed == 0 {
+            continue;
+        }
+        for path in paths {
+            let f = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(path)
+                .map_err(|e| ContainerErr::Landlock(format!("{}: {}", path, e)))?;
+            syscalls::landlock_add_path_rule(ruleset.as_raw_fd(), allowed, f.as_raw_fd())
+                .map_err(|e| ContainerErr::Landlock(format!("{}: {}", path, e)))?;
+        }
+    }
+    debug!("enforcing landlock ruleset handling {:#x}", handled);
+    syscalls::landlock_restrict_self(ruleset.as_raw_fd())
+        .map_err(|e| ContainerErr::Landlock(e.to_string()))
+}
+
+fn access_mask(names: &[String]) -> Result<u64, ContainerErr> {
+    names.iter().try_fold(0, |mask, name| {
+        ACCESS_FS
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, access, _)| mask | access)
+            .ok_or_else(|| ContainerErr::Landlock(format!("unknown access right: {}", name)))
+    })
+}
+
+/// The access rights of ABI `version` and those before it.
+fn supported_access(version: c_int) -> u64 {
+    ACCESS_FS
+        .iter()
+        .filter(|(_, _, abi)| *abi <= version)
+        .fold(0, |mask, (_, access, _)| mask | access)
+}
+
+fn access_names(mask: u64) -> Vec<&'static str> {
+    ACCESS_FS
+        .iter()
+        .filter(|(_, access, _)| mask & access != 0)
+        .map(|(name, _, _)| *name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_mask() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            0b101,
+            access_mask(&names(&["execute", "read_file"])).unwrap()
+        );
+        assert!(access_mask(&names(&["execute", "fly"])).is_err());
+        assert_eq!(0x1fff, supported_access(1));
+        assert_eq!(0x7fff, supported_access(4));
+        assert_eq!(0xffff, supported_access(5));
+        assert_eq!(
+            vec!["truncate", "ioctl_dev"],
+            access_names(0xffff & !supported_access(2))
+        );
+    }
+}
@@ -1,16 +1,54 @@
 #![feature(anonymous_pipe)]
 
+mod apparmor;
+mod attach;
+#[cfg(feature = "capi")]
+pub mod capi;
 mod cgroup;
 pub mod cmd;
 mod config;
 mod container;
+mod criu;
 mod ctx;
+mod dirs;
 pub mod error;
+mod etc_files;
+mod events;
+mod extensions;
+mod fds;
+mod features;
+mod gpu;
+mod hardening;
+mod health;
+mod hooks;
 mod init;
 mod ioprio;
+mod journal;
+mod landlock;
+mod libc_compat;
+mod lock;
+mod loopdev;
+mod monitor;
 mod mount;
 mod namespaces;
+mod oom;
+mod pod;
+mod portforward;
+mod privileges;
 mod process;
+mod procfs;
+mod restart;
 mod rlimit;
 mod rootfs;
+mod sched;
+mod seccomp;
+mod signal;
+mod start_signal;
 mod state;
+pub mod store;
+mod sync;
+mod syscalls;
+mod trace;
+mod tty;
+mod users;
+mod watch;
@@ -0,0 +1,31 @@
+//! Differences between the libc targets we build for. Everything else goes through the
+//! libc crate directly, only types and functions which differ between glibc, musl and
+//! the supported architectures (x86_64, aarch64) belong here.
+
+use libc::{c_long, statfs};
+
+/// Resource argument of getrlimit/setrlimit. glibc declares it as an enum, musl as an int.
+#[cfg(target_env = "gnu")]
+pub type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(not(target_env = "gnu"))]
+pub type RlimitResource = libc::c_int;
+
+/// Request argument of ioctl. glibc declares it as an unsigned long, musl as an int.
+#[cfg(target_env = "gnu")]
+pub type IoctlRequest = libc::c_ulong;
+#[cfg(not(target_env = "gnu"))]
+pub type IoctlRequest = libc::c_int;
+
+/// Filesystem type magic from statfs. f_type is signed on glibc and unsigned on musl,
+/// the magic numbers in libc are signed.
+#[allow(clippy::unnecessary_cast)]
+pub fn fs_magic(st: &statfs) -> c_long {
+    st.f_type as c_long
+}
+
+/// AUDIT_ARCH_* from linux/audit.h for the architecture we're built for, as seen by
+/// seccomp filters. libc doesn't have these.
+#[cfg(target_arch = "x86_64")]
+pub const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+pub const AUDIT_ARCH: u32 = 0xc000_00b7;
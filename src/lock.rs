@@ -0,0 +1,158 @@
+//! Per-container locks, so concurrent operations on the same container id serialize.
+//!
+//! Every container id has a lock file in the state root's locks dir, held with flock. It
+//! lives outside the container's state dir so the lock can be held while the state dir is
+//! created and removed. delete removes the lock file while holding the lock, so after
+//! acquiring a lock we check it's still the file at the path and start over otherwise.
+
+use crate::ctx::Ctx;
+use crate::error::ContainerErr;
+use crate::syscalls;
+use libc::{c_int, EWOULDBLOCK, LOCK_EX, LOCK_NB};
+use std::fs::{self, File, OpenOptions};
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Exclusive lock on a container id, released when dropped.
+pub struct ContainerLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl ContainerLock {
+    /// Blocks until no other operation holds the lock for `container_id`.
+    pub fn acquire(ctx: &Ctx, container_id: &str) -> Result<Self, ContainerErr> {
+        let dir = ctx.locks_dir();
+        fs::create_dir_all(&dir).map_err(ContainerErr::IO)?;
+        Self::acquire_path(dir.join(container_id))
+    }
+
+    /// Like `acquire`, but None rather than waiting if another operation holds the lock.
+    pub fn try_acquire(ctx: &Ctx, container_id: &str) -> Result<Option<Self>, ContainerErr> {
+        let dir = ctx.locks_dir();
+        fs::create_dir_all(&dir).map_err(ContainerErr::IO)?;
+        Self::lock_path(dir.join(container_id), LOCK_EX | LOCK_NB)
+    }
+
+    fn acquire_path(path: PathBuf) -> Result<Self, ContainerErr> {
+        // Without LOCK_NB flock waits until it has the lock.
+        Self::lock_path(path, LOCK_EX).map(|lock| lock.expect("blocking flock"))
+    }
+
+    /// Locks the file at `path` with the flock `operation`, None if it's LOCK_NB and the
+    /// lock is held elsewhere.
+    fn lock_path(path: PathBuf, operation: c_int) -> Result<Option<Self>, ContainerErr> {
+        loop {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .map_err(ContainerErr::IO)?;
+            match syscalls::flock(file.as_raw_fd(), operation) {
+                Ok(()) => {}
+                Err(e) if syscalls::errno(&e) == Some(EWOULDBLOCK) => return Ok(None),
+                Err(e) => return Err(ContainerErr::IO(e)),
+            }
+            if is_same_file(&file, &path)? {
+                return Ok(Some(Self { file, path }));
+            }
+        }
+    }
+
+    /// Removes the lock file, once the container is deleted. The lock is still held until
+    /// this is dropped.
+    pub fn remove(&self) -> Result<(), ContainerErr> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(ContainerErr::IO(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Lets go of a forked child's copy of the lock. flock locks belong to the open file,
+    /// which the child shares, so the lock would otherwise stay held for as long as the
+    /// child lives. Pointing the fd at /dev/null keeps it valid for when this is dropped.
+    pub fn release_inherited(&self) -> Result<(), ContainerErr> {
+        let null = File::open("/dev/null").map_err(ContainerErr::IO)?;
+        syscalls::dup2(null.as_raw_fd(), self.file.as_raw_fd()).map_err(ContainerErr::IO)
+    }
+}
+
+/// Whether `file` is still the file at `path`.
+fn is_same_file(file: &File, path: &Path) -> Result<bool, ContainerErr> {
+    let locked = file.metadata().map_err(ContainerErr::IO)?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(current.dev() == locked.dev() && current.ino() == locked.ino()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(ContainerErr::IO(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_parallel_create_delete() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = PathBuf::from(format!("/tmp/container_lock_{}", time));
+        fs::create_dir(&dir).unwrap();
+        let dir = Arc::new(dir);
+
+        // Each thread alternately creates and deletes the same container. Under the lock
+        // the state is never created twice or deleted while missing, even though delete
+        // removes the lock file.
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let dir = Arc::clone(&dir);
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        let lock = ContainerLock::acquire_path(dir.join("lock")).unwrap();
+                        let state = dir.join("state.json");
+                        if fs::metadata(&state).is_ok() {
+                            fs::remove_file(&state).unwrap();
+                            lock.remove().unwrap();
+                        } else {
+                            OpenOptions::new()
+                                .write(true)
+                                .create_new(true)
+                                .open(&state)
+                                .unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        fs::remove_dir_all(dir.as_path()).unwrap();
+    }
+
+    #[test]
+    fn test_try_acquire() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let mut ctx = Ctx::default();
+        ctx.state_dir = PathBuf::from(format!("/tmp/container_try_lock_{}", time));
+
+        let lock = ContainerLock::try_acquire(&ctx, "foo").unwrap().unwrap();
+        assert!(ContainerLock::try_acquire(&ctx, "foo").unwrap().is_none());
+        assert!(ContainerLock::try_acquire(&ctx, "bar").unwrap().is_some());
+        drop(lock);
+        assert!(ContainerLock::try_acquire(&ctx, "foo").unwrap().is_some());
+
+        fs::remove_dir_all(&ctx.state_dir).unwrap();
+    }
+}
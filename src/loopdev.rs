@@ -0,0 +1,69 @@
+//! Loop devices, for rootfs images.
+
+use crate::error::ContainerErr;
+use crate::libc_compat::IoctlRequest;
+use crate::syscalls::ioctl;
+use libc::c_ulong;
+use log::debug;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const LOOP_CONTROL: &str = "/dev/loop-control";
+
+// linux/loop.h, libc doesn't have these.
+const LOOP_SET_FD: IoctlRequest = 0x4c00;
+const LOOP_CLR_FD: IoctlRequest = 0x4c01;
+const LOOP_CTL_GET_FREE: IoctlRequest = 0x4c82;
+
+/// How often to look for another free device when the one we found was taken before we
+/// could bind it.
+const ATTACH_ATTEMPTS: usize = 10;
+
+/// Binds `image` to a free loop device and returns the device's path. The image is
+/// opened read-only, which makes the loop device read-only too.
+pub fn attach(image: &Path) -> Result<PathBuf, ContainerErr> {
+    let file = File::open(image).map_err(ContainerErr::IO)?;
+    let control = File::open(LOOP_CONTROL).map_err(ContainerErr::IO)?;
+    for _ in 0..ATTACH_ATTEMPTS {
+        let n = ioctl(control.as_raw_fd(), LOOP_CTL_GET_FREE, 0)
+            .map_err(|e| ContainerErr::RootFs(format!("no free loop device: {}", e)))?;
+        let path = PathBuf::from(format!("/dev/loop{}", n));
+        let device = File::open(&path).map_err(ContainerErr::IO)?;
+        match ioctl(device.as_raw_fd(), LOOP_SET_FD, file.as_raw_fd() as c_ulong) {
+            Ok(_) => {
+                debug!("attached {:?} to {:?}", image, path);
+                return Ok(path);
+            }
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => continue,
+            Err(e) => {
+                return Err(ContainerErr::RootFs(format!(
+                    "failed to attach {:?} to {:?}: {}",
+                    image, path, e
+                )))
+            }
+        }
+    }
+    Err(ContainerErr::RootFs(format!(
+        "failed to find a free loop device for {:?}",
+        image
+    )))
+}
+
+/// Unbinds a loop device. If the image is still mounted the kernel unbinds it once it's
+/// unmounted instead. Devices which are already gone are fine.
+pub fn detach(device: &Path) -> Result<(), ContainerErr> {
+    let file = match File::open(device) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(ContainerErr::IO(e)),
+    };
+    match ioctl(file.as_raw_fd(), LOOP_CLR_FD, 0) {
+        Err(e) if e.raw_os_error() != Some(libc::ENXIO) => Err(ContainerErr::RootFs(format!(
+            "failed to detach {:?}: {}",
+            device, e
+        ))),
+        _ => Ok(()),
+    }
+}
@@ -1,24 +1,95 @@
 mod args;
+mod completion;
 
-use args::Command;
-use container_runtime_lib::cmd::{create, delete, kill, start, state};
+use args::{Command, DeviceAction, PodAction};
+use container_runtime_lib::cmd::{
+    attach, checkpoint, create, delete, device_add, device_remove, events, exec, features, kill,
+    list, pod_create, pod_delete, pod_inspect, prune, resize, restore, run, selftest,
+    set_global_opts, start, state, stop, update, wait,
+};
 use container_runtime_lib::error::ContainerErr;
 use std::env::args;
+use std::process::exit;
 
 fn main() -> Result<(), ContainerErr> {
     pretty_env_logger::init();
-    match args::parse_args(args())? {
+    let (global, command) = args::parse_args(args())?;
+    set_global_opts(global);
+    match command {
         Command::Create {
             container_id,
             bundle_path,
-        } => create(container_id, bundle_path)?,
-        Command::State { container_id } => state(container_id)?,
-        Command::Start { container_id } => start(container_id)?,
+            opts,
+        } => create(container_id, bundle_path, opts)?,
+        Command::Checkpoint { container_id, opts } => checkpoint(container_id, opts)?,
+        Command::State { container_id, opts } => state(container_id, opts)?,
+        Command::List { opts } => list(opts)?,
+        Command::Restore {
+            container_id,
+            bundle_path,
+            opts,
+        } => restore(container_id, bundle_path, opts)?,
+        Command::Start { container_id, opts } => start(container_id, opts)?,
+        Command::Resize {
+            container_id,
+            rows,
+            cols,
+        } => resize(container_id, rows, cols)?,
+        Command::Stop { container_id, opts } => stop(container_id, opts)?,
+        Command::Update { container_id, opts } => update(container_id, opts)?,
         Command::Kill {
             container_id,
             signal,
-        } => kill(container_id, signal)?,
-        Command::Delete { container_id } => delete(container_id)?,
+            opts,
+        } => kill(container_id, signal, opts)?,
+        Command::Delete { container_id, opts } => delete(container_id, opts)?,
+        Command::Device {
+            action,
+            container_id,
+            host_path,
+        } => match action {
+            DeviceAction::Add(opts) => device_add(container_id, host_path, opts)?,
+            DeviceAction::Remove => device_remove(container_id, host_path)?,
+        },
+        Command::Events { container_id, opts } => events(container_id, opts)?,
+        Command::Features => features()?,
+        Command::Completion { shell } => print!("{}", completion::script(shell)),
+        Command::HelpJson => println!("{}", completion::help_json()),
+        Command::Prune { opts } => prune(opts)?,
+        Command::Selftest => selftest()?,
+        Command::Pod { action, name } => match action {
+            PodAction::Create => pod_create(name)?,
+            PodAction::Inspect => pod_inspect(name)?,
+            PodAction::Delete => pod_delete(name)?,
+        },
+        Command::Attach { container_id, opts } => {
+            let code = attach(container_id, opts)?;
+            log::logger().flush();
+            exit(code);
+        }
+        Command::Run {
+            container_id,
+            bundle_path,
+            opts,
+        } => {
+            let code = run(container_id, bundle_path, opts)?;
+            log::logger().flush();
+            exit(code);
+        }
+        Command::Wait { container_id } => {
+            let code = wait(container_id)?;
+            log::logger().flush();
+            exit(code);
+        }
+        Command::Exec {
+            container_id,
+            command,
+            opts,
+        } => {
+            let code = exec(container_id, command, opts)?;
+            log::logger().flush();
+            exit(code);
+        }
     }
     log::logger().flush();
     Ok(())
@@ -0,0 +1,684 @@
+//! Per-container monitor process.
+//!
+//! `create` forks the monitor, which moves into its own session and creates the container
+//! so that the init process is its child. While creating the container the monitor reports
+//! each phase back to `create` over a pipe, one line each: `phase <description>`, then
+//! `ok` or `error <message>`. Once the container is created the monitor lets go of the caller's stdio and stays around until the init
+//! process exits: it copies the container's stdout and stderr to a log in the state dir and
+//! to the clients attached to it, see `attach`,
+//! marks the container stopped, runs the poststop hooks, records the exit status and runs
+//! the optional cleanup command. With a restart policy it then creates and starts the
+//! container again, see `restart`. This gives detached containers lifecycle handling
+//! without a daemon.
+
+use crate::attach::{self, FrameKind};
+use crate::cgroup::state_cgroup_path;
+use crate::ctx::Ctx;
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use crate::extensions::Extensions;
+use crate::health::{self, HealthCheck};
+use crate::hooks::{run_hooks, HookPhase};
+use crate::journal::{Journal, PRIORITY_ERR, PRIORITY_INFO};
+use crate::oom;
+use crate::process::wait_exit_code;
+use crate::restart::{self, Backoff, RestartPolicy};
+use crate::state::{Pid, State, Status};
+use crate::store::StateStore;
+use crate::syscalls;
+use crate::trace;
+use libc::c_int;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::pipe::{PipeReader, PipeWriter};
+use std::process::{exit, Command, Stdio};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The monitor's end of the pipe to `create`. Restarts have no one to report to, their
+/// phases are only logged.
+pub struct Progress(Option<PipeWriter>);
+
+impl Progress {
+    /// Tells `create` which phase of creating the container we're in.
+    pub fn phase(&mut self, phase: &str) {
+        match &mut self.0 {
+            // Nothing useful to do if create went away, the container is created regardless.
+            Some(writer) => {
+                let _ = writeln!(writer, "phase {}", phase);
+            }
+            None => debug!("restart: {}", phase),
+        }
+    }
+
+    fn ok(self) {
+        if let Some(mut writer) = self.0 {
+            let _ = writeln!(writer, "ok");
+        }
+    }
+
+    fn error(self, err: &ContainerErr) {
+        if let Some(mut writer) = self.0 {
+            let _ = writeln!(writer, "error {:?}", err);
+        }
+    }
+}
+
+/// The container's ends of the pipes its stdio is connected to.
+pub struct ContainerStdio {
+    /// Only for interactive containers.
+    stdin: Option<PipeReader>,
+    stdout: PipeWriter,
+    stderr: PipeWriter,
+}
+
+impl ContainerStdio {
+    /// Connects the calling process' stdio to the monitor. Stdin is /dev/null unless the
+    /// container is interactive.
+    pub fn install(&self) -> Result<(), ContainerErr> {
+        match &self.stdin {
+            Some(stdin) => syscalls::dup2(stdin.as_raw_fd(), 0).map_err(ContainerErr::IO)?,
+            None => {
+                let null = File::open("/dev/null").map_err(ContainerErr::IO)?;
+                syscalls::dup2(null.as_raw_fd(), 0).map_err(ContainerErr::IO)?;
+            }
+        }
+        syscalls::dup2(self.stdout.as_raw_fd(), 1).map_err(ContainerErr::IO)?;
+        syscalls::dup2(self.stderr.as_raw_fd(), 2).map_err(ContainerErr::IO)
+    }
+
+    /// The stdout and stderr write ends, for processes which set up the container's stdio
+    /// themselves, like CRIU on restore.
+    pub fn fds(&self) -> [RawFd; 2] {
+        [self.stdout.as_raw_fd(), self.stderr.as_raw_fd()]
+    }
+}
+
+/// How the container's init process exited, written to the state dir by the monitor.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExitStatus {
+    /// 128 + the signal number if the process was killed by a signal.
+    pub exit_code: i32,
+    /// Seconds since the epoch.
+    pub created_at: u64,
+    /// Seconds since the epoch.
+    pub exited_at: u64,
+}
+
+impl ExitStatus {
+    /// Reads the exit status from a container's state dir.
+    pub fn load(dirs: &ContainerDirs) -> Result<Self, ContainerErr> {
+        let f = File::open(dirs.exit_status()).map_err(ContainerErr::IO)?;
+        serde_json::from_reader(f).map_err(|e| ContainerErr::State(e.to_string()))
+    }
+
+    /// Written to a temporary file and renamed, `wait` polls for it and mustn't see it
+    /// half written.
+    fn write(&self, dirs: &ContainerDirs) -> Result<(), ContainerErr> {
+        let raw = serde_json::to_vec(self).map_err(|e| ContainerErr::State(e.to_string()))?;
+        let path = dirs.exit_status();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, raw).map_err(ContainerErr::IO)?;
+        fs::rename(&tmp_path, path).map_err(ContainerErr::IO)
+    }
+}
+
+/// Forks the monitor for `container_id`. The monitor calls `create_container`, which
+/// returns the pid of the init process, and then supervises it. Returns once the
+/// container has been created. If that takes longer than `timeout` the monitor is killed
+/// and `ContainerErr::Timeout` is returned. With a `journal` the container's output goes
+/// there instead of the log in the state dir. `create_container` is called again for
+/// each restart the `restart` policy asks for. An `interactive` container's stdin is
+/// kept open for clients attaching to it.
+pub fn spawn<F>(
+    ctx: &Ctx,
+    container_id: &str,
+    timeout: Option<Duration>,
+    journal: Option<Journal>,
+    restart: RestartPolicy,
+    interactive: bool,
+    mut create_container: F,
+) -> Result<(), ContainerErr>
+where
+    F: FnMut(ContainerStdio, &mut Progress) -> Result<Pid, ContainerErr>,
+{
+    let (report_reader, report_writer) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+    log::logger().flush();
+    // We're single threaded at this point.
+    let pid = unsafe { syscalls::fork() }.map_err(ContainerErr::IO)?;
+    if pid != 0 {
+        debug!("monitor pid: {}", pid);
+        drop(report_writer);
+        let result = read_report(container_id, report_reader, timeout);
+        if let Err(ContainerErr::Timeout(_)) = result {
+            debug!("killing monitor {}", pid);
+            let _ = syscalls::kill(pid, libc::SIGKILL);
+            let _ = syscalls::waitpid(pid);
+        }
+        return result;
+    }
+
+    drop(report_reader);
+    let mut progress = Progress(Some(report_writer));
+    // Don't go away with the caller's terminal.
+    let created = syscalls::setsid()
+        .map_err(ContainerErr::IO)
+        .and_then(|_| setup(&mut create_container, &mut progress, interactive));
+    let (pid, mut streams) = match created {
+        Ok(created) => created,
+        Err(e) => {
+            progress.error(&e);
+            log::logger().flush();
+            exit(1);
+        }
+    };
+    // Listening before the container is reported created, so clients can attach as soon
+    // as create returns. The container still works without, attach is only a convenience.
+    let attach = attach::Server::listen(&ctx.container_dirs(container_id))
+        .inspect_err(|e| warn!("can't attach to {}: {:?}", container_id, e))
+        .ok();
+    if let Some(attach) = &attach {
+        attach.set_process(pid, streams.stdin.take());
+    }
+    progress.ok();
+    // The timings are create's, restarts aren't recorded.
+    trace::stop_recording();
+
+    let supervisor = Supervisor {
+        ctx,
+        container_id,
+        journal: journal.map(Arc::new),
+        restart,
+        interactive,
+    };
+    if let Err(e) = supervisor.run(pid, streams, attach, create_container) {
+        warn!("monitor for {} failed: {:?}", container_id, e);
+    }
+    log::logger().flush();
+    exit(0);
+}
+
+/// Waits for the monitor to report whether the container was created, logging the phases
+/// it goes through.
+fn read_report(
+    container_id: &str,
+    mut reader: PipeReader,
+    timeout: Option<Duration>,
+) -> Result<(), ContainerErr> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut phase = String::from("starting the monitor");
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]);
+            if line == "ok" {
+                return Ok(());
+            }
+            match line.split_once(' ') {
+                Some(("phase", p)) => {
+                    info!("create {}: {}", container_id, p);
+                    phase = p.to_string();
+                }
+                Some(("error", err)) => return Err(ContainerErr::Monitor(err.to_string())),
+                _ => debug!("unexpected report from monitor: {}", line),
+            }
+        }
+
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !wait_readable(&reader, remaining)? {
+                return Err(ContainerErr::Timeout(format!(
+                    "create timed out after {:?} while {}",
+                    timeout.unwrap_or_default(),
+                    phase
+                )));
+            }
+        }
+        match reader.read(&mut buf) {
+            Ok(0) => {
+                return Err(ContainerErr::Monitor(format!(
+                    "monitor exited while {}",
+                    phase
+                )))
+            }
+            Ok(n) => pending.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(ContainerErr::IO(e)),
+        }
+    }
+}
+
+/// Waits up to `timeout` for `reader` to become readable, returns false on timeout.
+fn wait_readable(reader: &PipeReader, timeout: Duration) -> Result<bool, ContainerErr> {
+    let mut fds = [libc::pollfd {
+        fd: reader.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    let timeout_ms = timeout.as_millis().min(c_int::MAX as u128) as c_int;
+    match syscalls::poll(&mut fds, timeout_ms) {
+        Ok(n) => Ok(n > 0),
+        // Poll again with whatever time is left.
+        Err(e) if e.kind() == ErrorKind::Interrupted => Ok(true),
+        Err(e) => Err(ContainerErr::IO(e)),
+    }
+}
+
+/// The monitor's ends of the pipes the container's stdio is connected to.
+struct Streams {
+    stdin: Option<PipeWriter>,
+    /// stdout and stderr.
+    output: [PipeReader; 2],
+}
+
+/// Runs in the monitor, creates the container and returns the init process' pid along with
+/// our ends of its stdio.
+fn setup<F>(
+    create_container: &mut F,
+    progress: &mut Progress,
+    interactive: bool,
+) -> Result<(Pid, Streams), ContainerErr>
+where
+    F: FnMut(ContainerStdio, &mut Progress) -> Result<Pid, ContainerErr>,
+{
+    let (stdin, stdin_writer) = match interactive {
+        true => {
+            let (reader, writer) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+            (Some(reader), Some(writer))
+        }
+        false => (None, None),
+    };
+    let (stdout_reader, stdout) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+    let (stderr_reader, stderr) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+    let stdio = ContainerStdio {
+        stdin,
+        stdout,
+        stderr,
+    };
+    let pid = create_container(stdio, progress)?;
+    let streams = Streams {
+        stdin: stdin_writer,
+        output: [stdout_reader, stderr_reader],
+    };
+    Ok((pid, streams))
+}
+
+/// What the monitor needs to supervise the container once it's created.
+struct Supervisor<'a> {
+    ctx: &'a Ctx,
+    container_id: &'a str,
+    journal: Option<Arc<Journal>>,
+    restart: RestartPolicy,
+    interactive: bool,
+}
+
+impl Supervisor<'_> {
+    /// Runs until the container's init process has exited and the restart policy doesn't
+    /// restart it.
+    fn run<F>(
+        &self,
+        mut pid: Pid,
+        mut streams: Streams,
+        attach: Option<Arc<attach::Server>>,
+        mut create_container: F,
+    ) -> Result<(), ContainerErr>
+    where
+        F: FnMut(ContainerStdio, &mut Progress) -> Result<Pid, ContainerErr>,
+    {
+        let dirs = self.ctx.container_dirs(self.container_id);
+        release_stdio(&dirs)?;
+        let mut backoff = Backoff::default();
+        loop {
+            let started = Instant::now();
+            let exit_code = self.supervise(&dirs, pid, streams, attach.as_ref())?;
+            if self.restart == RestartPolicy::Never {
+                return Ok(());
+            }
+            let state = self.ctx.store().load(self.container_id)?;
+            let restarts = state.restart_count();
+            if state.stop_requested() || !self.restart.should_restart(exit_code, restarts) {
+                return Ok(());
+            }
+            let delay = backoff.delay(started.elapsed());
+            info!("restarting {} in {:?}", self.container_id, delay);
+            thread::sleep(delay);
+            match self
+                .restart(&mut create_container, restarts + 1)
+                .inspect_err(|_| self.mark_stopped())?
+            {
+                Some((restarted, mut restarted_streams)) => {
+                    if let Some(attach) = &attach {
+                        attach.set_process(restarted, restarted_streams.stdin.take());
+                    }
+                    (pid, streams) = (restarted, restarted_streams);
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Waits for the init process to exit and handles its exit, returns its exit code.
+    fn supervise(
+        &self,
+        dirs: &ContainerDirs,
+        pid: Pid,
+        streams: Streams,
+        attach: Option<&Arc<attach::Server>>,
+    ) -> Result<i32, ContainerErr> {
+        let created_at = now();
+        let copiers = match &self.journal {
+            Some(journal) => forward_to_journal(streams.output, journal.clone(), pid, attach)?,
+            None => copy_to_log(streams.output, dirs, attach)?,
+        };
+        let config = dirs.load_config()?;
+        let (stop_checks, checks_stopped) = mpsc::channel();
+        let checker = HealthCheck::from_extensions(&Extensions::parse(&config)?).map(|check| {
+            let ctx = self.ctx.clone();
+            let container_id = self.container_id.to_string();
+            thread::spawn(move || health::run(ctx, container_id, check, checks_stopped))
+        });
+        let (stop_oom_watch, oom_watch_stopped) = mpsc::channel();
+        let cgroup = self
+            .ctx
+            .store()
+            .load(self.container_id)
+            .and_then(|state| state_cgroup_path(self.ctx, &state, &config));
+        let oom_watcher = match cgroup {
+            Ok(cgroup) => {
+                let (dirs, container_id) = (dirs.clone(), self.container_id.to_string());
+                Some(thread::spawn(move || {
+                    oom::run(dirs, container_id, cgroup, oom_watch_stopped)
+                }))
+            }
+            Err(e) => {
+                warn!("not watching {} for oom kills: {:?}", self.container_id, e);
+                None
+            }
+        };
+
+        let exit_code = wait_exit_code(pid)?;
+        if let Some(attach) = attach {
+            attach.clear_process();
+        }
+        drop(stop_checks);
+        // A check in progress could otherwise record a health after the exit.
+        if let Some(checker) = checker {
+            let _ = checker.join();
+        }
+        // Before the exit is recorded, so the events of a container killed by the OOM
+        // killer are there once it's stopped.
+        drop(stop_oom_watch);
+        if let Some(oom_watcher) = oom_watcher {
+            let _ = oom_watcher.join();
+        }
+        debug!("init process {} exited with {}", pid, exit_code);
+        if let Some(journal) = &self.journal {
+            let message = format!("container exited with {}", exit_code);
+            let _ = journal.send(PRIORITY_INFO, message.as_bytes());
+        }
+        let exit_status = ExitStatus {
+            exit_code,
+            created_at,
+            exited_at: now(),
+        };
+        on_exit(dirs, &exit_status)?;
+
+        // Anything the container wrote before exiting still has to make it to the log.
+        for copier in copiers {
+            let _ = copier.join();
+        }
+        Ok(exit_code)
+    }
+
+    /// Creates and starts the container again. Holds the container's lock throughout, so
+    /// it isn't deleted halfway. A deleted container isn't restarted, neither is one
+    /// which was stopped while we waited, that returns None.
+    fn restart<F>(
+        &self,
+        create_container: &mut F,
+        restarts: u32,
+    ) -> Result<Option<(Pid, Streams)>, ContainerErr>
+    where
+        F: FnMut(ContainerStdio, &mut Progress) -> Result<Pid, ContainerErr>,
+    {
+        let dirs = self.ctx.container_dirs(self.container_id);
+        let store = self.ctx.store();
+        let _lock = store.lock(self.container_id)?;
+        let state = store.load(self.container_id)?;
+        if state.stop_requested() {
+            debug!("not restarting {}, it was stopped", self.container_id);
+            return Ok(None);
+        }
+        if *state.status() != Status::Stopped {
+            return Err(ContainerErr::Monitor(format!(
+                "not restarting, container is {:?}",
+                state.status()
+            )));
+        }
+        dirs.remove_run_files()?;
+        let (pid, streams) = setup(create_container, &mut Progress(None), self.interactive)?;
+        let mut state = store.load(self.container_id)?;
+        restart::start(&dirs, &mut state, restarts)?;
+        Ok(Some((pid, streams)))
+    }
+
+    /// Leaves a container which failed to restart stopped rather than created.
+    fn mark_stopped(&self) {
+        let store = self.ctx.store();
+        if let Ok(mut state) = store.load(self.container_id) {
+            state.update_status(Status::Stopped);
+            let _ = store.save(&state);
+        }
+    }
+}
+
+type Copier = thread::JoinHandle<io::Result<()>>;
+
+/// Copies the container's stdout and stderr to the log in the state dir, and to the
+/// attached clients.
+fn copy_to_log(
+    output: [PipeReader; 2],
+    dirs: &ContainerDirs,
+    attach: Option<&Arc<attach::Server>>,
+) -> Result<Vec<Copier>, ContainerErr> {
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dirs.container_log())
+        .map_err(ContainerErr::IO)?;
+    let mut copiers = Vec::new();
+    for (mut reader, kind) in output
+        .into_iter()
+        .zip([FrameKind::Stdout, FrameKind::Stderr])
+    {
+        let mut log = log.try_clone().map_err(ContainerErr::IO)?;
+        let attach = attach.cloned();
+        copiers.push(thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                log.write_all(&buf[..n])?;
+                if let Some(attach) = &attach {
+                    attach.broadcast(kind, &buf[..n]);
+                }
+            }
+        }));
+    }
+    Ok(copiers)
+}
+
+/// Sends the container's output to the journal a line per entry, stderr with error
+/// priority. The container's lifecycle is logged there too.
+fn forward_to_journal(
+    output: [PipeReader; 2],
+    journal: Arc<Journal>,
+    pid: Pid,
+    attach: Option<&Arc<attach::Server>>,
+) -> Result<Vec<Copier>, ContainerErr> {
+    let _ = journal.send(
+        PRIORITY_INFO,
+        format!("container created, init process {}", pid).as_bytes(),
+    );
+    let mut copiers = Vec::new();
+    let streams = [
+        (PRIORITY_INFO, FrameKind::Stdout),
+        (PRIORITY_ERR, FrameKind::Stderr),
+    ];
+    for (reader, (priority, kind)) in output.into_iter().zip(streams) {
+        let journal = journal.clone();
+        let attach = attach.cloned();
+        copiers.push(thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            while reader.read_until(b'\n', &mut line)? > 0 {
+                if let Some(attach) = &attach {
+                    attach.broadcast(kind, &line);
+                }
+                if line.ends_with(b"\n") {
+                    line.pop();
+                }
+                journal.send(priority, &line)?;
+                line.clear();
+            }
+            Ok(())
+        }));
+    }
+    Ok(copiers)
+}
+
+/// Points our stdio away from the caller's, who is done with us once the container is
+/// created. The monitor's own log goes to the state dir.
+fn release_stdio(dirs: &ContainerDirs) -> Result<(), ContainerErr> {
+    let null = File::open("/dev/null").map_err(ContainerErr::IO)?;
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dirs.monitor_log())
+        .map_err(ContainerErr::IO)?;
+    syscalls::dup2(null.as_raw_fd(), 0).map_err(ContainerErr::IO)?;
+    syscalls::dup2(null.as_raw_fd(), 1).map_err(ContainerErr::IO)?;
+    syscalls::dup2(log.as_raw_fd(), 2).map_err(ContainerErr::IO)?;
+    trace::release_output(&log);
+    Ok(())
+}
+
+fn on_exit(dirs: &ContainerDirs, exit_status: &ExitStatus) -> Result<(), ContainerErr> {
+    // The container may have been deleted while it was running.
+    let mut state = State::load(dirs.state())?;
+    state.update_status(Status::Stopped);
+    state.write(dirs.state())?;
+
+    let config = dirs.load_config()?;
+    run_hooks(&config, HookPhase::Poststop, &state)?;
+    // Written after the poststop hooks, delete only runs them if there's no exit status.
+    exit_status.write(dirs)?;
+
+    // The cleanup annotation's command runs once the container's init process has exited.
+    if let Some(cmd) = Extensions::parse(&config)?.cleanup {
+        debug!("running cleanup command: {}", cmd);
+        run_cleanup(&cmd, &state, exit_status.exit_code)?;
+    }
+    Ok(())
+}
+
+/// Runs the cleanup command with the container state on stdin, like a hook.
+fn run_cleanup(cmd: &str, state: &State, exit_code: i32) -> Result<(), ContainerErr> {
+    let raw_state = serde_json::to_string(state).map_err(|e| ContainerErr::State(e.to_string()))?;
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("CONTAINER_EXIT_CODE", exit_code.to_string())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| ContainerErr::Monitor(format!("failed to run cleanup command: {}", e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The command may not read its stdin, so ignore broken pipes.
+        let _ = stdin.write_all(raw_state.as_bytes());
+    }
+
+    let status = child.wait().map_err(ContainerErr::IO)?;
+    if !status.success() {
+        return Err(ContainerErr::Monitor(format!(
+            "cleanup command exited with {}",
+            status
+        )));
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_exit_status_round_trip() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/exit_status_{}", time)));
+        dirs.create().unwrap();
+
+        let exit_status = ExitStatus {
+            exit_code: 137,
+            created_at: 1,
+            exited_at: 2,
+        };
+        exit_status.write(&dirs).unwrap();
+        assert_eq!(exit_status, ExitStatus::load(&dirs).unwrap());
+
+        dirs.remove().unwrap();
+    }
+
+    #[test]
+    fn test_read_report() {
+        let (reader, mut writer) = std::pipe::pipe().unwrap();
+        writer.write_all(b"phase creating cgroup\nok\n").unwrap();
+        assert!(read_report("foo", reader, None).is_ok());
+
+        let (reader, mut writer) = std::pipe::pipe().unwrap();
+        writer
+            .write_all(b"phase cloning\nerror Cgroup(\"EPERM\")\n")
+            .unwrap();
+        let err = read_report("foo", reader, None).unwrap_err();
+        assert!(matches!(err, ContainerErr::Monitor(msg) if msg == "Cgroup(\"EPERM\")"));
+
+        // The monitor is still around but stuck, the error says where.
+        let (reader, mut writer) = std::pipe::pipe().unwrap();
+        writer.write_all(b"phase running hooks\n").unwrap();
+        let err = read_report("foo", reader, Some(Duration::from_millis(50))).unwrap_err();
+        assert!(matches!(err, ContainerErr::Timeout(msg) if msg.ends_with("while running hooks")));
+    }
+
+    #[test]
+    fn test_run_cleanup() {
+        let state = State::new(
+            String::from("foobar"),
+            PathBuf::from("/bundle"),
+            String::from("1.0.2"),
+        );
+        let cmd = "grep -q foobar && [ \"$CONTAINER_EXIT_CODE\" = 3 ]";
+        assert!(run_cleanup(cmd, &state, 3).is_ok());
+        assert!(run_cleanup(cmd, &state, 0).is_err());
+    }
+}
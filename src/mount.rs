@@ -1,57 +1,445 @@
-use crate::{config::Config, error::ContainerErr};
+use crate::config::{Config, Mount};
+use crate::extensions::Extensions;
+use crate::syscalls::fstatvfs;
+use crate::{error::ContainerErr, syscalls};
 use libc::{
-    __errno_location, c_ulong, MS_ASYNC, MS_BIND, MS_DIRSYNC, MS_I_VERSION, MS_LAZYTIME,
+    c_ulong, EINVAL, ENOENT, MNT_DETACH, MS_BIND, MS_DIRSYNC, MS_I_VERSION, MS_LAZYTIME,
     MS_NOATIME, MS_NODEV, MS_NODIRATIME, MS_NOEXEC, MS_NOSUID, MS_PRIVATE, MS_RDONLY, MS_REC,
     MS_RELATIME, MS_REMOUNT, MS_SHARED, MS_SILENT, MS_SLAVE, MS_STRICTATIME, MS_SYNCHRONOUS,
-    MS_UNBINDABLE,
+    MS_UNBINDABLE, O_PATH, ST_NOATIME, ST_NODEV, ST_NODIRATIME, ST_NOEXEC, ST_NOSUID, ST_RDONLY,
+    ST_RELATIME,
 };
-use std::ffi::{c_void, CStr};
+use log::{debug, warn};
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
 use std::os::unix::ffi::OsStrExt;
-use std::{ffi::CString, path::Path};
+use std::os::unix::fs::{symlink, OpenOptionsExt};
+use std::path::{Component, Path, PathBuf};
 
-pub fn setup_mounts(config: &Config) -> Result<(), ContainerErr> {
-    if let Some(mounts) = config.mounts() {
+/// Group owning the pty slaves in the container, `tty` in most distributions.
+const TTY_GID: u32 = 5;
+
+/// Size of /dev/shm without the shm-size annotation, 64MiB.
+const DEFAULT_SHM_SIZE: u64 = 64 << 20;
+
+/// Filesystems which aren't backed by a device or server. They don't need a source, the
+/// type is used if the bundle doesn't give one.
+const VIRTUAL_FS_TYPES: [&str; 14] = [
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "mqueue",
+    "overlay",
+    "proc",
+    "ramfs",
+    "securityfs",
+    "sysfs",
+    "tmpfs",
+];
+
+/// Filesystems which can be mounted from inside a user namespace, FS_USERNS_MOUNT in the
+/// kernel. Anything else, e.g. nfs or ext4, needs CAP_SYS_ADMIN in the initial one.
+const USERNS_FS_TYPES: [&str; 11] = [
+    "binfmt_misc",
+    "bind",
+    "cgroup2",
+    "devpts",
+    "fuse",
+    "mqueue",
+    "overlay",
+    "proc",
+    "ramfs",
+    "sysfs",
+    "tmpfs",
+];
+
+/// Flags mount(2) ignores when it creates a bind mount, they're only applied by remounting
+/// the bind.
+const BIND_REMOUNT_FLAGS: c_ulong = MS_RDONLY
+    | MS_NOSUID
+    | MS_NODEV
+    | MS_NOEXEC
+    | MS_NOATIME
+    | MS_NODIRATIME
+    | MS_RELATIME
+    | MS_STRICTATIME;
+
+const ATIME_FLAGS: c_ulong = MS_NOATIME | MS_NODIRATIME | MS_RELATIME | MS_STRICTATIME;
+
+/// statvfs(3) flags of a mount and the mount(2) flag for each. A user namespace can't clear
+/// them on a bind of a mount from a more privileged one, the kernel locks them.
+const LOCKED_FLAGS: [(c_ulong, c_ulong); 7] = [
+    (ST_RDONLY, MS_RDONLY),
+    (ST_NOSUID, MS_NOSUID),
+    (ST_NODEV, MS_NODEV),
+    (ST_NOEXEC, MS_NOEXEC),
+    (ST_NOATIME, MS_NOATIME),
+    (ST_NODIRATIME, MS_NODIRATIME),
+    (ST_RELATIME, MS_RELATIME),
+];
+
+/// Mounts the configured mounts into the rootfs, before it becomes the container's root.
+/// If one fails those mounted already are unmounted again.
+pub fn setup_mounts(config: &Config, rootfs: &Path) -> Result<(), ContainerErr> {
+    let userns = config
+        .linux_namespaces()
+        .is_some_and(|ns| ns.iter().any(|ns| ns.typ == "user"));
+    let plan = MountPlan::new(config.mounts().unwrap_or_default())?;
+    let mut mounted = Vec::new();
+    for (destination, mnt) in &plan.mounts {
+        if let Err(e) = mount_one(mnt, rootfs, &rootfs.join(destination), userns, &mut mounted) {
+            unmount_all(&mounted);
+            return Err(ContainerErr::Mount(MountErr::Failed(
+                mnt.destination.clone(),
+                Box::new(e),
+            )));
+        }
+    }
+    setup_shm(config, rootfs).inspect_err(|_| unmount_all(&mounted))
+}
+
+/// The order the config's mounts are mounted in.
+struct MountPlan<'a> {
+    /// The mounts with their normalized destination, relative to the rootfs.
+    mounts: Vec<(PathBuf, &'a Mount)>,
+}
+
+impl<'a> MountPlan<'a> {
+    /// Orders the mounts parents first, a mount on /var goes before one on /var/lib/x
+    /// whatever the config's order. Otherwise config order is kept. Destinations leaving
+    /// the rootfs, and mounting on the same destination twice, are rejected.
+    fn new(mounts: &'a [Mount]) -> Result<Self, ContainerErr> {
+        let mut planned: Vec<(PathBuf, &Mount)> = Vec::with_capacity(mounts.len());
         for mnt in mounts {
-            let mut flags = 0;
-            let mut fs_opts = Vec::<String>::new();
-            let src = if mnt.source.is_some() && mnt.typ.is_none() {
-		mnt.source.as_ref().unwrap()
-	    } else {
-		""
-	    };
-
-            if let Some(opts) = &mnt.options {
-                flags |= parse_mount_options(opts, &mut fs_opts);
+            let destination = normalize_destination(&mnt.destination)?;
+            if planned.iter().any(|(d, _)| *d == destination) {
+                return Err(ContainerErr::Mount(MountErr::InvalidPath(format!(
+                    "{} is mounted on more than once",
+                    mnt.destination
+                ))));
             }
+            planned.push((destination, mnt));
+        }
+        // A stable sort, mounts as deep as each other keep their order.
+        planned.sort_by_key(|(destination, _)| destination.components().count());
+        Ok(Self { mounts: planned })
+    }
+}
 
-            let fs_opts = CString::new(fs_opts.join(",")).map_err(|e| {
-                ContainerErr::Options(format!("could not convert options to cstring: {}", e))
-            })?;
+/// A destination relative to the rootfs, without `.` and duplicate slashes. Destinations
+/// are absolute paths inside the container, relative ones are taken as relative to its
+/// root like older versions of the spec allowed.
+fn normalize_destination(destination: &str) -> Result<PathBuf, ContainerErr> {
+    let mut normalized = PathBuf::new();
+    for component in Path::new(destination).components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(ContainerErr::Mount(MountErr::InvalidPath(format!(
+                    "{} leaves the rootfs",
+                    destination
+                ))));
+            }
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        return Err(ContainerErr::Mount(MountErr::InvalidPath(format!(
+            "can't mount on the rootfs itself: {:?}",
+            destination
+        ))));
+    }
+    Ok(normalized)
+}
 
-	    let t = if let Some(t) = mnt.typ.as_ref() {
-		CString::new(t.as_bytes()).map_err(|e| ContainerErr::MountType(format!("mount type cstring conversion failed: {}", e)))?
-	    } else {
-		CString::new("".as_bytes()).unwrap()
-	    };
+/// Mounts `mnt` on `destination`, adding what it mounted to `mounted`.
+fn mount_one(
+    mnt: &Mount,
+    rootfs: &Path,
+    destination: &Path,
+    userns: bool,
+    mounted: &mut Vec<PathBuf>,
+) -> Result<(), ContainerErr> {
+    let typ = mount_type(mnt);
+    check_mountable(mnt, typ, userns)?;
+    let src = mount_source(mnt, typ)?;
 
+    let (mut flags, mut fs_opts) = parse_mount_options(mnt, typ)?;
+    if typ == Some("bind") {
+        flags |= MS_BIND;
+    }
+    let devpts = typ == Some("devpts");
+    if devpts {
+        devpts_options(&mut fs_opts, userns);
+    }
 
-            mount(
-                src,
-                &mnt.destination,
-                t.as_c_str(),
-                flags,
-                Some(fs_opts.as_ptr() as *const c_void),
-            )
-            .map_err(ContainerErr::Mount)?;
-        }
+    let fs_opts = CString::new(fs_opts.join(",")).map_err(|e| {
+        ContainerErr::Options(format!("could not convert options to cstring: {}", e))
+    })?;
+
+    let t = CString::new(typ.unwrap_or("").as_bytes()).map_err(|e| {
+        ContainerErr::MountType(format!("mount type cstring conversion failed: {}", e))
+    })?;
+
+    // Device nodes too, e.g. GPUs bound in from the host.
+    let bind_file = flags & MS_BIND != 0 && fs::metadata(src).is_ok_and(|m| !m.is_dir());
+    create_mount_point(destination, bind_file)?;
+    debug!(
+        "mounting {} on {}: {}, {:?}",
+        src,
+        mnt.destination,
+        flag_names(flags),
+        fs_opts
+    );
+    mount(
+        src,
+        destination,
+        t.as_c_str(),
+        flags,
+        Some(fs_opts.as_c_str()),
+    )
+    .map_err(ContainerErr::Mount)?;
+    mounted.push(destination.to_path_buf());
+    if flags & MS_BIND != 0 && flags & BIND_REMOUNT_FLAGS != 0 {
+        remount_bind(destination, flags, userns)?;
+    }
+
+    if devpts {
+        mounted.extend(setup_ptmx(rootfs, &mnt.destination)?);
     }
     Ok(())
 }
 
+/// Detaches the mounts, last mounted first. Failures are only logged, this is cleanup
+/// after something else failed already.
+fn unmount_all(mounted: &[PathBuf]) {
+    for path in mounted.iter().rev() {
+        debug!("unmounting {:?}", path);
+        if let Err(e) = unmount(path) {
+            warn!("failed to unmount {:?}: {:?}", path, e);
+        }
+    }
+}
+
+/// Applies the flags of the bind mount at `destination`, e.g. makes an `ro` bind read-only.
+/// In a user namespace the flags the bound mount is locked with are kept, the kernel
+/// refuses remounts dropping them.
+fn remount_bind(destination: &Path, flags: c_ulong, userns: bool) -> Result<(), ContainerErr> {
+    let locked = if userns {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(O_PATH)
+            .open(destination)
+            .map_err(ContainerErr::IO)?;
+        Some(fstatvfs(file.as_raw_fd()).map_err(ContainerErr::IO)?.f_flag)
+    } else {
+        None
+    };
+    let flags = bind_remount_flags(flags, locked);
+    debug!("remounting {:?}: {}", destination, flag_names(flags));
+    mount("", destination, c"", flags, None).map_err(ContainerErr::Mount)
+}
+
+/// The remount flags of a bind mount with `flags`, keeping the `locked` statvfs flags of
+/// the mount it binds. Its atime flags are locked as a whole, they replace the bundle's.
+fn bind_remount_flags(flags: c_ulong, locked: Option<c_ulong>) -> c_ulong {
+    let mut flags = MS_REMOUNT | MS_BIND | (flags & BIND_REMOUNT_FLAGS);
+    if let Some(locked) = locked {
+        flags &= !ATIME_FLAGS;
+        for (st_flag, ms_flag) in LOCKED_FLAGS {
+            if locked & st_flag != 0 {
+                flags |= ms_flag;
+            }
+        }
+    }
+    flags
+}
+
+/// Mounts a tmpfs at /dev/shm sized by the shm-size annotation, unless the bundle mounts
+/// something there itself.
+fn setup_shm(config: &Config, rootfs: &Path) -> Result<(), ContainerErr> {
+    let configured = config.mounts().is_some_and(|mounts| {
+        mounts
+            .iter()
+            .any(|m| m.destination.trim_end_matches('/') == "/dev/shm")
+    });
+    if configured {
+        return Ok(());
+    }
+
+    let size = Extensions::parse(config)?
+        .shm_size
+        .unwrap_or(DEFAULT_SHM_SIZE);
+    let shm = rootfs.join("dev/shm");
+    fs::create_dir_all(&shm).map_err(ContainerErr::IO)?;
+    let data = CString::new(format!("mode=1777,size={}", size)).unwrap();
+    mount(
+        "shm",
+        &shm,
+        c"tmpfs",
+        MS_NOSUID | MS_NODEV | MS_NOEXEC,
+        Some(data.as_c_str()),
+    )
+    .map_err(ContainerErr::Mount)
+}
+
+/// Parses a size in bytes, optionally with a k, m or g suffix.
+pub fn parse_size(size: &str) -> Result<u64, ContainerErr> {
+    let invalid = || ContainerErr::Options(format!("invalid size: {:?}", size));
+    let lower = size.trim().to_ascii_lowercase();
+    let shift = match lower.chars().last() {
+        Some('k') => 10,
+        Some('m') => 20,
+        Some('g') => 30,
+        _ => 0,
+    };
+    let digits = if shift > 0 {
+        &lower[..lower.len() - 1]
+    } else {
+        &lower
+    };
+    let n: u64 = digits.parse().map_err(|_| invalid())?;
+    n.checked_mul(1 << shift)
+        .filter(|&n| n > 0)
+        .ok_or_else(invalid)
+}
+
+/// The mount's filesystem type. Bundles often leave it out for bind mounts and only set
+/// the bind or rbind option.
+fn mount_type(mnt: &Mount) -> Option<&str> {
+    mnt.typ.as_deref().or_else(|| {
+        mnt.options
+            .iter()
+            .flatten()
+            .any(|o| o == "bind" || o == "rbind")
+            .then_some("bind")
+    })
+}
+
+/// Makes relative bind mount sources relative to the bundle, like runc does, so bundles
+/// shipping their own volumes can be moved around. Other sources, e.g. an nfs share's
+/// `host:/export`, are left as they are.
+pub fn resolve_bind_sources(mounts: &mut [Mount], bundle: &Path) {
+    for mnt in mounts {
+        if mount_type(mnt) != Some("bind") {
+            continue;
+        }
+        if let Some(source) = &mut mnt.source {
+            if !source.is_empty() && Path::new(source).is_relative() {
+                *source = bundle.join(&source).to_string_lossy().into_owned();
+            }
+        }
+    }
+}
+
+/// The source to mount from. Filesystems which need one fail with little more than
+/// EINVAL when given an empty source, so a missing source is reported up front. Mounts
+/// without a type only change flags or propagation and take whatever they're given.
+fn mount_source<'a>(mnt: &'a Mount, typ: Option<&'a str>) -> Result<&'a str, ContainerErr> {
+    match (mnt.source.as_deref(), typ) {
+        (Some(source), _) if !source.is_empty() => Ok(source),
+        (_, Some(typ)) if VIRTUAL_FS_TYPES.contains(&typ) => Ok(typ),
+        (_, Some(typ)) => Err(ContainerErr::MountType(format!(
+            "{} mount on {} needs a source",
+            typ, mnt.destination
+        ))),
+        (_, None) => Ok(""),
+    }
+}
+
+/// Rejects filesystems a user namespace isn't allowed to mount, the kernel would only
+/// say EPERM.
+fn check_mountable(mnt: &Mount, typ: Option<&str>, userns: bool) -> Result<(), ContainerErr> {
+    match typ {
+        Some(typ) if userns && !USERNS_FS_TYPES.contains(&typ) => {
+            Err(ContainerErr::MountType(format!(
+                "{} mount on {} isn't allowed in a user namespace",
+                typ, mnt.destination
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Creates the mount point at `destination`. A file is bound over an empty file,
+/// everything else is mounted on a directory.
+pub fn create_mount_point(destination: &Path, file: bool) -> Result<(), ContainerErr> {
+    if !file {
+        return fs::create_dir_all(destination).map_err(ContainerErr::IO);
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(ContainerErr::IO)?;
+    }
+    match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(destination)
+    {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => Err(ContainerErr::IO(e)),
+        _ => Ok(()),
+    }
+}
+
+/// Fills in the devpts options the bundle doesn't set. newinstance gives the container
+/// its own set of ptys instead of sharing the host's. The gid is only defaulted without
+/// a user namespace, the tty group might not be mapped in one.
+fn devpts_options(fs_opts: &mut Vec<String>, userns: bool) {
+    let has = |fs_opts: &[String], key: &str| {
+        fs_opts
+            .iter()
+            .any(|o| o == key || o.starts_with(&format!("{}=", key)))
+    };
+    let mut defaults = vec![
+        (String::from("newinstance"), "newinstance"),
+        (String::from("ptmxmode=0666"), "ptmxmode"),
+        (String::from("mode=0620"), "mode"),
+    ];
+    if !userns {
+        defaults.push((format!("gid={}", TTY_GID), "gid"));
+    }
+    for (opt, key) in defaults {
+        if !has(fs_opts, key) {
+            fs_opts.push(opt);
+        }
+    }
+}
+
+/// Points /dev/ptmx at the ptmx of the devpts instance mounted at `devpts`, so opening
+/// it allocates a pty from the container's instance. Returns /dev/ptmx if it was covered
+/// with a bind mount.
+fn setup_ptmx(rootfs: &Path, devpts: &str) -> Result<Option<PathBuf>, ContainerErr> {
+    let ptmx = rootfs.join("dev/ptmx");
+    if devpts.trim_end_matches('/') == "/dev/pts" {
+        match fs::remove_file(&ptmx) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(ContainerErr::IO(e)),
+            _ => {}
+        }
+        symlink("pts/ptmx", &ptmx).map_err(ContainerErr::IO)?;
+        return Ok(None);
+    }
+
+    // devpts is somewhere else, cover /dev/ptmx with its ptmx instead.
+    if fs::symlink_metadata(&ptmx).is_err() {
+        fs::File::create(&ptmx).map_err(ContainerErr::IO)?;
+    }
+    let source = rootfs.join(devpts.trim_start_matches('/')).join("ptmx");
+    mount(&source, &ptmx, c"", MS_BIND, None).map_err(ContainerErr::Mount)?;
+    Ok(Some(ptmx))
+}
+
 #[derive(Debug)]
 pub enum MountErr {
     InvalidPath(String),
     Generic(String),
+    /// Mounting the config's mount on the destination failed.
+    Failed(String, Box<ContainerErr>),
 }
 
 pub fn mount<S: AsRef<Path>, T: AsRef<Path>>(
@@ -59,74 +447,442 @@ pub fn mount<S: AsRef<Path>, T: AsRef<Path>>(
     target: T,
     fstype: &CStr,
     flags: c_ulong,
-    data: Option<*const c_void>,
+    data: Option<&CStr>,
 ) -> Result<(), MountErr> {
     let src = CString::new(src.as_ref().as_os_str().as_bytes())
         .map_err(|e| MountErr::InvalidPath(format!("{:?}", e)))?;
     let target = CString::new(target.as_ref().as_os_str().as_bytes())
         .map_err(|e| MountErr::InvalidPath(format!("{:?}", e)))?;
 
-    let ptr = data.unwrap_or(std::ptr::null());
+    syscalls::mount(&src, &target, fstype, flags, data)
+        .map_err(|e| MountErr::Generic(e.to_string()))
+}
 
-    let err = unsafe { libc::mount(src.as_ptr(), target.as_ptr(), fstype.as_ptr(), flags, ptr) };
-    if err != 0 {
-        return Err(MountErr::Generic(format!(
-            "exit code: {}, errno {}",
-            err,
-            unsafe { *__errno_location() }
-        )));
+/// How a mount option changes the mount(2) flags.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FlagOp {
+    Set,
+    Clear,
+}
+
+/// Mount options which are flags: the flag they set or clear, and what kind of option
+/// they are. Different options of the same kind contradict each other, ro and rw or
+/// shared and private. Options without a kind go with anything.
+const MOUNT_FLAGS: [(&str, c_ulong, FlagOp, &str); 37] = [
+    ("async", MS_SYNCHRONOUS, FlagOp::Clear, "sync"),
+    ("atime", MS_NOATIME, FlagOp::Clear, "atime"),
+    ("bind", MS_BIND, FlagOp::Set, ""),
+    ("defaults", 0, FlagOp::Set, ""),
+    ("dev", MS_NODEV, FlagOp::Clear, "dev"),
+    ("diratime", MS_NODIRATIME, FlagOp::Clear, "diratime"),
+    ("dirsync", MS_DIRSYNC, FlagOp::Set, ""),
+    ("exec", MS_NOEXEC, FlagOp::Clear, "exec"),
+    ("iversion", MS_I_VERSION, FlagOp::Set, "iversion"),
+    ("lazytime", MS_LAZYTIME, FlagOp::Set, "lazytime"),
+    ("loud", MS_SILENT, FlagOp::Clear, "silent"),
+    ("noatime", MS_NOATIME, FlagOp::Set, "atime"),
+    ("nodev", MS_NODEV, FlagOp::Set, "dev"),
+    ("nodiratime", MS_NODIRATIME, FlagOp::Set, "diratime"),
+    ("noexec", MS_NOEXEC, FlagOp::Set, "exec"),
+    ("noiversion", MS_I_VERSION, FlagOp::Clear, "iversion"),
+    ("nolazytime", MS_LAZYTIME, FlagOp::Clear, "lazytime"),
+    ("norelatime", MS_RELATIME, FlagOp::Clear, "atime"),
+    ("nostrictatime", MS_STRICTATIME, FlagOp::Clear, "atime"),
+    ("nosuid", MS_NOSUID, FlagOp::Set, "suid"),
+    ("private", MS_PRIVATE, FlagOp::Set, "propagation"),
+    ("rbind", MS_BIND | MS_REC, FlagOp::Set, ""),
+    ("relatime", MS_RELATIME, FlagOp::Set, "atime"),
+    ("remount", MS_REMOUNT, FlagOp::Set, ""),
+    ("ro", MS_RDONLY, FlagOp::Set, "ro"),
+    ("rprivate", MS_PRIVATE | MS_REC, FlagOp::Set, "propagation"),
+    ("rshared", MS_SHARED | MS_REC, FlagOp::Set, "propagation"),
+    ("rslave", MS_SLAVE | MS_REC, FlagOp::Set, "propagation"),
+    (
+        "runbindable",
+        MS_UNBINDABLE | MS_REC,
+        FlagOp::Set,
+        "propagation",
+    ),
+    ("rw", MS_RDONLY, FlagOp::Clear, "ro"),
+    ("shared", MS_SHARED, FlagOp::Set, "propagation"),
+    ("silent", MS_SILENT, FlagOp::Set, "silent"),
+    ("slave", MS_SLAVE, FlagOp::Set, "propagation"),
+    ("strictatime", MS_STRICTATIME, FlagOp::Set, "atime"),
+    ("suid", MS_NOSUID, FlagOp::Clear, "suid"),
+    ("sync", MS_SYNCHRONOUS, FlagOp::Set, "sync"),
+    ("unbindable", MS_UNBINDABLE, FlagOp::Set, "propagation"),
+];
+
+/// Names of the flags in the debug log.
+const FLAG_NAMES: [(c_ulong, &str); 19] = [
+    (MS_RDONLY, "MS_RDONLY"),
+    (MS_NOSUID, "MS_NOSUID"),
+    (MS_NODEV, "MS_NODEV"),
+    (MS_NOEXEC, "MS_NOEXEC"),
+    (MS_SYNCHRONOUS, "MS_SYNCHRONOUS"),
+    (MS_REMOUNT, "MS_REMOUNT"),
+    (MS_DIRSYNC, "MS_DIRSYNC"),
+    (MS_NOATIME, "MS_NOATIME"),
+    (MS_NODIRATIME, "MS_NODIRATIME"),
+    (MS_BIND, "MS_BIND"),
+    (MS_REC, "MS_REC"),
+    (MS_SILENT, "MS_SILENT"),
+    (MS_UNBINDABLE, "MS_UNBINDABLE"),
+    (MS_PRIVATE, "MS_PRIVATE"),
+    (MS_SLAVE, "MS_SLAVE"),
+    (MS_SHARED, "MS_SHARED"),
+    (MS_RELATIME, "MS_RELATIME"),
+    (MS_I_VERSION, "MS_I_VERSION"),
+    (MS_STRICTATIME, "MS_STRICTATIME"),
+];
+
+/// The filesystem specific options, without their `=value`, of the filesystems the
+/// runtime knows. Other filesystems, e.g. nfs or ext4, are given whatever the bundle
+/// has and left to the kernel to check.
+fn fs_option_keys(typ: Option<&str>) -> Option<&'static [&'static str]> {
+    match typ? {
+        "bind" | "mqueue" | "sysfs" => Some(&[]),
+        "cgroup2" => Some(&["memory_recursiveprot", "nsdelegate", "favordynmods"]),
+        "devpts" => Some(&["gid", "max", "mode", "newinstance", "ptmxmode", "uid"]),
+        "overlay" => Some(&[
+            "index",
+            "lowerdir",
+            "metacopy",
+            "redirect_dir",
+            "upperdir",
+            "userxattr",
+            "volatile",
+            "workdir",
+            "xino",
+        ]),
+        "proc" => Some(&["gid", "hidepid", "subset"]),
+        "ramfs" => Some(&["mode"]),
+        "tmpfs" => Some(&[
+            "gid",
+            "huge",
+            "inode32",
+            "inode64",
+            "mode",
+            "mpol",
+            "noswap",
+            "nr_blocks",
+            "nr_inodes",
+            "size",
+            "uid",
+        ]),
+        _ => None,
     }
-    Ok(())
+}
+
+/// Detaches the mount on `target`, lazily if it's busy.
+pub fn unmount<T: AsRef<Path>>(target: T) -> Result<(), MountErr> {
+    let target = CString::new(target.as_ref().as_os_str().as_bytes())
+        .map_err(|e| MountErr::InvalidPath(format!("{:?}", e)))?;
+    syscalls::umount2(&target, MNT_DETACH).map_err(|e| MountErr::Generic(e.to_string()))
+}
+
+/// Detaches the mounts on `targets`, last first, and returns those which were. Targets
+/// which aren't mount points, or are gone, are skipped.
+pub fn unmount_targets(targets: &[PathBuf]) -> Result<Vec<PathBuf>, ContainerErr> {
+    let mut unmounted = Vec::new();
+    for target in targets.iter().rev() {
+        let path = CString::new(target.as_os_str().as_bytes())
+            .map_err(|e| ContainerErr::Mount(MountErr::InvalidPath(format!("{:?}", e))))?;
+        match syscalls::umount2(&path, MNT_DETACH) {
+            Ok(()) => unmounted.push(target.clone()),
+            Err(e) if matches!(syscalls::errno(&e), Some(EINVAL | ENOENT)) => {
+                debug!("{:?} isn't mounted", target);
+            }
+            Err(e) => return Err(ContainerErr::Mount(MountErr::Generic(e.to_string()))),
+        }
+    }
+    Ok(unmounted)
 }
 
 /// Converts mount options from the config into mount(2) flags &
-/// filesystem specific options.
-fn parse_mount_options(options: &[String], fs_opts: &mut Vec<String>) -> c_ulong {
+/// filesystem specific options. Options which contradict each other are rejected, as
+/// are those the mount's filesystem doesn't take.
+fn parse_mount_options(
+    mnt: &Mount,
+    typ: Option<&str>,
+) -> Result<(c_ulong, Vec<String>), ContainerErr> {
     let mut flags: c_ulong = 0;
+    let mut fs_opts = Vec::new();
+    let mut unknown = Vec::new();
+    // The option given for each kind so far.
+    let mut kinds: Vec<(&str, &str)> = Vec::new();
+    let keys = fs_option_keys(typ);
 
-    for opt in options {
-        match opt.as_str() {
-            "async" => flags |= MS_ASYNC as c_ulong,
-            "atime" => flags ^= MS_NOATIME,
-            "bind" => flags |= MS_BIND,
-            "defaults" => flags |= 0,
-            "dev" => flags |= MS_NODEV,
-            "diratime" => flags ^= MS_NODIRATIME,
-            "dirsync" => flags |= MS_DIRSYNC,
-            "exec" => flags ^= MS_NOEXEC,
-            "iversion" => flags |= MS_I_VERSION,
-            "lazytime" => flags |= MS_LAZYTIME,
-            "loud" => flags ^= MS_SILENT,
-            "noatime" => flags |= MS_NOATIME,
-            "nodev" => flags |= MS_NODEV,
-            "nodiratime" => flags |= MS_NODIRATIME,
-            "noexec" => flags |= MS_NOEXEC,
-            "noiversion" => flags ^= MS_I_VERSION,
-            "nolazytime" => flags ^= MS_LAZYTIME,
-            "norelatime" => flags ^= MS_RELATIME,
-            "nostrictatime" => flags ^= MS_STRICTATIME,
-            "nosuid" => flags |= MS_NOSUID,
-            "private" => flags |= MS_PRIVATE,
-            "rbind" => flags |= MS_BIND | MS_REC,
-            "relatime" => flags |= MS_RELATIME,
-            "remount" => flags |= MS_REMOUNT,
-            "ro" => flags |= MS_RDONLY,
-            "rprivate" => flags |= MS_PRIVATE,
-            "rshared" => flags |= MS_SHARED,
-            "rslave" => flags |= MS_SLAVE,
-            "runbindable" => flags |= MS_UNBINDABLE,
-            "rw" => flags ^= MS_RDONLY,
-            "shared" => flags |= MS_SHARED,
-            "silent" => flags ^= MS_SILENT,
-            "slave" => flags |= MS_SLAVE,
-            "strictatime" => flags |= MS_STRICTATIME,
-            "suid" => flags ^= MS_NOSUID,
-            "sync" => flags |= MS_SYNCHRONOUS,
-            "unbindable" => flags |= MS_UNBINDABLE,
-            o => fs_opts.push(o.to_string()),
+    for opt in mnt.options.iter().flatten() {
+        let Some((name, flag, op, kind)) = MOUNT_FLAGS.iter().find(|(name, ..)| name == opt) else {
+            let key = opt.split('=').next().unwrap_or_default();
+            if keys.is_some_and(|keys| !keys.contains(&key)) {
+                unknown.push(opt.as_str());
+            }
+            fs_opts.push(opt.clone());
+            continue;
+        };
+        if !kind.is_empty() {
+            match kinds.iter().find(|(k, _)| k == kind) {
+                Some((_, other)) if other != name => {
+                    return Err(ContainerErr::Options(format!(
+                        "mount on {}: {} contradicts {}",
+                        mnt.destination, name, other
+                    )));
+                }
+                Some(_) => {}
+                None => kinds.push((kind, name)),
+            }
+        }
+        match op {
+            FlagOp::Set => flags |= flag,
+            FlagOp::Clear => flags &= !flag,
         }
     }
 
-    flags
+    if !unknown.is_empty() {
+        return Err(ContainerErr::Options(format!(
+            "mount on {}: unknown options for {}: {}",
+            mnt.destination,
+            typ.unwrap_or("a mount without a type"),
+            unknown.join(",")
+        )));
+    }
+    Ok((flags, fs_opts))
+}
+
+/// Checks the destinations and options of the config's mounts, before there's a container
+/// to mount them in.
+pub fn check_mounts(config: &Config) -> Result<(), ContainerErr> {
+    let plan = MountPlan::new(config.mounts().unwrap_or_default())?;
+    for (_, mnt) in plan.mounts {
+        parse_mount_options(mnt, mount_type(mnt))?;
+    }
+    Ok(())
 }
 
+/// `flags` as `MS_*` names, for the log.
+fn flag_names(flags: c_ulong) -> String {
+    let names: Vec<&str> = FLAG_NAMES
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if names.is_empty() {
+        return String::from("0");
+    }
+    names.join("|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_devpts_options() {
+        let mut fs_opts = vec![String::from("mode=0600")];
+        devpts_options(&mut fs_opts, false);
+        assert_eq!(
+            vec!["mode=0600", "newinstance", "ptmxmode=0666", "gid=5"],
+            fs_opts
+        );
+
+        let mut fs_opts = Vec::new();
+        devpts_options(&mut fs_opts, true);
+        assert_eq!(vec!["newinstance", "ptmxmode=0666", "mode=0620"], fs_opts);
+    }
+
+    #[test]
+    fn test_mount_type() {
+        let mnt: Mount = serde_json::from_value(serde_json::json!({
+            "destination": "/etc/resolv.conf",
+            "source": "/etc/resolv.conf",
+            "options": ["rbind", "ro"],
+        }))
+        .unwrap();
+        assert_eq!(Some("bind"), mount_type(&mnt));
+
+        let mnt: Mount = serde_json::from_value(serde_json::json!({
+            "destination": "/proc",
+            "type": "proc",
+        }))
+        .unwrap();
+        assert_eq!(Some("proc"), mount_type(&mnt));
+    }
+
+    #[test]
+    fn test_resolve_bind_sources() {
+        let mut mounts: Vec<Mount> = serde_json::from_value(serde_json::json!([
+            {"destination": "/data", "source": "volumes/data", "options": ["rbind"]},
+            {"destination": "/etc/app", "source": "/srv/app", "type": "bind"},
+            {"destination": "/mnt", "source": "10.0.0.1:/exports", "type": "nfs"},
+            {"destination": "/proc", "source": "proc", "type": "proc"},
+        ]))
+        .unwrap();
+        resolve_bind_sources(&mut mounts, Path::new("/bundles/web"));
+        let sources: Vec<&str> = mounts.iter().flat_map(|m| m.source.as_deref()).collect();
+        assert_eq!(
+            vec![
+                "/bundles/web/volumes/data",
+                "/srv/app",
+                "10.0.0.1:/exports",
+                "proc"
+            ],
+            sources
+        );
+    }
+
+    #[test]
+    fn test_mount_source() {
+        let mnt: Mount = serde_json::from_value(serde_json::json!({
+            "destination": "/data",
+            "type": "nfs",
+            "options": ["addr=10.0.0.1", "vers=4"],
+        }))
+        .unwrap();
+        assert!(mount_source(&mnt, mount_type(&mnt)).is_err());
+        assert!(check_mountable(&mnt, mount_type(&mnt), false).is_ok());
+        assert!(check_mountable(&mnt, mount_type(&mnt), true).is_err());
+
+        let mnt: Mount = serde_json::from_value(serde_json::json!({
+            "destination": "/data",
+            "type": "nfs",
+            "source": "10.0.0.1:/exports/data",
+        }))
+        .unwrap();
+        assert_eq!(
+            "10.0.0.1:/exports/data",
+            mount_source(&mnt, mount_type(&mnt)).unwrap()
+        );
+
+        let mnt: Mount = serde_json::from_value(serde_json::json!({
+            "destination": "/tmp",
+            "type": "tmpfs",
+        }))
+        .unwrap();
+        assert_eq!("tmpfs", mount_source(&mnt, mount_type(&mnt)).unwrap());
+        assert!(check_mountable(&mnt, mount_type(&mnt), true).is_ok());
+    }
+
+    #[test]
+    fn test_parse_mount_options() {
+        let parse = |typ: &str, options: &[&str]| {
+            let mnt: Mount = serde_json::from_value(serde_json::json!({
+                "destination": "/mnt",
+                "type": typ,
+                "options": options,
+            }))
+            .unwrap();
+            parse_mount_options(&mnt, mount_type(&mnt))
+        };
+
+        let (flags, fs_opts) = parse("tmpfs", &["nosuid", "strictatime", "mode=755"]).unwrap();
+        assert_eq!(MS_NOSUID | MS_STRICTATIME, flags);
+        assert_eq!(vec!["mode=755"], fs_opts);
+        let (flags, _) = parse("bind", &["rbind", "rw", "rprivate", "dev"]).unwrap();
+        assert_eq!(MS_BIND | MS_REC | MS_PRIVATE, flags);
+        // Repeating an option is fine.
+        assert!(parse("bind", &["ro", "ro"]).is_ok());
+        // Other filesystems' options are left to the kernel.
+        assert_eq!(vec!["vers=4"], parse("nfs", &["vers=4"]).unwrap().1);
+
+        for options in [
+            &["ro", "rw"][..],
+            &["shared", "private"],
+            &["rslave", "rprivate"],
+            &["noatime", "relatime"],
+            &["nosuid", "suid"],
+        ] {
+            assert!(parse("tmpfs", options).is_err(), "{:?}", options);
+        }
+        assert!(parse("tmpfs", &["sise=1m"]).is_err());
+        assert!(parse("bind", &["rbind", "mode=755"]).is_err());
+        assert!(parse("proc", &["hidepid=2"]).is_ok());
+
+        assert_eq!(
+            "MS_RDONLY|MS_BIND|MS_REC",
+            flag_names(MS_RDONLY | MS_BIND | MS_REC)
+        );
+        assert_eq!("0", flag_names(0));
+    }
+
+    #[test]
+    fn test_mount_plan() {
+        let mounts = |destinations: &[&str]| -> Vec<Mount> {
+            destinations
+                .iter()
+                .map(|d| {
+                    serde_json::from_value(serde_json::json!({"destination": d, "type": "tmpfs"}))
+                        .unwrap()
+                })
+                .collect()
+        };
+        let planned = |mounts: &[Mount]| -> Vec<String> {
+            MountPlan::new(mounts)
+                .unwrap()
+                .mounts
+                .iter()
+                .map(|(d, _)| d.display().to_string())
+                .collect()
+        };
+
+        let nested = mounts(&["/var/lib/x", "/tmp", "/var/", "/run", "dev//./shm"]);
+        assert_eq!(
+            vec!["tmp", "var", "run", "dev/shm", "var/lib/x"],
+            planned(&nested)
+        );
+        assert!(MountPlan::new(&mounts(&["/tmp", "/tmp/"])).is_err());
+        assert!(MountPlan::new(&mounts(&["/../etc"])).is_err());
+        assert!(MountPlan::new(&mounts(&["/var/../../etc"])).is_err());
+        assert!(MountPlan::new(&mounts(&["/"])).is_err());
+    }
+
+    #[test]
+    fn test_bind_remount_flags() {
+        // Propagation and recursion aren't for remounts.
+        assert_eq!(
+            MS_REMOUNT | MS_BIND | MS_RDONLY,
+            bind_remount_flags(MS_BIND | MS_REC | MS_PRIVATE | MS_RDONLY, None)
+        );
+        assert_eq!(
+            MS_REMOUNT | MS_BIND | MS_RDONLY | MS_STRICTATIME,
+            bind_remount_flags(MS_BIND | MS_RDONLY | MS_STRICTATIME, None)
+        );
+        // The bound mount's nosuid, nodev and relatime are kept in a user namespace.
+        assert_eq!(
+            MS_REMOUNT | MS_BIND | MS_RDONLY | MS_NOSUID | MS_NODEV | MS_RELATIME,
+            bind_remount_flags(
+                MS_BIND | MS_RDONLY | MS_STRICTATIME,
+                Some(ST_NOSUID | ST_NODEV | ST_RELATIME)
+            )
+        );
+    }
+
+    #[test]
+    fn test_create_mount_point() {
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::path::PathBuf::from(format!("/tmp/mount_point_{}", time));
+
+        create_mount_point(&dir.join("etc/hosts"), true).unwrap();
+        assert!(dir.join("etc/hosts").is_file());
+        // An existing file is reused.
+        create_mount_point(&dir.join("etc/hosts"), true).unwrap();
+        create_mount_point(&dir.join("dev/pts"), false).unwrap();
+        assert!(dir.join("dev/pts").is_dir());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(4096, parse_size("4096").unwrap());
+        assert_eq!(64 << 20, parse_size("64m").unwrap());
+        assert_eq!(1 << 30, parse_size("1G").unwrap());
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("m").is_err());
+    }
+}
@@ -1,12 +1,25 @@
 //! namespaces
 
-use crate::{config::Namespace, error::ContainerErr};
+use crate::{
+    config::Namespace,
+    container::validate_id,
+    ctx::Ctx,
+    error::ContainerErr,
+    state::{Pid, State, Status},
+    syscalls::setns,
+};
 use libc::{
-    c_int, setns, CLONE_NEWCGROUP, CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS, CLONE_NEWPID,
-    CLONE_NEWTIME, CLONE_NEWUSER, CLONE_NEWUTS,
+    c_int, CLONE_NEWCGROUP, CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS, CLONE_NEWPID, CLONE_NEWTIME,
+    CLONE_NEWUSER, CLONE_NEWUTS,
 };
 use log::debug;
-use std::{fs::File, os::fd::AsRawFd};
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+/// Namespace paths starting with this name another container, `container:<id>`, rather
+/// than a file.
+const CONTAINER_PREFIX: &str = "container:";
 
 /// returns the clone flags for any namespaces that need to be created
 pub fn clone_namespace_flags(namespaces: &[Namespace]) -> c_int {
@@ -66,27 +79,97 @@ pub fn join_namspaces(namespaces: &[Namespace]) -> Result<(), ContainerErr> {
             };
 
             // re-map any errors with the more human read-able information we've got.
-            set_namespace(fd, nstype).map_err(|_| {
-                ContainerErr::JoinNamespace(format!("failed to join namespace: {:?}", ns))
+            set_namespace(fd, nstype).map_err(|e| {
+                ContainerErr::JoinNamespace(format!("failed to join namespace {:?}: {}", ns, e))
             })?;
         }
     }
     Ok(())
 }
 
-/// setns wrapper
-fn set_namespace(fd: c_int, nstype: c_int) -> Result<(), ContainerErr> {
-    debug!("fd {}, nstype {}", fd, nstype);
-    if unsafe { setns(fd, nstype) } == -1 {
-        return Err(ContainerErr::JoinNamespace(format!(
-            "failed to join namespace: nstype {}",
-            nstype
-        )));
+/// Joins the namespaces of a running container process, for each namespace type in the
+/// config. The user namespace is joined first so we have privileges over the others. Joining
+/// pid and time namespaces only takes effect for children forked afterwards.
+pub fn join_process_namespaces(pid: Pid, namespaces: &[Namespace]) -> Result<(), ContainerErr> {
+    let mut namespaces: Vec<&Namespace> = namespaces.iter().collect();
+    namespaces.sort_by_key(|ns| ns.typ != "user");
+
+    // Open everything up front, after joining the mount namespace /proc is the container's.
+    let mut to_join = Vec::new();
+    for ns in namespaces {
+        let (Some(nstype), Some(name)) = (ns_type(&ns.typ), proc_ns_name(&ns.typ)) else {
+            return Err(ContainerErr::InvalidNamespace(format!(
+                "invalid nstype: {}",
+                ns.typ
+            )));
+        };
+        let path = format!("/proc/{}/ns/{}", pid, name);
+        // setns fails with EINVAL for a user namespace we're already in.
+        if fs::read_link(&path).ok() == fs::read_link(format!("/proc/self/ns/{}", name)).ok() {
+            continue;
+        }
+        let f = File::open(&path).map_err(ContainerErr::IO)?;
+        to_join.push((f, nstype, ns));
     }
 
+    for (f, nstype, ns) in to_join {
+        debug!("joining namespace of {}: {:?}", pid, ns);
+        set_namespace(f.as_raw_fd(), nstype).map_err(|e| {
+            ContainerErr::JoinNamespace(format!("failed to join namespace {:?}: {}", ns, e))
+        })?;
+    }
     Ok(())
 }
 
+/// Resolves namespace paths of the form `container:<id>` to that namespace of the
+/// container's process, `/proc/<pid>/ns/<type>`, so containers can share namespaces
+/// without the caller looking up pids. The container has to be created or running.
+pub fn resolve_container_paths(
+    ctx: &Ctx,
+    namespaces: &mut [Namespace],
+) -> Result<(), ContainerErr> {
+    for ns in namespaces {
+        let Some(id) = ns
+            .path
+            .as_deref()
+            .and_then(|path| path.strip_prefix(CONTAINER_PREFIX))
+        else {
+            continue;
+        };
+        validate_id(id)?;
+        let Some(name) = proc_ns_name(&ns.typ) else {
+            return Err(ContainerErr::InvalidNamespace(format!(
+                "invalid nstype: {}",
+                ns.typ
+            )));
+        };
+        let state = State::load(ctx.container_dirs(id).state()).map_err(|e| {
+            ContainerErr::InvalidNamespace(format!(
+                "no container {} to share its {} namespace: {:?}",
+                id, ns.typ, e
+            ))
+        })?;
+        let path = format!("/proc/{}/ns/{}", state.pid(), name);
+        let alive = matches!(state.status(), Status::Created | Status::Running)
+            && Path::new(&path).exists();
+        if !alive {
+            return Err(ContainerErr::InvalidNamespace(format!(
+                "container {} is not running, can't share its {} namespace",
+                id, ns.typ
+            )));
+        }
+        debug!("{} namespace of container {} is {}", ns.typ, id, path);
+        ns.path = Some(path);
+    }
+    Ok(())
+}
+
+/// setns wrapper
+fn set_namespace(fd: c_int, nstype: c_int) -> std::io::Result<()> {
+    debug!("fd {}, nstype {}", fd, nstype);
+    setns(fd, nstype)
+}
+
 fn ns_type(nstype: &str) -> Option<c_int> {
     match nstype {
         "pid" => Some(CLONE_NEWPID),
@@ -100,3 +183,69 @@ fn ns_type(nstype: &str) -> Option<c_int> {
         _ => None,
     }
 }
+
+/// Name of a namespace type under /proc/<pid>/ns
+fn proc_ns_name(nstype: &str) -> Option<&'static str> {
+    match nstype {
+        "pid" => Some("pid"),
+        "network" => Some("net"),
+        "mount" => Some("mnt"),
+        "ipc" => Some("ipc"),
+        "uts" => Some("uts"),
+        "user" => Some("user"),
+        "cgroup" => Some("cgroup"),
+        "time" => Some("time"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_resolve_container_paths() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let mut ctx = Ctx::default();
+        ctx.state_dir = PathBuf::from(format!("/tmp/resolve_container_paths_{}", time));
+        fs::create_dir_all(ctx.state_dir("sandbox")).unwrap();
+        let mut state = State::new(
+            String::from("sandbox"),
+            PathBuf::from("/bundle"),
+            String::from("1.0.1"),
+        );
+        state.set_pid(std::process::id());
+        state.update_status(Status::Running);
+        state.write(ctx.container_dirs("sandbox").state()).unwrap();
+
+        let mut namespaces: Vec<Namespace> = serde_json::from_value(json!([
+            {"type": "network", "path": "container:sandbox"},
+            {"type": "ipc", "path": "/proc/1/ns/ipc"},
+            {"type": "mount"},
+        ]))
+        .unwrap();
+        resolve_container_paths(&ctx, &mut namespaces).unwrap();
+        let net = format!("/proc/{}/ns/net", std::process::id());
+        assert_eq!(Some(net.as_str()), namespaces[0].path.as_deref());
+        assert_eq!(Some("/proc/1/ns/ipc"), namespaces[1].path.as_deref());
+        assert!(namespaces[2].path.is_none());
+
+        let mut missing: Vec<Namespace> =
+            serde_json::from_value(json!([{"type": "uts", "path": "container:nope"}])).unwrap();
+        assert!(resolve_container_paths(&ctx, &mut missing).is_err());
+
+        state.update_status(Status::Stopped);
+        state.write(ctx.container_dirs("sandbox").state()).unwrap();
+        let mut stopped: Vec<Namespace> =
+            serde_json::from_value(json!([{"type": "uts", "path": "container:sandbox"}])).unwrap();
+        assert!(resolve_container_paths(&ctx, &mut stopped).is_err());
+
+        fs::remove_dir_all(&ctx.state_dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+//! OOM kills in a container, watched by the monitor while the container is running.
+//!
+//! The kernel notifies changes of memory.events, so the monitor watches it with inotify
+//! and emits an `oom` event whenever its oom_kill count goes up, with the count so far.
+
+use crate::cgroup::read_oom_kills;
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use crate::events::{self, Event};
+use crate::syscalls;
+use libc::{IN_CLOEXEC, IN_MODIFY, IN_NONBLOCK};
+use log::{debug, warn};
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+/// How often the watcher looks for having been stopped, between changes.
+const STOP_INTERVAL_MS: i32 = 1000;
+
+/// Emits an event for every OOM kill in `cgroup`, until `stop` is dropped. Containers
+/// without the memory controller have no memory.events, nothing is watched then.
+pub fn run(dirs: ContainerDirs, container_id: String, cgroup: PathBuf, stop: Receiver<()>) {
+    if let Err(e) = watch(&dirs, &container_id, &cgroup, stop) {
+        warn!("failed to watch {} for oom kills: {:?}", container_id, e);
+    }
+}
+
+fn watch(
+    dirs: &ContainerDirs,
+    container_id: &str,
+    cgroup: &Path,
+    stop: Receiver<()>,
+) -> Result<(), ContainerErr> {
+    let Some(mut seen) = read_oom_kills(cgroup)? else {
+        debug!(
+            "{} has no memory controller, not watching for oom kills",
+            container_id
+        );
+        return Ok(());
+    };
+    let inotify = syscalls::inotify_init1(IN_CLOEXEC | IN_NONBLOCK).map_err(ContainerErr::IO)?;
+    syscalls::inotify_add_watch(
+        inotify.as_raw_fd(),
+        &cgroup.join("memory.events"),
+        IN_MODIFY,
+    )
+    .map_err(ContainerErr::IO)?;
+    let mut inotify = File::from(inotify);
+
+    loop {
+        let stopped = matches!(stop.try_recv(), Err(TryRecvError::Disconnected));
+        let mut fds = [libc::pollfd {
+            fd: inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        // Once stopped, a kill which came with the exit is still picked up below.
+        if !stopped {
+            match syscalls::poll(&mut fds, STOP_INTERVAL_MS) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ContainerErr::IO(e)),
+            }
+            if fds[0].revents == 0 {
+                continue;
+            }
+            drain(&mut inotify)?;
+        }
+        // The cgroup is removed once the container is deleted.
+        let kills = read_oom_kills(cgroup).ok().flatten().unwrap_or(seen);
+        if kills > seen {
+            debug!("{} oom kills in {}", kills - seen, container_id);
+            let event = Event::new("oom", container_id, json!({ "oom_kill": kills }));
+            events::emit(dirs, &event)?;
+            seen = kills;
+        }
+        if stopped {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the queued inotify events, only that there were any matters.
+fn drain(inotify: &mut File) -> Result<(), ContainerErr> {
+    let mut buf = [0u8; 1024];
+    loop {
+        match inotify.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(ContainerErr::IO(e)),
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Pods, groups of containers sharing a parent cgroup and their network, ipc and uts
+//! namespaces.
+//!
+//! The shared namespaces belong to a holder process the runtime starts when the pod is
+//! created, like the pause container of a kubernetes pod. It does nothing but keep the
+//! namespaces alive, so containers can come and go while the pod's network stays up. The
+//! holder lives in a `holder` cgroup below the pod's, next to the containers' cgroups,
+//! since a cgroup handing controllers to its children can't have processes itself.
+
+use crate::cgroup::{
+    detect_cgroup_version, enable_controllers, kill_cgroup, process_cgroup, remove_cgroup,
+    resolve_cgroup_path,
+};
+use crate::config::Config;
+use crate::ctx::{Ctx, STATE_DIR_MODE};
+use crate::error::ContainerErr;
+use crate::process::clone_into_cgroup;
+use crate::state::Pid;
+use crate::store::StateStore;
+use crate::syscalls;
+use libc::{CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWUTS};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, DirBuilder, File};
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const POD_FILENAME: &str = "pod.json";
+const HOLDER_CGROUP: &str = "holder";
+/// How long to wait for the holder's cgroup to empty once it's been killed.
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Namespaces shared by a pod's containers, by config type and name under /proc/<pid>/ns.
+const SHARED_NAMESPACES: [(&str, &str); 3] = [("network", "net"), ("ipc", "ipc"), ("uts", "uts")];
+
+/// A pod's state, in `pod.json` in its dir under the state dir.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pod {
+    name: String,
+    holder_pid: Pid,
+    cgroup_path: PathBuf,
+}
+
+impl Pod {
+    /// Creates the pod's cgroup and starts its holder process.
+    pub fn create(ctx: &Ctx, name: &str) -> Result<Self, ContainerErr> {
+        check_name(name)?;
+        let dir = ctx.pod_dir(name);
+        if let Some(parent) = dir.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(STATE_DIR_MODE)
+                .create(parent)
+                .map_err(ContainerErr::IO)?;
+        }
+        // Creating the dir reserves the name.
+        match DirBuilder::new().mode(STATE_DIR_MODE).create(&dir) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(ContainerErr::Pod(format!("pod {} already exists", name)))
+            }
+            result => result.map_err(ContainerErr::IO)?,
+        }
+
+        let mut pod = Self {
+            name: name.to_string(),
+            holder_pid: 0,
+            cgroup_path: ctx.cgroups_root().join(format!("pod-{}", name)),
+        };
+        let result = pod.start(ctx);
+        if let Err(e) = &result {
+            debug!("creating pod {} failed, cleaning up: {:?}", name, e);
+            if let Err(e) = pod.remove(ctx) {
+                warn!("failed to clean up pod {}: {:?}", name, e);
+            }
+        }
+        result.map(|_| pod)
+    }
+
+    fn start(&mut self, ctx: &Ctx) -> Result<(), ContainerErr> {
+        detect_cgroup_version(ctx.cgroups_root())?;
+        fs::create_dir(&self.cgroup_path).map_err(|e| {
+            ContainerErr::Cgroup(format!("failed to create {:?}: {}", self.cgroup_path, e))
+        })?;
+        enable_controllers(&self.cgroup_path)?;
+        let holder_cgroup = self.holder_cgroup();
+        fs::create_dir(&holder_cgroup).map_err(ContainerErr::IO)?;
+
+        log::logger().flush();
+        let pid = clone_into_cgroup(CLONE_NEWNET | CLONE_NEWIPC | CLONE_NEWUTS, &holder_cgroup)?;
+        if pid == 0 {
+            hold();
+        }
+        debug!("pod {} holder pid: {}", self.name, pid);
+        self.holder_pid = pid;
+        self.write(ctx)
+    }
+
+    /// Reads the state of the pod `name`.
+    pub fn load(ctx: &Ctx, name: &str) -> Result<Self, ContainerErr> {
+        check_name(name)?;
+        let f = File::open(ctx.pod_dir(name).join(POD_FILENAME)).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => ContainerErr::Pod(format!("no pod named {}", name)),
+            _ => ContainerErr::IO(e),
+        })?;
+        serde_json::from_reader(f).map_err(|e| ContainerErr::Pod(e.to_string()))
+    }
+
+    fn write(&self, ctx: &Ctx) -> Result<(), ContainerErr> {
+        let raw = serde_json::to_string(self).map_err(|e| ContainerErr::Pod(e.to_string()))?;
+        fs::write(ctx.pod_dir(&self.name).join(POD_FILENAME), raw).map_err(ContainerErr::IO)
+    }
+
+    /// Stops the holder and removes the pod's cgroup and state. Fails while the pod
+    /// still has containers.
+    pub fn delete(self, ctx: &Ctx) -> Result<(), ContainerErr> {
+        let containers = self.containers(ctx)?;
+        if !containers.is_empty() {
+            return Err(ContainerErr::Pod(format!(
+                "pod {} still has containers: {}",
+                self.name,
+                containers.join(", ")
+            )));
+        }
+        self.remove(ctx)
+    }
+
+    fn remove(&self, ctx: &Ctx) -> Result<(), ContainerErr> {
+        let holder_cgroup = self.holder_cgroup();
+        if fs::metadata(&holder_cgroup).is_ok() {
+            kill_cgroup(&holder_cgroup)?;
+            remove_cgroup(&holder_cgroup, REMOVE_TIMEOUT)?;
+        }
+        remove_cgroup(&self.cgroup_path, REMOVE_TIMEOUT)?;
+        match fs::remove_dir_all(ctx.pod_dir(&self.name)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(ContainerErr::IO(e)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn holder_cgroup(&self) -> PathBuf {
+        self.cgroup_path.join(HOLDER_CGROUP)
+    }
+
+    /// Whether the holder is still around. Its pid may have been reused if it died, so
+    /// the process has to be in the holder's cgroup too.
+    pub fn is_running(&self, ctx: &Ctx) -> bool {
+        process_cgroup(self.holder_pid, ctx.cgroups_root())
+            .is_ok_and(|cgroup| cgroup == self.holder_cgroup())
+    }
+
+    /// Ids of the containers created in the pod.
+    pub fn containers(&self, ctx: &Ctx) -> Result<Vec<String>, ContainerErr> {
+        let states = ctx.store().list()?;
+        Ok(states
+            .iter()
+            .filter(|state| state.pod() == Some(self.name.as_str()))
+            .map(|state| state.id().to_string())
+            .collect())
+    }
+
+    /// The cgroup of a container in the pod, cgroupsPath is taken relative to the pod's
+    /// cgroup rather than the cgroup mount.
+    pub fn container_cgroup_path(&self, config: &Config, container_id: &str) -> PathBuf {
+        resolve_cgroup_path(
+            config.cgroups_path().map(Path::new),
+            self.cgroup_path.as_path(),
+            container_id,
+        )
+    }
+
+    /// Points the config's network, ipc and uts namespaces at the holder's. Namespaces the
+    /// config already joins by path conflict with the pod's.
+    pub fn share_namespaces(&self, config: &mut Config) -> Result<(), ContainerErr> {
+        for (typ, name) in SHARED_NAMESPACES {
+            let joined = config
+                .linux_namespaces()
+                .into_iter()
+                .flatten()
+                .any(|ns| ns.typ == typ && ns.path.is_some());
+            if joined {
+                return Err(ContainerErr::Pod(format!(
+                    "the {} namespace is shared by pod {}, it can't have a path",
+                    typ, self.name
+                )));
+            }
+            config.set_namespace_path(typ, format!("/proc/{}/ns/{}", self.holder_pid, name));
+        }
+        Ok(())
+    }
+}
+
+/// Pod names end up in paths, keep them to something which can't escape the state dir.
+fn check_name(name: &str) -> Result<(), ContainerErr> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(ContainerErr::Pod(format!("invalid pod name: {:?}", name)));
+    }
+    Ok(())
+}
+
+/// Runs in the holder process, which only has to stay alive until the pod is deleted.
+fn hold() -> ! {
+    // Don't go away with the caller's terminal or keep its descriptors open.
+    let _ = syscalls::setsid();
+    if let Ok(null) = File::open("/dev/null") {
+        for fd in 0..=2 {
+            let _ = syscalls::dup2(null.as_raw_fd(), fd);
+        }
+    }
+    let _ = syscalls::close_range(3, libc::c_uint::MAX, 0);
+    loop {
+        unsafe { libc::pause() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pod() -> Pod {
+        Pod {
+            name: String::from("web"),
+            holder_pid: 42,
+            cgroup_path: PathBuf::from("/sys/fs/cgroup/pod-web"),
+        }
+    }
+
+    #[test]
+    fn test_share_namespaces() {
+        let mut config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "linux": {"namespaces": [{"type": "mount"}, {"type": "network"}]},
+        }))
+        .unwrap();
+        pod().share_namespaces(&mut config).unwrap();
+        let namespaces = config.linux_namespaces().unwrap();
+        let path = |typ: &str| {
+            let ns = namespaces.iter().find(|ns| ns.typ == typ).unwrap();
+            ns.path.clone()
+        };
+        assert_eq!(None, path("mount"));
+        assert_eq!(Some(String::from("/proc/42/ns/net")), path("network"));
+        assert_eq!(Some(String::from("/proc/42/ns/uts")), path("uts"));
+        assert_eq!(4, namespaces.len());
+
+        let mut config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "linux": {"namespaces": [{"type": "ipc", "path": "/proc/1/ns/ipc"}]},
+        }))
+        .unwrap();
+        assert!(pod().share_namespaces(&mut config).is_err());
+    }
+
+    #[test]
+    fn test_container_cgroup_path() {
+        let config: Config = serde_json::from_value(json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+            "linux": {"namespaces": [], "cgroupsPath": "/app"},
+        }))
+        .unwrap();
+        assert_eq!(
+            PathBuf::from("/sys/fs/cgroup/pod-web/app"),
+            pod().container_cgroup_path(&config, "c1")
+        );
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("web-1.example").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("..").is_err());
+        assert!(check_name("a/b").is_err());
+    }
+}
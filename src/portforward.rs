@@ -0,0 +1,443 @@
+//! Port forwarding from the host into a container's network namespace.
+//!
+//! When the runtime runs as root, published ports are implemented with iptables DNAT
+//! rules that point at the container's IPv4 address. Rootless containers can't touch the
+//! host's netfilter tables, so instead a small forwarder process is forked which listens
+//! on the host port and proxies connections into the container's network namespace.
+
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use crate::state::Pid;
+use crate::syscalls::setns;
+use libc::{fork, geteuid, kill, CLONE_NEWNET, CLONE_NEWUSER, SIGTERM};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::process::Command;
+use std::str::FromStr;
+use std::thread;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+/// A host port mapped to a port inside the container.
+/// Parsed from `host:container[/protocol]`, e.g. `8080:80` or `5353:53/udp`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortMapping {
+    pub host_port: u16,
+    pub container_port: u16,
+    pub protocol: Protocol,
+}
+
+impl FromStr for PortMapping {
+    type Err = ContainerErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ContainerErr::PortForward(format!("invalid port mapping: {}", s));
+
+        let (ports, protocol) = match s.split_once('/') {
+            Some((ports, "tcp")) => (ports, Protocol::Tcp),
+            Some((ports, "udp")) => (ports, Protocol::Udp),
+            Some(_) => return Err(invalid()),
+            None => (s, Protocol::Tcp),
+        };
+
+        let (host, container) = ports.split_once(':').ok_or_else(invalid)?;
+        let host_port = host.parse::<u16>().map_err(|_| invalid())?;
+        let container_port = container.parse::<u16>().map_err(|_| invalid())?;
+        if host_port == 0 || container_port == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            host_port,
+            container_port,
+            protocol,
+        })
+    }
+}
+
+/// A port mapping which has been set up on the host and needs to be torn down on delete.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Forward {
+    /// iptables DNAT rules pointing at the container's address.
+    Dnat {
+        mapping: PortMapping,
+        container_ip: Ipv4Addr,
+    },
+    /// Userspace forwarder process (rootless mode).
+    Proxy { mapping: PortMapping, pid: Pid },
+}
+
+/// Contents of <state_dir>/<container_id>/ports.json
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PortForwards {
+    pub mappings: Vec<PortMapping>,
+    pub active: Vec<Forward>,
+}
+
+impl PortForwards {
+    /// Collects the ports to publish from the command line and the publish annotation.
+    pub fn from_requested(
+        publish: &[String],
+        annotated: &[PortMapping],
+    ) -> Result<Self, ContainerErr> {
+        let mut mappings = Vec::new();
+        let requested = publish
+            .iter()
+            .map(|spec| spec.parse::<PortMapping>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for mapping in requested.into_iter().chain(annotated.iter().cloned()) {
+            if mappings.iter().any(|m: &PortMapping| {
+                m.host_port == mapping.host_port && m.protocol == mapping.protocol
+            }) {
+                return Err(ContainerErr::PortForward(format!(
+                    "host port {}/{} published more than once",
+                    mapping.host_port,
+                    mapping.protocol.as_str()
+                )));
+            }
+            mappings.push(mapping);
+        }
+
+        Ok(Self {
+            mappings,
+            active: Vec::new(),
+        })
+    }
+
+    pub fn load(dirs: &ContainerDirs) -> Result<Option<Self>, ContainerErr> {
+        let path = dirs.ports();
+        if fs::metadata(&path).is_err() {
+            return Ok(None);
+        }
+        let f = File::open(path).map_err(ContainerErr::IO)?;
+        let forwards =
+            serde_json::from_reader(f).map_err(|e| ContainerErr::PortForward(e.to_string()))?;
+        Ok(Some(forwards))
+    }
+
+    pub fn write(&self, dirs: &ContainerDirs) -> Result<(), ContainerErr> {
+        let raw =
+            serde_json::to_string(self).map_err(|e| ContainerErr::PortForward(e.to_string()))?;
+        let mut f = OpenOptions::new()
+            .truncate(true)
+            .create(true)
+            .write(true)
+            .open(dirs.ports())
+            .map_err(ContainerErr::IO)?;
+        f.write_all(raw.as_bytes()).map_err(ContainerErr::IO)?;
+        Ok(())
+    }
+
+    /// Sets up forwarding for every requested mapping to the container process `pid`.
+    pub fn apply(&mut self, pid: Pid) -> Result<(), ContainerErr> {
+        if self.mappings.is_empty() {
+            return Ok(());
+        }
+
+        let rootless = unsafe { geteuid() } != 0;
+        let container_ip = if rootless {
+            None
+        } else {
+            Some(container_ipv4(pid)?)
+        };
+
+        for mapping in self.mappings.clone() {
+            let forward = match container_ip {
+                Some(ip) => {
+                    add_dnat(&mapping, ip)?;
+                    Forward::Dnat {
+                        mapping,
+                        container_ip: ip,
+                    }
+                }
+                None => {
+                    let proxy_pid = spawn_proxy(&mapping, pid)?;
+                    Forward::Proxy {
+                        mapping,
+                        pid: proxy_pid,
+                    }
+                }
+            };
+            debug!("port forward active: {:?}", forward);
+            self.active.push(forward);
+        }
+
+        Ok(())
+    }
+
+    /// Removes any forwarding set up by `apply`. Errors are logged and skipped so a
+    /// missing rule doesn't prevent the rest of the cleanup.
+    pub fn teardown(&mut self) {
+        for forward in self.active.drain(..) {
+            debug!("removing port forward: {:?}", forward);
+            match forward {
+                Forward::Dnat {
+                    mapping,
+                    container_ip,
+                } => {
+                    if let Err(e) = delete_dnat(&mapping, container_ip) {
+                        warn!("failed to remove port forward {:?}: {:?}", mapping, e);
+                    }
+                }
+                Forward::Proxy { pid, .. } => unsafe {
+                    kill(pid as i32, SIGTERM);
+                },
+            }
+        }
+    }
+}
+
+/// The chains a mapping has DNAT rules in, PREROUTING for external traffic and OUTPUT
+/// for connections from the host itself.
+const DNAT_CHAINS: [&str; 2] = ["PREROUTING", "OUTPUT"];
+
+/// Adds the DNAT rules for a single mapping. If one of them can't be added, the ones
+/// which were are deleted again.
+fn add_dnat(mapping: &PortMapping, ip: Ipv4Addr) -> Result<(), ContainerErr> {
+    for (i, chain) in DNAT_CHAINS.iter().enumerate() {
+        if let Err(e) = iptables_dnat("-A", chain, mapping, ip) {
+            for added in &DNAT_CHAINS[..i] {
+                if let Err(e) = iptables_dnat("-D", added, mapping, ip) {
+                    warn!("failed to remove port forward {:?}: {:?}", mapping, e);
+                }
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the DNAT rules for a single mapping, from every chain even if one fails.
+/// Returns the first error.
+fn delete_dnat(mapping: &PortMapping, ip: Ipv4Addr) -> Result<(), ContainerErr> {
+    let mut result = Ok(());
+    for chain in DNAT_CHAINS {
+        if let Err(e) = iptables_dnat("-D", chain, mapping, ip) {
+            result = result.and(Err(e));
+        }
+    }
+    result
+}
+
+/// Adds (`-A`) or deletes (`-D`) the DNAT rule for a single mapping in `chain`.
+fn iptables_dnat(
+    op: &str,
+    chain: &str,
+    mapping: &PortMapping,
+    ip: Ipv4Addr,
+) -> Result<(), ContainerErr> {
+    let dport = mapping.host_port.to_string();
+    let destination = format!("{}:{}", ip, mapping.container_port);
+
+    let mut cmd = Command::new("iptables");
+    cmd.args(["-t", "nat", op, chain, "-p", mapping.protocol.as_str()]);
+    if chain == "OUTPUT" {
+        cmd.args(["-m", "addrtype", "--dst-type", "LOCAL"]);
+    }
+    cmd.args([
+        "--dport",
+        &dport,
+        "-j",
+        "DNAT",
+        "--to-destination",
+        &destination,
+    ]);
+
+    let status = cmd
+        .status()
+        .map_err(|e| ContainerErr::PortForward(format!("failed to run iptables: {}", e)))?;
+    if !status.success() {
+        return Err(ContainerErr::PortForward(format!(
+            "iptables {} {} for {:?} failed: {}",
+            op, chain, mapping, status
+        )));
+    }
+    Ok(())
+}
+
+/// Finds the first non-loopback IPv4 address in the network namespace of `pid`.
+fn container_ipv4(pid: Pid) -> Result<Ipv4Addr, ContainerErr> {
+    let fib_trie =
+        fs::read_to_string(format!("/proc/{}/net/fib_trie", pid)).map_err(ContainerErr::IO)?;
+    parse_local_addrs(&fib_trie)
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            ContainerErr::PortForward(String::from(
+                "container network namespace has no IPv4 address",
+            ))
+        })
+}
+
+/// Extracts local, non-loopback addresses from /proc/net/fib_trie.
+///
+/// Example file data:
+///
+///   |-- 10.0.0.2
+///      /32 host LOCAL
+///
+fn parse_local_addrs(fib_trie: &str) -> Vec<Ipv4Addr> {
+    let mut addrs = Vec::new();
+    let mut last_addr = None;
+
+    for line in fib_trie.lines() {
+        let line = line.trim();
+        if let Some(addr) = line.strip_prefix("|-- ") {
+            last_addr = addr.parse::<Ipv4Addr>().ok();
+        } else if line.starts_with("/32 host LOCAL") {
+            if let Some(addr) = last_addr {
+                if !addr.is_loopback() && !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+    }
+
+    addrs
+}
+
+/// Forks a process listening on the host port which joins the container's network
+/// namespace and proxies each connection to the container port.
+fn spawn_proxy(mapping: &PortMapping, pid: Pid) -> Result<Pid, ContainerErr> {
+    if mapping.protocol != Protocol::Tcp {
+        return Err(ContainerErr::PortForward(format!(
+            "rootless port forwarding only supports tcp: {:?}",
+            mapping
+        )));
+    }
+
+    // Bind while still in the host network namespace, the socket keeps it after setns.
+    let listener = TcpListener::bind(("0.0.0.0", mapping.host_port)).map_err(ContainerErr::IO)?;
+    let user_ns = File::open(format!("/proc/{}/ns/user", pid)).map_err(ContainerErr::IO)?;
+    let net_ns = File::open(format!("/proc/{}/ns/net", pid)).map_err(ContainerErr::IO)?;
+
+    let proxy_pid = unsafe { fork() };
+    if proxy_pid == -1 {
+        return Err(ContainerErr::PortForward(format!(
+            "fork failed: {}",
+            io::Error::last_os_error()
+        )));
+    }
+
+    if proxy_pid == 0 {
+        // Joining the user namespace fails with EINVAL if we're already in it, which is
+        // fine, we only need it for privileges over the network namespace.
+        let _ = setns(user_ns.as_raw_fd(), CLONE_NEWUSER);
+        if setns(net_ns.as_raw_fd(), CLONE_NEWNET).is_err() {
+            std::process::exit(1);
+        }
+        proxy(listener, mapping.container_port);
+    }
+
+    Ok(proxy_pid as Pid)
+}
+
+/// Accept loop for the forwarder process. Never returns.
+fn proxy(listener: TcpListener, container_port: u16) -> ! {
+    for conn in listener.incoming() {
+        let Ok(client) = conn else {
+            continue;
+        };
+        let Ok(upstream) = TcpStream::connect((Ipv4Addr::LOCALHOST, container_port)) else {
+            let _ = client.shutdown(Shutdown::Both);
+            continue;
+        };
+        thread::spawn(move || splice(client, upstream));
+    }
+    std::process::exit(0);
+}
+
+/// Copies data in both directions until either side closes.
+fn splice(client: TcpStream, upstream: TcpStream) {
+    let (Ok(mut client_rd), Ok(mut upstream_rd)) = (client.try_clone(), upstream.try_clone())
+    else {
+        return;
+    };
+    let (mut client_wr, mut upstream_wr) = (client, upstream);
+
+    let to_upstream = thread::spawn(move || {
+        let _ = io::copy(&mut client_rd, &mut upstream_wr);
+        let _ = upstream_wr.shutdown(Shutdown::Write);
+    });
+    let _ = io::copy(&mut upstream_rd, &mut client_wr);
+    let _ = client_wr.shutdown(Shutdown::Write);
+    let _ = to_upstream.join();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_port_mapping() {
+        let mapping = "8080:80".parse::<PortMapping>().unwrap();
+        assert_eq!(
+            PortMapping {
+                host_port: 8080,
+                container_port: 80,
+                protocol: Protocol::Tcp
+            },
+            mapping
+        );
+
+        let mapping = "5353:53/udp".parse::<PortMapping>().unwrap();
+        assert_eq!(Protocol::Udp, mapping.protocol);
+
+        assert!("8080".parse::<PortMapping>().is_err());
+        assert!("0:80".parse::<PortMapping>().is_err());
+        assert!("8080:80/sctp".parse::<PortMapping>().is_err());
+        assert!("70000:80".parse::<PortMapping>().is_err());
+    }
+
+    #[test]
+    fn test_duplicate_host_ports() {
+        let annotated = ["8080:81".parse().unwrap()];
+        let result = PortForwards::from_requested(&[String::from("8080:80")], &annotated);
+        assert!(result.is_err());
+
+        let annotated = ["8080:81/udp".parse().unwrap(), "9090:90".parse().unwrap()];
+        let forwards =
+            PortForwards::from_requested(&[String::from("8080:80")], &annotated).unwrap();
+        assert_eq!(3, forwards.mappings.len());
+    }
+
+    #[test]
+    fn test_parse_local_addrs() {
+        let data = "Main:
+  +-- 0.0.0.0/0 3 0 5
+     +-- 10.0.0.0/24 2 0 2
+        |-- 10.0.0.2
+           /32 host LOCAL
+     +-- 127.0.0.0/8 2 0 2
+        |-- 127.0.0.1
+           /32 host LOCAL
+        |-- 127.255.255.255
+           /32 link BROADCAST
+Local:
+  +-- 0.0.0.0/0 3 0 5
+        |-- 10.0.0.2
+           /32 host LOCAL
+";
+        assert_eq!(vec![Ipv4Addr::new(10, 0, 0, 2)], parse_local_addrs(data));
+    }
+}
@@ -0,0 +1,244 @@
+//! Dropping the container process' privileges: its user, groups, capability sets,
+//! no_new_privs and the seccomp filter.
+//!
+//! The order matters, and most wrong orders fail silently by leaving the process with
+//! more than it was configured with:
+//!
+//! - The bounding set is reduced first, that needs CAP_SETPCAP which we lose when
+//!   switching to a non-root user.
+//! - PR_SET_KEEPCAPS keeps the permitted set across setuid, otherwise it's cleared and
+//!   the configured sets can't be applied afterwards.
+//! - Groups before the gid before the uid, once the uid changes we can't change the
+//!   others anymore.
+//! - capset after setuid, since setuid to a non-root user clears the effective set.
+//! - Ambient capabilities last of the capabilities, they must be permitted and
+//!   inheritable already.
+//! - The seccomp filter needs either no_new_privs or CAP_SYS_ADMIN. With no_new_privs it
+//!   goes in last, so the profile doesn't have to allow the calls above. Without, it has
+//!   to go in before setuid gives up CAP_SYS_ADMIN, like runc does.
+//!
+//! The init and exec'd processes are single-threaded when they get here, so there are no
+//! other threads for the filter to be synchronized to with SECCOMP_FILTER_FLAG_TSYNC.
+
+use crate::config::{Capability, LinuxCapabilities, LinuxSeccomp, Process};
+use crate::error::ContainerErr;
+use crate::seccomp;
+use crate::syscalls::{capset, prctl, setgid, setgroups, setuid};
+use libc::{
+    c_ulong, gid_t, PR_CAPBSET_DROP, PR_CAP_AMBIENT, PR_CAP_AMBIENT_CLEAR_ALL,
+    PR_CAP_AMBIENT_RAISE, PR_SET_KEEPCAPS, PR_SET_NO_NEW_PRIVS,
+};
+use log::debug;
+use std::fs;
+
+/// One step of dropping privileges, see the module docs for why they're in this order.
+#[derive(Debug)]
+enum Step<'a> {
+    DropBounding(&'a [Capability]),
+    KeepCaps,
+    SetGroups(&'a [isize]),
+    SetGid(isize),
+    SetUid(isize),
+    SetCapabilities(&'a LinuxCapabilities),
+    RaiseAmbient(&'a [Capability]),
+    NoNewPrivs,
+    Seccomp(&'a LinuxSeccomp),
+}
+
+/// Drops the calling process' privileges to those of `process`, and installs `seccomp`
+/// if there is a profile. The calling process is about to exec the container process.
+pub fn drop(process: &Process, seccomp: Option<&LinuxSeccomp>) -> Result<(), ContainerErr> {
+    for step in plan(process, seccomp) {
+        debug!("privileges: {:?}", step);
+        step.run()?;
+    }
+    Ok(())
+}
+
+fn plan<'a>(process: &'a Process, seccomp: Option<&'a LinuxSeccomp>) -> Vec<Step<'a>> {
+    let caps = process.capabilities.as_ref();
+    let user = &process.user;
+    let mut steps = Vec::new();
+
+    if let Some(bounding) = caps.and_then(|caps| caps.bounding.as_deref()) {
+        steps.push(Step::DropBounding(bounding));
+    }
+    if let Some(seccomp) = seccomp.filter(|_| !process.no_new_privileges) {
+        steps.push(Step::Seccomp(seccomp));
+    }
+    if caps.is_some() {
+        steps.push(Step::KeepCaps);
+    }
+    steps.push(Step::SetGroups(
+        user.additional_gids.as_deref().unwrap_or(&[]),
+    ));
+    steps.push(Step::SetGid(user.gid));
+    steps.push(Step::SetUid(user.uid));
+    if let Some(caps) = caps {
+        steps.push(Step::SetCapabilities(caps));
+        steps.push(Step::RaiseAmbient(caps.ambient.as_deref().unwrap_or(&[])));
+    }
+    if process.no_new_privileges {
+        steps.push(Step::NoNewPrivs);
+        if let Some(seccomp) = seccomp {
+            steps.push(Step::Seccomp(seccomp));
+        }
+    }
+    steps
+}
+
+impl Step<'_> {
+    fn run(&self) -> Result<(), ContainerErr> {
+        let caps_err = |e: std::io::Error| ContainerErr::Capabilities(e.to_string());
+        match *self {
+            Step::DropBounding(bounding) => drop_bounding(bounding),
+            Step::KeepCaps => prctl(PR_SET_KEEPCAPS, 1, 0).map_err(caps_err),
+            Step::SetGroups(gids) => set_groups(gids),
+            Step::SetGid(gid) => setgid(gid as gid_t).map_err(ContainerErr::IO),
+            Step::SetUid(uid) => setuid(uid as libc::uid_t).map_err(ContainerErr::IO),
+            Step::SetCapabilities(caps) => {
+                let set = |caps: &Option<Vec<Capability>>| caps.as_deref().map_or(0, mask);
+                capset(
+                    set(&caps.effective),
+                    set(&caps.permitted),
+                    set(&caps.inheritable),
+                )
+                .map_err(caps_err)
+            }
+            Step::RaiseAmbient(ambient) => raise_ambient(ambient),
+            Step::NoNewPrivs => prctl(PR_SET_NO_NEW_PRIVS, 1, 0).map_err(caps_err),
+            Step::Seccomp(profile) => seccomp::install(profile),
+        }
+    }
+}
+
+fn drop_bounding(bounding: &[Capability]) -> Result<(), ContainerErr> {
+    let keep = mask(bounding);
+    for cap in 0..=last_cap() {
+        if keep & (1 << cap) == 0 {
+            prctl(PR_CAPBSET_DROP, cap as c_ulong, 0)
+                .map_err(|e| ContainerErr::Capabilities(e.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the runtime's supplementary groups with `gids`. Inside a user namespace with
+/// setgroups denied clearing them fails with EPERM, which is only an error if the process
+/// asked for groups.
+fn set_groups(gids: &[isize]) -> Result<(), ContainerErr> {
+    let gids: Vec<gid_t> = gids.iter().map(|gid| *gid as gid_t).collect();
+    match setgroups(&gids) {
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) && gids.is_empty() => {
+            debug!("not clearing supplementary groups: {}", e);
+            Ok(())
+        }
+        result => result.map_err(ContainerErr::IO),
+    }
+}
+
+/// Ambient capabilities must also be permitted and inheritable, capset checked those.
+fn raise_ambient(ambient: &[Capability]) -> Result<(), ContainerErr> {
+    prctl(PR_CAP_AMBIENT, PR_CAP_AMBIENT_CLEAR_ALL as c_ulong, 0)
+        .map_err(|e| ContainerErr::Capabilities(e.to_string()))?;
+    for cap in ambient {
+        prctl(
+            PR_CAP_AMBIENT,
+            PR_CAP_AMBIENT_RAISE as c_ulong,
+            *cap as c_ulong,
+        )
+        .map_err(|e| ContainerErr::Capabilities(format!("{}: {}", cap, e)))?;
+    }
+    Ok(())
+}
+
+fn mask(caps: &[Capability]) -> u64 {
+    caps.iter().fold(0, |mask, cap| mask | (1 << *cap as u8))
+}
+
+/// Highest capability number the running kernel knows about. Capabilities newer than the
+/// kernel can't be dropped from the bounding set.
+fn last_cap() -> u8 {
+    fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(Capability::CheckpointRestore as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn names(process: &Process, seccomp: Option<&LinuxSeccomp>) -> Vec<String> {
+        plan(process, seccomp)
+            .iter()
+            .map(|step| {
+                let step = format!("{:?}", step);
+                step.split('(').next().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(0, mask(&[]));
+        assert_eq!(
+            (1 << 5) | (1 << 40),
+            mask(&[Capability::Kill, Capability::CheckpointRestore])
+        );
+    }
+
+    #[test]
+    fn test_plan() {
+        let mut process: Process = serde_json::from_value(json!({
+            "cwd": "/",
+            "user": {"uid": 1000, "gid": 1000, "additionalGids": [10]},
+            "capabilities": {
+                "bounding": ["CAP_KILL"],
+                "permitted": ["CAP_KILL"],
+                "inheritable": ["CAP_KILL"],
+                "ambient": ["CAP_KILL"],
+            },
+            "noNewPrivileges": true,
+        }))
+        .unwrap();
+        let seccomp = LinuxSeccomp::new("SCMP_ACT_ALLOW");
+
+        assert_eq!(
+            vec![
+                "DropBounding",
+                "KeepCaps",
+                "SetGroups",
+                "SetGid",
+                "SetUid",
+                "SetCapabilities",
+                "RaiseAmbient",
+                "NoNewPrivs",
+                "Seccomp",
+            ],
+            names(&process, Some(&seccomp))
+        );
+
+        // Without no_new_privs the filter needs CAP_SYS_ADMIN, so it can't wait until
+        // after setuid.
+        process.no_new_privileges = false;
+        assert_eq!(
+            vec![
+                "DropBounding",
+                "Seccomp",
+                "KeepCaps",
+                "SetGroups",
+                "SetGid",
+                "SetUid",
+                "SetCapabilities",
+                "RaiseAmbient",
+            ],
+            names(&process, Some(&seccomp))
+        );
+
+        // The user is always switched, even without capabilities or a profile.
+        process.capabilities = None;
+        assert_eq!(vec!["SetGroups", "SetGid", "SetUid"], names(&process, None));
+    }
+}
@@ -1,60 +1,442 @@
 //! Module for manipulating a container process.
 
-use crate::{config::Config, error::ContainerErr, state::Pid};
-use libc::{c_int, clone_args, syscall, SYS_clone3, __errno_location, CLONE_INTO_CGROUP, SIG_IGN};
+use crate::{
+    apparmor::set_exec_profile,
+    config::{LinuxSeccomp, Process},
+    error::ContainerErr,
+    features::{self, Feature},
+    ioprio::set_iopriority,
+    landlock::apply_landlock,
+    privileges, procfs,
+    rlimit::set_rlimits,
+    sched::set_scheduler,
+    signal,
+    state::Pid,
+    syscalls,
+};
+use libc::{
+    c_int, c_ulong, clone_args, pid_t, syscall, CLONE_INTO_CGROUP, CLONE_NEWCGROUP, O_DIRECTORY,
+    O_PATH, SIGCHLD, SIGKILL,
+};
 use log::debug;
-use std::{env::set_var, os::fd::RawFd};
-
-/// Populates the environment of the current process from the config
-pub fn populate_env(cfg: &Config) {
-    if let Some(vars) = &cfg.process().env {
-        for env_var in vars {
-            let parts: Vec<_> = env_var.split("=").collect();
-            if parts.len() == 2 {
-                debug!("setting {} = {}", parts[0], parts[1]);
-                set_var(parts[0], parts[1])
-            }
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Applies the per-process settings of `process` to the calling process, which is about
+/// to exec it. Shared by the container's init and exec'd processes. Privileges are dropped
+/// last, together with installing the seccomp filter, see `privileges`.
+pub fn apply_process_spec(
+    process: &Process,
+    seccomp: Option<&LinuxSeccomp>,
+) -> Result<(), ContainerErr> {
+    signal::reset_signals()?;
+    set_rlimits(process)?;
+    set_iopriority(process)?;
+    set_scheduler(process)?;
+    if let Some(adj) = process.oom_score_adj {
+        procfs::write("/proc/self/oom_score_adj", &adj.to_string())?;
+    }
+    if let Some(profile) = &process.apparmor_profile {
+        set_exec_profile(profile)?;
+    }
+    // While we still have CAP_SYS_ADMIN, which enforcing it needs without no_new_privs.
+    if let Some(landlock) = &process.landlock {
+        apply_landlock(landlock)?;
+    }
+    privileges::drop(process, seccomp)
+}
+
+/// Builds the environment for the container process from process.env. The runtime's own
+/// environment is never inherited or modified. Entries are `KEY=VALUE` and only split on
+/// the first '=', so values may contain '='. If a key is repeated the last value wins.
+pub fn build_env(process: &Process) -> Result<Vec<CString>, ContainerErr> {
+    let mut vars: Vec<(&str, &str)> = Vec::new();
+    for var in process.env.iter().flatten() {
+        let Some((key, value)) = var.split_once('=').filter(|(key, _)| !key.is_empty()) else {
+            return Err(ContainerErr::Entrypoint(format!(
+                "invalid environment variable: {:?}",
+                var
+            )));
+        };
+        match vars.iter_mut().find(|(k, _)| *k == key) {
+            Some(existing) => existing.1 = value,
+            None => vars.push((key, value)),
         }
     }
+
+    vars.into_iter()
+        .map(|(key, value)| to_cstring(format!("{}={}", key, value)))
+        .collect()
 }
 
-/// Clears the current processes' environment.
-/// All safety conditions from `std::env::remove_var` apply here.
-/// See [remove_var docs](https://doc.rust-lang.org/stable/std/env/fn.remove_var.html) for details.
-pub fn clear_env() {
-    for pair in std::env::args() {
-        let parts = pair.split("=").collect::<Vec<_>>();
-        if parts.len() == 2 {
-            let key = parts[0];
-            debug!("delete env var: {} = {}", key, parts[1]);
-            unsafe { std::env::remove_var(key) }
+/// Adds PATH, TERM when the process has a terminal, and HOSTNAME to process.env unless
+/// it already sets them, like other runtimes do. `hostname` is the container's, the
+/// configured one or its id.
+pub fn add_default_env(process: &mut Process, hostname: &str) {
+    let mut defaults = vec![("PATH", DEFAULT_PATH), ("HOSTNAME", hostname)];
+    if process.terminal {
+        defaults.push(("TERM", "xterm"));
+    }
+    let env = process.env.get_or_insert_with(Vec::new);
+    for (key, value) in defaults {
+        let set = env
+            .iter()
+            .any(|var| var.split_once('=').is_some_and(|(k, _)| k == key));
+        if !set {
+            env.push(format!("{}={}", key, value));
         }
     }
 }
 
-/// Wrapper for the clone3 syscall
-pub fn clone3(flags: c_int, cgroup_fd: RawFd) -> Result<Pid, ContainerErr> {
+/// Builds the argv for the container process from process.args.
+pub fn build_args(process: &Process) -> Result<Vec<CString>, ContainerErr> {
+    match &process.args {
+        Some(args) if !args.is_empty() => args.iter().map(to_cstring).collect(),
+        _ => Err(ContainerErr::Entrypoint(String::from(
+            "process.args is empty",
+        ))),
+    }
+}
+
+fn to_cstring<S: AsRef<str>>(s: S) -> Result<CString, ContainerErr> {
+    CString::new(s.as_ref())
+        .map_err(|_| ContainerErr::Entrypoint(format!("contains a NUL byte: {:?}", s.as_ref())))
+}
+
+/// PATH used to look up the entrypoint when process.env doesn't set one.
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Maximum number of symlinks followed while resolving a path inside the rootfs.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolves `process.args[0]` to an executable inside the container rootfs, the same way
+/// execvp would once the container is running: names containing a '/' are relative to
+/// the process cwd, anything else is searched for in PATH. Returns the host path.
+pub fn find_executable<P: AsRef<Path>>(
+    rootfs: P,
+    process: &Process,
+) -> Result<PathBuf, ContainerErr> {
+    let Some(arg0) = process.args.as_ref().and_then(|args| args.first()) else {
+        return Err(ContainerErr::Entrypoint(String::from(
+            "process.args is empty",
+        )));
+    };
+
+    let candidates = if arg0.contains('/') {
+        vec![Path::new(&process.cwd).join(arg0)]
+    } else {
+        let path_var = process
+            .env
+            .iter()
+            .flatten()
+            .find_map(|var| var.strip_prefix("PATH="))
+            .unwrap_or(DEFAULT_PATH);
+        path_var
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(&process.cwd).join(dir).join(arg0))
+            .collect()
+    };
+
+    for candidate in candidates {
+        let Ok(host_path) = resolve_in_root(rootfs.as_ref(), &candidate) else {
+            continue;
+        };
+        let Ok(meta) = std::fs::metadata(&host_path) else {
+            continue;
+        };
+        if meta.is_file() && meta.permissions().mode() & 0o111 != 0 {
+            debug!("entrypoint {} resolved to {:?}", arg0, host_path);
+            return Ok(host_path);
+        }
+    }
+
+    Err(ContainerErr::Entrypoint(format!(
+        "executable not found in rootfs: {}",
+        arg0
+    )))
+}
+
+/// Resolves `path` as if `root` was '/': '..' can't escape the root and absolute symlinks
+/// are followed relative to it rather than the host.
+fn resolve_in_root(root: &Path, path: &Path) -> Result<PathBuf, ContainerErr> {
+    let mut resolved = PathBuf::from("/");
+    let mut pending: Vec<PathBuf> = path
+        .components()
+        .rev()
+        .map(|c| PathBuf::from(c.as_os_str()))
+        .collect();
+    let mut symlinks = 0;
+
+    while let Some(next) = pending.pop() {
+        match next.components().next() {
+            Some(Component::RootDir) => resolved = PathBuf::from("/"),
+            Some(Component::ParentDir) => {
+                resolved.pop();
+            }
+            Some(Component::Normal(name)) => {
+                let candidate = resolved.join(name);
+                let host_path = root.join(candidate.strip_prefix("/").unwrap());
+                match std::fs::read_link(&host_path) {
+                    Ok(target) => {
+                        symlinks += 1;
+                        if symlinks > MAX_SYMLINKS {
+                            return Err(ContainerErr::Entrypoint(format!(
+                                "too many levels of symbolic links: {:?}",
+                                path
+                            )));
+                        }
+                        pending.extend(
+                            target
+                                .components()
+                                .rev()
+                                .map(|c| PathBuf::from(c.as_os_str())),
+                        );
+                    }
+                    Err(_) => resolved = candidate,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(root.join(resolved.strip_prefix("/").unwrap()))
+}
+
+/// Clones a child straight into the cgroup at `cgroup`, returns 0 in the child. Kernels
+/// without clone3 (before 5.3) or CLONE_INTO_CGROUP (before 5.7) get a plain clone
+/// instead, and the child is moved into the cgroup before it carries on.
+pub fn clone_into_cgroup(flags: c_int, cgroup: &Path) -> Result<Pid, ContainerErr> {
+    if !features::available(Feature::CloneIntoCgroup) {
+        debug!("no CLONE_INTO_CGROUP, falling back to clone and cgroup.procs");
+        return clone_then_move(flags, cgroup);
+    }
+    // Only ever used to refer to the cgroup, it's never read.
+    let cgroup_dir = OpenOptions::new()
+        .read(true)
+        .custom_flags(O_PATH | O_DIRECTORY)
+        .open(cgroup)
+        .map_err(ContainerErr::IO)?;
+    clone3(flags, cgroup_dir.as_raw_fd())
+        .map(|pid| pid as Pid)
+        .map_err(|e| ContainerErr::Clone(e.to_string()))
+}
+
+fn clone3(flags: c_int, cgroup_fd: RawFd) -> io::Result<pid_t> {
     debug!("clone3");
     let mut args = unsafe { std::mem::zeroed::<clone_args>() };
 
     args.flags |= flags as u64;
     args.flags |= CLONE_INTO_CGROUP as u64;
     args.cgroup = cgroup_fd as u64;
-    args.exit_signal = SIG_IGN as u64;
+    // Like fork, so the parent can wait for the child.
+    args.exit_signal = SIGCHLD as u64;
+
+    // The child only runs init and then execs or exits.
+    unsafe { syscalls::clone3(&mut args) }
+}
 
-    let pid = unsafe {
+/// Clones a child in our own cgroup and moves it to `cgroup`. The child waits until it's
+/// been moved, and only then unshares its cgroup namespace, so the namespace is rooted
+/// at the container's cgroup like with CLONE_INTO_CGROUP.
+fn clone_then_move(flags: c_int, cgroup: &Path) -> Result<Pid, ContainerErr> {
+    let (mut moved_reader, mut moved_writer) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+    let clone_flags = (flags & !CLONE_NEWCGROUP) | SIGCHLD;
+    let pid = unsafe { syscalls::clone(clone_flags as c_ulong) }
+        .map_err(|e| ContainerErr::Clone(e.to_string()))?;
+    if pid == 0 {
+        drop(moved_writer);
+        let mut moved = [0u8];
+        let ok = moved_reader.read_exact(&mut moved).is_ok()
+            && (flags & CLONE_NEWCGROUP == 0 || syscalls::unshare(CLONE_NEWCGROUP).is_ok());
+        if !ok {
+            // Never return into the parent's code from the child.
+            unsafe { libc::_exit(1) };
+        }
+        return Ok(0);
+    }
+
+    drop(moved_reader);
+    if let Err(e) = fs::write(cgroup.join("cgroup.procs"), pid.to_string()) {
+        let _ = syscalls::kill(pid, SIGKILL);
+        let _ = syscalls::waitpid(pid);
+        return Err(ContainerErr::Cgroup(format!(
+            "failed to move {} into {:?}: {}",
+            pid, cgroup, e
+        )));
+    }
+    moved_writer.write_all(&[1]).map_err(ContainerErr::IO)?;
+    Ok(pid as Pid)
+}
+
+/// Wrapper for the pidfd_open syscall. A pidfd keeps referring to the same process even
+/// if its pid is reused after it exits.
+pub fn pidfd_open(pid: Pid) -> Result<OwnedFd, io::Error> {
+    let fd = unsafe { syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Checks whether the process referred to by a pidfd is still alive by sending it
+/// the null signal.
+pub fn pidfd_is_alive(pidfd: &OwnedFd) -> bool {
+    pidfd_send_signal(pidfd, 0).is_ok()
+}
+
+/// Wrapper for the pidfd_send_signal syscall.
+pub fn pidfd_send_signal(pidfd: &OwnedFd, sig: c_int) -> Result<(), io::Error> {
+    let err = unsafe {
         syscall(
-            SYS_clone3,
-            &raw mut args,
-            size_of::<clone_args>(),
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            sig,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
         )
     };
-    if pid == -1 {
-        return Err(ContainerErr::Clone(format!(
-            "clone failed, errno: {}",
-            unsafe { *__errno_location() }
-        )));
+    if err == -1 {
+        return Err(io::Error::last_os_error());
     }
+    Ok(())
+}
 
-    Ok(pid as Pid)
+/// Waits up to `timeout` for the process referred to by a pidfd to exit, returns false
+/// if it's still running. A pidfd becomes readable once its process has exited.
+pub fn pidfd_wait_exit(pidfd: &OwnedFd, timeout: Duration) -> Result<bool, ContainerErr> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut fds = [libc::pollfd {
+            fd: pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        let left = deadline.saturating_duration_since(Instant::now());
+        let timeout_ms = left.as_millis().min(c_int::MAX as u128) as c_int;
+        match syscalls::poll(&mut fds, timeout_ms) {
+            Ok(n) => return Ok(n > 0),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(ContainerErr::IO(e)),
+        }
+    }
+}
+
+/// Waits for a child to exit and returns its exit code, 128 + the signal number if it was
+/// killed by a signal, like a shell.
+pub fn wait_exit_code(pid: Pid) -> Result<i32, ContainerErr> {
+    let status = syscalls::waitpid(pid as libc::pid_t).map_err(ContainerErr::IO)?;
+    if libc::WIFSIGNALED(status) {
+        return Ok(128 + libc::WTERMSIG(status));
+    }
+    Ok(libc::WEXITSTATUS(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_build_env() {
+        let process: Process = serde_json::from_value(serde_json::json!({
+            "env": ["A=1", "OPTS=--x=y", "A=2", "EMPTY="],
+        }))
+        .unwrap();
+        let env = build_env(&process).unwrap();
+        assert_eq!(
+            vec![c"A=2", c"OPTS=--x=y", c"EMPTY="],
+            env.iter().map(|v| v.as_c_str()).collect::<Vec<_>>()
+        );
+
+        let process: Process = serde_json::from_value(serde_json::json!({"env": ["=1"]})).unwrap();
+        assert!(build_env(&process).is_err());
+    }
+
+    #[test]
+    fn test_add_default_env() {
+        let mut process: Process = serde_json::from_value(serde_json::json!({
+            "env": ["PATH=/bin", "A=1"],
+        }))
+        .unwrap();
+        add_default_env(&mut process, "web");
+        assert_eq!(
+            vec!["PATH=/bin", "A=1", "HOSTNAME=web"],
+            process.env.clone().unwrap()
+        );
+
+        process.terminal = true;
+        process.env = None;
+        add_default_env(&mut process, "web");
+        assert_eq!(
+            vec![
+                format!("PATH={}", DEFAULT_PATH),
+                String::from("HOSTNAME=web"),
+                String::from("TERM=xterm"),
+            ],
+            process.env.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_in_root() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let root = PathBuf::from(format!("/tmp/resolve_in_root_{}", time));
+        fs::create_dir_all(root.join("bin")).unwrap();
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("bin/busybox"), b"").unwrap();
+        fs::set_permissions(root.join("bin/busybox"), fs::Permissions::from_mode(0o755)).unwrap();
+        // Absolute symlinks must resolve inside the rootfs, not on the host.
+        symlink("/bin/busybox", root.join("usr/bin/sh")).unwrap();
+        symlink("../../../../../bin/busybox", root.join("usr/bin/escape")).unwrap();
+
+        assert_eq!(
+            root.join("bin/busybox"),
+            resolve_in_root(&root, Path::new("/usr/bin/sh")).unwrap()
+        );
+        assert_eq!(
+            root.join("bin/busybox"),
+            resolve_in_root(&root, Path::new("/usr/bin/escape")).unwrap()
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_clone_then_move() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let cgroup = PathBuf::from(format!("/tmp/clone_then_move_{}", time));
+        fs::create_dir(&cgroup).unwrap();
+
+        // A plain dir stands in for the cgroup, the child's pid is written to it.
+        let pid = clone_then_move(0, &cgroup).unwrap();
+        if pid == 0 {
+            unsafe { libc::_exit(0) };
+        }
+        assert_eq!(
+            pid.to_string(),
+            fs::read_to_string(cgroup.join("cgroup.procs")).unwrap()
+        );
+        assert_eq!(0, wait_exit_code(pid).unwrap());
+
+        // A child which can't be moved doesn't run.
+        fs::remove_dir_all(&cgroup).unwrap();
+        assert!(matches!(
+            clone_then_move(0, &cgroup),
+            Err(ContainerErr::Cgroup(_))
+        ));
+    }
 }
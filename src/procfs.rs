@@ -0,0 +1,61 @@
+//! Checked writes to /proc.
+//!
+//! Once we've joined a container's mount namespace, e.g. in exec, /proc is whatever the
+//! container made it. It might not be a procfs at all, or have other files bind mounted
+//! over the ones we write to so our writes land somewhere of the container's choosing.
+//! Files are checked after they're opened, so they can't be swapped out between the
+//! check and the write.
+
+use crate::error::ContainerErr;
+use crate::libc_compat::fs_magic;
+use crate::syscalls::{fstatfs, mount_id};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+
+/// Writes `contents` to `path` under /proc, e.g. `/proc/self/attr/exec`, after checking
+/// it's a file of the procfs mounted at /proc.
+pub fn write(path: &str, contents: &str) -> Result<(), ContainerErr> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(ContainerErr::IO)?;
+    verify(&file, path)?;
+    file.write_all(contents.as_bytes())
+        .map_err(ContainerErr::IO)
+}
+
+/// Checks `file` is on a procfs and on the same mount as /proc itself, i.e. nothing is
+/// mounted over it.
+fn verify(file: &File, path: &str) -> Result<(), ContainerErr> {
+    let statfs = fstatfs(file.as_raw_fd()).map_err(ContainerErr::IO)?;
+    if fs_magic(&statfs) != libc::PROC_SUPER_MAGIC {
+        return Err(ContainerErr::Procfs(format!("{} is not on a procfs", path)));
+    }
+    let proc = File::open("/proc").map_err(ContainerErr::IO)?;
+    let proc_mount = mount_id(proc.as_raw_fd()).map_err(ContainerErr::IO)?;
+    if mount_id(file.as_raw_fd()).map_err(ContainerErr::IO)? != proc_mount {
+        return Err(ContainerErr::Procfs(format!(
+            "{} has something mounted over it",
+            path
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let path = "/proc/self/status";
+        assert!(verify(&File::open(path).unwrap(), path).is_ok());
+
+        let path = "/dev/null";
+        assert!(matches!(
+            verify(&File::open(path).unwrap(), path),
+            Err(ContainerErr::Procfs(_))
+        ));
+    }
+}
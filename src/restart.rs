@@ -0,0 +1,170 @@
+//! Restart policies, applied by the monitor when the container's init process exits.
+//!
+//! A container with a policy other than `never` is created again by its monitor, like
+//! `create` would, and started, like `start` would. Restarts are delayed with an
+//! exponential backoff so a container which keeps crashing doesn't take the host down
+//! with it. The delay resets once the container stays up for a while.
+
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use crate::hooks::{run_hooks, HookPhase};
+use crate::portforward::PortForwards;
+use crate::start_signal::send_start;
+use crate::state::{State, Status};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Delay before the first restart, doubled for every restart after it.
+const INITIAL_DELAY: Duration = Duration::from_millis(100);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+/// A container which ran this long before exiting is restarted without delay.
+const RESET_AFTER: Duration = Duration::from_secs(10);
+/// How long to wait for the restarted init process to pick up the start signal.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// When the monitor restarts the container.
+/// Parsed from `never`, `on-failure[:max-restarts]` or `always`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    /// Restart when the init process exits with a non-zero code, at most `max_restarts`
+    /// times if set.
+    OnFailure {
+        max_restarts: Option<u32>,
+    },
+    Always,
+}
+
+impl RestartPolicy {
+    /// Whether a container which has been restarted `restarts` times so far should be
+    /// restarted after exiting with `exit_code`.
+    pub fn should_restart(&self, exit_code: i32, restarts: u32) -> bool {
+        match *self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure { max_restarts } => {
+                exit_code != 0 && max_restarts.is_none_or(|max| restarts < max)
+            }
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = ContainerErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ContainerErr::Options(format!("invalid restart policy: {}", s));
+        match s.split_once(':') {
+            None if s == "never" => Ok(RestartPolicy::Never),
+            None if s == "always" => Ok(RestartPolicy::Always),
+            None if s == "on-failure" => Ok(RestartPolicy::OnFailure { max_restarts: None }),
+            Some(("on-failure", max)) => Ok(RestartPolicy::OnFailure {
+                max_restarts: Some(max.parse().map_err(|_| invalid())?),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Delays between restarts, doubling each time up to `MAX_DELAY`.
+#[derive(Debug)]
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            next: INITIAL_DELAY,
+        }
+    }
+}
+
+impl Backoff {
+    /// How long to wait before restarting a container which ran for `ran_for`.
+    pub fn delay(&mut self, ran_for: Duration) -> Duration {
+        if ran_for >= RESET_AFTER {
+            *self = Self::default();
+            return Duration::ZERO;
+        }
+        let delay = self.next;
+        self.next = (self.next * 2).min(MAX_DELAY);
+        delay
+    }
+}
+
+/// Starts the created container again, recording the restart in its state.
+pub fn start(dirs: &ContainerDirs, state: &mut State, restarts: u32) -> Result<(), ContainerErr> {
+    let config = dirs.load_config()?;
+    // The container has a new network namespace, the forwards point at the old one.
+    if let Some(mut ports) = PortForwards::load(dirs)? {
+        debug!("setting up port forwarding again");
+        ports.teardown();
+        let result = ports.apply(state.pid());
+        ports.write(dirs)?;
+        result?;
+    }
+
+    send_start(dirs, START_TIMEOUT)?;
+    state.update_status(Status::Running);
+    state.set_restart_count(restarts);
+    state.write(dirs.state())?;
+    run_hooks(&config, HookPhase::Poststart, state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(RestartPolicy::Never, "never".parse().unwrap());
+        assert_eq!(RestartPolicy::Always, "always".parse().unwrap());
+        assert_eq!(
+            RestartPolicy::OnFailure { max_restarts: None },
+            "on-failure".parse().unwrap()
+        );
+        assert_eq!(
+            RestartPolicy::OnFailure {
+                max_restarts: Some(3)
+            },
+            "on-failure:3".parse().unwrap()
+        );
+        for invalid in ["", "sometimes", "on-failure:", "on-failure:-1", "always:3"] {
+            assert!(invalid.parse::<RestartPolicy>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_should_restart() {
+        assert!(!RestartPolicy::Never.should_restart(1, 0));
+        assert!(RestartPolicy::Always.should_restart(0, 100));
+
+        let policy = RestartPolicy::OnFailure {
+            max_restarts: Some(2),
+        };
+        assert!(!policy.should_restart(0, 0));
+        assert!(policy.should_restart(137, 1));
+        assert!(!policy.should_restart(137, 2));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::default();
+        let crashed = Duration::from_millis(10);
+        assert_eq!(Duration::from_millis(100), backoff.delay(crashed));
+        assert_eq!(Duration::from_millis(200), backoff.delay(crashed));
+        assert_eq!(Duration::from_millis(400), backoff.delay(crashed));
+        for _ in 0..20 {
+            backoff.delay(crashed);
+        }
+        assert_eq!(MAX_DELAY, backoff.delay(crashed));
+
+        // Staying up resets it.
+        assert_eq!(Duration::ZERO, backoff.delay(RESET_AFTER));
+        assert_eq!(Duration::from_millis(100), backoff.delay(crashed));
+    }
+}
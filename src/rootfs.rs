@@ -1,20 +1,178 @@
-use libc::{MS_BIND, MS_PRIVATE, MS_REC, MS_SLAVE};
+use libc::{MNT_DETACH, MS_BIND, MS_PRIVATE, MS_RDONLY, MS_REC, MS_SLAVE};
 
+use crate::dirs::ContainerDirs;
 use crate::mount::mount;
-use crate::{config::Config, error::ContainerErr};
+use crate::process::find_executable;
+use crate::syscalls;
+use crate::{
+    config::{Config, Process},
+    error::ContainerErr,
+};
+use std::env::set_current_dir;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
 use std::{fs, path::Path};
 
-/// Mounts the root filesystem for a container.
-pub fn setup_rootfs<P: AsRef<Path>>(config: &Config, bundle_path: P) -> Result<(), ContainerErr> {
-    let config_root = bundle_path.as_ref().join(&config.root.path);
-    let meta =
-        fs::metadata(&config_root).map_err(ContainerErr::IO)?;
-    if !meta.is_dir() {
-        return Err(ContainerErr::RootFs(format!(
-            "rootfs at {} is not a directory.",
-            config.root.path
-        )));
+/// Subdirectories of a layered rootfs, see `RootfsLayout::Overlay`.
+const LAYERS_DIR: &str = "layers";
+const UPPER_DIR: &str = "upper";
+const WORK_DIR: &str = "work";
+const MERGED_DIR: &str = "merged";
+
+/// Magic numbers of the supported image filesystems, and their offset into the image.
+const SQUASHFS_MAGIC: (usize, [u8; 4]) = (0, *b"hsqs");
+const EROFS_MAGIC: (usize, [u8; 4]) = (1024, 0xe0f5_e1e2_u32.to_le_bytes());
+
+/// What root.path points at.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RootfsLayout {
+    /// A directory used as the rootfs as is.
+    Directory(PathBuf),
+    /// A directory with a `layers/` directory, assembled into the rootfs with overlayfs.
+    /// The layers are stacked in name order, the last one on top. Writes go to `upper/`,
+    /// overlayfs needs `work/` for itself and the result is mounted on `merged/`.
+    Overlay {
+        lower: Vec<PathBuf>,
+        upper: PathBuf,
+        work: PathBuf,
+        merged: PathBuf,
+    },
+    /// A squashfs or erofs image, mounted from a loop device set up by create.
+    Image {
+        path: PathBuf,
+        fs_type: &'static str,
+    },
+}
+
+impl RootfsLayout {
+    pub fn detect<P: AsRef<Path>>(config: &Config, bundle_path: P) -> Result<Self, ContainerErr> {
+        let root = bundle_path.as_ref().join(&config.root.path);
+        let meta = fs::metadata(&root).map_err(ContainerErr::IO)?;
+        if meta.is_file() {
+            return match image_fs_type(&root)? {
+                Some(fs_type) => Ok(Self::Image {
+                    path: root,
+                    fs_type,
+                }),
+                None => Err(ContainerErr::RootFs(format!(
+                    "rootfs at {} is neither a directory nor a squashfs or erofs image.",
+                    config.root.path
+                ))),
+            };
+        }
+        if !meta.is_dir() {
+            return Err(ContainerErr::RootFs(format!(
+                "rootfs at {} is not a directory.",
+                config.root.path
+            )));
+        }
+
+        let layers = root.join(LAYERS_DIR);
+        if !layers.is_dir() {
+            return Ok(Self::Directory(root));
+        }
+        let mut lower = Vec::new();
+        for entry in fs::read_dir(&layers).map_err(ContainerErr::IO)? {
+            let path = entry.map_err(ContainerErr::IO)?.path();
+            if path.is_dir() {
+                lower.push(path);
+            }
+        }
+        if lower.is_empty() {
+            return Err(ContainerErr::RootFs(format!("no layers in {:?}", layers)));
+        }
+        lower.sort();
+        lower.reverse();
+        Ok(Self::Overlay {
+            lower,
+            upper: root.join(UPPER_DIR),
+            work: root.join(WORK_DIR),
+            merged: root.join(MERGED_DIR),
+        })
+    }
+
+    /// Like `find_executable`, before the rootfs is assembled. With layers the entrypoint
+    /// may be in any of them, whiteouts aren't taken into account. Images can't be looked
+    /// into until they're mounted, there's no result for them.
+    pub fn find_executable(&self, process: &Process) -> Result<Option<PathBuf>, ContainerErr> {
+        let dirs = match self {
+            Self::Directory(root) => vec![root],
+            Self::Overlay { lower, upper, .. } => std::iter::once(upper).chain(lower).collect(),
+            Self::Image { .. } => return Ok(None),
+        };
+        let mut result = Err(ContainerErr::RootFs(String::from("empty rootfs")));
+        for dir in dirs {
+            result = find_executable(dir, process);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result.map(Some)
+    }
+
+    /// Where `setup_rootfs` mounts the rootfs, everything else is mounted below them.
+    pub fn mount_points(&self, readonly: bool, dirs: &ContainerDirs) -> Vec<PathBuf> {
+        match self {
+            Self::Directory(root) => vec![root.clone()],
+            Self::Overlay { merged, .. } => vec![merged.clone()],
+            Self::Image { .. } if readonly => vec![dirs.image_dir()],
+            Self::Image { .. } => vec![
+                dirs.image_dir(),
+                dirs.image_rw_dir(),
+                dirs.image_rootfs_dir(),
+            ],
+        }
+    }
+}
+
+/// The mounts the container's init will make which outlive it, those of a container
+/// without a mount namespace of its own. Mounts in a new mount namespace go away with it.
+/// Detaching the rootfs mounts detaches the container's mounts below them as well.
+pub fn host_mounts<P: AsRef<Path>>(
+    config: &Config,
+    bundle_path: P,
+    dirs: &ContainerDirs,
+) -> Result<Vec<PathBuf>, ContainerErr> {
+    let new_namespace = config
+        .linux_namespaces()
+        .is_some_and(|ns| ns.iter().any(|ns| ns.typ == "mount" && ns.path.is_none()));
+    if new_namespace {
+        return Ok(Vec::new());
+    }
+    let layout = RootfsLayout::detect(config, bundle_path)?;
+    Ok(layout.mount_points(config.root.readonly, dirs))
+}
+
+/// The filesystem of a rootfs image, if it's one we support.
+fn image_fs_type(path: &Path) -> Result<Option<&'static str>, ContainerErr> {
+    let mut header = Vec::new();
+    File::open(path)
+        .and_then(|f| f.take(EROFS_MAGIC.0 as u64 + 4).read_to_end(&mut header))
+        .map_err(ContainerErr::IO)?;
+    let has_magic =
+        |(offset, magic): (usize, [u8; 4])| header.get(offset..offset + 4) == Some(&magic);
+    if has_magic(SQUASHFS_MAGIC) {
+        Ok(Some("squashfs"))
+    } else if has_magic(EROFS_MAGIC) {
+        Ok(Some("erofs"))
+    } else {
+        Ok(None)
     }
+}
+
+/// Mounts the root filesystem for a container. Returns the rootfs path, which becomes the
+/// container's root once `pivot_root` is called.
+/// `loop_device` is the device create set up for an image rootfs, mount points for it are
+/// made in the state dir.
+pub fn setup_rootfs<P: AsRef<Path>>(
+    config: &Config,
+    bundle_path: P,
+    loop_device: Option<&Path>,
+    dirs: &ContainerDirs,
+) -> Result<PathBuf, ContainerErr> {
+    let layout = RootfsLayout::detect(config, bundle_path)?;
 
     // See 'changing the propagation type of an existing mount' here:
     // https://www.man7.org/linux/man-pages/man2/mount.2.html
@@ -32,14 +190,212 @@ pub fn setup_rootfs<P: AsRef<Path>>(config: &Config, bundle_path: P) -> Result<(
         ))
     })?;
 
-    mount(
-        &config_root,
-	"/",
-        c"bind",
-        MS_BIND | MS_REC,
-        None,
-    )
-    .map_err(|e| ContainerErr::RootFs(format!("failed to mount rootfs: {:?}", e)))?;
+    match layout {
+        RootfsLayout::Directory(config_root) => {
+            // pivot_root needs the new root to be a mount point.
+            mount(&config_root, &config_root, c"bind", MS_BIND | MS_REC, None)
+                .map_err(|e| ContainerErr::RootFs(format!("failed to mount rootfs: {:?}", e)))?;
+            Ok(config_root)
+        }
+        RootfsLayout::Overlay {
+            lower,
+            upper,
+            work,
+            merged,
+        } => {
+            for dir in [&upper, &work, &merged] {
+                fs::create_dir_all(dir).map_err(ContainerErr::IO)?;
+            }
+            let data = overlay_data(&lower, &upper, &work)?;
+            mount("overlay", &merged, c"overlay", 0, Some(data.as_c_str())).map_err(|e| {
+                ContainerErr::RootFs(format!("failed to mount overlay rootfs: {:?}", e))
+            })?;
+            Ok(merged)
+        }
+        RootfsLayout::Image { fs_type, .. } => {
+            let device = loop_device.ok_or_else(|| {
+                ContainerErr::RootFs(String::from("no loop device for the rootfs image"))
+            })?;
+            mount_image(device, fs_type, config.root.readonly, dirs)
+        }
+    }
+}
+
+/// Mounts an image rootfs from its loop device. Unless the root is read-only, writes go
+/// to a tmpfs layered on top with overlayfs, they're lost when the container exits.
+fn mount_image(
+    device: &Path,
+    fs_type: &str,
+    readonly: bool,
+    dirs: &ContainerDirs,
+) -> Result<PathBuf, ContainerErr> {
+    let image = dirs.image_dir();
+    fs::create_dir_all(&image).map_err(ContainerErr::IO)?;
+    let fs_type = CString::new(fs_type).map_err(|e| ContainerErr::RootFs(e.to_string()))?;
+    mount(device, &image, &fs_type, MS_RDONLY, None)
+        .map_err(|e| ContainerErr::RootFs(format!("failed to mount rootfs image: {:?}", e)))?;
+    if readonly {
+        return Ok(image);
+    }
+
+    let rw = dirs.image_rw_dir();
+    fs::create_dir_all(&rw).map_err(ContainerErr::IO)?;
+    mount("tmpfs", &rw, c"tmpfs", 0, None)
+        .map_err(|e| ContainerErr::RootFs(format!("failed to mount rootfs tmpfs: {:?}", e)))?;
+    let (upper, work, merged) = (
+        rw.join(UPPER_DIR),
+        rw.join(WORK_DIR),
+        dirs.image_rootfs_dir(),
+    );
+    for dir in [&upper, &work, &merged] {
+        fs::create_dir_all(dir).map_err(ContainerErr::IO)?;
+    }
+    let data = overlay_data(&[image], &upper, &work)?;
+    mount("overlay", &merged, c"overlay", 0, Some(data.as_c_str()))
+        .map_err(|e| ContainerErr::RootFs(format!("failed to mount overlay rootfs: {:?}", e)))?;
+    Ok(merged)
+}
+
+/// overlayfs mount data. The option syntax uses ':' and ',' as separators, paths
+/// containing them can't be used.
+fn overlay_data(lower: &[PathBuf], upper: &Path, work: &Path) -> Result<CString, ContainerErr> {
+    let mut paths = Vec::new();
+    for path in lower.iter().map(PathBuf::as_path).chain([upper, work]) {
+        let path = path.to_str().ok_or_else(|| {
+            ContainerErr::RootFs(format!("layer path isn't valid UTF-8: {:?}", path))
+        })?;
+        if path.contains([':', ',']) {
+            return Err(ContainerErr::RootFs(format!(
+                "layer path can't contain ':' or ',': {}",
+                path
+            )));
+        }
+        paths.push(path);
+    }
+    let (lower, dirs) = paths.split_at(lower.len());
+    CString::new(format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lower.join(":"),
+        dirs[0],
+        dirs[1]
+    ))
+    .map_err(|e| ContainerErr::RootFs(e.to_string()))
+}
+
+/// Makes `rootfs` the root of the current mount namespace and detaches the old root.
+pub fn pivot_root(rootfs: &Path) -> Result<(), ContainerErr> {
+    set_current_dir(rootfs).map_err(ContainerErr::IO)?;
+    // pivot_root(".", ".") stacks the old root on top of the new one, so it can be
+    // detached without needing a directory for it inside the rootfs.
+    syscalls::pivot_root(c".", c".")
+        .map_err(|e| ContainerErr::RootFs(format!("failed to pivot root: {}", e)))?;
+    syscalls::umount2(c".", MNT_DETACH)
+        .map_err(|e| ContainerErr::RootFs(format!("failed to detach old root: {}", e)))?;
+    set_current_dir("/").map_err(ContainerErr::IO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_overlay_layout() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let bundle = PathBuf::from(format!("/tmp/overlay_bundle_{}", time));
+        for layer in ["002", "001", "010"] {
+            fs::create_dir_all(bundle.join("rootfs/layers").join(layer)).unwrap();
+        }
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "ociVersion": "1.0.1",
+            "root": {"path": "rootfs", "readonly": false},
+        }))
+        .unwrap();
+
+        let layout = RootfsLayout::detect(&config, &bundle).unwrap();
+        let layers = bundle.join("rootfs/layers");
+        let lower = vec![layers.join("010"), layers.join("002"), layers.join("001")];
+        let upper = bundle.join("rootfs/upper");
+        let work = bundle.join("rootfs/work");
+        assert_eq!(
+            RootfsLayout::Overlay {
+                lower: lower.clone(),
+                upper: upper.clone(),
+                work: work.clone(),
+                merged: bundle.join("rootfs/merged"),
+            },
+            layout
+        );
+
+        let data = overlay_data(&lower, &upper, &work).unwrap();
+        let expected = format!(
+            "lowerdir={0}/010:{0}/002:{0}/001,upperdir={1},workdir={2}",
+            layers.display(),
+            upper.display(),
+            work.display()
+        );
+        assert_eq!(expected, data.to_str().unwrap());
 
-    Ok(())
+        fs::remove_dir_all(&bundle).unwrap();
+    }
+
+    #[test]
+    fn test_host_mounts() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let bundle = PathBuf::from(format!("/tmp/host_mounts_{}", time));
+        fs::create_dir_all(bundle.join("rootfs")).unwrap();
+        let dirs = ContainerDirs::new(bundle.join("state"));
+        let config = |namespaces: serde_json::Value| -> Config {
+            serde_json::from_value(serde_json::json!({
+                "ociVersion": "1.0.1",
+                "root": {"path": "rootfs", "readonly": false},
+                "linux": {"namespaces": namespaces},
+            }))
+            .unwrap()
+        };
+
+        let own = config(serde_json::json!([{"type": "mount"}, {"type": "pid"}]));
+        assert!(host_mounts(&own, &bundle, &dirs).unwrap().is_empty());
+        let shared = config(serde_json::json!([{"type": "mount", "path": "/proc/1/ns/mnt"}]));
+        assert_eq!(
+            vec![bundle.join("rootfs")],
+            host_mounts(&shared, &bundle, &dirs).unwrap()
+        );
+        let image = RootfsLayout::Image {
+            path: bundle.join("rootfs.img"),
+            fs_type: "squashfs",
+        };
+        assert_eq!(vec![dirs.image_dir()], image.mount_points(true, &dirs));
+        assert_eq!(3, image.mount_points(false, &dirs).len());
+
+        fs::remove_dir_all(&bundle).unwrap();
+    }
+
+    #[test]
+    fn test_image_fs_type() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let path = PathBuf::from(format!("/tmp/rootfs_image_{}", time));
+
+        fs::write(&path, b"hsqs\x04\x00\x00\x00").unwrap();
+        assert_eq!(Some("squashfs"), image_fs_type(&path).unwrap());
+
+        let mut erofs = vec![0; 1024];
+        erofs.extend([0xe2, 0xe1, 0xf5, 0xe0]);
+        fs::write(&path, &erofs).unwrap();
+        assert_eq!(Some("erofs"), image_fs_type(&path).unwrap());
+
+        fs::write(&path, b"not an image").unwrap();
+        assert_eq!(None, image_fs_type(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
 }
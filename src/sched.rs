@@ -0,0 +1,125 @@
+//! Scheduling policy of the container process, from process.scheduler, and its
+//! core-scheduling cookie.
+
+use crate::{
+    config::Process,
+    error::ContainerErr,
+    state::Pid,
+    syscalls::{self, errno, sched_setattr, SchedAttr},
+};
+use libc::{
+    ENODEV, PR_SCHED_CORE_CREATE, PR_SCHED_CORE_SCOPE_THREAD, PR_SCHED_CORE_SCOPE_THREAD_GROUP,
+    PR_SCHED_CORE_SHARE_FROM,
+};
+use log::debug;
+
+// linux/sched.h, libc doesn't have SCHED_DEADLINE or the sched_setattr flags.
+const POLICIES: [(&str, u32); 7] = [
+    ("SCHED_OTHER", 0),
+    ("SCHED_FIFO", 1),
+    ("SCHED_RR", 2),
+    ("SCHED_BATCH", 3),
+    ("SCHED_ISO", 4),
+    ("SCHED_IDLE", 5),
+    ("SCHED_DEADLINE", 6),
+];
+const FLAGS: [(&str, u64); 7] = [
+    ("SCHED_FLAG_RESET_ON_FORK", 0x01),
+    ("SCHED_FLAG_RECLAIM", 0x02),
+    ("SCHED_FLAG_DL_OVERRUN", 0x04),
+    ("SCHED_FLAG_KEEP_POLICY", 0x08),
+    ("SCHED_FLAG_KEEP_PARAMS", 0x10),
+    ("SCHED_FLAG_UTIL_CLAMP_MIN", 0x20),
+    ("SCHED_FLAG_UTIL_CLAMP_MAX", 0x40),
+];
+
+/// Sets the scheduling policy, nice value and priority of the calling process.
+pub fn set_scheduler(process: &Process) -> Result<(), ContainerErr> {
+    let Some(scheduler) = &process.scheduler else {
+        return Ok(());
+    };
+    debug!("{:?}", scheduler);
+    let mut attr = SchedAttr {
+        policy: lookup(&POLICIES, &scheduler.policy)?,
+        nice: scheduler.nice,
+        priority: scheduler.priority as u32,
+        runtime: scheduler.runtime.unwrap_or_default(),
+        deadline: scheduler.deadline.unwrap_or_default(),
+        period: scheduler.period.unwrap_or_default(),
+        ..Default::default()
+    };
+    for flag in scheduler.flags.iter().flatten() {
+        attr.flags |= lookup(&FLAGS, flag)?;
+    }
+    sched_setattr(&mut attr).map_err(|e| ContainerErr::Scheduler(e.to_string()))
+}
+
+/// Gives the calling process a core-scheduling cookie of its own, which its children
+/// inherit. Only tasks with the same cookie share a core's SMT siblings, so other
+/// containers can't use them as a side channel. Without SMT there's nothing to isolate
+/// and the kernel says ENODEV, that's fine.
+pub fn create_core_cookie() -> Result<(), ContainerErr> {
+    let result =
+        syscalls::prctl_sched_core(PR_SCHED_CORE_CREATE, 0, PR_SCHED_CORE_SCOPE_THREAD_GROUP);
+    core_cookie_result(result)
+}
+
+/// Takes the core-scheduling cookie of `pid`, e.g. the container's init, for the calling
+/// thread and the children it forks afterwards.
+pub fn share_core_cookie(pid: Pid) -> Result<(), ContainerErr> {
+    let result = syscalls::prctl_sched_core(
+        PR_SCHED_CORE_SHARE_FROM,
+        pid as libc::pid_t,
+        PR_SCHED_CORE_SCOPE_THREAD,
+    );
+    core_cookie_result(result)
+}
+
+fn core_cookie_result(result: std::io::Result<()>) -> Result<(), ContainerErr> {
+    match result {
+        Err(e) if errno(&e) == Some(ENODEV) => {
+            debug!("core scheduling isn't available: {}", e);
+            Ok(())
+        }
+        result => result.map_err(|e| ContainerErr::Scheduler(e.to_string())),
+    }
+}
+
+fn lookup<T: Copy>(table: &[(&str, T)], name: &str) -> Result<T, ContainerErr> {
+    table
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| *value)
+        .ok_or_else(|| ContainerErr::Scheduler(format!("unknown scheduler setting: {}", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_scheduler() {
+        let process: Process = serde_json::from_value(serde_json::json!({
+            "scheduler": {"policy": "SCHED_BATCH", "flags": ["SCHED_FLAG_RESET_ON_FORK"]},
+        }))
+        .unwrap();
+        // Scheduler settings are per thread, keep them away from the test harness.
+        std::thread::spawn(move || {
+            set_scheduler(&process).unwrap();
+            assert_eq!(libc::SCHED_BATCH | libc::SCHED_RESET_ON_FORK, unsafe {
+                libc::sched_getscheduler(0)
+            });
+        })
+        .join()
+        .unwrap();
+
+        let process: Process = serde_json::from_value(serde_json::json!({
+            "scheduler": {"policy": "SCHED_BOGUS"},
+        }))
+        .unwrap();
+        assert!(matches!(
+            set_scheduler(&process),
+            Err(ContainerErr::Scheduler(_))
+        ));
+    }
+}
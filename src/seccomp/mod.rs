@@ -0,0 +1,163 @@
+//! Seccomp filters for the container process, from linux.seccomp.
+//!
+//! Profiles are compiled to a classic BPF program here rather than going through
+//! libseccomp. Only the subset without argument conditions is supported: a default action
+//! and an action per syscall name. Names which don't exist on this architecture are
+//! skipped, like libseccomp does. The names only resolve to native syscall numbers, so
+//! syscalls made through another ABI, e.g. by 32-bit binaries, fail with ENOSYS.
+
+mod table;
+
+use crate::config::LinuxSeccomp;
+use crate::error::ContainerErr;
+use crate::libc_compat::AUDIT_ARCH;
+use crate::syscalls::seccomp_set_filter;
+use libc::{
+    sock_filter, BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W, SECCOMP_RET_ALLOW,
+    SECCOMP_RET_DATA, SECCOMP_RET_ERRNO, SECCOMP_RET_KILL_PROCESS, SECCOMP_RET_KILL_THREAD,
+    SECCOMP_RET_LOG, SECCOMP_RET_TRACE, SECCOMP_RET_TRAP,
+};
+use log::debug;
+use table::syscall_number;
+
+/// Offsets of the syscall number and audit arch in struct seccomp_data.
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+/// x32 syscalls share x86_64's audit arch, they have this bit set in their number.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+/// The kernel's limit on the length of a filter, BPF_MAXINSNS.
+const MAX_INSTRUCTIONS: usize = 4096;
+
+/// Installs the profile's filter for this process and everything it execs. Needs
+/// no_new_privs or CAP_SYS_ADMIN, `privileges::drop` installs it accordingly.
+pub fn install(seccomp: &LinuxSeccomp) -> Result<(), ContainerErr> {
+    let filter = compile(seccomp)?;
+    debug!("installing seccomp filter, {} instructions", filter.len());
+    seccomp_set_filter(&filter).map_err(|e| ContainerErr::Seccomp(e.to_string()))
+}
+
+fn compile(seccomp: &LinuxSeccomp) -> Result<Vec<sock_filter>, ContainerErr> {
+    let enosys = SECCOMP_RET_ERRNO | libc::ENOSYS as u32;
+    let mut filter = vec![
+        stmt(BPF_LD | BPF_W | BPF_ABS, ARCH_OFFSET),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+        stmt(BPF_RET | BPF_K, enosys),
+        stmt(BPF_LD | BPF_W | BPF_ABS, NR_OFFSET),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    filter.extend([
+        jump(BPF_JMP | libc::BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1),
+        stmt(BPF_RET | BPF_K, enosys),
+    ]);
+
+    for syscall in seccomp.syscalls.iter().flatten() {
+        if syscall.args.as_ref().is_some_and(|args| !args.is_empty()) {
+            return Err(ContainerErr::Seccomp(format!(
+                "argument conditions are not supported: {}",
+                syscall.names.join(", ")
+            )));
+        }
+        let ret = action(&syscall.action, syscall.errno_ret)?;
+        for name in &syscall.names {
+            let Some(nr) = syscall_number(name) else {
+                debug!("seccomp: skipping unknown syscall {}", name);
+                continue;
+            };
+            filter.push(jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1));
+            filter.push(stmt(BPF_RET | BPF_K, ret));
+        }
+    }
+    let default = action(&seccomp.default_action, seccomp.default_errno_ret)?;
+    filter.push(stmt(BPF_RET | BPF_K, default));
+
+    if filter.len() > MAX_INSTRUCTIONS {
+        return Err(ContainerErr::Seccomp(format!(
+            "profile needs {} instructions, the limit is {}",
+            filter.len(),
+            MAX_INSTRUCTIONS
+        )));
+    }
+    Ok(filter)
+}
+
+/// The filter's return value for an SCMP_ACT_* action. `errno_ret` is the errno for
+/// SCMP_ACT_ERRNO, EPERM if unset, and the message for the tracer with SCMP_ACT_TRACE.
+fn action(action: &str, errno_ret: Option<u32>) -> Result<u32, ContainerErr> {
+    match action {
+        "SCMP_ACT_ALLOW" => Ok(SECCOMP_RET_ALLOW),
+        "SCMP_ACT_ERRNO" => {
+            Ok(SECCOMP_RET_ERRNO | (errno_ret.unwrap_or(libc::EPERM as u32) & SECCOMP_RET_DATA))
+        }
+        "SCMP_ACT_KILL" | "SCMP_ACT_KILL_THREAD" => Ok(SECCOMP_RET_KILL_THREAD),
+        "SCMP_ACT_KILL_PROCESS" => Ok(SECCOMP_RET_KILL_PROCESS),
+        "SCMP_ACT_LOG" => Ok(SECCOMP_RET_LOG),
+        "SCMP_ACT_TRACE" => Ok(SECCOMP_RET_TRACE | (errno_ret.unwrap_or(0) & SECCOMP_RET_DATA)),
+        "SCMP_ACT_TRAP" => Ok(SECCOMP_RET_TRAP),
+        _ => Err(ContainerErr::Seccomp(format!(
+            "unsupported action: {}",
+            action
+        ))),
+    }
+}
+
+fn stmt(code: u32, k: u32) -> sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LinuxSyscall;
+    use crate::syscalls::prctl;
+    use std::io;
+    use std::thread;
+
+    #[test]
+    fn test_compile() {
+        let mut seccomp = LinuxSeccomp::new("SCMP_ACT_ERRNO");
+        seccomp.syscalls = Some(vec![LinuxSyscall::new(
+            &["read", "write", "no_such_syscall"],
+            "SCMP_ACT_ALLOW",
+            None,
+        )]);
+        let filter = compile(&seccomp).unwrap();
+        let last = filter.last().unwrap();
+        assert_eq!(SECCOMP_RET_ERRNO | libc::EPERM as u32, last.k);
+        // The unknown name is skipped, the others get a compare and a return each.
+        let allowed = filter.iter().filter(|i| i.k == SECCOMP_RET_ALLOW).count();
+        assert_eq!(2, allowed);
+
+        seccomp.default_action = String::from("SCMP_ACT_NOTIFY");
+        assert!(matches!(compile(&seccomp), Err(ContainerErr::Seccomp(_))));
+    }
+
+    #[test]
+    fn test_install() {
+        // Filters apply to the thread installing them, keep it away from the test harness.
+        let result = thread::spawn(|| {
+            let mut seccomp = LinuxSeccomp::new("SCMP_ACT_ALLOW");
+            seccomp.syscalls = Some(vec![LinuxSyscall::new(
+                &["getppid"],
+                "SCMP_ACT_ERRNO",
+                Some(libc::EACCES as u32),
+            )]);
+            prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0).unwrap();
+            install(&seccomp).unwrap();
+            let ret = unsafe { libc::syscall(libc::SYS_getppid) };
+            (ret, io::Error::last_os_error().raw_os_error())
+        })
+        .join()
+        .unwrap();
+        assert_eq!((-1, Some(libc::EACCES)), result);
+    }
+}
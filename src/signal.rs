@@ -0,0 +1,182 @@
+//! Signal names, for the signals given to the runtime's commands and annotations.
+
+use crate::config::Config;
+use crate::error::ContainerErr;
+use crate::extensions::Extensions;
+use crate::syscalls;
+use libc::c_int;
+use std::os::fd::{AsRawFd, IntoRawFd};
+use std::pipe::PipeReader;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Stop signal of the image the bundle was built from, set by tools converting images.
+pub const IMAGE_STOP_SIGNAL_ANNOTATION: &str = "org.opencontainers.image.stopSignal";
+
+const SIGNALS: [(&str, c_int); 31] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("IOT", libc::SIGIOT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("STKFLT", libc::SIGSTKFLT),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Parses a signal given by name, with or without the SIG prefix, or by number.
+pub fn parse_signal(signal: &str) -> Result<c_int, ContainerErr> {
+    let invalid = || ContainerErr::invalid_args(&format!("Unknown signal: {}", signal));
+    if let Ok(n) = signal.parse::<c_int>() {
+        let max = libc::SIGRTMAX();
+        return (1..=max).contains(&n).then_some(n).ok_or_else(invalid);
+    }
+    let upper = signal.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, sig)| *sig)
+        .ok_or_else(invalid)
+}
+
+/// The signal which stops the container's process: the runtime's stop-signal annotation,
+/// the image's stop signal or SIGTERM.
+pub fn stop_signal(config: &Config) -> Result<c_int, ContainerErr> {
+    if let Some(signal) = Extensions::parse(config)?.stop_signal {
+        return Ok(signal);
+    }
+    match config.annotation(IMAGE_STOP_SIGNAL_ANNOTATION) {
+        Some(signal) => parse_signal(signal.trim()).map_err(|e| {
+            ContainerErr::Options(format!(
+                "annotation {}: {:?}",
+                IMAGE_STOP_SIGNAL_ANNOTATION, e
+            ))
+        }),
+        None => Ok(libc::SIGTERM),
+    }
+}
+
+/// Write end of the pipe `on_signal` reports signals on.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(sig: c_int) {
+    // Only async-signal-safe calls in here.
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    unsafe { libc::write(fd, [sig as u8].as_ptr().cast(), 1) };
+}
+
+/// Installs a handler for `signals` which reports each one as a byte with its number on
+/// the returned pipe, so they can be handled outside of the signal handler. Signals
+/// arriving while the pipe is full are dropped. There's one pipe per process, a later
+/// call replaces it.
+pub fn notify(signals: &[c_int]) -> Result<PipeReader, ContainerErr> {
+    let (reader, writer) = std::pipe::pipe().map_err(ContainerErr::IO)?;
+    syscalls::set_nonblocking(writer.as_raw_fd()).map_err(ContainerErr::IO)?;
+    // Never closed, the handlers stay installed for as long as we run.
+    SIGNAL_PIPE.store(writer.into_raw_fd(), Ordering::Relaxed);
+    for sig in signals {
+        syscalls::sigaction(*sig, on_signal).map_err(ContainerErr::IO)?;
+    }
+    Ok(reader)
+}
+
+/// Restores the default disposition of every signal and unblocks them all, for a process
+/// about to exec the container's. Ignored signals and the mask survive execve, and the
+/// runtime's shouldn't leak into the container: Rust ignores SIGPIPE for one.
+pub fn reset_signals() -> Result<(), ContainerErr> {
+    for sig in 1..=libc::SIGRTMAX() {
+        match syscalls::sigaction_default(sig) {
+            // SIGKILL, SIGSTOP and the real-time signals libc reserves for itself.
+            Err(e) if syscalls::errno(&e) == Some(libc::EINVAL) => {}
+            result => result.map_err(ContainerErr::IO)?,
+        }
+    }
+    syscalls::unblock_signals().map_err(ContainerErr::IO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(libc::SIGTERM, parse_signal("SIGTERM").unwrap());
+        assert_eq!(libc::SIGTERM, parse_signal("TERM").unwrap());
+        assert_eq!(libc::SIGQUIT, parse_signal("sigquit").unwrap());
+        assert_eq!(libc::SIGKILL, parse_signal("9").unwrap());
+        assert!(parse_signal("SIGFOO").is_err());
+        assert!(parse_signal("0").is_err());
+        assert!(parse_signal("").is_err());
+    }
+
+    #[test]
+    fn test_stop_signal() {
+        let config = |annotations: serde_json::Value| -> Config {
+            serde_json::from_value(json!({
+                "ociVersion": "1.0.1",
+                "root": {"path": "rootfs", "readonly": false},
+                "annotations": annotations,
+            }))
+            .unwrap()
+        };
+        assert_eq!(libc::SIGTERM, stop_signal(&config(json!({}))).unwrap());
+        let image = config(json!({IMAGE_STOP_SIGNAL_ANNOTATION: "SIGINT"}));
+        assert_eq!(libc::SIGINT, stop_signal(&image).unwrap());
+        // The runtime's annotation wins over the image's.
+        let both = config(json!({
+            IMAGE_STOP_SIGNAL_ANNOTATION: "SIGINT",
+            "org.beersonthewall.runtime.stop-signal": "QUIT",
+        }));
+        assert_eq!(libc::SIGQUIT, stop_signal(&both).unwrap());
+        let invalid = config(json!({IMAGE_STOP_SIGNAL_ANNOTATION: "SIGNOPE"}));
+        assert!(stop_signal(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_reset_signals() {
+        let pid = unsafe { syscalls::fork() }.unwrap();
+        if pid == 0 {
+            // Like a runtime which ignores and blocks SIGTERM.
+            unsafe {
+                libc::signal(libc::SIGTERM, libc::SIG_IGN);
+                let mut set = std::mem::zeroed::<libc::sigset_t>();
+                libc::sigemptyset(&mut set);
+                libc::sigaddset(&mut set, libc::SIGTERM);
+                libc::sigprocmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+            }
+            let code = match reset_signals() {
+                Ok(()) => unsafe { libc::raise(libc::SIGTERM) },
+                Err(_) => 2,
+            };
+            // Only reached if SIGTERM didn't terminate us.
+            unsafe { libc::_exit(code + 1) };
+        }
+        let status = syscalls::waitpid(pid).unwrap();
+        assert!(libc::WIFSIGNALED(status), "exited with {}", status);
+        assert_eq!(libc::SIGTERM, libc::WTERMSIG(status));
+    }
+}
@@ -0,0 +1,472 @@
+//! The start signal sent by `start` to unblock the container's init process.
+//!
+//! By default this is an abstract unix socket: `create` binds the listening socket in the
+//! host's network namespace before cloning the container process (sockets keep the
+//! namespace they were created in), the init process blocks in accept and `start` connects
+//! and sends the container's state dir, opened for reading, as SCM_RIGHTS. Only root can
+//! open it, so that's the proof `start` may start the container, and nothing needs to be
+//! written for it. Peer credentials would be translated into the container's user
+//! namespace. Abstract sockets need no filesystem entry, so they work with read-only state
+//! dirs and disappear when the init process dies.
+//!
+//! If the socket can't be bound we fall back to the legacy mkfifo based handshake. The
+//! FIFO is only accessible to root, or the container's root if it has a user namespace.
+//! The init process opens it through a descriptor taken before it was cloned, the state
+//! dir isn't accessible from the container, and unlinks it once opened so a later `start`
+//! finds it gone rather than waiting for a reader that never comes.
+
+use crate::dirs::{ContainerDirs, FIFO_FILENAME};
+use crate::error::ContainerErr;
+use crate::syscalls::{mkfifo, recv_fd, send_fd, unlinkat};
+use libc::{ECONNREFUSED, ENXIO, O_DIRECTORY, O_NONBLOCK, O_PATH};
+use log::{debug, warn};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{chown, MetadataExt, OpenOptionsExt};
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Byte `start` sends the state dir with.
+const START: u8 = b's';
+
+/// Byte sent back to `start` once the init process accepted the state dir.
+const ACK: u8 = b'1';
+
+/// How long the init process waits for a connected peer to send the state dir.
+const DIR_READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often `start` retries opening the legacy FIFO.
+const FIFO_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The container process' end of the start signal.
+/// `dir` is an O_PATH descriptor of the state dir.
+pub enum StartListener {
+    Socket {
+        listener: UnixListener,
+        dir: File,
+    },
+    /// With the FIFO's O_PATH descriptor.
+    Fifo {
+        fifo: File,
+        dir: File,
+    },
+}
+
+impl StartListener {
+    /// Sets up the start signal for the container whose state lives in `dirs`. Must be
+    /// called before the container process is cloned. `owner` is the host uid and gid of
+    /// the container's root, if it's in a user namespace.
+    pub fn new(dirs: &ContainerDirs, owner: Option<(u32, u32)>) -> Result<Self, ContainerErr> {
+        match bind_socket(dirs.dir()) {
+            Ok(listener) => {
+                let dir = OpenOptions::new()
+                    .read(true)
+                    .custom_flags(O_PATH | O_DIRECTORY)
+                    .open(dirs.dir())
+                    .map_err(|e| ContainerErr::StartSignal(e.to_string()))?;
+                Ok(Self::Socket { listener, dir })
+            }
+            Err(e) => {
+                warn!("start socket unavailable ({}), falling back to fifo", e);
+                let fifo_path = dirs.exec_fifo();
+                fifo(&fifo_path, owner)?;
+                let fifo = OpenOptions::new()
+                    .read(true)
+                    .custom_flags(O_PATH)
+                    .open(&fifo_path)
+                    .map_err(|e| ContainerErr::Fifo(e.to_string()))?;
+                let dir = OpenOptions::new()
+                    .read(true)
+                    .custom_flags(O_PATH | O_DIRECTORY)
+                    .open(dirs.dir())
+                    .map_err(|e| ContainerErr::Fifo(e.to_string()))?;
+                Ok(Self::Fifo { fifo, dir })
+            }
+        }
+    }
+
+    /// Blocks the container process until `start` sends the signal.
+    pub fn wait(self) -> Result<(), ContainerErr> {
+        match self {
+            Self::Socket { listener, dir } => loop {
+                let (mut conn, _) = match listener.accept() {
+                    Ok(conn) => conn,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(ContainerErr::StartSignal(e.to_string())),
+                };
+
+                let _ = conn.set_read_timeout(Some(DIR_READ_TIMEOUT));
+                if !sent_state_dir(&conn, &dir) {
+                    // Abstract sockets have no permissions, anyone can connect. Ignore
+                    // anything which couldn't open the state dir and keep waiting.
+                    debug!("ignoring start signal without the state dir");
+                    continue;
+                }
+
+                conn.write_all(&[ACK])
+                    .map_err(|e| ContainerErr::StartSignal(e.to_string()))?;
+                return Ok(());
+            },
+            Self::Fifo { fifo, dir } => {
+                debug!("opening fifo");
+                // Reopening the O_PATH descriptor checks the FIFO's own permissions, not
+                // those of the directories on the way to it.
+                let _ = OpenOptions::new()
+                    .read(true)
+                    .open(format!("/proc/self/fd/{}", fifo.as_raw_fd()))
+                    .map_err(|e| ContainerErr::Fifo(format!("err: {:?}", e)))?;
+                // `start` removes it as well, the container's root may not be allowed to.
+                if let Err(e) = unlinkat(dir.as_raw_fd(), Path::new(FIFO_FILENAME), 0) {
+                    debug!("failed to remove fifo: {}", e);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Sends the start signal to the container whose state lives in `dirs`, giving up if the
+/// container process hasn't picked it up within `timeout`. Fails with `AlreadyStarted` if
+/// the signal was picked up before, or the container process is gone.
+pub fn send_start(dirs: &ContainerDirs, timeout: Duration) -> Result<(), ContainerErr> {
+    let fifo_path = dirs.exec_fifo();
+
+    if fs::symlink_metadata(&fifo_path).is_err() {
+        debug!("sending start signal over socket");
+        let mut conn = connect(dirs)?;
+        conn.set_read_timeout(Some(timeout))
+            .and_then(|_| conn.set_write_timeout(Some(timeout)))
+            .map_err(ContainerErr::IO)?;
+        send_state_dir(&conn, dirs)?;
+
+        let mut ack = [0u8; 1];
+        conn.read_exact(&mut ack)
+            .map_err(|e| ContainerErr::StartSignal(format!("no acknowledgement: {}", e)))?;
+        return check_ack(ack[0]);
+    }
+
+    // Legacy FIFO handshake. The FIFO is single use so remove it once it's been opened,
+    // unless the init process already did.
+    debug!("opening FIFO");
+    open_fifo_writer(&fifo_path, timeout)?;
+    remove_fifo(&fifo_path);
+    Ok(())
+}
+
+/// Like `send_start`, but awaits the acknowledgement, or the reader of the legacy FIFO,
+/// instead of blocking the thread.
+#[cfg(feature = "tokio")]
+pub async fn send_start_async(dirs: &ContainerDirs, timeout: Duration) -> Result<(), ContainerErr> {
+    let fifo_path = dirs.exec_fifo();
+
+    if fs::symlink_metadata(&fifo_path).is_err() {
+        debug!("sending start signal over socket");
+        let conn = connect(dirs)?;
+        // Nothing was sent on the connection yet, the state dir fits in its buffer.
+        send_state_dir(&conn, dirs)?;
+        conn.set_nonblocking(true).map_err(ContainerErr::IO)?;
+        let conn = tokio::net::UnixStream::from_std(conn).map_err(ContainerErr::IO)?;
+
+        let mut ack = [0u8; 1];
+        let read = async {
+            loop {
+                conn.readable().await?;
+                match conn.try_read(&mut ack) {
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                    read => return read,
+                }
+            }
+        };
+        return match tokio::time::timeout(timeout, read).await {
+            Ok(Ok(1)) => check_ack(ack[0]),
+            Ok(Ok(_)) => Err(ContainerErr::StartSignal(String::from(
+                "no acknowledgement: connection closed",
+            ))),
+            Ok(Err(e)) => Err(ContainerErr::StartSignal(format!(
+                "no acknowledgement: {}",
+                e
+            ))),
+            Err(_) => Err(ContainerErr::StartSignal(format!(
+                "no acknowledgement within {:?}",
+                timeout
+            ))),
+        };
+    }
+
+    // A FIFO has no readiness for a reader showing up, keep retrying the open.
+    debug!("opening FIFO");
+    let deadline = tokio::time::Instant::now() + timeout;
+    while try_open_fifo_writer(&fifo_path)?.is_none() {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(fifo_timed_out(timeout));
+        }
+        tokio::time::sleep(FIFO_POLL_INTERVAL).await;
+    }
+    remove_fifo(&fifo_path);
+    Ok(())
+}
+
+fn already_started() -> ContainerErr {
+    ContainerErr::AlreadyStarted(String::from(
+        "the container process isn't waiting for the start signal",
+    ))
+}
+
+/// Connects to the container process' start socket.
+fn connect(dirs: &ContainerDirs) -> Result<UnixStream, ContainerErr> {
+    let addr = socket_addr(dirs.dir()).map_err(ContainerErr::IO)?;
+    // The init process stops listening once it got the state dir.
+    UnixStream::connect_addr(&addr).map_err(|e| match e.raw_os_error() {
+        Some(ECONNREFUSED) => already_started(),
+        _ => ContainerErr::StartSignal(format!("connect failed: {}", e)),
+    })
+}
+
+/// Sends the state dir, opened for reading, to the container process.
+fn send_state_dir(conn: &UnixStream, dirs: &ContainerDirs) -> Result<(), ContainerErr> {
+    let dir = OpenOptions::new()
+        .read(true)
+        .custom_flags(O_DIRECTORY)
+        .open(dirs.dir())
+        .map_err(ContainerErr::IO)?;
+    send_fd(conn.as_raw_fd(), dir.as_raw_fd(), &[START])
+        .map_err(|e| ContainerErr::StartSignal(e.to_string()))
+}
+
+fn check_ack(ack: u8) -> Result<(), ContainerErr> {
+    if ack != ACK {
+        return Err(ContainerErr::StartSignal(String::from(
+            "unexpected acknowledgement",
+        )));
+    }
+    Ok(())
+}
+
+/// Opens the write end of a FIFO without blocking, None while there's no reader. A
+/// non-blocking open for writing fails with ENXIO until there is one.
+fn try_open_fifo_writer(fifo_path: &Path) -> Result<Option<File>, ContainerErr> {
+    match OpenOptions::new()
+        .write(true)
+        .custom_flags(O_NONBLOCK)
+        .open(fifo_path)
+    {
+        Ok(f) => Ok(Some(f)),
+        Err(e) if e.raw_os_error() == Some(ENXIO) => Ok(None),
+        Err(e) => Err(ContainerErr::Fifo(format!("err: {:?}", e))),
+    }
+}
+
+/// Opens the write end of a FIFO without blocking forever, retrying until the deadline.
+fn open_fifo_writer(fifo_path: &Path, timeout: Duration) -> Result<File, ContainerErr> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(f) = try_open_fifo_writer(fifo_path)? {
+            return Ok(f);
+        }
+        if Instant::now() >= deadline {
+            return Err(fifo_timed_out(timeout));
+        }
+        thread::sleep(FIFO_POLL_INTERVAL);
+    }
+}
+
+fn fifo_timed_out(timeout: Duration) -> ContainerErr {
+    ContainerErr::Fifo(format!(
+        "container process did not open the fifo within {:?}",
+        timeout
+    ))
+}
+
+/// Removes the opened FIFO, unless the init process already did.
+fn remove_fifo(fifo_path: &Path) {
+    match fs::remove_file(fifo_path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            warn!("failed to remove fifo {:?}: {}", fifo_path, e)
+        }
+        _ => {}
+    }
+    debug!("done with fifo");
+}
+
+/// Abstract socket names are global within a network namespace, so derive the name from
+/// the container's state dir which is unique per runtime instance and container.
+fn socket_addr(container_dir: &Path) -> std::io::Result<SocketAddr> {
+    let mut name = container_dir.as_os_str().as_bytes().to_vec();
+    name.extend_from_slice(b"/start");
+    SocketAddr::from_abstract_name(name)
+}
+
+fn bind_socket(container_dir: &Path) -> std::io::Result<UnixListener> {
+    UnixListener::bind_addr(&socket_addr(container_dir)?)
+}
+
+/// Whether the peer on `conn` sent the state dir `dir` refers to, opened for reading.
+/// O_PATH descriptors don't count, they only need search permission on the way to it.
+fn sent_state_dir(conn: &UnixStream, dir: &File) -> bool {
+    let mut data = [0u8; 1];
+    let Ok((1, Some(received))) = recv_fd(conn.as_raw_fd(), &mut data) else {
+        return false;
+    };
+    let flags = unsafe { libc::fcntl(received.as_raw_fd(), libc::F_GETFL) };
+    if data[0] != START || flags == -1 || flags & O_PATH != 0 {
+        return false;
+    }
+    let (Ok(received), Ok(dir)) = (File::from(received).metadata(), dir.metadata()) else {
+        return false;
+    };
+    received.dev() == dir.dev() && received.ino() == dir.ino()
+}
+
+/// Creates a FIFO only `owner`, root by default, can open.
+fn fifo<P: AsRef<Path>>(path: P, owner: Option<(u32, u32)>) -> Result<(), ContainerErr> {
+    debug!("creating fifo: {:?}", path.as_ref());
+    mkfifo(path.as_ref(), 0o600).map_err(|e| ContainerErr::Fifo(e.to_string()))?;
+    if let Some((uid, gid)) = owner {
+        chown(path.as_ref(), Some(uid), Some(gid))
+            .map_err(|e| ContainerErr::Fifo(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_socket_start_signal() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/start_signal_{}", time)));
+        dirs.create().unwrap();
+
+        let listener = StartListener::new(&dirs, None).unwrap();
+        assert!(matches!(listener, StartListener::Socket { .. }));
+
+        // Nothing is written to the state dir for it.
+        assert_eq!(0, fs::read_dir(dirs.dir()).unwrap().count());
+
+        // Connections without the state dir opened for reading must not unblock the
+        // container.
+        let addr = socket_addr(dirs.dir()).unwrap();
+        let mut bogus = UnixStream::connect_addr(&addr).unwrap();
+        bogus.write_all(b"s").unwrap();
+        let path_fd = OpenOptions::new()
+            .read(true)
+            .custom_flags(O_PATH | O_DIRECTORY)
+            .open(dirs.dir())
+            .unwrap();
+        let bogus_path = UnixStream::connect_addr(&addr).unwrap();
+        send_fd(bogus_path.as_raw_fd(), path_fd.as_raw_fd(), &[START]).unwrap();
+        let tmp = File::open("/tmp").unwrap();
+        let bogus_tmp = UnixStream::connect_addr(&addr).unwrap();
+        send_fd(bogus_tmp.as_raw_fd(), tmp.as_raw_fd(), &[START]).unwrap();
+
+        let waiter = thread::spawn(move || listener.wait());
+        send_start(&dirs, Duration::from_secs(5)).unwrap();
+        assert!(waiter.join().unwrap().is_ok());
+        assert!(matches!(
+            send_start(&dirs, Duration::from_secs(5)),
+            Err(ContainerErr::AlreadyStarted(_))
+        ));
+
+        dirs.remove().unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_socket_start_signal_async() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/start_async_{}", time)));
+        dirs.create().unwrap();
+        let listener = StartListener::new(&dirs, None).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let waiter = thread::spawn(move || listener.wait());
+        let timeout = Duration::from_secs(5);
+        runtime.block_on(send_start_async(&dirs, timeout)).unwrap();
+        assert!(waiter.join().unwrap().is_ok());
+        assert!(matches!(
+            runtime.block_on(send_start_async(&dirs, timeout)),
+            Err(ContainerErr::AlreadyStarted(_))
+        ));
+
+        // Nobody is reading the fifo, start gives up instead of hanging.
+        fifo(dirs.exec_fifo(), None).unwrap();
+        let result = runtime.block_on(send_start_async(&dirs, Duration::from_millis(50)));
+        assert!(matches!(result, Err(ContainerErr::Fifo(_))));
+
+        dirs.remove().unwrap();
+    }
+
+    #[test]
+    fn test_fifo_start_signal() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/start_fifo_ok_{}", time)));
+        dirs.create().unwrap();
+        let path = dirs.exec_fifo();
+        fifo(&path, None).unwrap();
+        assert_eq!(
+            0o600,
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777
+        );
+
+        let fifo = OpenOptions::new()
+            .read(true)
+            .custom_flags(O_PATH)
+            .open(&path)
+            .unwrap();
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(O_PATH | O_DIRECTORY)
+            .open(dirs.dir())
+            .unwrap();
+        let waiter = thread::spawn(move || StartListener::Fifo { fifo, dir }.wait());
+        let writer = open_fifo_writer(&path, Duration::from_secs(5)).unwrap();
+        assert!(waiter.join().unwrap().is_ok());
+        drop(writer);
+        // The init process removed it, starting again fails rather than waiting.
+        assert!(!path.exists());
+        assert!(matches!(
+            send_start(&dirs, Duration::from_secs(5)),
+            Err(ContainerErr::AlreadyStarted(_))
+        ));
+
+        dirs.remove().unwrap();
+    }
+
+    #[test]
+    fn test_fifo_start_signal_timeout() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/start_fifo_{}", time)));
+        dirs.create().unwrap();
+        fifo(dirs.exec_fifo(), None).unwrap();
+
+        // Nobody is reading the fifo, so start must give up instead of hanging.
+        let result = send_start(&dirs, Duration::from_millis(50));
+        assert!(matches!(result, Err(ContainerErr::Fifo(_))));
+
+        dirs.remove().unwrap();
+    }
+}
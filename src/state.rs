@@ -1,6 +1,12 @@
+use crate::ctx::CgroupManager;
+use crate::error::ContainerErr;
+use crate::health::Health;
+use libc::c_int;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 pub type Pid = u32;
 
@@ -16,6 +22,40 @@ pub struct State {
     status: Status,
     bundle: PathBuf,
     annotations: HashMap<String, String>,
+    // Extensions to the OCI state, so tools can find the container's cgroup.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cgroup_path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cgroup_manager: Option<CgroupManager>,
+    // Loop device backing an image rootfs, detached by delete.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    loop_device: Option<PathBuf>,
+    // Mounts outside a mount namespace of the container's own, unmounted by delete.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    host_mounts: Vec<PathBuf>,
+    // Pod the container was created in, see `pod`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pod: Option<String>,
+    // Times the monitor restarted the container, see `restart`.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    restart_count: u32,
+    // Result of the health check, if the container has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<Health>,
+    // Signal stopping the container's process, from its config, see `signal`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_signal: Option<c_int>,
+    // Set by `stop`, so the monitor doesn't restart the container.
+    #[serde(default, skip_serializing_if = "is_false")]
+    stop_requested: bool,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
 }
 
 impl State {
@@ -27,23 +67,154 @@ impl State {
             status: Status::Creating,
             bundle,
             annotations: HashMap::new(),
+            cgroup_path: None,
+            cgroup_manager: None,
+            loop_device: None,
+            host_mounts: Vec::new(),
+            pod: None,
+            restart_count: 0,
+            health: None,
+            stop_signal: None,
+            stop_requested: false,
         }
     }
 
+    /// Reads a state.json written by `State::write`, see `store::FileStore`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ContainerErr> {
+        let f = File::open(path).map_err(ContainerErr::IO)?;
+        serde_json::from_reader(f).map_err(|e| ContainerErr::State(e.to_string()))
+    }
+
+    /// Writes the state as json to `path`, replacing any existing state. The state is
+    /// written to a temporary file named after our pid and renamed over `path`, so readers
+    /// never see a partially written state.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), ContainerErr> {
+        let path = path.as_ref();
+        let raw_state =
+            serde_json::to_string(self).map_err(|e| ContainerErr::State(e.to_string()))?;
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(format!(".{}.tmp", std::process::id()));
+        let tmp_path = path.with_file_name(tmp_name);
+
+        let mut f = OpenOptions::new()
+            .truncate(true)
+            .create(true)
+            .write(true)
+            .open(&tmp_path)
+            .map_err(ContainerErr::IO)?;
+
+        f.write_all(raw_state.as_bytes())
+            .map_err(ContainerErr::IO)?;
+        fs::rename(&tmp_path, path).map_err(ContainerErr::IO)
+    }
+
+    pub fn set_annotations(&mut self, annotations: HashMap<String, String>) {
+        self.annotations = annotations;
+    }
+
+    pub fn annotations(&self) -> &HashMap<String, String> {
+        &self.annotations
+    }
+
+    /// Sets an annotation, replacing the config's annotation of the same name.
+    pub fn set_annotation(&mut self, key: String, value: String) {
+        self.annotations.insert(key, value);
+    }
+
     pub fn update_status(&mut self, status: Status) {
         self.status = status;
     }
 
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
     pub fn id(&self) -> &str {
         &self.container_id
     }
 
+    pub fn bundle(&self) -> &Path {
+        &self.bundle
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
     pub fn set_pid(&mut self, pid: Pid) {
         self.pid = pid;
     }
+
+    /// The container's cgroup, as resolved by create.
+    pub fn cgroup_path(&self) -> Option<&Path> {
+        self.cgroup_path.as_deref()
+    }
+
+    pub fn set_cgroup(&mut self, path: PathBuf, manager: CgroupManager) {
+        self.cgroup_path = Some(path);
+        self.cgroup_manager = Some(manager);
+    }
+
+    pub fn loop_device(&self) -> Option<&Path> {
+        self.loop_device.as_deref()
+    }
+
+    pub fn set_loop_device(&mut self, device: PathBuf) {
+        self.loop_device = Some(device);
+    }
+
+    /// See `rootfs::host_mounts`.
+    pub fn host_mounts(&self) -> &[PathBuf] {
+        &self.host_mounts
+    }
+
+    pub fn set_host_mounts(&mut self, mounts: Vec<PathBuf>) {
+        self.host_mounts = mounts;
+    }
+
+    pub fn pod(&self) -> Option<&str> {
+        self.pod.as_deref()
+    }
+
+    pub fn set_pod(&mut self, name: String) {
+        self.pod = Some(name);
+    }
+
+    pub fn restart_count(&self) -> u32 {
+        self.restart_count
+    }
+
+    pub fn set_restart_count(&mut self, count: u32) {
+        self.restart_count = count;
+    }
+
+    pub fn health(&self) -> Option<&Health> {
+        self.health.as_ref()
+    }
+
+    pub fn set_health(&mut self, health: Health) {
+        self.health = Some(health);
+    }
+
+    /// The signal to stop the container with, SIGTERM for containers created without one.
+    pub fn stop_signal(&self) -> c_int {
+        self.stop_signal.unwrap_or(libc::SIGTERM)
+    }
+
+    pub fn set_stop_signal(&mut self, signal: c_int) {
+        self.stop_signal = Some(signal);
+    }
+
+    pub fn stop_requested(&self) -> bool {
+        self.stop_requested
+    }
+
+    pub fn request_stop(&mut self) {
+        self.stop_requested = true;
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Status {
     #[serde(rename = "creating")]
     Creating,
@@ -68,4 +239,18 @@ mod tests {
         assert_eq!("{\"ociVersion\":\"1.0.1\",\"pid\":0,\"id\":\"foobar\",\"status\":\"creating\",\"bundle\":\"/blag/\",\"annotations\":{}}",
 		   serde_json::to_string(&state).unwrap());
     }
+
+    #[test]
+    fn test_stop_requested() {
+        let mut state = State::new(
+            String::from("foobar"),
+            PathBuf::from("/blag/"),
+            String::from("1.0.1"),
+        );
+        state.request_stop();
+        let raw = serde_json::to_string(&state).unwrap();
+        assert!(raw.ends_with(",\"stopRequested\":true}"));
+        let state: State = serde_json::from_str(&raw).unwrap();
+        assert!(state.stop_requested());
+    }
 }
@@ -0,0 +1,292 @@
+//! Where container states are kept.
+//!
+//! Commands go through a [`StateStore`] rather than reading and writing state.json
+//! themselves. [`FileStore`] is the real one, a state.json in each container's state dir
+//! with flock locks, [`MemoryStore`] keeps them in memory for tests and for anything
+//! managing containers from a single long running process.
+
+use crate::ctx::Ctx;
+use crate::error::ContainerErr;
+use crate::lock::ContainerLock;
+use crate::state::State;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::sync::{Arc, Condvar, Mutex};
+
+pub trait StateStore {
+    /// Exclusive hold on a container id, released when dropped.
+    type Lock;
+
+    /// Claims the state's id, failing if a container with it already exists. The state
+    /// isn't saved until `save`, `load` fails until then.
+    fn reserve(&self, state: &State) -> Result<(), ContainerErr>;
+
+    fn load(&self, container_id: &str) -> Result<State, ContainerErr>;
+
+    /// Saves the state, replacing the container's previous one.
+    fn save(&self, state: &State) -> Result<(), ContainerErr>;
+
+    /// States of all containers, by id. Containers which are reserved but not saved yet
+    /// are left out.
+    fn list(&self) -> Result<Vec<State>, ContainerErr>;
+
+    /// Blocks until no one else holds the lock for `container_id`.
+    fn lock(&self, container_id: &str) -> Result<Self::Lock, ContainerErr>;
+
+    /// Forgets the container, it's fine if it doesn't exist.
+    fn delete(&self, container_id: &str) -> Result<(), ContainerErr>;
+}
+
+fn not_found(container_id: &str) -> ContainerErr {
+    ContainerErr::State(format!("Container: {} does not exist.", container_id))
+}
+
+fn already_exists(container_id: &str) -> ContainerErr {
+    ContainerErr::State(format!("Container: {} already exists.", container_id))
+}
+
+/// States as state.json in the containers' state dirs. Deleting a container removes its
+/// whole state dir.
+pub struct FileStore<'a> {
+    ctx: &'a Ctx,
+}
+
+impl<'a> FileStore<'a> {
+    pub fn new(ctx: &'a Ctx) -> Self {
+        Self { ctx }
+    }
+}
+
+impl StateStore for FileStore<'_> {
+    type Lock = ContainerLock;
+
+    fn reserve(&self, state: &State) -> Result<(), ContainerErr> {
+        let dirs = self.ctx.container_dirs(state.id());
+        dirs.create()?;
+        // An empty state.json, created with O_EXCL.
+        let reserved = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dirs.state());
+        match reserved {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(already_exists(state.id())),
+            Err(e) => Err(ContainerErr::IO(e)),
+        }
+    }
+
+    fn load(&self, container_id: &str) -> Result<State, ContainerErr> {
+        State::load(self.ctx.container_dirs(container_id).state())
+    }
+
+    fn save(&self, state: &State) -> Result<(), ContainerErr> {
+        let dirs = self.ctx.container_dirs(state.id());
+        dirs.create()?;
+        state.write(dirs.state())
+    }
+
+    fn list(&self) -> Result<Vec<State>, ContainerErr> {
+        let mut states = Vec::new();
+        for entry in fs::read_dir(&self.ctx.state_dir).map_err(ContainerErr::IO)? {
+            let entry = entry.map_err(ContainerErr::IO)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // The locks and pods dirs aren't containers.
+            if name.starts_with('.') {
+                continue;
+            }
+            // Reserved, or removed since we read the dir.
+            if let Ok(state) = self.load(&name) {
+                states.push(state);
+            }
+        }
+        states.sort_by(|a, b| a.id().cmp(b.id()));
+        Ok(states)
+    }
+
+    fn lock(&self, container_id: &str) -> Result<ContainerLock, ContainerErr> {
+        ContainerLock::acquire(self.ctx, container_id)
+    }
+
+    fn delete(&self, container_id: &str) -> Result<(), ContainerErr> {
+        self.ctx.container_dirs(container_id).remove()
+    }
+}
+
+/// Ids which are locked, shared with the locks so they can let go when dropped.
+type Locked = Arc<(Mutex<HashSet<String>>, Condvar)>;
+
+/// States in memory, gone with the process.
+#[derive(Default)]
+pub struct MemoryStore {
+    /// None for reserved containers.
+    states: Mutex<HashMap<String, Option<State>>>,
+    locked: Locked,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Lock on a container id in a `MemoryStore`.
+pub struct MemoryLock {
+    locked: Locked,
+    container_id: String,
+}
+
+impl Drop for MemoryLock {
+    fn drop(&mut self) {
+        let (ids, released) = &*self.locked;
+        ids.lock().unwrap().remove(&self.container_id);
+        released.notify_all();
+    }
+}
+
+impl StateStore for MemoryStore {
+    type Lock = MemoryLock;
+
+    fn reserve(&self, state: &State) -> Result<(), ContainerErr> {
+        let mut states = self.states.lock().unwrap();
+        if states.contains_key(state.id()) {
+            return Err(already_exists(state.id()));
+        }
+        states.insert(state.id().to_string(), None);
+        Ok(())
+    }
+
+    fn load(&self, container_id: &str) -> Result<State, ContainerErr> {
+        match self.states.lock().unwrap().get(container_id) {
+            Some(Some(state)) => Ok(state.clone()),
+            _ => Err(not_found(container_id)),
+        }
+    }
+
+    fn save(&self, state: &State) -> Result<(), ContainerErr> {
+        self.states
+            .lock()
+            .unwrap()
+            .insert(state.id().to_string(), Some(state.clone()));
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<State>, ContainerErr> {
+        let mut states: Vec<State> = self
+            .states
+            .lock()
+            .unwrap()
+            .values()
+            .flatten()
+            .cloned()
+            .collect();
+        states.sort_by(|a, b| a.id().cmp(b.id()));
+        Ok(states)
+    }
+
+    fn lock(&self, container_id: &str) -> Result<MemoryLock, ContainerErr> {
+        let (ids, released) = &*self.locked;
+        let mut ids = ids.lock().unwrap();
+        while ids.contains(container_id) {
+            ids = released.wait(ids).unwrap();
+        }
+        ids.insert(container_id.to_string());
+        Ok(MemoryLock {
+            locked: self.locked.clone(),
+            container_id: container_id.to_string(),
+        })
+    }
+
+    fn delete(&self, container_id: &str) -> Result<(), ContainerErr> {
+        self.states.lock().unwrap().remove(container_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Status;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn state(id: &str) -> State {
+        State::new(
+            id.to_string(),
+            PathBuf::from("/bundle"),
+            String::from("1.0.1"),
+        )
+    }
+
+    /// What every store has to do.
+    fn check_store<S: StateStore>(store: &S) {
+        assert!(store.load("a").is_err());
+        store.reserve(&state("b")).unwrap();
+        assert!(store.reserve(&state("b")).is_err());
+        // Reserved containers aren't listed until they're saved.
+        assert!(store.list().unwrap().is_empty());
+
+        let mut b = state("b");
+        b.update_status(Status::Created);
+        store.save(&b).unwrap();
+        store.save(&state("a")).unwrap();
+        assert_eq!(Status::Created, *store.load("b").unwrap().status());
+        let ids: Vec<String> = store
+            .list()
+            .unwrap()
+            .iter()
+            .map(|s| s.id().to_string())
+            .collect();
+        assert_eq!(vec!["a", "b"], ids);
+
+        store.delete("b").unwrap();
+        store.delete("b").unwrap();
+        assert!(store.load("b").is_err());
+        assert_eq!(1, store.list().unwrap().len());
+        // Deleted ids can be claimed again.
+        store.reserve(&state("b")).unwrap();
+
+        let _lock = store.lock("a").unwrap();
+        let _other = store.lock("b").unwrap();
+    }
+
+    #[test]
+    fn test_file_store() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let mut ctx = Ctx::default();
+        ctx.state_dir = PathBuf::from(format!("/tmp/file_store_{}", time));
+        fs::create_dir(&ctx.state_dir).unwrap();
+        check_store(&FileStore::new(&ctx));
+        fs::remove_dir_all(&ctx.state_dir).unwrap();
+    }
+
+    #[test]
+    fn test_memory_store() {
+        check_store(&MemoryStore::new());
+
+        // The lock serializes updates of the same container.
+        let store = Arc::new(MemoryStore::new());
+        store.save(&state("c")).unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        let _lock = store.lock("c").unwrap();
+                        let mut state = store.load("c").unwrap();
+                        state.set_restart_count(state.restart_count() + 1);
+                        store.save(&state).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(400, store.load("c").unwrap().restart_count());
+    }
+}
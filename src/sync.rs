@@ -0,0 +1,107 @@
+//! Messages exchanged over pipes between `create` and the container process while the
+//! container is being initialized.
+
+use crate::error::ContainerErr;
+use libc::{c_int, c_void, read, write};
+use log::debug;
+use std::io::{Error, ErrorKind};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyncMsg {
+    /// Container process is ready and waiting for the start signal.
+    Ready,
+    /// Container process failed to initialize.
+    Error,
+    /// Mounts are set up, the runtime should run the prestart and createRuntime hooks.
+    CreateRuntimeHooks,
+    /// The runtime has finished running hooks.
+    HooksDone,
+}
+
+impl SyncMsg {
+    fn to_raw(&self) -> c_int {
+        match self {
+            SyncMsg::Ready => 0,
+            SyncMsg::Error => 1,
+            SyncMsg::CreateRuntimeHooks => 2,
+            SyncMsg::HooksDone => 3,
+        }
+    }
+
+    fn from_raw(raw: c_int) -> Option<Self> {
+        match raw {
+            0 => Some(SyncMsg::Ready),
+            1 => Some(SyncMsg::Error),
+            2 => Some(SyncMsg::CreateRuntimeHooks),
+            3 => Some(SyncMsg::HooksDone),
+            _ => None,
+        }
+    }
+}
+
+/// Writes a message to a pipe, retrying interrupted writes.
+pub fn write_sync(fd: c_int, msg: SyncMsg) -> Result<(), ContainerErr> {
+    debug!("sync write: {:?}", msg);
+    let raw = msg.to_raw();
+    loop {
+        let n = unsafe { write(fd, &raw const raw as *const c_void, size_of_val(&raw)) };
+        if n == size_of_val(&raw) as isize {
+            return Ok(());
+        }
+
+        let err = Error::last_os_error();
+        if n == -1 && err.kind() == ErrorKind::Interrupted {
+            continue;
+        }
+        return Err(ContainerErr::Pipe(format!("sync write failed: {}", err)));
+    }
+}
+
+/// Reads a message from a pipe, retrying interrupted reads. Errors if the other end
+/// was closed without sending anything.
+pub fn read_sync(fd: c_int) -> Result<SyncMsg, ContainerErr> {
+    let mut raw: c_int = 0;
+    loop {
+        let n = unsafe { read(fd, &raw mut raw as *mut c_void, size_of_val(&raw)) };
+        if n == size_of_val(&raw) as isize {
+            break;
+        }
+
+        let err = Error::last_os_error();
+        if n == -1 && err.kind() == ErrorKind::Interrupted {
+            continue;
+        }
+        if n == 0 {
+            return Err(ContainerErr::Pipe(String::from(
+                "sync pipe closed unexpectedly",
+            )));
+        }
+        return Err(ContainerErr::Pipe(format!("sync read failed: {}", err)));
+    }
+
+    let msg = SyncMsg::from_raw(raw)
+        .ok_or_else(|| ContainerErr::Pipe(format!("unknown sync message: {}", raw)))?;
+    debug!("sync read: {:?}", msg);
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn test_sync_round_trip() {
+        let (reader, writer) = std::pipe::pipe().unwrap();
+        write_sync(writer.as_raw_fd(), SyncMsg::CreateRuntimeHooks).unwrap();
+        write_sync(writer.as_raw_fd(), SyncMsg::Ready).unwrap();
+        assert_eq!(
+            SyncMsg::CreateRuntimeHooks,
+            read_sync(reader.as_raw_fd()).unwrap()
+        );
+        assert_eq!(SyncMsg::Ready, read_sync(reader.as_raw_fd()).unwrap());
+
+        drop(writer);
+        assert!(read_sync(reader.as_raw_fd()).is_err());
+    }
+}
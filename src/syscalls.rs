@@ -0,0 +1,918 @@
+//! Safe wrappers for the raw syscalls we make.
+//!
+//! errno is captured right after the call, before anything else (logging included) gets a
+//! chance to overwrite it. Errors name the syscall and its arguments along with the decoded
+//! errno, e.g. `setns(5, 0x40000000): Operation not permitted (os error 1)`.
+
+use crate::libc_compat::{IoctlRequest, RlimitResource};
+use libc::{
+    c_char, c_int, c_long, c_uint, c_ulong, clone_args, dev_t, gid_t, mode_t, pid_t, pollfd,
+    rlimit, sock_filter, sock_fprog, statfs, statvfs, uid_t, SYS_clone3,
+};
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// A failed syscall, wrapped in an `io::Error` so callers keep the error kind.
+#[derive(Debug)]
+struct SyscallError {
+    call: String,
+    source: io::Error,
+}
+
+impl fmt::Display for SyscallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.call, self.source)
+    }
+}
+
+impl Error for SyscallError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// The errno of an error returned by one of these wrappers, or any other io::Error.
+pub fn errno(e: &io::Error) -> Option<c_int> {
+    e.raw_os_error().or_else(|| {
+        e.get_ref()
+            .and_then(|e| e.downcast_ref::<SyscallError>())
+            .and_then(|e| e.source.raw_os_error())
+    })
+}
+
+/// Builds the error for a failed call from the current errno.
+fn last_error(call: String) -> io::Error {
+    let source = io::Error::last_os_error();
+    io::Error::new(source.kind(), SyscallError { call, source })
+}
+
+fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// mount(2)
+pub fn mount(
+    src: &CStr,
+    target: &CStr,
+    fstype: &CStr,
+    flags: c_ulong,
+    data: Option<&CStr>,
+) -> io::Result<()> {
+    let data_ptr = data.map_or(std::ptr::null(), |d| d.as_ptr());
+    let ret = unsafe {
+        libc::mount(
+            src.as_ptr(),
+            target.as_ptr(),
+            fstype.as_ptr(),
+            flags,
+            data_ptr.cast(),
+        )
+    };
+    if ret == -1 {
+        return Err(last_error(format!(
+            "mount({:?}, {:?}, {:?}, {:#x}, {:?})",
+            src, target, fstype, flags, data
+        )));
+    }
+    Ok(())
+}
+
+/// umount2(2)
+pub fn umount2(target: &CStr, flags: c_int) -> io::Result<()> {
+    if unsafe { libc::umount2(target.as_ptr(), flags) } == -1 {
+        return Err(last_error(format!("umount2({:?}, {:#x})", target, flags)));
+    }
+    Ok(())
+}
+
+/// pivot_root(2), libc has no wrapper for it.
+pub fn pivot_root(new_root: &CStr, put_old: &CStr) -> io::Result<()> {
+    let ret = unsafe { libc::syscall(libc::SYS_pivot_root, new_root.as_ptr(), put_old.as_ptr()) };
+    if ret == -1 {
+        return Err(last_error(format!(
+            "pivot_root({:?}, {:?})",
+            new_root, put_old
+        )));
+    }
+    Ok(())
+}
+
+/// execve(2). Only returns if the exec failed.
+pub fn execve(path: &Path, argv: &[CString], envp: &[CString]) -> io::Error {
+    let c_path = match cstring(path) {
+        Ok(c_path) => c_path,
+        Err(e) => return e,
+    };
+    let argv_ptrs = null_terminated(argv);
+    let envp_ptrs = null_terminated(envp);
+    unsafe { libc::execve(c_path.as_ptr(), argv_ptrs.as_ptr(), envp_ptrs.as_ptr()) };
+    last_error(format!("execve({:?}, {:?})", path, argv))
+}
+
+/// The pointer array execve expects for argv and envp.
+fn null_terminated(strings: &[CString]) -> Vec<*const c_char> {
+    strings
+        .iter()
+        .map(|s| s.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect()
+}
+
+/// clone3(2), returns the child's pid in the parent and 0 in the child.
+///
+/// # Safety
+///
+/// Without a new stack the child continues on a copy of the parent's, like fork. Only
+/// the calling thread exists in the child, so the same restrictions as after fork apply.
+pub unsafe fn clone3(args: &mut clone_args) -> io::Result<pid_t> {
+    let ret: c_long = libc::syscall(SYS_clone3, args as *mut clone_args, size_of::<clone_args>());
+    if ret == -1 {
+        return Err(last_error(format!("clone3(flags: {:#x})", args.flags)));
+    }
+    Ok(ret as pid_t)
+}
+
+/// The legacy clone(2) without a new stack, for kernels without clone3. Returns the
+/// child's pid in the parent and 0 in the child.
+///
+/// # Safety
+///
+/// The same as for `clone3`.
+pub unsafe fn clone(flags: c_ulong) -> io::Result<pid_t> {
+    // The stack, parent and child tid and tls arguments are all unused.
+    let ret: c_long = libc::syscall(libc::SYS_clone, flags, 0, 0, 0, 0);
+    if ret == -1 {
+        return Err(last_error(format!("clone(flags: {:#x})", flags)));
+    }
+    Ok(ret as pid_t)
+}
+
+/// fork(2), returns the child's pid in the parent and 0 in the child.
+///
+/// # Safety
+///
+/// Only the calling thread exists in the child, so only async-signal-safe functions may
+/// be used if the process was multithreaded until it execs or exits.
+pub unsafe fn fork() -> io::Result<pid_t> {
+    let pid = libc::fork();
+    if pid == -1 {
+        return Err(last_error(String::from("fork()")));
+    }
+    Ok(pid)
+}
+
+/// waitpid(2), retried if interrupted. Returns the raw wait status. Children are waited
+/// for regardless of the signal they send their parent on exit.
+pub fn waitpid(pid: pid_t) -> io::Result<c_int> {
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, libc::__WALL) } != -1 {
+            return Ok(status);
+        }
+        let err = last_error(format!("waitpid({})", pid));
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// flock(2), retried if interrupted.
+pub fn flock(fd: RawFd, operation: c_int) -> io::Result<()> {
+    loop {
+        if unsafe { libc::flock(fd, operation) } != -1 {
+            return Ok(());
+        }
+        let err = last_error(format!("flock({}, {:#x})", fd, operation));
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// kill(2)
+pub fn kill(pid: pid_t, sig: c_int) -> io::Result<()> {
+    if unsafe { libc::kill(pid, sig) } == -1 {
+        return Err(last_error(format!("kill({}, {})", pid, sig)));
+    }
+    Ok(())
+}
+
+/// poll(2), returns the number of ready fds. An interrupted poll is an error of kind
+/// `Interrupted`.
+pub fn poll(fds: &mut [pollfd], timeout_ms: c_int) -> io::Result<c_int> {
+    let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+    if ret == -1 {
+        return Err(last_error(format!(
+            "poll({} fds, {}ms)",
+            fds.len(),
+            timeout_ms
+        )));
+    }
+    Ok(ret)
+}
+
+/// setsid(2)
+pub fn setsid() -> io::Result<pid_t> {
+    let sid = unsafe { libc::setsid() };
+    if sid == -1 {
+        return Err(last_error(String::from("setsid()")));
+    }
+    Ok(sid)
+}
+
+/// dup2(2)
+pub fn dup2(old: RawFd, new: RawFd) -> io::Result<()> {
+    if unsafe { libc::dup2(old, new) } == -1 {
+        return Err(last_error(format!("dup2({}, {})", old, new)));
+    }
+    Ok(())
+}
+
+/// close_range(2), libc only wraps it for glibc.
+pub fn close_range(first: c_uint, last: c_uint, flags: c_uint) -> io::Result<()> {
+    if unsafe { libc::syscall(libc::SYS_close_range, first, last, flags) } == -1 {
+        return Err(last_error(format!(
+            "close_range({}, {}, {:#x})",
+            first, last, flags
+        )));
+    }
+    Ok(())
+}
+
+/// Sets FD_CLOEXEC on `fd` with fcntl(2).
+pub fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 {
+        return Err(last_error(format!("fcntl({}, F_GETFD)", fd)));
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+        return Err(last_error(format!("fcntl({}, F_SETFD)", fd)));
+    }
+    Ok(())
+}
+
+/// Clears FD_CLOEXEC on `fd` with fcntl(2), so it's inherited across execve.
+pub fn clear_cloexec(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 {
+        return Err(last_error(format!("fcntl({}, F_GETFD)", fd)));
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) } == -1 {
+        return Err(last_error(format!("fcntl({}, F_SETFD)", fd)));
+    }
+    Ok(())
+}
+
+/// Sets O_NONBLOCK on `fd` with fcntl(2).
+pub fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(last_error(format!("fcntl({}, F_GETFL)", fd)));
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } == -1 {
+        return Err(last_error(format!("fcntl({}, F_SETFL)", fd)));
+    }
+    Ok(())
+}
+
+/// sigaction(2), installs `handler` for `sig` with SA_RESTART so that blocking calls
+/// interrupted by it are restarted.
+pub fn sigaction(sig: c_int, handler: extern "C" fn(c_int)) -> io::Result<()> {
+    let mut action = unsafe { std::mem::zeroed::<libc::sigaction>() };
+    action.sa_sigaction = handler as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    if unsafe { libc::sigaction(sig, &action, std::ptr::null_mut()) } == -1 {
+        return Err(last_error(format!("sigaction({})", sig)));
+    }
+    Ok(())
+}
+
+/// sigaction(2), restores the default disposition of `sig`.
+pub fn sigaction_default(sig: c_int) -> io::Result<()> {
+    let mut action = unsafe { std::mem::zeroed::<libc::sigaction>() };
+    action.sa_sigaction = libc::SIG_DFL;
+    if unsafe { libc::sigaction(sig, &action, std::ptr::null_mut()) } == -1 {
+        return Err(last_error(format!("sigaction({}, SIG_DFL)", sig)));
+    }
+    Ok(())
+}
+
+/// sigprocmask(2), unblocks every signal.
+pub fn unblock_signals() -> io::Result<()> {
+    let mut set = unsafe { std::mem::zeroed::<libc::sigset_t>() };
+    unsafe { libc::sigemptyset(&mut set) };
+    if unsafe { libc::sigprocmask(libc::SIG_SETMASK, &set, std::ptr::null_mut()) } == -1 {
+        return Err(last_error(String::from("sigprocmask(SIG_SETMASK)")));
+    }
+    Ok(())
+}
+
+/// setgid(2)
+pub fn setgid(gid: gid_t) -> io::Result<()> {
+    if unsafe { libc::setgid(gid) } == -1 {
+        return Err(last_error(format!("setgid({})", gid)));
+    }
+    Ok(())
+}
+
+/// setgroups(2)
+pub fn setgroups(groups: &[gid_t]) -> io::Result<()> {
+    if unsafe { libc::setgroups(groups.len(), groups.as_ptr()) } == -1 {
+        return Err(last_error(format!("setgroups({:?})", groups)));
+    }
+    Ok(())
+}
+
+/// setuid(2)
+pub fn setuid(uid: uid_t) -> io::Result<()> {
+    if unsafe { libc::setuid(uid) } == -1 {
+        return Err(last_error(format!("setuid({})", uid)));
+    }
+    Ok(())
+}
+
+/// prctl(2) for options taking up to two unsigned long arguments.
+pub fn prctl(option: c_int, arg2: c_ulong, arg3: c_ulong) -> io::Result<()> {
+    if unsafe { libc::prctl(option, arg2, arg3, 0, 0) } == -1 {
+        return Err(last_error(format!("prctl({}, {}, {})", option, arg2, arg3)));
+    }
+    Ok(())
+}
+
+/// prctl(2) PR_SCHED_CORE, `cmd` on the task `pid` and those in its `scope`.
+pub fn prctl_sched_core(cmd: c_int, pid: pid_t, scope: c_int) -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SCHED_CORE, cmd, pid, scope, 0) } == -1 {
+        return Err(last_error(format!(
+            "prctl(PR_SCHED_CORE, {}, {}, {})",
+            cmd, pid, scope
+        )));
+    }
+    Ok(())
+}
+
+/// fstatfs(2)
+pub fn fstatfs(fd: RawFd) -> io::Result<statfs> {
+    let mut buf = unsafe { std::mem::zeroed::<statfs>() };
+    if unsafe { libc::fstatfs(fd, &mut buf) } == -1 {
+        return Err(last_error(format!("fstatfs({})", fd)));
+    }
+    Ok(buf)
+}
+
+/// fstatvfs(3), unlike fstatfs it has the mount's flags.
+pub fn fstatvfs(fd: RawFd) -> io::Result<statvfs> {
+    let mut buf = unsafe { std::mem::zeroed::<statvfs>() };
+    if unsafe { libc::fstatvfs(fd, &mut buf) } == -1 {
+        return Err(last_error(format!("fstatvfs({})", fd)));
+    }
+    Ok(buf)
+}
+
+/// Id of the mount `fd` is on, from statx(2) with STATX_MNT_ID.
+pub fn mount_id(fd: RawFd) -> io::Result<u64> {
+    // linux/stat.h. libc only has struct statx for glibc, it's 256 bytes with the u32
+    // stx_mask first and stx_mnt_id at offset 144.
+    const STATX_MNT_ID: u32 = 0x1000;
+    let mut buf = [0u64; 32];
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_statx,
+            fd,
+            c"".as_ptr(),
+            libc::AT_EMPTY_PATH,
+            STATX_MNT_ID,
+            buf.as_mut_ptr(),
+        )
+    };
+    if ret == -1 {
+        return Err(last_error(format!("statx({}, STATX_MNT_ID)", fd)));
+    }
+    if buf[0] as u32 & STATX_MNT_ID == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "statx didn't return a mount id",
+        ));
+    }
+    Ok(buf[18])
+}
+
+/// ioctl(2) with an integer or pointer argument.
+pub fn ioctl(fd: RawFd, request: IoctlRequest, arg: c_ulong) -> io::Result<c_int> {
+    let ret = unsafe { libc::ioctl(fd, request, arg) };
+    if ret == -1 {
+        return Err(last_error(format!("ioctl({}, {:#x})", fd, request)));
+    }
+    Ok(ret)
+}
+
+/// sendmsg(2) of `data` with `fd` attached as SCM_RIGHTS, over the unix socket `socket`.
+/// `data` can't be empty, stream sockets don't deliver control messages without it.
+pub fn send_fd(socket: RawFd, fd: RawFd, data: &[u8]) -> io::Result<()> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let fd_len = std::mem::size_of::<RawFd>() as c_uint;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fd_len) } as usize];
+    let mut msg = unsafe { std::mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+    }
+    if unsafe { libc::sendmsg(socket, &msg, 0) } == -1 {
+        return Err(last_error(format!("sendmsg({}, fd {})", socket, fd)));
+    }
+    Ok(())
+}
+
+/// recvmsg(2) of `send_fd`'s message into `data`, returns its length and the fd attached
+/// to it, if any. The fd is close-on-exec.
+pub fn recv_fd(socket: RawFd, data: &mut [u8]) -> io::Result<(usize, Option<OwnedFd>)> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    let fd_len = std::mem::size_of::<RawFd>() as c_uint;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fd_len) } as usize];
+    let mut msg = unsafe { std::mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() as _;
+    let n = unsafe { libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if n == -1 {
+        return Err(last_error(format!("recvmsg({})", socket)));
+    }
+    let fd = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (!cmsg.is_null()
+            && (*cmsg).cmsg_level == libc::SOL_SOCKET
+            && (*cmsg).cmsg_type == libc::SCM_RIGHTS)
+            .then(|| {
+                OwnedFd::from_raw_fd(std::ptr::read_unaligned(
+                    libc::CMSG_DATA(cmsg).cast::<RawFd>(),
+                ))
+            })
+    };
+    Ok((n as usize, fd))
+}
+
+/// Installs a seccomp filter for the calling thread, which its children inherit.
+pub fn seccomp_set_filter(filter: &[sock_filter]) -> io::Result<()> {
+    let prog = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut sock_filter,
+    };
+    prctl(
+        libc::PR_SET_SECCOMP,
+        libc::SECCOMP_MODE_FILTER as c_ulong,
+        &prog as *const sock_fprog as c_ulong,
+    )
+}
+
+/// capset(2) for the current thread, each set is a bitmask of capability numbers.
+pub fn capset(effective: u64, permitted: u64, inheritable: u64) -> io::Result<()> {
+    // linux/capability.h, libc doesn't have these.
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: c_int,
+    }
+    #[repr(C)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    let header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    // Version 3 splits each 64 bit set into two 32 bit words, low word first.
+    let data = [0, 32].map(|shift| CapData {
+        effective: (effective >> shift) as u32,
+        permitted: (permitted >> shift) as u32,
+        inheritable: (inheritable >> shift) as u32,
+    });
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } == -1 {
+        return Err(last_error(format!(
+            "capset(effective: {:#x}, permitted: {:#x}, inheritable: {:#x})",
+            effective, permitted, inheritable
+        )));
+    }
+    Ok(())
+}
+
+/// struct sched_attr from linux/sched/types.h, libc doesn't have it. `size` is filled in
+/// by `sched_setattr`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SchedAttr {
+    pub size: u32,
+    pub policy: u32,
+    pub flags: u64,
+    pub nice: i32,
+    pub priority: u32,
+    pub runtime: u64,
+    pub deadline: u64,
+    pub period: u64,
+}
+
+/// sched_setattr(2) for the calling thread, libc has no wrapper for it.
+pub fn sched_setattr(attr: &mut SchedAttr) -> io::Result<()> {
+    attr.size = size_of::<SchedAttr>() as u32;
+    let ret = unsafe { libc::syscall(libc::SYS_sched_setattr, 0, attr as *const SchedAttr, 0) };
+    if ret == -1 {
+        return Err(last_error(format!("sched_setattr(0, {:?})", attr)));
+    }
+    Ok(())
+}
+
+/// landlock_create_ruleset(2) with LANDLOCK_CREATE_RULESET_VERSION, the highest Landlock
+/// ABI version the kernel supports.
+pub fn landlock_abi_version() -> io::Result<c_int> {
+    // linux/landlock.h, libc doesn't have it.
+    const LANDLOCK_CREATE_RULESET_VERSION: c_uint = 1;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<u64>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if ret == -1 {
+        return Err(last_error(String::from("landlock_create_ruleset(VERSION)")));
+    }
+    Ok(ret as c_int)
+}
+
+/// landlock_create_ruleset(2) handling the filesystem access rights `handled_access_fs`.
+pub fn landlock_create_ruleset(handled_access_fs: u64) -> io::Result<OwnedFd> {
+    // struct landlock_ruleset_attr, later ABI versions only add fields after this one.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &handled_access_fs as *const u64,
+            size_of::<u64>(),
+            0,
+        )
+    };
+    if ret == -1 {
+        return Err(last_error(format!(
+            "landlock_create_ruleset({:#x})",
+            handled_access_fs
+        )));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(ret as RawFd) })
+}
+
+/// landlock_add_rule(2) allowing `allowed_access` beneath the file `parent_fd` refers to.
+pub fn landlock_add_path_rule(
+    ruleset: RawFd,
+    allowed_access: u64,
+    parent_fd: RawFd,
+) -> io::Result<()> {
+    // linux/landlock.h, libc doesn't have these.
+    const LANDLOCK_RULE_PATH_BENEATH: c_int = 1;
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    let attr = PathBeneathAttr {
+        allowed_access,
+        parent_fd,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    if ret == -1 {
+        return Err(last_error(format!(
+            "landlock_add_rule({}, {:#x}, {})",
+            ruleset, allowed_access, parent_fd
+        )));
+    }
+    Ok(())
+}
+
+/// landlock_restrict_self(2), enforces the ruleset on the calling thread and its children.
+pub fn landlock_restrict_self(ruleset: RawFd) -> io::Result<()> {
+    if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) } == -1 {
+        return Err(last_error(format!("landlock_restrict_self({})", ruleset)));
+    }
+    Ok(())
+}
+
+/// An eBPF instruction, struct bpf_insn from linux/bpf.h. libc only has classic BPF's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct BpfInsn {
+    pub code: u8,
+    /// The destination register in the low four bits, the source in the high ones.
+    pub regs: u8,
+    pub off: i16,
+    pub imm: i32,
+}
+
+// bpf(2) commands from linux/bpf.h, libc doesn't have them.
+const BPF_PROG_LOAD: c_int = 5;
+const BPF_PROG_ATTACH: c_int = 8;
+const BPF_PROG_DETACH: c_int = 9;
+const BPF_PROG_GET_FD_BY_ID: c_int = 13;
+const BPF_PROG_QUERY: c_int = 16;
+
+/// The members of union bpf_attr for attaching and detaching programs.
+#[repr(C)]
+#[derive(Default)]
+struct BpfAttachAttr {
+    target_fd: u32,
+    attach_bpf_fd: u32,
+    attach_type: u32,
+    attach_flags: u32,
+}
+
+/// bpf(2) with the member of union bpf_attr for `cmd`. Later kernels only add members
+/// after the ones we pass.
+fn bpf<T>(cmd: c_int, attr: &mut T) -> c_long {
+    unsafe { libc::syscall(libc::SYS_bpf, cmd, attr as *mut T, size_of::<T>() as c_uint) }
+}
+
+/// bpf(BPF_PROG_LOAD) of a GPL program `insns` of `prog_type`, for `expected_attach_type`.
+pub fn bpf_prog_load(
+    prog_type: u32,
+    expected_attach_type: u32,
+    insns: &[BpfInsn],
+) -> io::Result<OwnedFd> {
+    #[repr(C)]
+    struct ProgLoadAttr {
+        prog_type: u32,
+        insn_cnt: u32,
+        insns: u64,
+        license: u64,
+        log_level: u32,
+        log_size: u32,
+        log_buf: u64,
+        kern_version: u32,
+        prog_flags: u32,
+        prog_name: [u8; 16],
+        prog_ifindex: u32,
+        expected_attach_type: u32,
+    }
+
+    let license = c"GPL";
+    let mut attr = ProgLoadAttr {
+        prog_type,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 0,
+        log_size: 0,
+        log_buf: 0,
+        kern_version: 0,
+        prog_flags: 0,
+        prog_name: [0; 16],
+        prog_ifindex: 0,
+        expected_attach_type,
+    };
+    let ret = bpf(BPF_PROG_LOAD, &mut attr);
+    if ret == -1 {
+        return Err(last_error(format!(
+            "bpf(BPF_PROG_LOAD, type {}, {} instructions)",
+            prog_type,
+            insns.len()
+        )));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(ret as RawFd) })
+}
+
+/// bpf(BPF_PROG_ATTACH) of the program `prog` to the cgroup `target`.
+pub fn bpf_prog_attach(target: RawFd, prog: RawFd, attach_type: u32, flags: u32) -> io::Result<()> {
+    let mut attr = BpfAttachAttr {
+        target_fd: target as u32,
+        attach_bpf_fd: prog as u32,
+        attach_type,
+        attach_flags: flags,
+    };
+    if bpf(BPF_PROG_ATTACH, &mut attr) == -1 {
+        return Err(last_error(format!(
+            "bpf(BPF_PROG_ATTACH, {}, {}, {}, {:#x})",
+            target, prog, attach_type, flags
+        )));
+    }
+    Ok(())
+}
+
+/// bpf(BPF_PROG_DETACH) of the program `prog` from the cgroup `target`.
+pub fn bpf_prog_detach(target: RawFd, prog: RawFd, attach_type: u32) -> io::Result<()> {
+    let mut attr = BpfAttachAttr {
+        target_fd: target as u32,
+        attach_bpf_fd: prog as u32,
+        attach_type,
+        ..Default::default()
+    };
+    if bpf(BPF_PROG_DETACH, &mut attr) == -1 {
+        return Err(last_error(format!(
+            "bpf(BPF_PROG_DETACH, {}, {}, {})",
+            target, prog, attach_type
+        )));
+    }
+    Ok(())
+}
+
+/// bpf(BPF_PROG_QUERY), the ids of the programs attached to the cgroup `target` itself.
+pub fn bpf_prog_query(target: RawFd, attach_type: u32) -> io::Result<Vec<u32>> {
+    #[repr(C)]
+    struct QueryAttr {
+        target_fd: u32,
+        attach_type: u32,
+        query_flags: u32,
+        attach_flags: u32,
+        prog_ids: u64,
+        prog_cnt: u32,
+    }
+
+    // The first call only counts them.
+    let mut ids: Vec<u32> = Vec::new();
+    loop {
+        let mut attr = QueryAttr {
+            target_fd: target as u32,
+            attach_type,
+            query_flags: 0,
+            attach_flags: 0,
+            prog_ids: ids.as_mut_ptr() as u64,
+            prog_cnt: ids.len() as u32,
+        };
+        if bpf(BPF_PROG_QUERY, &mut attr) == -1 {
+            let e = last_error(format!("bpf(BPF_PROG_QUERY, {}, {})", target, attach_type));
+            // Programs were attached since counting them.
+            if errno(&e) == Some(libc::ENOSPC) {
+                ids = vec![0; ids.len() * 2];
+                continue;
+            }
+            return Err(e);
+        }
+        if attr.prog_cnt as usize <= ids.len() {
+            ids.truncate(attr.prog_cnt as usize);
+            return Ok(ids);
+        }
+        ids = vec![0; attr.prog_cnt as usize];
+    }
+}
+
+/// bpf(BPF_PROG_GET_FD_BY_ID)
+pub fn bpf_prog_get_fd_by_id(id: u32) -> io::Result<OwnedFd> {
+    #[repr(C)]
+    struct GetIdAttr {
+        prog_id: u32,
+        next_id: u32,
+        open_flags: u32,
+    }
+
+    let mut attr = GetIdAttr {
+        prog_id: id,
+        next_id: 0,
+        open_flags: 0,
+    };
+    let ret = bpf(BPF_PROG_GET_FD_BY_ID, &mut attr);
+    if ret == -1 {
+        return Err(last_error(format!("bpf(BPF_PROG_GET_FD_BY_ID, {})", id)));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(ret as RawFd) })
+}
+
+/// setns(2)
+pub fn unshare(flags: c_int) -> io::Result<()> {
+    if unsafe { libc::unshare(flags) } == -1 {
+        return Err(last_error(format!("unshare({:#x})", flags)));
+    }
+    Ok(())
+}
+
+pub fn setns(fd: RawFd, nstype: c_int) -> io::Result<()> {
+    if unsafe { libc::setns(fd, nstype) } == -1 {
+        return Err(last_error(format!("setns({}, {:#x})", fd, nstype)));
+    }
+    Ok(())
+}
+
+/// ioprio_set(2), libc has no wrapper for it.
+pub fn ioprio_set(which: c_int, who: c_int, ioprio: c_int) -> io::Result<()> {
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, which, who, ioprio) } == -1 {
+        return Err(last_error(format!(
+            "ioprio_set({}, {}, {:#x})",
+            which, who, ioprio
+        )));
+    }
+    Ok(())
+}
+
+/// getrlimit(2)
+pub fn getrlimit(resource: RlimitResource) -> io::Result<rlimit> {
+    let mut rlim = rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(resource, &mut rlim) } == -1 {
+        return Err(last_error(format!("getrlimit({})", resource)));
+    }
+    Ok(rlim)
+}
+
+/// setrlimit(2)
+pub fn setrlimit(resource: RlimitResource, rlim: &rlimit) -> io::Result<()> {
+    if unsafe { libc::setrlimit(resource, rlim) } == -1 {
+        return Err(last_error(format!(
+            "setrlimit({}, soft: {}, hard: {})",
+            resource, rlim.rlim_cur, rlim.rlim_max
+        )));
+    }
+    Ok(())
+}
+
+/// inotify_init1(2)
+pub fn inotify_init1(flags: c_int) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::inotify_init1(flags) };
+    if fd == -1 {
+        return Err(last_error(format!("inotify_init1({:#x})", flags)));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// inotify_add_watch(2), returns the watch descriptor.
+pub fn inotify_add_watch(fd: RawFd, path: &Path, mask: u32) -> io::Result<c_int> {
+    let c_path = cstring(path)?;
+    let wd = unsafe { libc::inotify_add_watch(fd, c_path.as_ptr(), mask) };
+    if wd == -1 {
+        return Err(last_error(format!(
+            "inotify_add_watch({}, {:?}, {:#x})",
+            fd, path, mask
+        )));
+    }
+    Ok(wd)
+}
+
+/// mkfifo(3)
+pub fn mkfifo(path: &Path, mode: mode_t) -> io::Result<()> {
+    let c_path = cstring(path)?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), mode) } == -1 {
+        return Err(last_error(format!("mkfifo({:?}, {:o})", path, mode)));
+    }
+    Ok(())
+}
+
+/// mknod(2)
+pub fn mknod(path: &Path, mode: mode_t, dev: dev_t) -> io::Result<()> {
+    let c_path = cstring(path)?;
+    if unsafe { libc::mknod(c_path.as_ptr(), mode, dev) } == -1 {
+        return Err(last_error(format!("mknod({:?}, {:o})", path, mode)));
+    }
+    Ok(())
+}
+
+/// unlinkat(2)
+pub fn unlinkat(dirfd: RawFd, path: &Path, flags: c_int) -> io::Result<()> {
+    let c_path = cstring(path)?;
+    if unsafe { libc::unlinkat(dirfd, c_path.as_ptr(), flags) } == -1 {
+        return Err(last_error(format!("unlinkat({}, {:?})", dirfd, path)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc::CLONE_NEWNET;
+
+    #[test]
+    fn test_error_context() {
+        let err = setns(-1, CLONE_NEWNET).unwrap_err();
+        let source = err.get_ref().and_then(|e| e.source()).unwrap();
+        let source = source.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(Some(libc::EBADF), source.raw_os_error());
+        assert!(err.to_string().starts_with("setns(-1, 0x40000000): "));
+        assert!(err.to_string().contains("os error"));
+    }
+}
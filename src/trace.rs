@@ -0,0 +1,323 @@
+//! Timing of the container lifecycle's phases.
+//!
+//! Each phase runs inside a `tracing` span, e.g. `debug_span!("clone", container_id =
+//! %id)`, and the subscriber `init` installs reports how long it took when it closes:
+//! through the debug log by default, or with `--trace-output json` a JSON object per line
+//! on the runtime's stderr, e.g.
+//! `{"span":"clone","containerId":"foo","pid":4242,"durationUs":1520}`. Phases run in
+//! whichever process does the work. The JSON goes to a descriptor of its own, which the
+//! monitor and the container process inherit, so they never write spans to the
+//! container's stdio. Both outlive create, once it returns they send their spans to the
+//! monitor's log in the state dir instead, see `release_output`.
+//!
+//! With `create --time-report` the spans are also recorded in `timings.jsonl` in the
+//! container's state dir, as the same JSON objects. The monitor and the container process
+//! inherit the open file, so it collects the phases of every process involved in creating
+//! the container, and `start` adds its own if the file exists.
+
+use crate::ctx::TraceOutput;
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use log::debug;
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt::{self, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::fd::AsFd;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+/// Where spans are recorded, see `record`.
+static RECORDER: Mutex<Recorder> = Mutex::new(Recorder {
+    recording: false,
+    file: None,
+    pending: Vec::new(),
+});
+
+/// Where spans go with `--trace-output json`, none otherwise.
+static JSON_OUTPUT: Mutex<Option<File>> = Mutex::new(None);
+
+struct Recorder {
+    recording: bool,
+    file: Option<File>,
+    /// Lines of the spans which ended before there was a file to record them in.
+    pending: Vec<String>,
+}
+
+/// Installs the subscriber reporting spans to `output`. Spans are dropped before exec,
+/// and not at all in a process which exits instead.
+pub fn init(output: TraceOutput) {
+    let log = match output {
+        TraceOutput::Log if log::log_enabled!(log::Level::Debug) => Some(
+            tracing_subscriber::fmt::layer()
+                .with_writer(io::stderr)
+                .with_span_events(FmtSpan::CLOSE),
+        ),
+        TraceOutput::Log => None,
+        TraceOutput::Json => {
+            // Our own copy, stderr is the container's stdio in the container process.
+            match io::stderr().as_fd().try_clone_to_owned() {
+                Ok(fd) => *JSON_OUTPUT.lock().unwrap() = Some(File::from(fd)),
+                Err(e) => debug!("no json trace output: {}", e),
+            }
+            None
+        }
+    };
+    let subscriber = Registry::default().with(Timings).with(log);
+    if tracing::subscriber::set_global_default(subscriber).is_err() {
+        debug!("a tracing subscriber is already installed");
+    }
+}
+
+/// Sends the JSON spans to `log` from now on, for processes which outlive the command
+/// whose stderr they went to.
+pub fn release_output(log: &File) {
+    let mut output = JSON_OUTPUT.lock().unwrap();
+    if output.is_none() {
+        return;
+    }
+    match log.try_clone() {
+        Ok(log) => *output = Some(log),
+        Err(e) => debug!("failed to release trace output: {}", e),
+    }
+}
+
+/// Reports spans when they close, and records them, see `record`.
+struct Timings;
+
+/// What `Timings` keeps of an open span.
+struct Started {
+    container_id: String,
+    at: Instant,
+}
+
+impl<S> Layer<S> for Timings
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut container_id = ContainerId(String::new());
+        attrs.record(&mut container_id);
+        span.extensions_mut().insert(Started {
+            container_id: container_id.0,
+            at: Instant::now(),
+        });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let extensions = span.extensions();
+        let Some(started) = extensions.get::<Started>() else {
+            return;
+        };
+        let line = format!(
+            "{}\n",
+            json!({
+                "span": span.name(),
+                "containerId": started.container_id,
+                "pid": std::process::id(),
+                "durationUs": started.at.elapsed().as_micros() as u64,
+            })
+        );
+        // A line per write, so the processes sharing the output don't interleave.
+        if let Some(output) = &mut *JSON_OUTPUT.lock().unwrap() {
+            let _ = output.write_all(line.as_bytes());
+        }
+
+        let mut recorder = RECORDER.lock().unwrap();
+        if let Some(file) = &mut recorder.file {
+            if let Err(e) = file.write_all(line.as_bytes()) {
+                debug!("failed to record span {}: {}", span.name(), e);
+            }
+        } else if recorder.recording {
+            recorder.pending.push(line);
+        }
+    }
+}
+
+/// The container_id field of a span.
+struct ContainerId(String);
+
+impl Visit for ContainerId {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "container_id" {
+            self.0 = value.to_string();
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "container_id" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+/// Starts recording the spans which end from now on, they're kept until `record_to`.
+pub fn record() {
+    RECORDER.lock().unwrap().recording = true;
+}
+
+/// Records the spans in the state dir's timings file from now on, including those which
+/// ended since `record`.
+pub fn record_to(dirs: &ContainerDirs) -> Result<(), ContainerErr> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dirs.timings())
+        .map_err(ContainerErr::IO)?;
+    let mut recorder = RECORDER.lock().unwrap();
+    for line in recorder.pending.drain(..) {
+        file.write_all(line.as_bytes()).map_err(ContainerErr::IO)?;
+    }
+    recorder.recording = true;
+    recorder.file = Some(file);
+    Ok(())
+}
+
+/// Adds the spans from now on to the state dir's timings file, if the container was
+/// created with `--time-report`.
+pub fn resume(dirs: &ContainerDirs) -> Result<(), ContainerErr> {
+    match OpenOptions::new().append(true).open(dirs.timings()) {
+        Ok(file) => {
+            let mut recorder = RECORDER.lock().unwrap();
+            recorder.recording = true;
+            recorder.file = Some(file);
+            Ok(())
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(ContainerErr::IO(e)),
+    }
+}
+
+/// Stops recording spans, processes forked later don't either.
+pub fn stop_recording() {
+    let mut recorder = RECORDER.lock().unwrap();
+    recorder.recording = false;
+    recorder.file = None;
+    recorder.pending.clear();
+}
+
+/// A recorded span.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Timing {
+    pub span: String,
+    pub pid: u32,
+    pub duration_us: u64,
+}
+
+/// Reads the spans recorded in the state dir, in the order they ended.
+pub fn load_timings(dirs: &ContainerDirs) -> Result<Vec<Timing>, ContainerErr> {
+    let file = File::open(dirs.timings()).map_err(ContainerErr::IO)?;
+    let mut timings = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(ContainerErr::IO)?;
+        let timing = serde_json::from_str(&line)
+            .map_err(|e| ContainerErr::State(format!("invalid timing {:?}: {}", line, e)))?;
+        timings.push(timing);
+    }
+    Ok(timings)
+}
+
+/// Lays out the timings in a table, a phase per line with its duration and the process
+/// it ran in, followed by the total time taken.
+pub fn report(timings: &[Timing], total: Duration) -> String {
+    let width = timings
+        .iter()
+        .map(|t| t.span.len())
+        .chain([5])
+        .max()
+        .unwrap_or_default();
+    let mut report = String::new();
+    for timing in timings {
+        let duration = Duration::from_micros(timing.duration_us);
+        let _ = writeln!(
+            report,
+            "{:<width$}  {:>12}  pid {}",
+            timing.span,
+            format!("{:?}", duration),
+            timing.pid,
+            width = width
+        );
+    }
+    let _ = writeln!(
+        report,
+        "{:<width$}  {:>12}",
+        "total",
+        format!("{:?}", total),
+        width = width
+    );
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_record() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/trace_{}", time)));
+        dirs.create().unwrap();
+
+        // Tests run in threads of one process, this is the only one recording.
+        let subscriber = Registry::default().with(Timings);
+        tracing::subscriber::with_default(subscriber, || {
+            record();
+            drop(tracing::debug_span!("before", container_id = "timed").entered());
+            record_to(&dirs).unwrap();
+            drop(tracing::debug_span!("after", container_id = "timed").entered());
+            stop_recording();
+            drop(tracing::debug_span!("stopped", container_id = "timed").entered());
+        });
+
+        let spans: Vec<String> = load_timings(&dirs)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.span)
+            .collect();
+        assert_eq!(vec!["before", "after"], spans);
+        let recorded = std::fs::read_to_string(dirs.timings()).unwrap();
+        assert!(recorded.contains(r#""containerId":"timed""#));
+        dirs.remove().unwrap();
+    }
+
+    #[test]
+    fn test_report() {
+        let timings = [
+            Timing {
+                span: String::from("load-config"),
+                pid: 10,
+                duration_us: 1500,
+            },
+            Timing {
+                span: String::from("clone"),
+                pid: 11,
+                duration_us: 250,
+            },
+        ];
+        assert_eq!(
+            "load-config         1.5ms  pid 10\n\
+             clone               250µs  pid 11\n\
+             total                 5ms\n",
+            report(&timings, Duration::from_millis(5))
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Pseudo terminals for container processes started with `terminal: true`.
+//!
+//! The process gets a session of its own with the pty as its controlling terminal, so
+//! job control works. The master end is either forwarded to our stdio, with our terminal
+//! in raw mode, or handed to whoever listens on a console socket.
+
+use crate::error::ContainerErr;
+use crate::libc_compat::IoctlRequest;
+use crate::signal;
+use crate::syscalls;
+use libc::{c_ulong, O_CLOEXEC, O_NOCTTY, O_RDWR};
+use log::debug;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// A newly allocated pty pair.
+pub struct Pty {
+    pub master: File,
+    pub slave: File,
+    /// The slave's path in /dev/pts.
+    pub path: PathBuf,
+}
+
+impl Pty {
+    pub fn open() -> Result<Self, ContainerErr> {
+        let fd = unsafe { libc::posix_openpt(O_RDWR | O_NOCTTY | O_CLOEXEC) };
+        if fd == -1 {
+            return Err(ContainerErr::IO(io::Error::last_os_error()));
+        }
+        let master = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        if unsafe { libc::unlockpt(master.as_raw_fd()) } == -1 {
+            return Err(ContainerErr::IO(io::Error::last_os_error()));
+        }
+
+        let mut name = [0u8; 128];
+        let err =
+            unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr().cast(), name.len()) };
+        if err != 0 {
+            return Err(ContainerErr::IO(io::Error::from_raw_os_error(err)));
+        }
+        let name = CStr::from_bytes_until_nul(&name)
+            .map_err(|e| ContainerErr::IO(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        let path = PathBuf::from(name.to_string_lossy().as_ref());
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(O_NOCTTY | O_CLOEXEC)
+            .open(&path)
+            .map_err(ContainerErr::IO)?;
+
+        Ok(Self {
+            master,
+            slave,
+            path,
+        })
+    }
+}
+
+/// Runs in the process about to be exec'd: starts a new session, with the pty's slave as
+/// its controlling terminal and stdio.
+pub fn set_controlling_terminal(pty: &Pty) -> Result<(), ContainerErr> {
+    syscalls::setsid().map_err(ContainerErr::IO)?;
+    dup_stdio(pty.slave.as_raw_fd())?;
+    let request = libc::TIOCSCTTY as IoctlRequest;
+    syscalls::ioctl(libc::STDIN_FILENO, request, 0)
+        .map(|_| ())
+        .map_err(ContainerErr::IO)
+}
+
+/// Sends the pty's master to the unix socket at `socket`, like runc's `--console-socket`:
+/// the fd as SCM_RIGHTS, with the pty's path as the message.
+pub fn send_console(socket: &Path, pty: &Pty) -> Result<(), ContainerErr> {
+    let stream = UnixStream::connect(socket).map_err(ContainerErr::IO)?;
+    let path = pty.path.to_string_lossy();
+    syscalls::send_fd(stream.as_raw_fd(), pty.master.as_raw_fd(), path.as_bytes())
+        .map_err(ContainerErr::IO)
+}
+
+/// Our terminal in raw mode, so that input like ctrl-c reaches the process on the pty as
+/// is rather than being acted on by us. Restored when dropped.
+struct RawMode {
+    fd: RawFd,
+    saved: libc::termios,
+}
+
+impl RawMode {
+    /// None if `fd` isn't a terminal.
+    fn enable(fd: RawFd) -> Option<Self> {
+        let mut saved = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(fd, &mut saved) } == -1 {
+            return None;
+        }
+        let mut raw = saved;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } == -1 {
+            debug!("can't make {} raw: {}", fd, io::Error::last_os_error());
+            return None;
+        }
+        Some(Self { fd, saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved) };
+    }
+}
+
+/// Makes `fd` the stdin, stdout and stderr of the current process.
+pub fn dup_stdio(fd: RawFd) -> Result<(), ContainerErr> {
+    for target in 0..=2 {
+        if unsafe { libc::dup2(fd, target) } == -1 {
+            return Err(ContainerErr::IO(io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// Sets the size of the terminal `fd` refers to. The kernel sends the terminal's
+/// foreground process group SIGWINCH, so it works on either end of a pty.
+pub fn set_size(fd: RawFd, rows: u16, cols: u16) -> Result<(), ContainerErr> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let request = libc::TIOCSWINSZ as IoctlRequest;
+    syscalls::ioctl(fd, request, &size as *const libc::winsize as c_ulong)
+        .map(|_| ())
+        .map_err(ContainerErr::IO)
+}
+
+/// The rows and columns of the terminal `fd` refers to, None if it isn't a terminal.
+fn size(fd: RawFd) -> Option<(u16, u16)> {
+    let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+    let request = libc::TIOCGWINSZ as IoctlRequest;
+    syscalls::ioctl(fd, request, &mut size as *mut libc::winsize as c_ulong).ok()?;
+    Some((size.ws_row, size.ws_col))
+}
+
+/// Keeps the pty's size in sync with the terminal on our stdin, if there is one: copies
+/// its size now and again whenever it's resized, which we learn about from SIGWINCH.
+fn forward_resize(master: &File) -> Result<(), ContainerErr> {
+    let Some((rows, cols)) = size(libc::STDIN_FILENO) else {
+        return Ok(());
+    };
+    set_size(master.as_raw_fd(), rows, cols)?;
+
+    let mut resized = signal::notify(&[libc::SIGWINCH])?;
+    let master = master.try_clone().map_err(ContainerErr::IO)?;
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        while resized.read(&mut buf).is_ok_and(|n| n > 0) {
+            if let Some((rows, cols)) = size(libc::STDIN_FILENO) {
+                debug!("resizing terminal to {}x{}", rows, cols);
+                let _ = set_size(master.as_raw_fd(), rows, cols);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Copies our stdin to the pty master and its output to our stdout, until the process
+/// on the other end closes the terminal. The pty follows the size of our terminal, which
+/// is raw until then.
+pub fn forward_stdio(master: File) -> Result<(), ContainerErr> {
+    forward_resize(&master)?;
+    let _raw = RawMode::enable(libc::STDIN_FILENO);
+    let mut input = master.try_clone().map_err(ContainerErr::IO)?;
+    // Reading our stdin blocks, the thread goes away with the process.
+    thread::spawn(move || io::copy(&mut io::stdin(), &mut input));
+
+    let mut output = master;
+    let mut stdout = io::stdout();
+    let mut buf = [0u8; 4096];
+    loop {
+        match output.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                stdout.write_all(&buf[..n]).map_err(ContainerErr::IO)?;
+                stdout.flush().map_err(ContainerErr::IO)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            // Reads on the master fail with EIO once every slave fd is closed.
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => return Ok(()),
+            Err(e) => return Err(ContainerErr::IO(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_size() {
+        let pty = Pty::open().unwrap();
+        set_size(pty.master.as_raw_fd(), 24, 80).unwrap();
+        assert_eq!(Some((24, 80)), size(pty.slave.as_raw_fd()));
+        set_size(pty.slave.as_raw_fd(), 50, 132).unwrap();
+        assert_eq!(Some((50, 132)), size(pty.master.as_raw_fd()));
+
+        let file = File::open("/dev/null").unwrap();
+        assert_eq!(None, size(file.as_raw_fd()));
+    }
+
+    #[test]
+    fn test_raw_mode() {
+        let pty = Pty::open().unwrap();
+        assert!(pty.path.starts_with("/dev/pts"));
+        let fd = pty.slave.as_raw_fd();
+        let lflag = || {
+            let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+            assert_eq!(0, unsafe { libc::tcgetattr(fd, &mut termios) });
+            termios.c_lflag
+        };
+        assert_ne!(0, lflag() & libc::ICANON);
+        let raw = RawMode::enable(fd).unwrap();
+        assert_eq!(0, lflag() & (libc::ICANON | libc::ECHO | libc::ISIG));
+        drop(raw);
+        assert_ne!(0, lflag() & libc::ICANON);
+
+        let file = File::open("/dev/null").unwrap();
+        assert!(RawMode::enable(file.as_raw_fd()).is_none());
+    }
+
+    #[test]
+    fn test_send_console() {
+        use std::os::unix::net::UnixListener;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let socket = PathBuf::from(format!("/tmp/console_{}.sock", time));
+        let listener = UnixListener::bind(&socket).unwrap();
+        let pty = Pty::open().unwrap();
+        send_console(&socket, &pty).unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut data = [0u8; 64];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr().cast(),
+            iov_len: data.len(),
+        };
+        let mut control = [0u8; 64];
+        let mut msg = unsafe { std::mem::zeroed::<libc::msghdr>() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = control.len() as _;
+        let n = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, 0) };
+        assert_eq!(pty.path.as_os_str().len() as isize, n);
+        let master = unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            assert_eq!(libc::SCM_RIGHTS, (*cmsg).cmsg_type);
+            File::from_raw_fd(std::ptr::read_unaligned(
+                libc::CMSG_DATA(cmsg).cast::<RawFd>(),
+            ))
+        };
+        // The received master is the same pty's.
+        set_size(master.as_raw_fd(), 30, 100).unwrap();
+        assert_eq!(Some((30, 100)), size(pty.slave.as_raw_fd()));
+        std::fs::remove_file(&socket).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+//! Looking up host users, and the users of a container.
+//!
+//! Host users are normally looked up through libc's getpwuid_r, so users from NSS (LDAP,
+//! systemd-homed, ...) are found too. Static builds, with the `static` feature, can't load
+//! NSS modules, they parse /etc/passwd themselves instead. A container's users are always
+//! looked up in its own /etc/passwd and /etc/group, NSS is the host's.
+
+use crate::config;
+use crate::error::ContainerErr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+const PASSWD_PATH: &str = "/etc/passwd";
+const GROUP_PATH: &str = "/etc/group";
+
+/// An entry of the user database.
+#[derive(Debug, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// An entry of the group database.
+#[derive(Debug, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub gid: u32,
+    /// Users with the group as a supplementary group.
+    pub members: Vec<String>,
+}
+
+/// Parses /etc/passwd contents, `name:password:uid:gid:gecos:home:shell` lines. Lines
+/// which don't parse are skipped, like libc does.
+pub fn parse_passwd(contents: &str) -> Vec<User> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next().filter(|name| !name.is_empty())?;
+            let uid = fields.nth(1)?.parse().ok()?;
+            let gid = fields.next()?.parse().ok()?;
+            Some(User {
+                name: name.to_string(),
+                uid,
+                gid,
+            })
+        })
+        .collect()
+}
+
+/// Parses /etc/group contents, `name:password:gid:member,member` lines.
+pub fn parse_group(contents: &str) -> Vec<Group> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next().filter(|name| !name.is_empty())?;
+            let gid = fields.nth(1)?.parse().ok()?;
+            let members = fields
+                .next()
+                .unwrap_or_default()
+                .split(',')
+                .filter(|member| !member.is_empty())
+                .map(String::from)
+                .collect();
+            Some(Group {
+                name: name.to_string(),
+                gid,
+                members,
+            })
+        })
+        .collect()
+}
+
+/// Reads a database like /etc/passwd under `root`, empty if there's none.
+fn read_db(root: &Path, path: &str) -> Result<String, ContainerErr> {
+    match fs::read_to_string(root.join(path.trim_start_matches('/'))) {
+        Ok(contents) => Ok(contents),
+        // Minimal hosts, initramfs and images may not have one, no users then.
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(ContainerErr::IO(e)),
+    }
+}
+
+/// The user with `uid`, None if there's no such user.
+#[cfg(feature = "static")]
+pub fn user_by_uid(uid: u32) -> Result<Option<User>, ContainerErr> {
+    let contents = read_db(Path::new("/"), PASSWD_PATH)?;
+    Ok(parse_passwd(&contents).into_iter().find(|u| u.uid == uid))
+}
+
+/// The user with `uid`, None if there's no such user.
+#[cfg(not(feature = "static"))]
+pub fn user_by_uid(uid: u32) -> Result<Option<User>, ContainerErr> {
+    use std::ffi::CStr;
+    use std::io;
+
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let ret =
+            unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+        match ret {
+            0 if result.is_null() => return Ok(None),
+            0 => {
+                let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+                return Ok(Some(User {
+                    name: name.to_string_lossy().into_owned(),
+                    uid: pwd.pw_uid,
+                    gid: pwd.pw_gid,
+                }));
+            }
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            e => return Err(ContainerErr::IO(io::Error::from_raw_os_error(e))),
+        }
+    }
+}
+
+/// Splits `user[:group]` into its parts, each a name or a numeric id.
+pub fn split_user(spec: &str) -> Result<(&str, Option<&str>), ContainerErr> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    if user.is_empty() || group.is_some_and(|group| group.is_empty() || group.contains(':')) {
+        return Err(ContainerErr::invalid_args(&format!(
+            "Invalid user, expected user[:group]: {}",
+            spec
+        )));
+    }
+    Ok((user, group))
+}
+
+/// The ids a container process runs as.
+#[derive(Debug, PartialEq)]
+pub struct ProcessUser {
+    pub uid: u32,
+    pub gid: u32,
+    /// The groups listing the user as a member, besides its primary group.
+    pub additional_gids: Vec<u32>,
+}
+
+impl ProcessUser {
+    /// Makes `user` this one, its groups included.
+    pub fn apply(&self, user: &mut config::User) {
+        user.uid = self.uid as isize;
+        user.gid = self.gid as isize;
+        user.additional_gids = (!self.additional_gids.is_empty()).then(|| {
+            self.additional_gids
+                .iter()
+                .map(|gid| *gid as isize)
+                .collect()
+        });
+    }
+}
+
+/// Resolves `user[:group]` against /etc/passwd and /etc/group under `root`, the
+/// container's root. Names have to be there, numeric ids don't. Without a group the
+/// user's primary group is used, or the gid of the same number if the user isn't known.
+pub fn resolve_user(root: &Path, spec: &str) -> Result<ProcessUser, ContainerErr> {
+    let (user, group) = split_user(spec)?;
+    let passwd = parse_passwd(&read_db(root, PASSWD_PATH)?);
+    let groups = parse_group(&read_db(root, GROUP_PATH)?);
+    let unknown = |what: &str, name: &str, path: &str| {
+        ContainerErr::invalid_args(&format!("No {} {} in the container's {}", what, name, path))
+    };
+
+    let (uid, entry) = match user.parse::<u32>() {
+        Ok(uid) => (uid, passwd.iter().find(|u| u.uid == uid)),
+        Err(_) => {
+            let entry = passwd
+                .iter()
+                .find(|u| u.name == user)
+                .ok_or_else(|| unknown("user", user, PASSWD_PATH))?;
+            (entry.uid, Some(entry))
+        }
+    };
+    let gid = match group {
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => groups
+                .iter()
+                .find(|g| g.name == group)
+                .map(|g| g.gid)
+                .ok_or_else(|| unknown("group", group, GROUP_PATH))?,
+        },
+        None => entry.map_or(uid, |entry| entry.gid),
+    };
+    let additional_gids = match entry {
+        Some(entry) => groups
+            .iter()
+            .filter(|g| g.gid != gid && g.members.contains(&entry.name))
+            .map(|g| g.gid)
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(ProcessUser {
+        uid,
+        gid,
+        additional_gids,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_parse_passwd() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\n\
+                      # comment\n\
+                      \n\
+                      broken:x:abc:0::/:/bin/false\n\
+                      nobody:x:65534:65534:nobody:/nonexistent:/usr/sbin/nologin\n\
+                      short:x:1000\n";
+        assert_eq!(
+            vec![
+                User {
+                    name: String::from("root"),
+                    uid: 0,
+                    gid: 0,
+                },
+                User {
+                    name: String::from("nobody"),
+                    uid: 65534,
+                    gid: 65534,
+                },
+            ],
+            parse_passwd(passwd)
+        );
+    }
+
+    #[test]
+    fn test_resolve_user() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let root = PathBuf::from(format!("/tmp/users_{}", time));
+        fs::create_dir_all(root.join("etc")).unwrap();
+        let user = |spec: &str| resolve_user(&root, spec);
+
+        // Numeric ids don't need the databases.
+        let numeric = ProcessUser {
+            uid: 1000,
+            gid: 100,
+            additional_gids: Vec::new(),
+        };
+        assert_eq!(numeric, user("1000:100").unwrap());
+        assert_eq!(1000, user("1000").unwrap().gid);
+        assert!(user("www").is_err());
+
+        fs::write(
+            root.join("etc/passwd"),
+            "root:x:0:0:root:/root:/bin/sh
+www:x:33:33::/var/www:/bin/false
+",
+        )
+        .unwrap();
+        fs::write(
+            root.join("etc/group"),
+            "root:x:0:
+www:x:33:
+log:x:4:www,root
+docker:x:999:www
+",
+        )
+        .unwrap();
+        let www = ProcessUser {
+            uid: 33,
+            gid: 33,
+            additional_gids: vec![4, 999],
+        };
+        assert_eq!(www, user("www").unwrap());
+        assert_eq!(www, user("33").unwrap());
+        let www_log = user("www:log").unwrap();
+        assert_eq!(
+            (33, 4, vec![999]),
+            (www_log.uid, www_log.gid, www_log.additional_gids)
+        );
+        assert_eq!((0, 999), {
+            let root = user("root:999").unwrap();
+            (root.uid, root.gid)
+        });
+        assert!(user("www:nogroup").is_err());
+        for invalid in ["", ":33", "www:", "www:log:x"] {
+            assert!(user(invalid).is_err(), "{}", invalid);
+        }
+
+        let mut config_user = config::User::default();
+        www.apply(&mut config_user);
+        assert_eq!((33, 33), (config_user.uid, config_user.gid));
+        assert_eq!(Some(vec![4, 999]), config_user.additional_gids);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_user_by_uid() {
+        let root = user_by_uid(0).unwrap().unwrap();
+        assert_eq!("root", root.name);
+        assert_eq!(0, root.gid);
+    }
+}
@@ -0,0 +1,164 @@
+//! Following a container's state as it changes, for `state --watch`.
+//!
+//! state.json is replaced by renaming a new file over it, so rather than the file itself
+//! we watch the container's state dir with inotify for the state being moved into place,
+//! and for the dir going away once the container is deleted. The init process may exit
+//! without its state being updated, if its monitor is gone too, so we keep a pidfd for it
+//! as well.
+
+use crate::dirs::ContainerDirs;
+use crate::error::ContainerErr;
+use crate::process::pidfd_open;
+use crate::state::Pid;
+use crate::syscalls;
+use libc::{IN_CLOEXEC, IN_CLOSE_WRITE, IN_DELETE_SELF, IN_IGNORED, IN_MOVED_TO, IN_ONLYDIR};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+
+/// What woke the watcher up.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// The state was written.
+    State,
+    /// The init process exited.
+    Exited,
+    /// The state dir was removed, the container is gone.
+    Deleted,
+}
+
+pub struct StateWatcher {
+    inotify: File,
+    /// state.json's name in the state dir.
+    state_name: OsString,
+    /// The followed init process, None once it exited.
+    pidfd: Option<OwnedFd>,
+    pid: Pid,
+}
+
+impl StateWatcher {
+    /// Starts watching the container's state dir, changes from now on are reported.
+    pub fn new(dirs: &ContainerDirs) -> Result<Self, ContainerErr> {
+        let inotify = syscalls::inotify_init1(IN_CLOEXEC).map_err(ContainerErr::IO)?;
+        let mask = IN_MOVED_TO | IN_CLOSE_WRITE | IN_DELETE_SELF | IN_ONLYDIR;
+        syscalls::inotify_add_watch(inotify.as_raw_fd(), dirs.dir(), mask)
+            .map_err(ContainerErr::IO)?;
+        Ok(Self {
+            inotify: File::from(inotify),
+            state_name: dirs.state().file_name().unwrap_or_default().to_os_string(),
+            pidfd: None,
+            pid: 0,
+        })
+    }
+
+    /// Follows the init process `pid`, if it's not the one followed already. Returns
+    /// whether it's still running.
+    pub fn follow(&mut self, pid: Pid) -> Result<bool, ContainerErr> {
+        if pid != self.pid {
+            self.pid = pid;
+            // Containers which haven't been created yet have no process.
+            if pid == 0 {
+                self.pidfd = None;
+                return Ok(false);
+            }
+            self.pidfd = match pidfd_open(pid) {
+                Ok(pidfd) => Some(pidfd),
+                Err(e) if e.raw_os_error() == Some(libc::ESRCH) => None,
+                Err(e) => return Err(ContainerErr::IO(e)),
+            };
+        }
+        Ok(self.pidfd.is_some())
+    }
+
+    /// Blocks until the next change.
+    pub fn wait(&mut self) -> Result<Change, ContainerErr> {
+        loop {
+            let mut fds = [self.inotify.as_raw_fd(), -1].map(|fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            });
+            // poll skips negative fds.
+            if let Some(pidfd) = &self.pidfd {
+                fds[1].fd = pidfd.as_raw_fd();
+            }
+            match syscalls::poll(&mut fds, -1) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ContainerErr::IO(e)),
+            }
+            // The state is looked at first, the monitor may already have recorded the exit.
+            if fds[0].revents != 0 {
+                if let Some(change) = self.read_events()? {
+                    return Ok(change);
+                }
+            }
+            if fds[1].revents != 0 {
+                self.pidfd = None;
+                return Ok(Change::Exited);
+            }
+        }
+    }
+
+    /// Reads the queued inotify events, None if none of them were about the state.
+    fn read_events(&mut self) -> Result<Option<Change>, ContainerErr> {
+        // Big enough for a few events, names are at most NAME_MAX + 1 bytes long.
+        let mut buf = [0u8; 4096];
+        let n = self.inotify.read(&mut buf).map_err(ContainerErr::IO)?;
+        let mut change = None;
+        let mut events = &buf[..n];
+        while events.len() >= size_of::<libc::inotify_event>() {
+            // inotify_event is wd, mask, cookie and len, followed by len bytes of name.
+            let field = |i: usize| u32::from_ne_bytes(events[i * 4..i * 4 + 4].try_into().unwrap());
+            let (mask, len) = (field(1), field(3) as usize);
+            let header = size_of::<libc::inotify_event>();
+            let name = &events[header..header + len];
+            let name = name.split(|b| *b == 0).next().unwrap_or_default();
+            events = &events[header + len..];
+
+            if mask & (IN_DELETE_SELF | IN_IGNORED) != 0 {
+                return Ok(Some(Change::Deleted));
+            }
+            if name == self.state_name.as_bytes() {
+                change = Some(Change::State);
+            }
+        }
+        Ok(change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_state_watcher() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dirs = ContainerDirs::new(PathBuf::from(format!("/tmp/state_watcher_{}", time)));
+        dirs.create().unwrap();
+        let dir = dirs.dir();
+        let mut watcher = StateWatcher::new(&dirs).unwrap();
+
+        // Other files in the state dir don't count.
+        fs::write(dir.join("container.log"), "output").unwrap();
+        fs::write(dir.join("state.json.tmp"), "{}").unwrap();
+        fs::rename(dir.join("state.json.tmp"), dirs.state()).unwrap();
+        assert_eq!(Change::State, watcher.wait().unwrap());
+
+        // Our own process is followed until it exits, which it doesn't here.
+        assert!(watcher.follow(std::process::id()).unwrap());
+        assert!(!watcher.follow(0).unwrap());
+
+        dirs.remove().unwrap();
+        assert_eq!(Change::Deleted, watcher.wait().unwrap());
+    }
+}